@@ -1,10 +1,11 @@
 use super::execute::Execute;
-use super::filestream::FileStream;
+use super::filestream::{FifoMode, FifoStream, FileStream};
 use super::info::{resolve_file_stream_option, resolve_file_streams, Info};
 use super::pipe::{
     create_and_insert_channels, create_buffer_file, get_channel_name, BufferedPipe, PipeMode,
     SharedChannelMap,
 };
+use super::process::register_process;
 use super::rapper::copy_wrapper as copy;
 use super::rapper::stream_initiate_filter;
 use super::{program, stream, Location, Result};
@@ -12,6 +13,7 @@ use failure::bail;
 use itertools::join;
 use program::{Link, NodeId, ProgId};
 use std::convert::Into;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::{ChildStdin, Command, Stdio};
 use std::slice::IterMut;
@@ -107,6 +109,13 @@ pub struct CommandNode {
     options: CmdExtraInfo,
     /// PWD for executing the command.
     pwd: PathBuf,
+    /// If each input stream is passed to the command as a fifo argument, instead of all of them
+    /// being concatenated into its stdin. Used for commands that merge their inputs, like
+    /// `sort -m`.
+    stdin_as_fifos: bool,
+    /// Environment variables set for the command, with their values where the program was
+    /// started.
+    env: Vec<(String, String)>,
 }
 
 impl CommandNode {
@@ -140,6 +149,34 @@ impl CommandNode {
         self.options = options;
     }
 
+    pub fn set_stdin_as_fifos(&mut self, stdin_as_fifos: bool) {
+        self.stdin_as_fifos = stdin_as_fifos;
+    }
+
+    pub fn get_stdin_as_fifos(&self) -> bool {
+        self.stdin_as_fifos
+    }
+
+    pub fn set_env(&mut self, env: Vec<(String, String)>) {
+        self.env = env;
+    }
+
+    pub fn get_env(&self) -> &[(String, String)] {
+        &self.env
+    }
+
+    /// Path of the fifo the input stream at `index` is written to, when inputs are passed as
+    /// fifos.
+    fn input_fifo(&self, tmp_folder: &Path, index: usize) -> FifoStream {
+        FifoStream::new(
+            tmp_folder
+                .join(format!("input_{}_{}", self.node_id, index))
+                .as_path(),
+            self.location.clone(),
+            FifoMode::WRITE,
+        )
+    }
+
     pub fn clear_stdin(&mut self) {
         self.stdin.clear();
     }
@@ -340,6 +377,10 @@ impl Info for CommandNode {
         self.node_id = id;
     }
 
+    fn set_prog_id(&mut self, id: ProgId) {
+        self.prog_id = id;
+    }
+
     fn get_id(&self) -> NodeId {
         self.node_id
     }
@@ -686,8 +727,15 @@ impl Execute for CommandNode {
     ) -> Result<()> {
         let mut cmd = Command::new(self.name.clone());
         cmd.args(self.resolved_args.clone());
+        cmd.envs(self.env.iter().cloned());
 
-        if self.stdin.len() > 0 {
+        if self.stdin_as_fifos {
+            for index in 0..self.stdin.len() {
+                let fifo = self.input_fifo(tmp_folder.as_path(), index);
+                fifo.create()?;
+                cmd.arg(fifo.get_path());
+            }
+        } else if self.stdin.len() > 0 {
             debug!(
                 "Setting stdin for node {:?} to be Stdio::Piped",
                 self.node_id
@@ -745,7 +793,8 @@ impl Execute for CommandNode {
             }
         }
         let child = cmd.spawn().expect("Failed to spawn child");
-        if self.stdin.len() > 0 {
+        register_process(self.prog_id, child.id())?;
+        if self.stdin.len() > 0 && !self.stdin_as_fifos {
             let stdin_handle = child.stdin.expect("Could not get stdin handle for proc");
             pipes.insert(
                 self.get_handle_identifier(IOType::Stdin),
@@ -781,8 +830,37 @@ impl Execute for CommandNode {
     ) -> Result<()> {
         let mut threads: Vec<(IOType, JoinHandle<Result<()>>)> = Vec::new();
 
+        // spawn a thread per input to write it into its fifo
+        if self.stdin_as_fifos {
+            for (index, input_stream) in self.stdin.iter().enumerate() {
+                let prog_id = self.prog_id;
+                let fifo = self.input_fifo(tmp_folder.as_path(), index);
+                let input_stream = input_stream.clone();
+                let pipes_clone = pipes.clone();
+                let network_connections_clone = network_connections.clone();
+                let channels_clone = channels.clone();
+                let tmp_folder_clone = tmp_folder.clone();
+                threads.push((
+                    IOType::Stdin,
+                    spawn(move || {
+                        // blocks until the command opens the fifo for reading
+                        let mut handle = fifo.open()?;
+                        copy_input(
+                            prog_id,
+                            &input_stream,
+                            &mut handle,
+                            pipes_clone,
+                            network_connections_clone,
+                            channels_clone,
+                            tmp_folder_clone,
+                        )
+                    }),
+                ));
+            }
+        }
+
         // spawn a stdin thread to handle the input
-        if self.stdin.len() > 0 {
+        if self.stdin.len() > 0 && !self.stdin_as_fifos {
             let stdin_prog_id = self.prog_id;
             let stdin_handle = pipes.remove(&self.get_handle_identifier(IOType::Stdin))?;
             let stdin_streams = self.stdin.clone();
@@ -914,66 +992,88 @@ fn redirect_stdin(
     prog_id: ProgId,
     stdin_handle: OutputHandle,
     stdin_streams: Vec<DashStream>,
-    mut pipes: SharedPipeMap,
-    mut network_connections: SharedStreamMap,
-    mut channels: SharedChannelMap,
+    pipes: SharedPipeMap,
+    network_connections: SharedStreamMap,
+    channels: SharedChannelMap,
     tmp_folder: PathBuf,
 ) -> Result<()> {
     let stdin_handle_option: Option<ChildStdin> = stdin_handle.into();
     let mut stdin = stdin_handle_option.unwrap();
     for input_stream in stdin_streams.iter() {
-        match input_stream {
-            DashStream::Tcp(netstream) => {
-                let mut tcpstream = network_connections.remove(&netstream)?;
-                copy(&mut tcpstream, &mut stdin)?;
-            }
-            DashStream::Pipe(pipestream) => {
-                if pipestream.get_bufferable() {
-                    let channel_end = channels.remove(&get_channel_name(
-                        pipestream.get_left(),
-                        PipeMode::Read,
-                        pipestream.get_output_type(),
-                    ))?;
-                    // copy from the buffer file, not the process
-                    // buffered pipe is indexed by left end of the pipe
-                    let mut buffered_pipe = BufferedPipe::new(
-                        pipestream.get_left(),
-                        pipestream.get_output_type(),
-                        tmp_folder.as_path(),
-                        PipeMode::Read,
-                        channel_end,
-                    )?;
-                    copy(&mut buffered_pipe, &mut stdin)?;
-                    buffered_pipe.drop_file()?;
+        copy_input(
+            prog_id,
+            input_stream,
+            &mut stdin,
+            pipes.clone(),
+            network_connections.clone(),
+            channels.clone(),
+            tmp_folder.clone(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Copies a single input stream of a command into `stdin`.
+fn copy_input(
+    prog_id: ProgId,
+    input_stream: &DashStream,
+    stdin: &mut dyn Write,
+    mut pipes: SharedPipeMap,
+    mut network_connections: SharedStreamMap,
+    mut channels: SharedChannelMap,
+    tmp_folder: PathBuf,
+) -> Result<()> {
+    match input_stream {
+        DashStream::Tcp(netstream) => {
+            let mut tcpstream = network_connections.remove(netstream)?;
+            copy(&mut tcpstream, stdin)?;
+        }
+        DashStream::Pipe(pipestream) => {
+            if pipestream.get_bufferable() {
+                let channel_end = channels.remove(&get_channel_name(
+                    pipestream.get_left(),
+                    PipeMode::Read,
+                    pipestream.get_output_type(),
+                ))?;
+                // copy from the buffer file, not the process
+                // buffered pipe is indexed by left end of the pipe
+                let mut buffered_pipe = BufferedPipe::new(
+                    pipestream.get_left(),
+                    pipestream.get_output_type(),
+                    tmp_folder.as_path(),
+                    PipeMode::Read,
+                    channel_end,
+                )?;
+                copy(&mut buffered_pipe, stdin)?;
+                buffered_pipe.drop_file()?;
+            } else {
+                // just copy from the process directly as normal
+                let handle_identifier = HandleIdentifier::new(
+                    prog_id,
+                    pipestream.get_left(),
+                    pipestream.get_output_type(),
+                );
+                // if left side of the pipe is a command node, then there will be a previous
+                // handle
+                let contains = pipes.contains_key(&handle_identifier)?;
+                if contains {
+                    let mut prev_handle = pipes.remove(&handle_identifier)?;
+                    copy(&mut prev_handle, stdin)?;
                 } else {
-                    // just copy from the process directly as normal
-                    let handle_identifier = HandleIdentifier::new(
-                        prog_id,
-                        pipestream.get_left(),
-                        pipestream.get_output_type(),
+                    tracing::debug!(
+                        "No pipe found for handle identifier: {:?}",
+                        handle_identifier
                     );
-                    // if left side of the pipe is a command node, then there will be a previous
-                    // handle
-                    let contains = pipes.contains_key(&handle_identifier)?;
-                    if contains {
-                        let mut prev_handle = pipes.remove(&handle_identifier)?;
-                        copy(&mut prev_handle, &mut stdin)?;
-                    } else {
-                        tracing::debug!(
-                            "No pipe found for handle identifier: {:?}",
-                            handle_identifier
-                        );
-                    }
                 }
             }
-            DashStream::File(filestream) => {
-                // Open a read version of the file, and copy it into the current process
-                let mut file_handle = filestream.open()?;
-                copy(&mut file_handle, &mut stdin)?;
-            }
-            _ => {
-                bail!("Command node should not see input from file, stdout, or stderr stream handle: {:?}", input_stream);
-            }
+        }
+        DashStream::File(filestream) => {
+            // Open a read version of the file, and copy it into the current process
+            let mut file_handle = filestream.open()?;
+            copy(&mut file_handle, stdin)?;
+        }
+        _ => {
+            bail!("Command node should not see input from file, stdout, or stderr stream handle: {:?}", input_stream);
         }
     }
     Ok(())
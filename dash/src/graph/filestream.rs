@@ -69,6 +69,10 @@ impl FifoStream {
     pub fn get_location(&self) -> Location {
         self.dest_location.clone()
     }
+
+    pub fn get_path(&self) -> &Path {
+        self.path.as_path()
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Hash, Eq, Copy)]
@@ -205,7 +209,9 @@ impl FileStream {
     /// Attempts to canonicalize the filepath.
     /// If the file does not exist, modifies the path to prefix the pwd.
     pub fn dash_canonicalize(&mut self, pwd: &Path) -> Result<()> {
-        match canonicalize(self.path.as_path()) {
+        // resolve against the given directory, which can differ from the process's directory
+        let new_relative_path = pwd.to_path_buf().join(self.path.clone());
+        match canonicalize(new_relative_path.as_path()) {
             Ok(full_path) => {
                 self.path = full_path;
                 return Ok(());
@@ -216,7 +222,6 @@ impl FileStream {
             },
         }
 
-        let new_relative_path = pwd.to_path_buf().join(self.path.clone());
         self.path = new_relative_path;
         Ok(())
     }
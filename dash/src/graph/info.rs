@@ -1,4 +1,4 @@
-use super::program::{Link, NodeId};
+use super::program::{Link, NodeId, ProgId};
 use super::stream::{DashStream, IOType, NetStream, PipeStream};
 use super::{Location, Result};
 use std::path::{Path, PathBuf};
@@ -10,6 +10,8 @@ pub trait Info {
 
     fn get_id(&self) -> NodeId;
 
+    fn set_prog_id(&mut self, id: ProgId);
+
     fn get_loc(&self) -> Location;
 
     fn set_loc(&mut self, loc: Location);
@@ -5,6 +5,7 @@ pub mod execute;
 pub mod filestream;
 pub mod info;
 pub mod pipe;
+pub mod process;
 pub mod program;
 pub mod rapper;
 pub mod read;
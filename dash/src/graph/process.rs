@@ -0,0 +1,49 @@
+use super::program::ProgId;
+use super::Result;
+use failure::bail;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Processes spawned on this machine, by the program that spawned them.
+/// Used to deliver job control signals (e.g., stop, continue, kill) to a running program.
+static PROCESSES: Mutex<BTreeMap<ProgId, Vec<u32>>> = Mutex::new(BTreeMap::new());
+
+/// Records that the given process was spawned as part of the given program.
+pub fn register_process(prog_id: ProgId, pid: u32) -> Result<()> {
+    let mut map = match PROCESSES.lock() {
+        Ok(m) => m,
+        Err(e) => bail!("Lock is poisoned: {:?}", e),
+    };
+    map.entry(prog_id).or_insert_with(Vec::new).push(pid);
+    Ok(())
+}
+
+/// Forgets the processes of a program once it has finished executing.
+pub fn forget_program(prog_id: ProgId) -> Result<()> {
+    let mut map = match PROCESSES.lock() {
+        Ok(m) => m,
+        Err(e) => bail!("Lock is poisoned: {:?}", e),
+    };
+    map.remove(&prog_id);
+    Ok(())
+}
+
+/// Sends the signal to every process this machine spawned for the program.
+/// Returns the number of processes signalled; processes that already exited are skipped.
+pub fn signal_program(prog_id: ProgId, signal: Signal) -> Result<usize> {
+    let map = match PROCESSES.lock() {
+        Ok(m) => m,
+        Err(e) => bail!("Lock is poisoned: {:?}", e),
+    };
+    let mut count = 0;
+    if let Some(pids) = map.get(&prog_id) {
+        for pid in pids.iter() {
+            if kill(Pid::from_raw(*pid as i32), signal).is_ok() {
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
@@ -5,7 +5,7 @@ use super::pipe::SharedChannelMap;
 use super::rapper::Rapper;
 use super::read2 as read;
 use super::write2 as write;
-use super::{filestream, stream, Location, Result};
+use super::{filestream, process, stream, Location, Result};
 use failure::bail;
 use filestream::{FifoMode, FifoStream, FileStream};
 use serde::{Deserialize, Serialize};
@@ -13,6 +13,7 @@ use std::collections::hash_map;
 use std::collections::HashMap;
 use std::env;
 use std::fmt;
+use std::fs;
 use std::fs::File;
 use std::path::Path;
 use std::path::PathBuf;
@@ -32,6 +33,17 @@ pub enum Elem {
     Cmd(cmd::CommandNode),
 }
 
+impl Elem {
+    /// Sets the id of the program this element belongs to.
+    pub fn set_prog_id(&mut self, id: ProgId) {
+        match self {
+            Elem::Write(write_node) => write_node.set_prog_id(id),
+            Elem::Read(read_node) => read_node.set_prog_id(id),
+            Elem::Cmd(cmd_node) => cmd_node.set_prog_id(id),
+        }
+    }
+}
+
 impl Into<Option<cmd::CommandNode>> for Elem {
     fn into(self) -> Option<cmd::CommandNode> {
         match self {
@@ -542,8 +554,25 @@ impl Program {
         self.id
     }
 
+    /// Sets the program id, on the program and all of its nodes.
+    /// Set this before the program is networked, so all of its network streams carry the id.
     pub fn set_id(&mut self, id: ProgId) {
         self.id = id;
+        for (_, node) in self.nodes.iter_mut() {
+            node.get_mut_elem().set_prog_id(id);
+        }
+    }
+
+    /// Returns each location that executes part of this program.
+    pub fn get_locations(&self) -> Vec<Location> {
+        let mut locations: Vec<Location> = Vec::new();
+        for (_, node) in self.nodes.iter() {
+            let loc = node.get_loc();
+            if !locations.contains(&loc) {
+                locations.push(loc);
+            }
+        }
+        locations
     }
 
     pub fn write_dot(&self, filename: &str) -> Result<()> {
@@ -908,6 +937,58 @@ impl Program {
         Ok(new_node_ids)
     }
 
+    /// Adds `elem` after node `id`: the new node takes over where the stdout of `id` went, and
+    /// the stdout of `id` is piped into the new node instead.
+    /// Used to add a node that combines the outputs of `id` before it is split into parallel
+    /// copies.
+    pub fn insert_after(&mut self, id: NodeId, elem: Elem) -> Result<NodeId> {
+        let stdout = match self.get_node(id) {
+            Some(node) => node.get_stdout(),
+            None => bail!("Could not find node id: {:?} to insert a node after", id),
+        };
+        let pipestream = match stdout.as_slice() {
+            [DashStream::Pipe(pipestream)] => pipestream.clone(),
+            _ => bail!(
+                "Can only insert a node after one that pipes its stdout, found: {:?}",
+                stdout
+            ),
+        };
+        let new_id = self.add_elem(elem);
+        let old_edge = Link {
+            left: id,
+            right: pipestream.get_right(),
+        };
+        let into_new = Link {
+            left: id,
+            right: new_id,
+        };
+        let out_of_new = Link {
+            left: new_id,
+            right: pipestream.get_right(),
+        };
+        self.nodes
+            .get_mut(&id)
+            .unwrap()
+            .replace_stream_edges(old_edge.clone(), vec![into_new.clone()])?;
+        match self.nodes.get_mut(&pipestream.get_right()) {
+            Some(node) => node.replace_stream_edges(old_edge.clone(), vec![out_of_new.clone()])?,
+            None => bail!("Pipestream has a right node that doesn't exist"),
+        }
+        let mut stdin_pipe = pipestream.clone();
+        stdin_pipe.set_right(new_id);
+        let mut stdout_pipe = pipestream.clone();
+        stdout_pipe.set_left(new_id);
+        {
+            let new_node = self.nodes.get_mut(&new_id).unwrap();
+            new_node.add_stdin(DashStream::Pipe(stdin_pipe))?;
+            new_node.add_stdout(DashStream::Pipe(stdout_pipe))?;
+        }
+        self.edges.retain(|x| *x != old_edge);
+        self.add_unique_edge(into_new.get_left(), into_new.get_right());
+        self.add_unique_edge(out_of_new.get_left(), out_of_new.get_right());
+        Ok(new_id)
+    }
+
     pub fn remove_node(&mut self, id: NodeId) -> Result<()> {
         match self.nodes.remove(&id) {
             Some(_) => {}
@@ -1234,13 +1315,14 @@ impl Program {
 
         // add edge in between them
         self.add_unique_edge(readnode_id, writenode_id);
-        let netstream = NetStream::new(
+        let mut netstream = NetStream::new(
             readnode_id,
             writenode_id,
             IOType::Stdout,
             origin_loc.clone(),
             access_loc.clone(),
         )?;
+        netstream.set_prog_id(self.id);
 
         // set the connection between two nodes
         let read = self.nodes.get_mut(&readnode_id).unwrap();
@@ -1258,6 +1340,128 @@ impl Program {
         Ok(())
     }
 
+    /// Adds a read node at the origin and a write node at the access location that copy the
+    /// entire file into `staged_file` before any command at the access location starts.
+    /// Used for commands that seek in their input, which cannot read it from a fifo.
+    pub fn add_remote_file_stage(
+        &mut self,
+        origin_loc: &Location,
+        access_loc: &Location,
+        origin_filestream: &FileStream,
+        staged_file: &FileStream,
+    ) -> Result<()> {
+        let mut readnode = read::ReadNode::default();
+        readnode.add_stdin(DashStream::File(origin_filestream.clone()))?;
+        readnode.set_loc(origin_loc.clone());
+        let readnode_id = self.add_elem(Elem::Read(readnode));
+        let mut writenode = write::WriteNode::default();
+        writenode.set_stdout(DashStream::File(staged_file.clone()))?;
+        writenode.set_staged(true);
+        writenode.set_loc(access_loc.clone());
+        let writenode_id = self.add_elem(Elem::Write(writenode));
+
+        // the nodes run on different machines, so they are only connected over the network; an
+        // edge would be turned into a network stream again by make_pipes_networked
+        let mut netstream = NetStream::new(
+            readnode_id,
+            writenode_id,
+            IOType::Stdout,
+            origin_loc.clone(),
+            access_loc.clone(),
+        )?;
+        netstream.set_prog_id(self.id);
+        let read = self.nodes.get_mut(&readnode_id).unwrap();
+        read.get_mut_elem()
+            .add_stdout(DashStream::Tcp(netstream.clone()))?;
+        let write = self.nodes.get_mut(&writenode_id).unwrap();
+        write
+            .get_mut_elem()
+            .add_stdin(DashStream::Tcp(netstream.clone()))?;
+        Ok(())
+    }
+
+    /// Local copies of remote files made for commands that read them with random access.
+    pub fn get_staged_files(&self) -> Vec<FileStream> {
+        let mut ret: Vec<FileStream> = Vec::new();
+        for (_id, node) in self.nodes.iter() {
+            if let Elem::Write(writenode) = &node.elem {
+                if let (true, DashStream::File(fs)) =
+                    (writenode.is_staged(), writenode.get_output_ref())
+                {
+                    ret.push(fs.clone());
+                }
+            }
+        }
+        ret
+    }
+
+    /// Adds a read node at the access location and a write node at the origin that move
+    /// `produced_file`, which a command at the access location writes, to `origin_filestream`
+    /// once every command at the access location has finished.
+    pub fn add_remote_output_collect(
+        &mut self,
+        access_loc: &Location,
+        origin_loc: &Location,
+        produced_file: &FileStream,
+        origin_filestream: &FileStream,
+    ) -> Result<()> {
+        let mut readnode = read::ReadNode::default();
+        readnode.add_stdin(DashStream::File(produced_file.clone()))?;
+        readnode.set_collects_output(true);
+        readnode.set_loc(access_loc.clone());
+        let readnode_id = self.add_elem(Elem::Read(readnode));
+        let mut writenode = write::WriteNode::default();
+        writenode.set_stdout(DashStream::File(origin_filestream.clone()))?;
+        writenode.set_commits_output(true);
+        writenode.set_loc(origin_loc.clone());
+        let writenode_id = self.add_elem(Elem::Write(writenode));
+
+        // as with staged files, the nodes are only connected over the network
+        let mut netstream = NetStream::new(
+            readnode_id,
+            writenode_id,
+            IOType::Stdout,
+            access_loc.clone(),
+            origin_loc.clone(),
+        )?;
+        netstream.set_prog_id(self.id);
+        let read = self.nodes.get_mut(&readnode_id).unwrap();
+        read.get_mut_elem()
+            .add_stdout(DashStream::Tcp(netstream.clone()))?;
+        let write = self.nodes.get_mut(&writenode_id).unwrap();
+        write
+            .get_mut_elem()
+            .add_stdin(DashStream::Tcp(netstream.clone()))?;
+        Ok(())
+    }
+
+    /// Files that commands write on this side of the network for output files elsewhere.
+    pub fn get_collected_files(&self) -> Vec<FileStream> {
+        let mut ret: Vec<FileStream> = Vec::new();
+        for (_id, node) in self.nodes.iter() {
+            if let Elem::Read(readnode) = &node.elem {
+                if readnode.collects_output() {
+                    ret.push(readnode.get_input_ref().clone());
+                }
+            }
+        }
+        ret
+    }
+
+    fn is_collector(&self, id: NodeId) -> bool {
+        match self.nodes.get(&id).map(|node| &node.elem) {
+            Some(Elem::Read(readnode)) => readnode.collects_output(),
+            _ => false,
+        }
+    }
+
+    fn is_staged(&self, id: NodeId) -> bool {
+        match self.nodes.get(&id).map(|node| &node.elem) {
+            Some(Elem::Write(writenode)) => writenode.is_staged(),
+            _ => false,
+        }
+    }
+
     /// Iterates through all the edges in the program,
     /// and if any two nodes connected by an edge are not at the same location,
     /// makes the corresponding pipe a TCP stream.
@@ -1286,13 +1490,14 @@ impl Program {
                 .get_pipe(link.get_left(), link.get_right())?;
 
             // find the corresponding pipestream
-            let new_stream = NetStream::new(
+            let mut new_stream = NetStream::new(
                 link.get_left(),
                 link.get_right(),
                 pipestream.get_output_type(),
                 left_loc,
                 right_loc,
             )?;
+            new_stream.set_prog_id(self.id);
 
             // replace the pipes
             self.nodes
@@ -1378,6 +1583,7 @@ impl Program {
                 }
                 None => {
                     let mut prog = Program::default();
+                    prog.id = self.id;
                     prog.add_unique_node(node.clone());
                     map.insert(location.clone(), prog);
                 }
@@ -1402,6 +1608,7 @@ impl Program {
 
     pub fn add_elem(&mut self, mut elem: Elem) -> NodeId {
         elem.set_id(self.counter + 1);
+        elem.set_prog_id(self.id);
         let node: Node = Node {
             elem: elem,
             id: self.counter + 1,
@@ -1649,12 +1856,6 @@ impl Program {
     /// when executing the node. Note that if it's a client, folder should be none; no filepaths
     /// need to be resolved.
     pub fn execute(&mut self, stream_map: SharedStreamMap, tmp_folder: String) -> Result<()> {
-        let pipe_map = SharedPipeMap::new();
-        let channel_map = SharedChannelMap::new();
-        let execution_order = self.execution_order();
-        let mut node_threads: Vec<JoinHandle<Result<()>>> = Vec::new();
-        let mut node_thread_ids: Vec<NodeId> = Vec::new();
-
         // First, set the current dir if this program requires it.
         // theoretically should not break anything else, as stuff is being executed with full paths
         match self.get_current_dir() {
@@ -1664,8 +1865,58 @@ impl Program {
             }
             None => {}
         }
+
+        // Programs can run concurrently (e.g., background jobs), so each one buffers into its
+        // own folder.
+        let tmp = Path::new(&tmp_folder).join(format!("prog_{}", self.id));
+        fs::create_dir_all(&tmp)?;
+        let ret = self.execute_nodes(stream_map, &tmp);
+        for staged in self.get_staged_files().iter() {
+            let _ = fs::remove_file(staged.get_path());
+        }
+        for collected in self.get_collected_files().iter() {
+            let _ = fs::remove_file(collected.get_path());
+        }
+        process::forget_program(self.id)?;
+        let _ = fs::remove_dir_all(&tmp);
+        ret
+    }
+
+    /// Spawns every node, then runs and joins the redirection threads.
+    fn execute_nodes(&mut self, stream_map: SharedStreamMap, tmp_folder: &Path) -> Result<()> {
+        let pipe_map = SharedPipeMap::new();
+        let channel_map = SharedChannelMap::new();
+        let execution_order = self.execution_order();
+        let mut node_threads: Vec<JoinHandle<Result<()>>> = Vec::new();
+        let mut node_thread_ids: Vec<NodeId> = Vec::new();
+
+        // Files read with random access are copied over in full before any command starts
+        for node_id in execution_order.iter() {
+            if !self.is_staged(*node_id) {
+                continue;
+            }
+            let mut node_clone = self.nodes.get(node_id).unwrap().clone();
+            let tmp = tmp_folder.to_path_buf();
+            node_clone.spawn(
+                pipe_map.clone(),
+                stream_map.clone(),
+                channel_map.clone(),
+                tmp.clone(),
+            )?;
+            node_clone.run_redirection(
+                pipe_map.clone(),
+                stream_map.clone(),
+                channel_map.clone(),
+                tmp,
+            )?;
+            tracing::debug!("finished staging: {:?}", node_id);
+        }
+
         // First execute any commands, e.g. spawn the initial processes
         for node_id in execution_order.iter() {
+            if self.is_staged(*node_id) {
+                continue;
+            }
             let node = match self.nodes.get_mut(node_id) {
                 Some(n) => n,
                 None => bail!(
@@ -1676,7 +1927,7 @@ impl Program {
             let pipe_map_copy = pipe_map.clone();
             let stream_map_copy = stream_map.clone();
             let mut node_clone = node.clone();
-            let tmp = Path::new(&tmp_folder).to_path_buf();
+            let tmp = tmp_folder.to_path_buf();
             // This call is non-blocking
             node_clone.spawn(pipe_map_copy, stream_map_copy, channel_map.clone(), tmp)?;
             tracing::debug!("finished spawning: {:?}", node);
@@ -1684,6 +1935,9 @@ impl Program {
 
         // Next, loop over and run redirection commands
         for node_id in execution_order.iter() {
+            if self.is_staged(*node_id) {
+                continue;
+            }
             let node = match self.nodes.get_mut(node_id) {
                 Some(n) => n,
                 None => bail!(
@@ -1695,7 +1949,7 @@ impl Program {
             let stream_map_copy = stream_map.clone();
             let channels_clone = channel_map.clone();
             let mut node_clone = node.clone();
-            let tmp = Path::new(&tmp_folder).to_path_buf();
+            let tmp = tmp_folder.to_path_buf();
             // This call is non-blocking
             tracing::debug!("about to run redirection for: {:?},", node_id);
             node_threads.push(spawn(move || {
@@ -1704,29 +1958,43 @@ impl Program {
             node_thread_ids.push(*node_id);
         }
 
-        // Join all the threads to make sure it worked
-        let mut count: usize = 0;
-        for thread in node_threads {
-            match thread.join() {
-                Ok(res) => match res {
-                    Ok(_) => {}
-                    Err(e) => {
-                        tracing::error!("Error on thread {:?}", count);
-                        bail!(
-                            "Node failed to execute: {:?} id {:?}",
-                            e,
-                            node_thread_ids[count]
-                        );
+        let mut ret = join_node_threads(node_threads, node_thread_ids);
+        tracing::debug!("joined all the threads");
+
+        // Output files for other machines are only sent once the commands writing them are done
+        for node_id in execution_order.iter() {
+            if !self.is_collector(*node_id) {
+                continue;
+            }
+            let mut node_clone = self.nodes.get(node_id).unwrap().clone();
+            if ret.is_err() {
+                // closing the connection tells the other machine the output is not coming
+                for stream in node_clone.get_elem().get_stdout().iter() {
+                    if let DashStream::Tcp(netstream) = stream {
+                        let _ = stream_map.clone().remove(netstream);
                     }
-                },
-                Err(e) => {
-                    bail!("Thread failed to join!: {:?}", e);
                 }
+                continue;
             }
-            count += 1;
+            let tmp = tmp_folder.to_path_buf();
+            ret = node_clone
+                .spawn(
+                    pipe_map.clone(),
+                    stream_map.clone(),
+                    channel_map.clone(),
+                    tmp.clone(),
+                )
+                .and_then(|_| {
+                    node_clone.run_redirection(
+                        pipe_map.clone(),
+                        stream_map.clone(),
+                        channel_map.clone(),
+                        tmp,
+                    )
+                });
+            tracing::debug!("finished collecting: {:?}", node_id);
         }
-        tracing::debug!("joined all the threads");
-        Ok(())
+        ret
     }
 
     /// Returns a list of outward streams this server should initiate
@@ -1756,6 +2024,36 @@ impl Program {
         ret
     }
 }
+
+/// Joins the redirection threads of a program, failing if any of them failed.
+fn join_node_threads(
+    node_threads: Vec<JoinHandle<Result<()>>>,
+    node_thread_ids: Vec<NodeId>,
+) -> Result<()> {
+    // Join all the threads to make sure it worked
+    let mut count: usize = 0;
+    for thread in node_threads {
+        match thread.join() {
+            Ok(res) => match res {
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("Error on thread {:?}", count);
+                    bail!(
+                        "Node failed to execute: {:?} id {:?}",
+                        e,
+                        node_thread_ids[count]
+                    );
+                }
+            },
+            Err(e) => {
+                bail!("Thread failed to join!: {:?}", e);
+            }
+        }
+        count += 1;
+    }
+    Ok(())
+}
+
 impl fmt::Debug for Program {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // print ID
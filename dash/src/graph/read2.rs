@@ -7,6 +7,7 @@ use super::rapper::stream_initiate_filter;
 use super::{program, stream, Location, Result};
 use failure::bail;
 use program::{Link, NodeId, ProgId};
+use std::io::Write;
 use std::path::PathBuf;
 use stream::{DashStream, IOType, NetStream, PipeStream, SharedPipeMap, SharedStreamMap};
 use tracing::error;
@@ -24,9 +25,20 @@ pub struct ReadNode {
     stdout: DashStream,
     /// Execution location of read node.
     location: Location,
+    /// If the input is a file that commands on this machine write for another machine. Such
+    /// files are only sent once the commands have finished, preceded by their length.
+    collects_output: bool,
 }
 
 impl ReadNode {
+    pub fn set_collects_output(&mut self, collects_output: bool) {
+        self.collects_output = collects_output;
+    }
+
+    pub fn collects_output(&self) -> bool {
+        self.collects_output
+    }
+
     pub fn get_stdout_mut(&mut self) -> &mut DashStream {
         &mut self.stdout
     }
@@ -49,6 +61,10 @@ impl Info for ReadNode {
         self.node_id = id;
     }
 
+    fn set_prog_id(&mut self, id: ProgId) {
+        self.prog_id = id;
+    }
+
     fn get_id(&self) -> NodeId {
         self.node_id
     }
@@ -247,6 +263,12 @@ impl Execute for ReadNode {
         match &self.stdout {
             DashStream::Tcp(netstream) => {
                 let mut tcpstream = network_connections.remove(&netstream)?;
+                if self.collects_output {
+                    // lets the receiver tell a complete copy from a failed or cut off one
+                    let len = file_handle.metadata()?.len();
+                    tcpstream.set_nonblocking(false)?;
+                    tcpstream.write_all(&len.to_be_bytes())?;
+                }
                 // hopefully this will immediately block until the next process is ready
                 copy(&mut file_handle, &mut tcpstream)?;
             }
@@ -85,6 +85,8 @@ pub struct NetStream {
     right_location: Location,
     /// Should we buffer into a file
     buffer_into_file: bool,
+    /// Program this stream belongs to; keeps streams of concurrent programs apart.
+    prog_id: ProgId,
 }
 
 impl Default for NetStream {
@@ -96,6 +98,7 @@ impl Default for NetStream {
             left_location: Location::Client,
             right_location: Location::Client,
             buffer_into_file: false,
+            prog_id: 0,
         }
     }
 }
@@ -119,9 +122,18 @@ impl NetStream {
             left_location: left_location,
             right_location: right_location,
             buffer_into_file: false,
+            prog_id: 0,
         })
     }
 
+    pub fn set_prog_id(&mut self, id: ProgId) {
+        self.prog_id = id;
+    }
+
+    pub fn get_prog_id(&self) -> ProgId {
+        self.prog_id
+    }
+
     /// Returns string to display on a pipe stream node
     /// Mainly used for debugging purposes.
     pub fn get_dot_label(&self) -> String {
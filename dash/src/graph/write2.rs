@@ -6,8 +6,11 @@ use super::rapper::stream_initiate_filter;
 use super::{program, stream, Location, Result};
 use failure::bail;
 use program::{Link, NodeId, ProgId};
+use std::fs::{self, File, OpenOptions};
+use std::io::Read;
 use std::mem::drop;
-use std::path::PathBuf;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
 use std::slice::IterMut;
 use stream::{
     DashStream, HandleIdentifier, IOType, NetStream, PipeStream, SharedPipeMap, SharedStreamMap,
@@ -26,9 +29,31 @@ pub struct WriteNode {
     output: DashStream,
     /// Execution location of the read node.
     location: Location,
+    /// If the output is a local copy of a remote file that a command on this machine reads with
+    /// random access. Such copies are written in full before any command starts.
+    staged: bool,
+    /// If the output is produced by a command on another machine. It is written to a partial
+    /// file next to the output, which is renamed over the output once all of it has arrived.
+    commits_output: bool,
 }
 
 impl WriteNode {
+    pub fn set_commits_output(&mut self, commits_output: bool) {
+        self.commits_output = commits_output;
+    }
+
+    pub fn commits_output(&self) -> bool {
+        self.commits_output
+    }
+
+    pub fn set_staged(&mut self, staged: bool) {
+        self.staged = staged;
+    }
+
+    pub fn is_staged(&self) -> bool {
+        self.staged
+    }
+
     pub fn get_stdout_mut(&mut self) -> &mut DashStream {
         &mut self.output
     }
@@ -66,6 +91,10 @@ impl Info for WriteNode {
         self.node_id = id;
     }
 
+    fn set_prog_id(&mut self, id: ProgId) {
+        self.prog_id = id;
+    }
+
     fn get_id(&self) -> NodeId {
         self.node_id
     }
@@ -276,8 +305,15 @@ impl Execute for WriteNode {
     ) -> Result<()> {
         // open a file for appending
         match &self.output {
+            DashStream::File(filestream) if self.commits_output => {
+                File::create(partial_path(filestream.get_path().as_path())?)?;
+            }
             DashStream::File(filestream) => {
                 let f = filestream.open()?;
+                if self.staged {
+                    // the copy is appended to, so start from an empty file
+                    f.set_len(0)?;
+                }
                 drop(f);
             }
             DashStream::Fifo(fifostream) => {
@@ -307,6 +343,9 @@ impl Execute for WriteNode {
                 DashStream::Tcp(netstream) => {
                     let mut tcpstream = network_connections.remove(&netstream)?;
                     match &self.output {
+                        DashStream::File(filestream) if self.commits_output => {
+                            commit_output(&mut tcpstream, filestream.get_path().as_path())?;
+                        }
                         DashStream::File(filestream) => {
                             let mut f = filestream.open_with_append()?;
                             copy(&mut tcpstream, &mut f)?;
@@ -420,3 +459,47 @@ impl Execute for WriteNode {
         Ok(())
     }
 }
+
+/// Where output produced on another machine is written until all of it has arrived. It is in the
+/// same directory as the output so the final rename is atomic.
+fn partial_path(output: &Path) -> Result<PathBuf> {
+    match output.file_name() {
+        Some(name) => {
+            Ok(output.with_file_name(format!(".{}.dash_partial", name.to_string_lossy())))
+        }
+        None => bail!("Output {:?} does not name a file", output),
+    }
+}
+
+/// Receives output that a command produced on another machine, which is preceded by its length,
+/// and moves it over `output` if all of it arrived. Otherwise `output` is left untouched.
+fn commit_output(stream: &mut TcpStream, output: &Path) -> Result<()> {
+    let partial = partial_path(output)?;
+    // the length is checked against what copy reports, which is only exact when blocking
+    stream.set_nonblocking(false)?;
+    let mut len = [0u8; 8];
+    let expected = match stream.read_exact(&mut len) {
+        Ok(()) => u64::from_be_bytes(len),
+        Err(_) => {
+            let _ = fs::remove_file(&partial);
+            bail!(
+                "No output was sent for {:?}: the command producing it failed",
+                output
+            );
+        }
+    };
+    let mut f = OpenOptions::new().append(true).open(&partial)?;
+    let received = copy(stream, &mut f)?;
+    drop(f);
+    if received != expected {
+        let _ = fs::remove_file(&partial);
+        bail!(
+            "Output for {:?} was cut off after {} of {} bytes",
+            output,
+            received,
+            expected
+        );
+    }
+    fs::rename(&partial, output)?;
+    Ok(())
+}
@@ -1,9 +1,10 @@
-use super::graph::{program, stream, Location};
+use super::graph::{process, program, stream, Location};
 use super::runtime_util::Addr;
 use super::serialize::{read_msg_and_type, rpc, write_msg_and_type};
 use super::Result;
 use bincode::{deserialize, serialize};
 use failure::bail;
+use nix::sys::signal::Signal;
 use std::collections::HashMap;
 use std::net::TcpStream;
 use std::path::PathBuf;
@@ -143,6 +144,41 @@ impl ShellClient {
         Ok(())
     }
 
+    /// Sends the signal to every process the given program spawned, at each of its locations.
+    pub fn signal_program(
+        &self,
+        prog_id: program::ProgId,
+        locations: &[Location],
+        signal: Signal,
+    ) -> Result<()> {
+        for location in locations.iter() {
+            match location {
+                Location::Client => {
+                    process::signal_program(prog_id, signal)?;
+                }
+                Location::Server(ip) => {
+                    let addr = Addr::new(ip, &self.port).get_addr();
+                    let mut stream = TcpStream::connect(addr)?;
+                    let request = rpc::SignalRequest {
+                        prog_id,
+                        signal: signal as i32,
+                    };
+                    let message = serialize(&request)?;
+                    write_msg_and_type(message.to_vec(), rpc::MessageType::Signal, &mut stream)?;
+                    let (_, next_msg) = read_msg_and_type(&mut stream)?;
+                    let msg = deserialize(&next_msg[..])?;
+                    match msg {
+                        rpc::ClientReturnCode::Success => {}
+                        rpc::ClientReturnCode::Failure => {
+                            bail!("Server {:?} failed to signal program {:?}", ip, prog_id);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Asks servers to stat given files.
     pub fn stat_files(
         &self,
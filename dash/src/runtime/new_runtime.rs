@@ -1,11 +1,13 @@
 extern crate walkdir;
-use super::graph::{filestream::FileStream, program, stream, Location};
+use super::graph::{filestream::FileStream, process, program, stream, Location};
 use super::runtime_util::{new_server, Addr, Server};
 use super::serialize::{read_msg_and_type, rpc, write_msg_and_type};
 use super::Result;
 use bincode::{deserialize, serialize};
 use failure::bail;
+use nix::sys::signal::Signal;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
 use std::path::{Path, PathBuf};
 use std::{fs, thread};
@@ -317,6 +319,35 @@ fn handle_spawned_client(
 
             Ok(())
         }
+        rpc::MessageType::Signal => {
+            let request: rpc::SignalRequest = match deserialize(&buf[..]) {
+                Ok(req) => req,
+                Err(e) => {
+                    let response = serialize(&rpc::ClientReturnCode::Failure)?;
+                    write_msg_and_type(response.to_vec(), rpc::MessageType::Control, &mut stream)?;
+                    bail!("Could not deserialize signal request: {:?}", e)
+                }
+            };
+            let result = match Signal::try_from(request.signal) {
+                Ok(signal) => process::signal_program(request.prog_id, signal),
+                Err(e) => Err(e.into()),
+            };
+            let response = match result {
+                Ok(count) => {
+                    debug!(
+                        "Sent signal {:?} to {} processes of program {:?}",
+                        request.signal, count, request.prog_id
+                    );
+                    serialize(&rpc::ClientReturnCode::Success)?
+                }
+                Err(e) => {
+                    error!("Could not signal program {:?}: {:?}", request.prog_id, e);
+                    serialize(&rpc::ClientReturnCode::Failure)?
+                }
+            };
+            write_msg_and_type(response.to_vec(), rpc::MessageType::Control, &mut stream)?;
+            Ok(())
+        }
         _ => Ok(()),
     }
 }
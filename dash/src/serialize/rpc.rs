@@ -60,6 +60,15 @@ pub struct SizeRequest {
     pub failed: bool,
 }
 
+/// Request to signal the processes a program spawned on a machine (e.g., for job control).
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SignalRequest {
+    /// Program to signal
+    pub prog_id: program::ProgId,
+    /// Signal number, e.g. SIGSTOP
+    pub signal: i32,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub enum MessageType {
     /// Request to execute a set of nodes
@@ -72,6 +81,8 @@ pub enum MessageType {
     SetupStreams,
     /// Request size for files
     SizeRequest,
+    /// Request to signal a running program
+    Signal,
 }
 impl MessageType {
     pub fn from_u32(value: u32) -> MessageType {
@@ -81,6 +92,7 @@ impl MessageType {
             3 => MessageType::Control,
             4 => MessageType::SetupStreams,
             5 => MessageType::SizeRequest,
+            6 => MessageType::Signal,
             _ => panic!("Passing in unknown message type to constructor: {}", value),
         }
     }
@@ -92,6 +104,7 @@ impl MessageType {
             MessageType::Control => 3,
             MessageType::SetupStreams => 4,
             MessageType::SizeRequest => 5,
+            MessageType::Signal => 6,
         }
     }
 }
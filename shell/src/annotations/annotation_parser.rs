@@ -540,7 +540,7 @@ named_complete!(
 // syntax so it works?
 // Then, need to define the syntax for splitting commands across inputs
 named_complete!(
-    parse_annotation<Result<Command>>,
+    pub parse_annotation<Result<Command>>,
     map!(
         do_parse!(
             name: parse_command_name
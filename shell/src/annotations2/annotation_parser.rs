@@ -94,14 +94,22 @@ named_complete!(
     map!(
         do_parse!(
             tag!("type:")
-                >> argtype: alt!(tag!("input_file") | tag!("output_file") | tag!("str"))
+                >> argtype: alt!(
+                    tag!("input_file")
+                        | tag!("output_file")
+                        | tag!("output_prefix")
+                        | tag!("str")
+                        | tag!("command")
+                )
                 >> (argtype)
         ),
         |n: CompleteByteSlice| {
             match str::from_utf8(n.0).unwrap() {
                 "input_file" => Info::ParamType(ArgType::InputFile),
                 "output_file" => Info::ParamType(ArgType::OutputFile),
+                "output_prefix" => Info::ParamType(ArgType::OutputPrefix),
                 "str" => Info::ParamType(ArgType::Str),
+                "command" => Info::ParamType(ArgType::Command),
                 _ => {
                     panic!("Non allowed shell type allowed");
                 }
@@ -492,6 +500,208 @@ named_complete!(
     })
 );
 
+named_complete!(
+    parse_random_access<IndividualParseOption>,
+    map!(tag!("random_access"), {
+        |_| IndividualParseOption::RandomAccess
+    })
+);
+
+named_complete!(
+    parse_params_first<IndividualParseOption>,
+    map!(tag!("params_first"), {
+        |_| IndividualParseOption::ParamsFirst
+    })
+);
+
+named_complete!(
+    parse_cost_number<f64>,
+    map_res!(
+        take_while1!(|c: u8| c.is_ascii_digit() || c == b'.'),
+        |number: CompleteByteSlice| str::from_utf8(number.0).unwrap().parse::<f64>()
+    )
+);
+
+named_complete!(
+    parse_selectivity<IndividualParseOption>,
+    do_parse!(
+        tag!("selectivity:")
+            >> selectivity:
+                alt!(
+                    do_parse!(
+                        tag!("(")
+                            >> ratio: parse_cost_number
+                            >> when: many1!(preceded!(
+                                tag!(",when:"),
+                                take_while1!(|c: u8| c.is_ascii_alphanumeric() || c == b'-')
+                            ))
+                            >> tag!(")")
+                            >> (Selectivity {
+                                ratio,
+                                when: when
+                                    .iter()
+                                    .map(|name| str::from_utf8(name.0).unwrap().to_string())
+                                    .collect(),
+                            })
+                    ) | map!(parse_cost_number, |ratio| Selectivity {
+                        ratio,
+                        when: vec![],
+                    })
+                )
+            >> (IndividualParseOption::Cost(CostModel {
+                selectivity: vec![selectivity],
+                ..Default::default()
+            }))
+    )
+);
+
+named_complete!(
+    parse_cpu_cost<IndividualParseOption>,
+    do_parse!(
+        tag!("cpu_cost:")
+            >> cost: parse_cost_number
+            >> (IndividualParseOption::Cost(CostModel {
+                cpu_cost: Some(cost),
+                ..Default::default()
+            }))
+    )
+);
+
+named_complete!(
+    parse_startup_cost<IndividualParseOption>,
+    do_parse!(
+        tag!("startup_cost:")
+            >> cost: parse_cost_number
+            >> (IndividualParseOption::Cost(CostModel {
+                startup_cost: Some(cost),
+                ..Default::default()
+            }))
+    )
+);
+
+named_complete!(
+    parse_condition_change<ValueCondition>,
+    alt!(
+        do_parse!(
+            tag!("selectivity:")
+                >> ratio: parse_cost_number
+                >> (ValueCondition {
+                    selectivity: Some(ratio),
+                    ..Default::default()
+                })
+        ) | map!(tag!("random_access"), |_| ValueCondition {
+            access_type: Some(AccessType::Random),
+            ..Default::default()
+        }) | map!(tag!("sequential_access"), |_| ValueCondition {
+            access_type: Some(AccessType::Sequential),
+            ..Default::default()
+        }) | map!(tag!("not_splittable"), |_| ValueCondition {
+            splittable: Some(false),
+            ..Default::default()
+        }) | map!(tag!("splittable"), |_| ValueCondition {
+            splittable: Some(true),
+            ..Default::default()
+        })
+    )
+);
+
+named_complete!(
+    parse_condition<IndividualParseOption>,
+    do_parse!(
+        tag!("matching:(")
+            >> pattern:
+                alt!(
+                    map!(
+                        delimited!(tag!("/"), take_until!("/"), tag!("/")),
+                        |re: CompleteByteSlice| (true, re)
+                    ) | map!(
+                        take_while1!(|c: u8| c != b',' && c != b')'),
+                        |glob: CompleteByteSlice| (false, glob)
+                    )
+                )
+            >> changes: many1!(preceded!(tag!(","), parse_condition_change))
+            >> tag!(")")
+            >> ({
+                let mut condition = ValueCondition {
+                    pattern: str::from_utf8(pattern.1 .0).unwrap().to_string(),
+                    is_regex: pattern.0,
+                    ..Default::default()
+                };
+                for change in changes.iter() {
+                    condition.merge(change);
+                }
+                IndividualParseOption::Condition(condition)
+            })
+    )
+);
+
+named_complete!(
+    parse_extends<IndividualParseOption>,
+    map!(tag!("extends"), { |_| IndividualParseOption::Extends })
+);
+
+named_complete!(
+    parse_reducer<IndividualParseOption>,
+    map!(
+        do_parse!(
+            tag!("reducer:")
+                >> reducer: alt!(
+                    map!(tag!("concat"), |_| Reducer::Concat)
+                        | map!(tag!("sum"), |_| Reducer::Sum)
+                        | map!(tag!("merge"), |_| Reducer::Merge)
+                        | map!(tag!("uniq"), |_| Reducer::Uniq)
+                        | map!(
+                            delimited!(tag!("("), take_until!(")"), tag!(")")),
+                            |s: CompleteByteSlice| {
+                                Reducer::Custom(str::from_utf8(s.0).unwrap().to_string())
+                            }
+                        )
+                )
+                >> (reducer)
+        ),
+        |reducer: Reducer| IndividualParseOption::Reducer(reducer)
+    )
+);
+
+named_complete!(
+    parse_implicit_input<IndividualParseOption>,
+    map!(
+        do_parse!(
+            tag!("implicit_input:")
+                >> spec: delimited!(tag!("("), take_until!(")"), tag!(")"))
+                >> (spec)
+        ),
+        |spec: CompleteByteSlice| {
+            let spec = str::from_utf8(spec.0).unwrap();
+            let implicit_file = match spec.find(",relative_to:") {
+                Some(idx) => ImplicitFile {
+                    path: spec[..idx].to_string(),
+                    relative_to: spec[idx + ",relative_to:".len()..].to_string(),
+                },
+                None => ImplicitFile {
+                    path: spec.to_string(),
+                    relative_to: "".to_string(),
+                },
+            };
+            IndividualParseOption::ImplicitInput(implicit_file)
+        }
+    )
+);
+
+fn is_env_var_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+named_complete!(
+    parse_env<IndividualParseOption>,
+    map!(
+        do_parse!(tag!("env:") >> name: take_while1!(is_env_var_char) >> (name)),
+        |name: CompleteByteSlice| {
+            IndividualParseOption::Env(str::from_utf8(name.0).unwrap().to_string())
+        }
+    )
+);
+
 named_complete!(
     parse_individual_parsing_option<IndividualParseOption>,
     alt!(
@@ -499,6 +709,16 @@ named_complete!(
             | parse_splittable_across_input
             | parse_reduces_input
             | parse_needs_current_dir
+            | parse_random_access
+            | parse_params_first
+            | parse_selectivity
+            | parse_cpu_cost
+            | parse_startup_cost
+            | parse_condition
+            | parse_reducer
+            | parse_implicit_input
+            | parse_env
+            | parse_extends
     )
 );
 named_complete!(
@@ -527,6 +747,31 @@ named_complete!(
                     IndividualParseOption::NeedsCurrentDir => {
                         parsing_opt.needs_current_dir = true;
                     }
+                    IndividualParseOption::RandomAccess => {
+                        parsing_opt.access_type = AccessType::Random;
+                    }
+                    IndividualParseOption::ParamsFirst => {
+                        parsing_opt.params_first = true;
+                    }
+                    IndividualParseOption::Cost(cost) => {
+                        parsing_opt.cost.merge(cost);
+                    }
+                    IndividualParseOption::Condition(condition) => {
+                        condition.validate()?;
+                        parsing_opt.conditions.push(condition.clone());
+                    }
+                    IndividualParseOption::Reducer(reducer) => {
+                        parsing_opt.reducer = reducer.clone();
+                    }
+                    IndividualParseOption::ImplicitInput(implicit_file) => {
+                        parsing_opt.implicit_inputs.push(implicit_file.clone());
+                    }
+                    IndividualParseOption::Env(name) => {
+                        parsing_opt.env_vars.push(name.clone());
+                    }
+                    IndividualParseOption::Extends => {
+                        parsing_opt.extends = true;
+                    }
                 }
             }
             Ok(parsing_opt)
@@ -597,6 +842,12 @@ mod tests {
             parse_type(CompleteByteSlice(b"type:output_file")).unwrap();
         assert_eq!(n2, Info::ParamType(ArgType::OutputFile));
 
+        let (_, prefix): (CompleteByteSlice, Info) =
+            parse_type(CompleteByteSlice(b"type:output_prefix")).unwrap();
+        assert_eq!(prefix, Info::ParamType(ArgType::OutputPrefix));
+        let (_, command) = parse_type(CompleteByteSlice(b"type:command")).unwrap();
+        assert_eq!(command, Info::ParamType(ArgType::Command));
+
         let (_, n3): (CompleteByteSlice, Info) =
             parse_type(CompleteByteSlice(b"type:str")).unwrap();
         assert_eq!(n3, Info::ParamType(ArgType::Str));
@@ -976,4 +1227,158 @@ mod tests {
         assert_eq!(Argument::LoneOption(option1), arg_list[0]);
         assert_eq!(Argument::LoneOption(option2), arg_list[1]);
     }
+
+    #[test]
+    fn test_parse_random_access() {
+        let (_, command): (CompleteByteSlice, Result<Command>) = parse_annotation(
+            CompleteByteSlice(b"unzip[random_access,reduces_input]: FLAGS:[(short:l)] PARAMS:[(type:input_file,size:1)]"),
+        )
+        .unwrap();
+        let options = command.unwrap().parsing_options;
+        assert_eq!(options.access_type, AccessType::Random);
+        assert!(options.reduces_input);
+        assert_eq!(
+            Command::new("unzip: PARAMS:[(type:input_file,size:1)]")
+                .unwrap()
+                .parsing_options
+                .access_type,
+            AccessType::Sequential
+        );
+    }
+
+    #[test]
+    fn test_parse_cost() {
+        let options = Command::new(
+            "grep[selectivity:(0.001,when:c),selectivity:(0,when:q,when:l),selectivity:0.1,cpu_cost:0.004,startup_cost:0.5]: FLAGS:[(short:c),(short:q),(short:l)] PARAMS:[(type:str,size:1)]",
+        )
+        .unwrap()
+        .parsing_options;
+        assert_eq!(
+            options.cost,
+            CostModel {
+                selectivity: vec![
+                    Selectivity {
+                        ratio: 0.001,
+                        when: vec!["c".to_string()],
+                    },
+                    Selectivity {
+                        ratio: 0.0,
+                        when: vec!["q".to_string(), "l".to_string()],
+                    },
+                    Selectivity {
+                        ratio: 0.1,
+                        when: vec![],
+                    },
+                ],
+                cpu_cost: Some(0.004),
+                startup_cost: Some(0.5),
+            }
+        );
+        assert!(Command::new("grep[cpu_cost:fast]: FLAGS:[(short:c)]").is_err());
+    }
+
+    #[test]
+    fn test_parse_conditions() {
+        let options = Command::new(
+            r"zcat[matching:(*.gz,random_access,selectivity:4),matching:(/\.(parquet|orc)$/,not_splittable)]: PARAMS:[(type:input_file,size:list(list_separator:( )))]",
+        )
+        .unwrap()
+        .parsing_options;
+        assert_eq!(
+            options.conditions,
+            vec![
+                ValueCondition {
+                    pattern: "*.gz".to_string(),
+                    is_regex: false,
+                    selectivity: Some(4.0),
+                    access_type: Some(AccessType::Random),
+                    splittable: None,
+                },
+                ValueCondition {
+                    pattern: r"\.(parquet|orc)$".to_string(),
+                    is_regex: true,
+                    selectivity: None,
+                    access_type: None,
+                    splittable: Some(false),
+                },
+            ]
+        );
+        assert!(options.conditions[1].matches("/e/d/x.parquet"));
+        assert!(!options.conditions[1].matches("/e/d/x.parquet.bak"));
+        assert!(options.conditions[0].matches("/e/d/x.gz"));
+
+        let err = Command::new("cat[matching:(/(/,random_access)]: PARAMS:[(type:str,size:1)]")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("invalid regex"));
+    }
+
+    #[test]
+    fn test_parse_reducer() {
+        let options = Command::new(
+            "wc[splittable_across_input,reducer:sum,reduces_input]: FLAGS:[(short:l)]",
+        )
+        .unwrap()
+        .parsing_options;
+        assert_eq!(options.reducer, Reducer::Sum);
+        assert!(options.splittable_across_input);
+        assert!(options.reduces_input);
+
+        let options = Command::new("tally[reducer:(awk -F, '{ s += $1 }')]: FLAGS:[(short:l)]")
+            .unwrap()
+            .parsing_options;
+        assert_eq!(
+            options.reducer,
+            Reducer::Custom("awk -F, '{ s += $1 }'".to_string())
+        );
+        assert_eq!(
+            Command::new("grep[splittable_across_input]: FLAGS:[(short:v)]")
+                .unwrap()
+                .parsing_options
+                .reducer,
+            Reducer::Concat
+        );
+    }
+
+    #[test]
+    fn test_parse_implicit_dependencies() {
+        let options = Command::new(
+            "git status[implicit_input:(.git),implicit_input:(.git,relative_to:C),env:GIT_DIR,needs_current_dir]: OPTPARAMS:[(short:C,type:input_file,size:1)]",
+        )
+        .unwrap()
+        .parsing_options;
+        assert_eq!(
+            options.implicit_inputs,
+            vec![
+                ImplicitFile {
+                    path: ".git".to_string(),
+                    relative_to: "".to_string()
+                },
+                ImplicitFile {
+                    path: ".git".to_string(),
+                    relative_to: "C".to_string()
+                }
+            ]
+        );
+        assert_eq!(options.env_vars, vec!["GIT_DIR".to_string()]);
+        assert!(options.needs_current_dir);
+
+        let options = Command::new("sort[env:LC_ALL,env:TMPDIR]: FLAGS:[(short:n)]")
+            .unwrap()
+            .parsing_options;
+        assert_eq!(options.env_vars, vec!["LC_ALL", "TMPDIR"]);
+        assert!(options.implicit_inputs.is_empty());
+    }
+
+    #[test]
+    fn test_parse_extends() {
+        let cmd = Command::new("grep[extends]: FLAGS:[(short:P,long:perl-regexp)] PARAMS:[(type:str,size:1),(type:input_file,size:list(list_separator:( )))]").unwrap();
+        assert!(cmd.parsing_options.extends);
+        assert!(
+            !Command::new("grep: FLAGS:[(short:P)]")
+                .unwrap()
+                .parsing_options
+                .extends
+        );
+    }
 }
@@ -9,8 +9,7 @@ use dash::util::Result;
 use failure::bail;
 use glob::glob;
 use std::collections::HashMap;
-use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 /// Attempts to run glob on the input FileStream and returns a vector of NodeArgs.
 /// TODO: some of the Errors in glob might result from certain directories being unreadable.
 fn glob_wrapper(input: &FileStream) -> Option<Vec<NodeArg>> {
@@ -53,6 +52,53 @@ pub struct ArgMatch {
     parsing_options: ParsingOptions,
     /// Is there an arg that is splittable?
     splittable_arg: Option<usize>,
+    /// Options given before a subcommand word (e.g. `-C dir` in `git -C dir log`), mapped to
+    /// how many subcommand words come before them.
+    global_depth: HashMap<usize, usize>,
+    /// Files read that are not named by the arguments, resolved to full paths.
+    implicit_inputs: Vec<FileStream>,
+    /// The invocation used `--` to end the options, so parameters may start with a dash.
+    end_of_options: bool,
+    /// Commands run by arguments of type `command`, by argument index.
+    nested: HashMap<usize, NestedCommand>,
+}
+
+/// Another command an argument runs, like `grep foo` in `xargs grep foo`, matched against its
+/// own annotations.
+#[derive(Debug, PartialEq, Clone)]
+struct NestedCommand {
+    arg_match: ArgMatch,
+    /// Words after the nested command that belong to the command running it, like the `+` that
+    /// ends `find -exec` or the inputs after `:::` for `parallel`.
+    trailing: Vec<String>,
+    /// The command line is a single word, like the script of `sh -c`, so the paths in it cannot
+    /// be rewritten for another machine.
+    single_word: bool,
+}
+
+/// Characters that make a single word command line more than a simple command.
+const SHELL_SYNTAX: &[char] = &['|', ';', '&', '<', '>', '$', '`', '(', ')', '\n'];
+
+/// Values of a nested command's arguments that are filled in at runtime by the command that runs
+/// it, like `find -exec`, `xargs -I` or `parallel`.
+const PLACEHOLDERS: &[&str] = &["{}", "{.}", "{/}", "{//}", "{/.}", "{#}"];
+
+/// Whether the value of a file argument names no file: `-` is the command's stdin or stdout.
+/// Such values are kept as strings, and the data flows over the node's stdin or stdout edge.
+fn names_no_file(value: &str) -> bool {
+    value == "-"
+}
+
+/// Splits the words of a nested command line from those after it that belong to the command
+/// running it: `{} +` ends the command for `find -exec`, and `:::` starts the inputs of
+/// `parallel`.
+fn split_nested_words(words: &[String]) -> (Vec<String>, Vec<String>) {
+    for (idx, word) in words.iter().enumerate() {
+        if word.starts_with(":::") || (word == "+" && idx > 0 && words[idx - 1] == "{}") {
+            return (words[..idx].to_vec(), words[idx..].to_vec());
+        }
+    }
+    (words.to_vec(), vec![])
 }
 
 /// Helper struct to handle when nodes are assigned to locations where a certain argument doesn't
@@ -134,11 +180,23 @@ impl ArgMatch {
                     Argument::LoneOption(_) => {}
                     Argument::OptWithParam(_, param) | Argument::LoneParam(param) => {
                         match param.param_type {
-                            ArgType::Str => val_list.push(NodeArg::Str(value.to_string())),
+                            ArgType::Str | ArgType::Command => {
+                                val_list.push(NodeArg::Str(value.to_string()))
+                            }
                             ArgType::InputFile
                             | ArgType::OutputFile
                             | ArgType::InputFileList
-                            | ArgType::OutputFileList => {
+                            | ArgType::OutputFileList
+                            | ArgType::OutputPrefix
+                                if names_no_file(value) =>
+                            {
+                                val_list.push(NodeArg::Str(value.to_string()))
+                            }
+                            ArgType::InputFile
+                            | ArgType::OutputFile
+                            | ArgType::InputFileList
+                            | ArgType::OutputFileList
+                            | ArgType::OutputPrefix => {
                                 val_list.push(NodeArg::Stream(FileStream::new(
                                     Path::new(value),
                                     Location::default(),
@@ -152,8 +210,11 @@ impl ArgMatch {
             map.insert(*ct, argument.clone());
             *ct += 1;
         };
-        // first pass for the options or options with parameters
-        for arg in matches.args.iter() {
+        // first pass for the options or options with parameters, in the order they were given
+        // (for commands like find, the order of the options changes what they do)
+        let mut options: Vec<(&&str, _)> = matches.args.iter().collect();
+        options.sort_by_key(|(name, _)| matches.index_of(name));
+        for arg in options.into_iter() {
             let arg_info = &annotation.args[annotation_map[arg.0.clone()] as usize];
             match arg_info {
                 Argument::LoneOption(opt) => {
@@ -209,12 +270,30 @@ impl ArgMatch {
             }
             count += 1;
         }
+        // the annotation can change the options for invocations with values matching a pattern
+        let mut parsing_options = annotation.parsing_options.clone();
+        for condition in annotation.parsing_options.conditions.iter() {
+            let matched = arg_list.iter().flatten().any(|value| match value {
+                NodeArg::Str(s) => condition.matches(s),
+                NodeArg::Stream(fs) => condition.matches(&fs.get_path().to_string_lossy()),
+            });
+            if matched {
+                condition.apply(&mut parsing_options);
+                if condition.splittable == Some(false) {
+                    splittable_arg = None;
+                }
+            }
+        }
         Ok(ArgMatch {
             cmd_name: command_name,
             arg_list: arg_list,
             map: map,
-            parsing_options: annotation.parsing_options.clone(),
+            parsing_options,
             splittable_arg: splittable_arg,
+            global_depth: HashMap::default(),
+            implicit_inputs: Vec::new(),
+            end_of_options: false,
+            nested: HashMap::default(),
         })
     }
 
@@ -223,12 +302,20 @@ impl ArgMatch {
             .iter()
             .map(|x| vec![NodeArg::Str(x.clone())])
             .collect();
+        // unannotated arguments are treated as plain strings
+        let map: HashMap<usize, Argument> = (0..arg_list.len())
+            .map(|i| (i, Argument::LoneParam(Param::default())))
+            .collect();
         ArgMatch {
             cmd_name: vec![cmd.to_string()],
             arg_list: arg_list,
-            map: HashMap::default(),
+            map: map,
             parsing_options: ParsingOptions::default(),
             splittable_arg: None,
+            global_depth: HashMap::default(),
+            implicit_inputs: Vec::new(),
+            end_of_options: false,
+            nested: HashMap::default(),
         }
     }
 
@@ -244,83 +331,237 @@ impl ArgMatch {
             map: self.map.clone(),
             parsing_options: self.parsing_options.clone(),
             splittable_arg: None,
+            global_depth: self.global_depth.clone(),
+            implicit_inputs: self.implicit_inputs.clone(),
+            end_of_options: self.end_of_options,
+            nested: self.nested.clone(),
         })
     }
 
+    /// Records that the invocation ended its options with `--`, so it is passed on again.
+    pub fn set_end_of_options(&mut self, end_of_options: bool) {
+        self.end_of_options = end_of_options;
+    }
+
+    /// The command lines held by arguments of type `command`, split into words, with the index
+    /// of their argument, to be matched and given back with `set_nested`. A single word with
+    /// shell syntax in it, like `sh -c 'a | b'`, is left out.
+    pub fn nested_invocations(&self) -> Vec<(usize, Vec<String>)> {
+        (0..self.arg_list.len())
+            .filter_map(|ind| match self.nested_words(ind) {
+                Some((words, _, _)) if !words.is_empty() => Some((ind, words)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Sets the match of the command line held by the argument at `ind`.
+    pub fn set_nested(&mut self, ind: usize, mut arg_match: ArgMatch) {
+        arg_match.keep_placeholders();
+        if let Some((_, trailing, single_word)) = self.nested_words(ind) {
+            self.nested.insert(
+                ind,
+                NestedCommand {
+                    arg_match,
+                    trailing,
+                    single_word,
+                },
+            );
+        }
+    }
+
+    /// Turns file arguments that are placeholders (e.g. `{}`) back into strings, as where the
+    /// files they stand for are is not known.
+    fn keep_placeholders(&mut self) {
+        for value in self.arg_list.iter_mut().flatten() {
+            let placeholder = match value {
+                NodeArg::Stream(fs) => fs.get_path().to_string_lossy().to_string(),
+                NodeArg::Str(_) => continue,
+            };
+            if PLACEHOLDERS.contains(&placeholder.as_str()) {
+                *value = NodeArg::Str(placeholder);
+            }
+        }
+    }
+
+    /// Splits the command line held by the argument at `ind`, if it has type `command`.
+    /// Returns its words, the words after it and whether it was given as a single word.
+    fn nested_words(&self, ind: usize) -> Option<(Vec<String>, Vec<String>, bool)> {
+        let param = match self.map.get(&ind)? {
+            Argument::OptWithParam(_, param) | Argument::LoneParam(param)
+                if param.param_type == ArgType::Command =>
+            {
+                param
+            }
+            _ => return None,
+        };
+        let values: Vec<String> = self.arg_list[ind]
+            .iter()
+            .filter_map(|arg| match arg {
+                NodeArg::Str(s) => Some(s.clone()),
+                NodeArg::Stream(_) => None,
+            })
+            .collect();
+        match param.size {
+            ParamSize::One => {
+                let script = values.first()?;
+                if script.contains(SHELL_SYNTAX) {
+                    return None;
+                }
+                let words = shellwords::split(script).ok()?;
+                Some((words, vec![], true))
+            }
+            _ => {
+                let (words, trailing) = split_nested_words(&values);
+                Some((words, trailing, false))
+            }
+        }
+    }
+
+    /// Values of the argument at `ind` as they are passed to the command. A nested command line
+    /// is rebuilt from its match, so its paths are those for the machine it runs on.
+    fn values(&self, ind: usize) -> Result<Vec<NodeArg>> {
+        let mut values = match self.nested.get(&ind) {
+            Some(nested) if !nested.single_word => {
+                let mut values = vec![NodeArg::Str(nested.arg_match.cmd_name[0].clone())];
+                values.append(&mut nested.arg_match.reconstruct()?);
+                values.extend(
+                    nested
+                        .trailing
+                        .iter()
+                        .map(|word| NodeArg::Str(word.clone())),
+                );
+                values
+            }
+            _ => self.arg_list[ind].clone(),
+        };
+        // clap takes the `;` that ends a command line given to an option, like `find -exec`
+        if let Some(Argument::OptWithParam(_, param)) = self.map.get(&ind) {
+            if param.param_type == ArgType::Command
+                && param.size != ParamSize::One
+                && values.last() != Some(&NodeArg::Str("+".to_string()))
+            {
+                values.push(NodeArg::Str(";".to_string()));
+            }
+        }
+        Ok(values)
+    }
+
+    /// Adds the options given before the subcommand word at `depth`, matched separately against
+    /// the options the subcommand inherits.
+    pub fn add_global_args(&mut self, depth: usize, global: ArgMatch) {
+        for (argument, values) in global.get_arguments().into_iter() {
+            let ind = self.arg_list.len();
+            self.arg_list.push(values);
+            self.map.insert(ind, argument);
+            self.global_depth.insert(ind, depth);
+        }
+    }
+
     /// Reconstructs the arguments into a string that can be used at runtime.
     pub fn reconstruct(&self) -> Result<Vec<NodeArg>> {
-        // TODO: handle subcommands in a more robust way
-
         let mut ret: Vec<NodeArg> = Vec::new();
-        if self.cmd_name.len() > 1 {
-            for cmpt in self.cmd_name[1..].iter() {
+        // subcommand words, each after the options that were given before it
+        for (depth, cmpt) in self.cmd_name.iter().enumerate() {
+            if depth > 0 {
                 ret.push(NodeArg::Str(format!("{}", cmpt)));
             }
-        }
-        // iterate through the options first, then lone parameters last
-        for (ind, args) in self.arg_list.iter().enumerate() {
-            let arg_info = self.map.get(&ind).unwrap();
-            match arg_info {
-                Argument::LoneOption(opt) => {
-                    assert!(args.len() == 0);
-                    if opt.short != "" {
-                        ret.push(NodeArg::Str(format!("-{}", &opt.short)));
-                    } else {
-                        if self.parsing_options.long_arg_single_dash {
-                            ret.push(NodeArg::Str(format!("-{}", &opt.long)));
-                        } else {
-                            ret.push(NodeArg::Str(format!("--{}", &opt.long)));
-                        }
-                    }
+            for ind in 0..self.arg_list.len() {
+                if self.global_depth.get(&ind) == Some(&depth) {
+                    self.push_option(ind, &self.values(ind)?, &mut ret)?;
                 }
-                Argument::OptWithParam(opt, param) => {
-                    if param.attached_to_short && opt.short != "" {
-                        if param.param_type != ArgType::Str {
-                            bail!("Dash doesn't handle attached_to_short for non-string arg types");
-                        }
-                        assert!(param.size == ParamSize::One);
-                        assert!(args.len() == 1);
-                        match &args[0] {
-                            NodeArg::Str(s) => {
-                                ret.push(NodeArg::Str(format!("-{}{}", &opt.short, s)));
-                            }
-                            NodeArg::Stream(_) => {
-                                unreachable!();
-                            }
-                        }
-                        continue;
-                    }
+            }
+        }
+        // some commands (like find) take their parameters before the options, which can only
+        // be done if there is no `--` in between
+        let params_first = self.parsing_options.params_first && !self.end_of_options;
+        if params_first {
+            self.push_params(&mut ret)?;
+        }
 
-                    if opt.short != "" {
-                        ret.push(NodeArg::Str(format!("-{}", &opt.short)));
-                    } else {
-                        if self.parsing_options.long_arg_single_dash {
-                            ret.push(NodeArg::Str(format!("-{}", &opt.long)));
-                        } else {
-                            ret.push(NodeArg::Str(format!("--{}", &opt.long)));
-                        }
-                    }
-                    // add the values
-                    ret.append(&mut args.clone());
-                }
-                Argument::LoneParam(_) => {}
+        // iterate through the options first, then lone parameters last
+        for ind in 0..self.arg_list.len() {
+            if !self.global_depth.contains_key(&ind) {
+                self.push_option(ind, &self.values(ind)?, &mut ret)?;
             }
         }
 
         // now add the lone parameters in to the return list
-        for (ind, args) in self.arg_list.iter().enumerate() {
+        if self.end_of_options {
+            ret.push(NodeArg::Str("--".to_string()));
+        }
+        if !params_first {
+            self.push_params(&mut ret)?;
+        }
+
+        Ok(ret)
+    }
+
+    /// Adds the values of the lone parameters to the reconstructed arguments.
+    fn push_params(&self, ret: &mut Vec<NodeArg>) -> Result<()> {
+        for ind in 0..self.arg_list.len() {
             let argument = self.map.get(&ind).unwrap();
             match argument {
                 Argument::LoneOption(_) => {}
                 Argument::OptWithParam(_, _) => {}
                 Argument::LoneParam(_param) => {
                     // push all the values
-                    ret.append(&mut args.clone());
+                    ret.append(&mut self.values(ind)?);
                 }
             }
         }
+        Ok(())
+    }
 
-        Ok(ret)
+    /// Adds an option and its values to the reconstructed arguments.
+    fn push_option(&self, ind: usize, args: &[NodeArg], ret: &mut Vec<NodeArg>) -> Result<()> {
+        let arg_info = self.map.get(&ind).unwrap();
+        match arg_info {
+            Argument::LoneOption(opt) => {
+                assert!(args.len() == 0);
+                if opt.short != "" {
+                    ret.push(NodeArg::Str(format!("-{}", &opt.short)));
+                } else {
+                    if self.parsing_options.long_arg_single_dash {
+                        ret.push(NodeArg::Str(format!("-{}", &opt.long)));
+                    } else {
+                        ret.push(NodeArg::Str(format!("--{}", &opt.long)));
+                    }
+                }
+            }
+            Argument::OptWithParam(opt, param) => {
+                if param.attached_to_short && opt.short != "" {
+                    if param.param_type != ArgType::Str {
+                        bail!("Dash doesn't handle attached_to_short for non-string arg types");
+                    }
+                    assert!(param.size == ParamSize::One);
+                    assert!(args.len() == 1);
+                    match &args[0] {
+                        NodeArg::Str(s) => {
+                            ret.push(NodeArg::Str(format!("-{}{}", &opt.short, s)));
+                        }
+                        NodeArg::Stream(_) => {
+                            unreachable!();
+                        }
+                    }
+                    return Ok(());
+                }
+
+                if opt.short != "" {
+                    ret.push(NodeArg::Str(format!("-{}", &opt.short)));
+                } else {
+                    if self.parsing_options.long_arg_single_dash {
+                        ret.push(NodeArg::Str(format!("-{}", &opt.long)));
+                    } else {
+                        ret.push(NodeArg::Str(format!("--{}", &opt.long)));
+                    }
+                }
+                // add the values
+                ret.extend(args.iter().cloned());
+            }
+            Argument::LoneParam(_) => {}
+        }
+        Ok(())
     }
 
     /// Splits into multiple matches by the given argument.
@@ -331,7 +572,20 @@ impl ArgMatch {
         match self.splittable_arg {
             Some(ind) => {
                 let mut args: Vec<Vec<NodeArg>> = Vec::new();
+                // a command run on several files labels its output with them (e.g. wc's
+                // total line), so only outputs that are simply concatenated can be split
+                if *self.get_reducer() != Reducer::Concat {
+                    return Ok(vec![]);
+                }
                 let values = &mut self.arg_list[ind];
+                // stdin cannot be split between the copies, and where the files placeholders
+                // stand for are is not known
+                if values.iter().any(|value| match value {
+                    NodeArg::Str(s) => names_no_file(s) || PLACEHOLDERS.contains(&s.as_str()),
+                    NodeArg::Stream(_) => false,
+                }) {
+                    return Ok(vec![]);
+                }
                 let argument = self.map.get_mut(&ind).unwrap();
                 let is_str_arg: bool = match argument {
                     Argument::OptWithParam(_, param) => match param.param_type {
@@ -449,20 +703,210 @@ impl ArgMatch {
         }
     }
 
+    /// Returns each argument with its values, in the order they are reconstructed: options first,
+    /// then lone parameters.
+    pub fn get_arguments(&self) -> Vec<(Argument, Vec<NodeArg>)> {
+        self.arg_list
+            .iter()
+            .enumerate()
+            .map(|(i, args)| (self.map.get(&i).unwrap().clone(), args.clone()))
+            .collect()
+    }
+
+    pub fn get_parsing_options(&self) -> &ParsingOptions {
+        &self.parsing_options
+    }
+
     pub fn get_access_type(&self) -> AccessType {
         self.parsing_options.access_type
     }
 
+    /// Does the command, or a command it runs, output less than it reads?
     pub fn get_reduces_input(&self) -> bool {
         self.parsing_options.reduces_input
+            || self
+                .nested
+                .values()
+                .any(|nested| nested.arg_match.get_reduces_input())
+    }
+
+    /// Estimated size of the output over the size of the input: the first selectivity in the
+    /// annotation whose options were all given, or else one from a command it runs. Without one,
+    /// commands that reduce their input are assumed to output half of it.
+    pub fn get_selectivity(&self) -> f64 {
+        match self.annotated_selectivity() {
+            Some(ratio) => ratio,
+            None if self.get_reduces_input() => 0.5,
+            None => 1.0,
+        }
+    }
+
+    fn annotated_selectivity(&self) -> Option<f64> {
+        let given = |name: &String| {
+            self.map.values().any(|argument| match argument {
+                Argument::LoneOption(opt) | Argument::OptWithParam(opt, _) => {
+                    opt.short == *name || opt.long == *name
+                }
+                Argument::LoneParam(_) => false,
+            })
+        };
+        self.parsing_options
+            .cost
+            .selectivity
+            .iter()
+            .find(|selectivity| selectivity.when.iter().all(given))
+            .map(|selectivity| selectivity.ratio)
+            .or_else(|| {
+                self.get_nested()
+                    .iter()
+                    .find_map(|(_, nested)| nested.annotated_selectivity())
+            })
+    }
+
+    /// Estimated seconds it takes the command, and the commands it runs, to start and to process
+    /// `input_size` bytes, if the annotations give any costs.
+    pub fn get_cpu_time(&self, input_size: f64) -> f64 {
+        let cost = &self.parsing_options.cost;
+        let own = cost.startup_cost.unwrap_or(0.0)
+            + cost.cpu_cost.unwrap_or(0.0) * input_size / 1_000_000.0;
+        own + self
+            .nested
+            .values()
+            .map(|nested| nested.arg_match.get_cpu_time(input_size))
+            .sum::<f64>()
     }
 
     pub fn get_splittable_across_input(&self) -> bool {
         self.parsing_options.splittable_across_input
     }
 
+    /// Does the command rely on the current directory, because the annotation says so or it
+    /// reads an implicit input relative to it?
     pub fn get_needs_current_dir(&self) -> bool {
         self.parsing_options.needs_current_dir
+            || self.parsing_options.implicit_inputs.iter().any(|input| {
+                Path::new(&input.path).is_relative()
+                    && self
+                        .option_value(&input.relative_to)
+                        .is_none_or(|base| base.is_relative())
+            })
+            || self
+                .nested
+                .values()
+                .any(|nested| nested.arg_match.get_needs_current_dir())
+    }
+
+    /// Files the command reads that are not named by its arguments; resolved by
+    /// `resolve_file_paths`.
+    pub fn implicit_dependencies(&self) -> &[FileStream] {
+        &self.implicit_inputs
+    }
+
+    /// Files the commands run by this one read or write, which cannot be reached from another
+    /// machine, so the command must run where they are.
+    pub fn nested_dependencies(&self) -> Vec<FileStream> {
+        let mut ret: Vec<FileStream> = Vec::new();
+        for nested in self.nested.values().filter(|nested| !nested.single_word) {
+            let arg_match = &nested.arg_match;
+            ret.extend(arg_match.file_dependencies().into_iter().map(|(_, fs)| fs));
+            ret.extend(arg_match.implicit_dependencies().iter().cloned());
+            ret.extend(arg_match.nested_dependencies());
+        }
+        ret
+    }
+
+    /// The command runs a command line that could not be parsed, or that names files in a single
+    /// word (like the script of `sh -c`), whose paths are only right on the client.
+    pub fn must_run_on_client(&self) -> bool {
+        (0..self.arg_list.len()).any(|ind| match self.nested.get(&ind) {
+            Some(nested) if nested.single_word => {
+                let arg_match = &nested.arg_match;
+                !arg_match.file_dependencies().is_empty()
+                    || !arg_match.implicit_dependencies().is_empty()
+                    || !arg_match.nested_dependencies().is_empty()
+                    || arg_match.must_run_on_client()
+            }
+            Some(nested) => nested.arg_match.must_run_on_client(),
+            None => match self.map.get(&ind) {
+                Some(Argument::OptWithParam(_, param)) | Some(Argument::LoneParam(param)) => {
+                    param.param_type == ArgType::Command && !self.arg_list[ind].is_empty()
+                }
+                _ => false,
+            },
+        })
+    }
+
+    /// The commands run by this one, with the index of the argument holding each.
+    pub fn get_nested(&self) -> Vec<(usize, &ArgMatch)> {
+        let mut nested: Vec<(usize, &ArgMatch)> = self
+            .nested
+            .iter()
+            .map(|(ind, nested)| (*ind, &nested.arg_match))
+            .collect();
+        nested.sort_by_key(|(ind, _)| *ind);
+        nested
+    }
+
+    /// Environment variables the command reads.
+    pub fn get_env_vars(&self) -> Vec<String> {
+        let mut env_vars = self.parsing_options.env_vars.clone();
+        for nested in self.nested.values() {
+            for var in nested.arg_match.get_env_vars().into_iter() {
+                if !env_vars.contains(&var) {
+                    env_vars.push(var);
+                }
+            }
+        }
+        env_vars
+    }
+
+    /// The value given to the option with this short or long name, as a path.
+    fn option_value(&self, name: &str) -> Option<PathBuf> {
+        if name.is_empty() {
+            return None;
+        }
+        let idx = self.map.iter().find_map(|(idx, argument)| match argument {
+            Argument::OptWithParam(opt, _) if opt.short == name || opt.long == name => Some(idx),
+            _ => None,
+        })?;
+        match self.arg_list[*idx].first()? {
+            NodeArg::Str(value) => Some(PathBuf::from(value)),
+            NodeArg::Stream(fs) => Some(fs.get_path()),
+        }
+    }
+
+    pub fn get_reducer(&self) -> &Reducer {
+        &self.parsing_options.reducer
+    }
+
+    /// Was the flag with this short or long name given?
+    pub fn has_flag(&self, short: &str, long: &str) -> bool {
+        self.map.values().any(|argument| match argument {
+            Argument::LoneOption(opt) => {
+                (!opt.short.is_empty() && opt.short == short)
+                    || (!opt.long.is_empty() && opt.long == long)
+            }
+            _ => false,
+        })
+    }
+
+    /// The options given that do not name files, as they are written on the command line.
+    /// Used to run the command again over the outputs of its parallel copies.
+    pub fn option_words(&self) -> Result<Vec<String>> {
+        let mut args: Vec<NodeArg> = Vec::new();
+        for (ind, values) in self.arg_list.iter().enumerate() {
+            match self.map.get(&ind).unwrap() {
+                Argument::OptWithParam(_, param) if param.is_file_type() => {}
+                _ => self.push_option(ind, values, &mut args)?,
+            }
+        }
+        Ok(args
+            .into_iter()
+            .filter_map(|arg| match arg {
+                NodeArg::Str(s) => Some(s),
+                NodeArg::Stream(_) => None,
+            })
+            .collect())
     }
 
     /// Returns vector of all the file related dependencies this node sees.
@@ -475,9 +919,8 @@ impl ArgMatch {
                     if param.is_file_type() {
                         for arg in args.iter() {
                             match arg {
-                                NodeArg::Str(_) => {
-                                    unreachable!();
-                                }
+                                // stdin or stdout
+                                NodeArg::Str(_) => {}
                                 NodeArg::Stream(fs) => {
                                     ret.push((param.param_type, fs.clone()));
                                 }
@@ -524,38 +967,10 @@ impl ArgMatch {
         for (i, args) in repl_list.into_iter() {
             let _ = std::mem::replace(&mut self.arg_list[i], args);
         }
-
-        Ok(())
-    }
-
-    /// Resolves any environment variables present in any arguments in the invocation.
-    pub fn resolve_env_vars(&mut self) -> Result<()> {
-        for arg_list in self.arg_list.iter_mut() {
-            for arg in arg_list.iter_mut() {
-                match arg {
-                    NodeArg::Str(ref mut arg) => {
-                        if arg.starts_with("$") {
-                            let var_name = arg.split_at(1).1.to_string();
-                            match env::var(&var_name) {
-                                Ok(val) => {
-                                    *arg = val;
-                                }
-                                Err(e) => {
-                                    bail!(
-                                        "Could not resolve environment variable {:?}->{:?}",
-                                        arg,
-                                        e
-                                    );
-                                }
-                            }
-                        }
-                    }
-                    NodeArg::Stream(ref mut fs) => {
-                        fs.resolve_env_var()?;
-                    }
-                }
-            }
+        for nested in self.nested.values_mut() {
+            nested.arg_match.resolve_glob()?;
         }
+
         Ok(())
     }
 
@@ -571,13 +986,28 @@ impl ArgMatch {
                                 NodeArg::Stream(ref mut fs) => {
                                     filecache.resolve_path(fs, pwd)?;
                                 }
-                                NodeArg::Str(_) => unreachable!(),
+                                NodeArg::Str(_) => {}
                             }
                         }
                     }
                 }
             }
         }
+        // implicit inputs are found from the resolved arguments
+        let mut implicit_inputs: Vec<FileStream> = Vec::new();
+        for input in self.parsing_options.implicit_inputs.iter() {
+            let path = match self.option_value(&input.relative_to) {
+                Some(base) => base.join(&input.path),
+                None => PathBuf::from(&input.path),
+            };
+            let mut fs = FileStream::new(&path, Location::default());
+            filecache.resolve_path(&mut fs, pwd)?;
+            implicit_inputs.push(fs);
+        }
+        self.implicit_inputs = implicit_inputs;
+        for nested in self.nested.values_mut() {
+            nested.arg_match.resolve_file_paths(filecache, pwd)?;
+        }
         Ok(())
     }
 
@@ -606,8 +1036,11 @@ impl ArgMatch {
                                         fs.set_location(location.clone());
                                     } else {
                                         // need to modify argument for remote access
+                                        // output files are sent to the client when the
+                                        // command finishes, but inputs cannot be read from it
                                         if file_location == Location::Client
                                             && location != Location::Client
+                                            && !param.is_output_type()
                                         {
                                             bail!("File {:?} cannot be accessed in loc {:?} from outside the client", fs, location);
                                         }
@@ -622,15 +1055,31 @@ impl ArgMatch {
                                         remote_access.push(remote_access_info);
                                     }
                                 }
-                                NodeArg::Str(_) => {
-                                    unreachable!();
-                                }
+                                NodeArg::Str(_) => {}
                             }
                         }
                     }
                 }
             }
         }
+        // the paths in a nested command are rewritten, but it cannot be given copies of files
+        // from other machines
+        for nested in self.nested.values_mut() {
+            if nested.single_word {
+                continue;
+            }
+            let arg_match = &mut nested.arg_match;
+            let nested_access =
+                arg_match.strip_file_paths(origin_location.clone(), location.clone(), config)?;
+            if let Some(info) = nested_access.first() {
+                bail!(
+                    "{} runs {}, which names {:?} on another machine",
+                    self.cmd_name[0],
+                    arg_match.cmd_name[0],
+                    info.filestream.get_path()
+                );
+            }
+        }
         Ok(remote_access)
     }
 
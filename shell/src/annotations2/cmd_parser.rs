@@ -5,7 +5,7 @@ extern crate shellwords;
 
 use super::argument_matcher::ArgMatch;
 use super::grammar;
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches};
 use dash::util::Result;
 use failure::bail;
 use std::collections::HashMap;
@@ -26,6 +26,9 @@ pub struct CmdParser {
     debug: bool,
     /// Temporarily here. Max splitting factor for parallelization
     splitting_factor: u32,
+    /// For a subcommand (e.g. `git log`), the options of each command it is part of, outermost
+    /// first; they can be given before the subcommand word (`git -C dir log`).
+    global_options: Vec<Vec<grammar::Argument>>,
 }
 
 impl fmt::Debug for CmdParser {
@@ -45,6 +48,7 @@ impl CmdParser {
             annotations: vec![],
             debug: false,
             splitting_factor: 1,
+            global_options: vec![],
         }
     }
 
@@ -58,14 +62,23 @@ impl CmdParser {
     ///     - options with params have short or long specified
     ///     - lone params cannot have multiple values until the last one
     ///     - should be at most 1 SPLITTABLE ARG (no more)
+    ///     - a command line given as a list of words must be the last param
     ///     TODO: should the error be a specific error type?
-    fn validate(&self, annotation: &grammar::Command) -> Result<()> {
+    pub fn validate(&self, annotation: &grammar::Command) -> Result<()> {
         if annotation.command_name != self.name {
             bail!("Annotation does not refer to the same command as the parser");
         }
         let mut lone_args_with_multiple = false;
         let mut found_splittable = false;
+        let mut found_command_list = false;
         for arg in annotation.args.iter() {
+            if let grammar::Argument::OptWithParam(_, param) | grammar::Argument::LoneParam(param) =
+                arg
+            {
+                if param.param_type == grammar::ArgType::Command && param.splittable {
+                    bail!("Cannot have splittable command line");
+                }
+            }
             match arg {
                 grammar::Argument::LoneOption(opt) => {
                     if opt.short == "" && opt.long == "" {
@@ -81,6 +94,15 @@ impl CmdParser {
                     }
                 }
                 grammar::Argument::LoneParam(param) => {
+                    // the words of a command line take the rest of the invocation
+                    if found_command_list {
+                        bail!("Cannot have params after a command line given as a list of words");
+                    }
+                    if param.param_type == grammar::ArgType::Command
+                        && param.size != grammar::ParamSize::One
+                    {
+                        found_command_list = true;
+                    }
                     // can only have multiple args if it's the last one
                     if param.splittable {
                         if found_splittable {
@@ -147,167 +169,311 @@ impl CmdParser {
     /// Returns:
     /// * Result<ArgMatch>
     ///     - allows the interpreter to later reconstruct the arguments back together
-    fn parse_invocation(&self, invocation: &Vec<String>, ind: usize) -> Result<ArgMatch> {
+    fn parse_invocation(&self, invocation: &[String], ind: usize) -> Result<ArgMatch> {
         let annotation = &self.annotations[ind];
-        let mut annotation_map: HashMap<String, usize> = Default::default();
-        let mut app = App::new(annotation.command_name.clone())
-            .version("1.0")
-            .author("doesn't matter"); // local variable
-        let argnames: Vec<String> = (0..annotation.args.len() as u32)
-            .map(|x| x.to_string())
-            .collect();
-        for (i, argument) in annotation.args.iter().enumerate() {
-            let argname = &argnames[i];
-            let mut arg = Arg::with_name(argname);
-            annotation_map.insert(argnames[i].to_string(), i);
-            match argument {
-                grammar::Argument::LoneOption(opt) => {
-                    if opt.short != "" {
-                        arg = arg.short(&opt.short);
-                    }
-                    if opt.long != "" {
-                        arg = arg.long(&opt.long);
+        let argnames = arg_names(annotation);
+        let (app, annotation_map) = build_app(annotation, &argnames);
+
+        // if the command name has more than 1 word --> need to remove words from the arglist
+        let (globals, mut invocation_clone) = self.split_subcommand(invocation)?;
+        let parser_name_list: Vec<String> = self.name.split(' ').map(|x| x.to_string()).collect();
+
+        // now, if lone_args_single_dash turned on, deal with this
+        // Note that ALL long args will be turned back into -dashes at the end of the parsing.
+        // the words of a command line given to an option (like `find -exec`) are left alone,
+        // up to the `;` or `{} +` that ends it
+        if annotation.long_arg_single_dash() {
+            let mut in_command = false;
+            let mut last_word = String::new();
+            for word in invocation_clone
+                .iter_mut()
+                .take_while(|word| word.as_str() != "--")
+            {
+                if in_command {
+                    in_command = !(word == ";" || (word == "+" && last_word == "{}"));
+                    last_word = word.clone();
+                    continue;
+                }
+                match annotation.check_matches_long_option(&word) {
+                    Some(grammar::Argument::OptWithParam(_, param))
+                        if param.param_type == grammar::ArgType::Command =>
+                    {
+                        word.insert_str(0, "-");
+                        in_command = true;
                     }
-                    if opt.multiple {
-                        arg = arg.multiple(true);
+                    Some(_arg) => {
+                        word.insert_str(0, "-");
                     }
-                    app = app.arg(arg);
+                    None => {}
                 }
-                grammar::Argument::OptWithParam(opt, param) => {
-                    // TODO: do something with the default value
-                    // based on the param_info and the
-                    if opt.short != "" {
-                        arg = arg.short(&opt.short);
-                    }
-                    if opt.long != "" {
-                        arg = arg.long(&opt.long);
+            }
+        }
+
+        let end_of_options = invocation_clone.iter().any(|word| word == "--");
+        let matches = get_matches(app, &self.name, invocation_clone)?;
+
+        // construct and return an argmatch object
+        let mut arg_match = ArgMatch::new(
+            parser_name_list.clone(),
+            matches,
+            &annotation,
+            annotation_map,
+        )?;
+        arg_match.set_end_of_options(end_of_options);
+
+        // options given before each subcommand word are matched against the options the
+        // subcommand inherits from the commands it is part of
+        for (depth, words) in globals.into_iter().enumerate() {
+            if words.is_empty() {
+                continue;
+            }
+            let parent = grammar::Command {
+                command_name: parser_name_list[..depth + 1].join(" "),
+                args: self
+                    .global_options
+                    .iter()
+                    .take(depth + 1)
+                    .flatten()
+                    .cloned()
+                    .collect(),
+                parsing_options: Default::default(),
+            };
+            let argnames = arg_names(&parent);
+            let (app, annotation_map) = build_app(&parent, &argnames);
+            let matches = get_matches(app, &parent.command_name, words)?;
+            let global_match = ArgMatch::new(
+                vec![parent.command_name.clone()],
+                matches,
+                &parent,
+                annotation_map,
+            )?;
+            arg_match.add_global_args(depth, global_match);
+        }
+        Ok(arg_match)
+    }
+
+    /// Separates the words that name the subcommand (e.g. `log` for `git log`) from the rest of
+    /// the invocation.
+    /// Words before a subcommand word must be options the subcommand inherits, like `-C dir` in
+    /// `git -C dir log -n 5`.
+    ///
+    /// Returns:
+    /// * the options given before each subcommand word, in order
+    /// * the arguments after the last subcommand word
+    pub fn split_subcommand(
+        &self,
+        invocation: &[String],
+    ) -> Result<(Vec<Vec<String>>, Vec<String>)> {
+        let mut globals: Vec<Vec<String>> = Vec::new();
+        let mut words = invocation.iter();
+        for (depth, subcommand) in self.name.split(' ').skip(1).enumerate() {
+            let mut before: Vec<String> = Vec::new();
+            loop {
+                let word = match words.next() {
+                    Some(word) => word,
+                    None => bail!(
+                        "Invocation {:?} does not match full command name {:?}",
+                        invocation,
+                        self.name
+                    ),
+                };
+                if word == subcommand {
+                    break;
+                }
+                let num_values = match self.global_option_values(depth, word) {
+                    Some(n) => n,
+                    None => bail!(
+                        "Invocation {:?} does not match full command name {:?}: {:?} is not an option before {:?}",
+                        invocation,
+                        self.name,
+                        word,
+                        subcommand
+                    ),
+                };
+                before.push(word.clone());
+                for _ in 0..num_values {
+                    match words.next() {
+                        Some(value) => before.push(value.clone()),
+                        None => bail!("Option {:?} in {:?} is missing its value", word, invocation),
                     }
-                    if param.default_value != "".to_string() {
-                        arg = arg.default_value(&param.default_value);
+                }
+            }
+            globals.push(before);
+        }
+        Ok((globals, words.cloned().collect()))
+    }
+
+    /// If the word is an option that can be given before the subcommand word at this depth,
+    /// returns how many of the following words are its values.
+    /// Bundled short options like `-pC` are accepted if every letter is such an option; the
+    /// first one that takes a value takes the rest of the word, or the following words.
+    fn global_option_values(&self, depth: usize, word: &str) -> Option<usize> {
+        if let Some(values) = self.single_global_option_values(depth, word) {
+            return Some(values);
+        }
+        if word.starts_with("--") || word.len() <= 2 {
+            return None;
+        }
+        for (idx, letter) in word[1..].char_indices() {
+            match self.single_global_option_values(depth, &format!("-{}", letter))? {
+                0 => {}
+                values => {
+                    return match idx + letter.len_utf8() == word.len() - 1 {
+                        true => Some(values),
+                        false => Some(0),
+                    };
+                }
+            }
+        }
+        Some(0)
+    }
+
+    fn single_global_option_values(&self, depth: usize, word: &str) -> Option<usize> {
+        for argument in self.global_options.iter().take(depth + 1).flatten() {
+            match argument {
+                grammar::Argument::LoneOption(opt) => {
+                    if (!opt.short.is_empty() && word == format!("-{}", opt.short))
+                        || (!opt.long.is_empty() && word == format!("--{}", opt.long))
+                    {
+                        return Some(0);
                     }
-                    match param.size {
-                        grammar::ParamSize::Zero => {
-                            unreachable!();
-                        }
-                        grammar::ParamSize::One => {
-                            arg = arg.takes_value(true);
-                        }
-                        grammar::ParamSize::SpecificSize(amt, separator) => {
-                            // default delimiter should be a comma
-                            arg = arg.takes_value(true);
-                            arg = arg.number_of_values(amt);
-                            match separator {
-                                // TODO: other separators
-                                grammar::ListSeparator::Comma => {
-                                    arg = arg.use_delimiter(true);
-                                    arg = arg.value_terminator(",");
-                                }
-                                _ => {}
-                            }
-                        }
-                        grammar::ParamSize::List(separator) => {
-                            arg = arg.takes_value(true);
-                            arg = arg.multiple(true);
-                            match separator {
-                                grammar::ListSeparator::Comma => {
-                                    arg = arg.use_delimiter(true);
-                                    arg = arg.value_terminator(",");
-                                }
-                                _ => {} // default
+                }
+                grammar::Argument::OptWithParam(opt, param) => {
+                    if (!opt.short.is_empty() && word == format!("-{}", opt.short))
+                        || (!opt.long.is_empty() && word == format!("--{}", opt.long))
+                    {
+                        return match param.size {
+                            grammar::ParamSize::SpecificSize(n, grammar::ListSeparator::Space) => {
+                                Some(n as usize)
                             }
-                        }
+                            _ => Some(1),
+                        };
                     }
-                    if opt.multiple || param.multiple {
-                        arg = arg.multiple(true);
+                    // the value is part of the word: -Cdir or --git-dir=dir
+                    if (!opt.short.is_empty() && word.starts_with(&format!("-{}", opt.short)))
+                        || (!opt.long.is_empty() && word.starts_with(&format!("--{}=", opt.long)))
+                    {
+                        return Some(0);
                     }
-                    app = app.arg(arg);
                 }
-                grammar::Argument::LoneParam(param) => {
-                    match param.size {
-                        grammar::ParamSize::Zero => {
-                            unreachable!();
-                        }
-                        grammar::ParamSize::One => {
-                            arg = arg.takes_value(true);
-                        }
-                        grammar::ParamSize::SpecificSize(num, separator) => {
-                            arg = arg.takes_value(true);
-                            arg = arg.number_of_values(num);
-                            match separator {
-                                grammar::ListSeparator::Comma => {
-                                    arg = arg.use_delimiter(true);
-                                    arg = arg.value_terminator(",");
-                                }
-                                _ => {} // default
-                            }
-                        }
-                        grammar::ParamSize::List(separator) => {
-                            arg = arg.takes_value(true);
-                            arg = arg.multiple(true);
-                            match separator {
-                                grammar::ListSeparator::Comma => {
-                                    arg = arg.use_delimiter(true);
-                                    arg = arg.value_terminator(",");
-                                }
-                                _ => {} // default
-                            }
+                grammar::Argument::LoneParam(_) => {}
+            }
+        }
+        None
+    }
+
+    /// Options across all annotations for this command, which its subcommands inherit.
+    pub fn get_options(&self) -> Vec<grammar::Argument> {
+        let mut options: Vec<grammar::Argument> = Vec::new();
+        for annotation in self.annotations.iter() {
+            for arg in annotation.args.iter() {
+                match arg {
+                    grammar::Argument::LoneParam(_) => {}
+                    _ => {
+                        if !options.contains(arg) {
+                            options.push(arg.clone());
                         }
                     }
-                    app = app.arg(arg);
                 }
             }
         }
+        options
+    }
 
-        let mut invocation_clone = invocation.clone();
-        // if the command name has more than 1 word --> need to remove words from the arglist
-        let parser_name_list: Vec<String> = self
-            .name
-            .clone()
-            .split(" ")
-            .map(|x| x.to_string())
-            .collect();
-        let num_args_to_pop = parser_name_list.len() - 1;
-        if num_args_to_pop > 0 {
-            if invocation_clone.len() < num_args_to_pop {
-                bail!(
-                    "Not enough arguments in invocation {:?} to fill the command name {:?}",
-                    invocation_clone,
-                    self.name
-                );
+    /// Option words in the invocation that neither its annotations nor those of the commands it
+    /// is a subcommand of declare, e.g. `--color` in `grep --color foo`.
+    /// Bundled short options such as `-la` are checked a letter at a time, up to the first one
+    /// that takes a value.
+    pub fn unknown_options(&self, invocation: &[String]) -> Vec<String> {
+        let mut shorts: HashMap<String, bool> = HashMap::default();
+        let mut longs: Vec<String> = Vec::new();
+        let declared = self.get_options();
+        for argument in declared.iter().chain(self.global_options.iter().flatten()) {
+            let (opt, takes_value) = match argument {
+                grammar::Argument::LoneOption(opt) => (opt, false),
+                grammar::Argument::OptWithParam(opt, _) => (opt, true),
+                grammar::Argument::LoneParam(_) => continue,
+            };
+            if !opt.short.is_empty() {
+                shorts.insert(opt.short.clone(), takes_value);
             }
-            for i in 0..num_args_to_pop {
-                let popped = invocation_clone.remove(0);
-                if popped != parser_name_list[i + 1] {
-                    bail!(
-                        "Invocation {:?} does not match full command name {:?}",
-                        invocation,
-                        self.name
-                    );
-                }
+            if !opt.long.is_empty() {
+                longs.push(opt.long.clone());
             }
         }
+        let long_arg_single_dash = self
+            .annotations
+            .iter()
+            .any(|annotation| annotation.parsing_options.long_arg_single_dash);
 
-        // now, if lone_args_single_dash turned on, deal with this
-        // Note that ALL long args will be turned back into -dashes at the end of the parsing.
-        if annotation.long_arg_single_dash() {
-            for word in invocation_clone.iter_mut() {
-                match annotation.check_matches_long_option(&word) {
-                    Some(_arg) => {
-                        word.insert_str(0, "-");
-                    }
-                    None => {}
+        let mut unknown: Vec<String> = Vec::new();
+        for word in invocation.iter() {
+            if word == "--" {
+                break;
+            }
+            if word == "-" || !word.starts_with('-') {
+                continue;
+            }
+            let option = if let Some(long) = word.strip_prefix("--") {
+                let name = long.split('=').next().unwrap_or(long);
+                match longs.iter().any(|known| known == name) {
+                    true => continue,
+                    false => format!("--{}", name),
+                }
+            } else {
+                let letters = &word[1..];
+                // negative numbers are values, not options
+                if (long_arg_single_dash && longs.iter().any(|known| known == letters))
+                    || letters.parse::<f64>().is_ok()
+                {
+                    continue;
+                }
+                // the first letter that takes a value takes the rest of the word as well
+                match letters
+                    .chars()
+                    .map(|letter| (letter, shorts.get(&letter.to_string())))
+                    .find(|(_, takes_value)| *takes_value != Some(&false))
+                {
+                    Some((letter, None)) => format!("-{}", letter),
+                    _ => continue,
                 }
+            };
+            if !unknown.contains(&option) {
+                unknown.push(option);
             }
         }
+        unknown
+    }
 
-        invocation_clone.insert(0, self.name.clone());
-        let matches: clap::ArgMatches = match app.get_matches_from_safe(invocation_clone) {
-            Ok(m) => m,
-            Err(e) => bail!("Could not get matches: {:?}", e),
-        };
+    /// Sets the options inherited from each command this is a subcommand of, outermost first
+    /// (e.g. the options of `docker` and then of `docker container` for
+    /// `docker container run`).
+    pub fn set_global_options(&mut self, global_options: Vec<Vec<grammar::Argument>>) {
+        self.global_options = global_options;
+    }
 
-        // construct and return an argmatch object
-        ArgMatch::new(parser_name_list, matches, &annotation, annotation_map)
+    /// Tries each annotation in the whitelist in order.
+    /// Returns the index of the first annotation that matches the invocation with its match, or
+    /// why each of the annotations did not match it.
+    pub fn match_annotations(
+        &self,
+        invocation: &[String],
+    ) -> std::result::Result<(usize, ArgMatch), Vec<String>> {
+        let mut reasons: Vec<String> = Vec::new();
+        for i in 0..self.annotations.len() {
+            match self.parse_invocation(invocation, i) {
+                Ok(ret) => {
+                    return Ok((i, ret));
+                }
+                Err(e) => {
+                    if self.debug {
+                        debug!("Failed to parse: {:?}", e);
+                    }
+                    reasons.push(e.to_string());
+                }
+            }
+        }
+        Err(reasons)
     }
 
     /// Tries to parse a command with each of the parsers in the whitelist.
@@ -315,28 +481,184 @@ impl CmdParser {
     /// If no parser matches this invocation, returns a parsed command where all arguments are of
     /// type "str" (the default).
     pub fn parse_command(&self, invocation: &Vec<String>) -> Result<ArgMatch> {
-        for i in 0..self.annotations.len() {
-            match self.parse_invocation(&invocation, i) {
-                Ok(ret) => {
-                    return Ok(ret);
+        match self.match_annotations(invocation) {
+            Ok((_, ret)) => Ok(ret),
+            Err(_) => {
+                debug!(
+                    "Warning: invocation {:?} was not parsed by any parsers",
+                    invocation
+                );
+                Ok(ArgMatch::new_default(&self.name, invocation))
+            }
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Annotations for this command, in the order they are tried.
+    pub fn get_annotations(&self) -> &[grammar::Command] {
+        &self.annotations
+    }
+}
+/// Clap needs a name for each argument; arguments are named by their index in the annotation.
+fn arg_names(annotation: &grammar::Command) -> Vec<String> {
+    (0..annotation.args.len()).map(|x| x.to_string()).collect()
+}
+
+/// Builds a clap parser for the annotation.
+/// Returns the parser and a map from clap argument name to index in the annotation.
+fn build_app<'a>(
+    annotation: &'a grammar::Command,
+    argnames: &'a [String],
+) -> (App<'a, 'a>, HashMap<String, usize>) {
+    let mut annotation_map: HashMap<String, usize> = Default::default();
+    let mut app = App::new(annotation.command_name.clone())
+        .version("1.0")
+        .author("doesn't matter") // local variable
+        .setting(AppSettings::ColorNever);
+    for (i, argument) in annotation.args.iter().enumerate() {
+        let argname = &argnames[i];
+        let mut arg = Arg::with_name(argname);
+        annotation_map.insert(argnames[i].to_string(), i);
+        match argument {
+            grammar::Argument::LoneOption(opt) => {
+                if opt.short != "" {
+                    arg = arg.short(&opt.short);
                 }
-                Err(e) => {
-                    if self.debug {
-                        debug!("Failed to parse: {:?}", e);
+                if opt.long != "" {
+                    arg = arg.long(&opt.long);
+                }
+                if opt.multiple {
+                    arg = arg.multiple(true);
+                }
+                app = app.arg(arg);
+            }
+            grammar::Argument::OptWithParam(opt, param) => {
+                // TODO: do something with the default value
+                // based on the param_info and the
+                if opt.short != "" {
+                    arg = arg.short(&opt.short);
+                }
+                if opt.long != "" {
+                    arg = arg.long(&opt.long);
+                }
+                if param.default_value != "".to_string() {
+                    arg = arg.default_value(&param.default_value);
+                }
+                match param.size {
+                    grammar::ParamSize::Zero => {
+                        unreachable!();
+                    }
+                    grammar::ParamSize::One => {
+                        arg = arg.takes_value(true);
                     }
+                    grammar::ParamSize::SpecificSize(amt, separator) => {
+                        // default delimiter should be a comma
+                        arg = arg.takes_value(true);
+                        arg = arg.number_of_values(amt);
+                        match separator {
+                            // TODO: other separators
+                            grammar::ListSeparator::Comma => {
+                                arg = arg.use_delimiter(true);
+                                arg = arg.value_terminator(",");
+                            }
+                            _ => {}
+                        }
+                    }
+                    grammar::ParamSize::List(separator) => {
+                        arg = arg.takes_value(true);
+                        arg = arg.multiple(true);
+                        match separator {
+                            grammar::ListSeparator::Comma => {
+                                arg = arg.use_delimiter(true);
+                                arg = arg.value_terminator(",");
+                            }
+                            _ => {} // default
+                        }
+                    }
+                }
+                if opt.multiple || param.multiple {
+                    arg = arg.multiple(true);
+                }
+                // a command line runs up to a `;`, and its words can look like options
+                if param.param_type == grammar::ArgType::Command
+                    && param.size != grammar::ParamSize::One
+                {
+                    arg = arg.allow_hyphen_values(true).value_terminator(";");
+                }
+                app = app.arg(arg);
+            }
+            grammar::Argument::LoneParam(param) => {
+                match param.size {
+                    grammar::ParamSize::Zero => {
+                        unreachable!();
+                    }
+                    grammar::ParamSize::One => {
+                        arg = arg.takes_value(true);
+                    }
+                    grammar::ParamSize::SpecificSize(num, separator) => {
+                        arg = arg.takes_value(true);
+                        arg = arg.number_of_values(num);
+                        match separator {
+                            grammar::ListSeparator::Comma => {
+                                arg = arg.use_delimiter(true);
+                                arg = arg.value_terminator(",");
+                            }
+                            _ => {} // default
+                        }
+                    }
+                    grammar::ParamSize::List(separator) => {
+                        arg = arg.takes_value(true);
+                        arg = arg.multiple(true);
+                        match separator {
+                            grammar::ListSeparator::Comma => {
+                                arg = arg.use_delimiter(true);
+                                arg = arg.value_terminator(",");
+                            }
+                            _ => {} // default
+                        }
+                    }
+                }
+                // a command line takes the rest of the words, even those that look like options
+                if param.param_type == grammar::ArgType::Command
+                    && param.size != grammar::ParamSize::One
+                {
+                    arg = arg.allow_hyphen_values(true);
+                    app = app.setting(AppSettings::TrailingVarArg);
                 }
+                app = app.arg(arg);
             }
         }
-        debug!(
-            "Warning: invocation {:?} was not parsed by any parsers",
-            invocation
-        );
-        return Ok(ArgMatch::new_default(&self.name, &invocation));
+    }
+    (app, annotation_map)
+}
+
+/// Runs the clap parser over the words after the command name.
+fn get_matches<'a>(app: App<'a, 'a>, name: &str, mut words: Vec<String>) -> Result<ArgMatches<'a>> {
+    words.insert(0, name.to_string());
+    match app.get_matches_from_safe(words) {
+        Ok(m) => Ok(m),
+        // the first line of clap's message says which word did not fit
+        Err(e) => bail!(
+            "{}",
+            e.message
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .trim_start_matches("error: ")
+        ),
     }
 }
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use dash::graph::command::NodeArg;
+    use dash::graph::filestream::FileStream;
+    use dash::graph::Location;
+    use std::path::Path;
 
     #[test]
     fn test_validate_invalid_opt() {
@@ -447,4 +769,116 @@ mod tests {
         let parser = CmdParser::new("test_command");
         assert_eq!(parser.validate(&annotation).unwrap(), ());
     }
+
+    #[test]
+    fn test_unknown_options() {
+        let mut parser = CmdParser::new("grep");
+        parser
+            .add_annotation(
+                grammar::Command::new(
+                    "grep: FLAGS:[(short:v),(short:i,long:ignore-case)] OPTPARAMS:[(short:e,type:str,size:1)] PARAMS:[(type:str,size:1),(type:input_file,size:list(list_separator:( )))]",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        let words = |s: &str| -> Vec<String> { s.split(' ').map(|x| x.to_string()).collect() };
+        assert!(parser
+            .unknown_options(&words("-vi --ignore-case foo a.txt"))
+            .is_empty());
+        assert_eq!(
+            parser.unknown_options(&words("-vefoo -A -5 --color=auto -vP -A -- -x a.txt")),
+            vec!["-A".to_string(), "--color".to_string(), "-P".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_value_conditions() {
+        let words = |s: &str| -> Vec<String> { s.split(' ').map(|x| x.to_string()).collect() };
+        let mut grep = CmdParser::new("grep");
+        grep.add_annotation(
+            grammar::Command::new(
+                "grep[splittable_across_input,reduces_input,matching:(*.gz,random_access,selectivity:0.05,not_splittable)]: FLAGS:[(short:c)] PARAMS:[(type:str,size:1),(type:input_file,size:list(list_separator:( )))]",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let arg_match = grep.parse_command(&words("foo a.txt")).unwrap();
+        assert_eq!(arg_match.get_access_type(), grammar::AccessType::Sequential);
+        assert_eq!(arg_match.get_selectivity(), 0.5);
+        assert!(arg_match.get_splittable_across_input());
+
+        let arg_match = grep.parse_command(&words("foo a.txt /d/b.gz")).unwrap();
+        assert_eq!(arg_match.get_access_type(), grammar::AccessType::Random);
+        assert_eq!(arg_match.get_selectivity(), 0.05);
+        assert!(!arg_match.get_splittable_across_input());
+    }
+
+    #[test]
+    fn test_option_conventions() {
+        let words = |s: &str| -> Vec<String> { s.split(' ').map(|x| x.to_string()).collect() };
+        let str_arg = |s: &str| NodeArg::Str(s.to_string());
+        let file_arg = |s: &str| NodeArg::Stream(FileStream::new(Path::new(s), Location::Client));
+
+        // bundled short flags, the last one taking the next word as its value
+        let mut tar = CmdParser::new("tar");
+        tar.add_annotation(
+            grammar::Command::new(
+                "tar: FLAGS:[(short:x),(short:z)] OPTPARAMS:[(short:f,type:input_file,size:1)]",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        for invocation in ["-xzf a.tgz", "-xzfa.tgz"].iter() {
+            let args = tar
+                .parse_command(&words(invocation))
+                .unwrap()
+                .reconstruct()
+                .unwrap();
+            assert_eq!(args.len(), 4);
+            assert!(args.contains(&str_arg("-x")) && args.contains(&str_arg("-z")));
+            let f = args.iter().position(|arg| *arg == str_arg("-f")).unwrap();
+            assert_eq!(args[f + 1], file_arg("a.tgz"));
+        }
+
+        // `--` ends the options and is passed on; `-` is stdin, not a file
+        let mut grep = CmdParser::new("grep");
+        grep.add_annotation(
+            grammar::Command::new(
+                "grep: FLAGS:[(short:v)] PARAMS:[(type:str,size:1),(type:input_file,size:list(list_separator:( )))]",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let arg_match = grep.parse_command(&words("-v -- -x - a.txt")).unwrap();
+        assert_eq!(
+            arg_match.reconstruct().unwrap(),
+            vec![
+                str_arg("-v"),
+                str_arg("--"),
+                str_arg("-x"),
+                str_arg("-"),
+                file_arg("a.txt")
+            ]
+        );
+        assert_eq!(arg_match.file_dependencies().len(), 1);
+
+        // bundled options the subcommand inherits, before the subcommand word
+        let mut log = CmdParser::new("git log");
+        log.set_global_options(vec![
+            grammar::Command::new(
+                "git: FLAGS:[(short:p)] OPTPARAMS:[(short:C,type:input_file,size:1)]",
+            )
+            .unwrap()
+            .args,
+        ]);
+        assert_eq!(
+            log.split_subcommand(&words("-pC repo log -n")).unwrap(),
+            (vec![words("-pC repo")], words("-n"))
+        );
+        assert_eq!(
+            log.split_subcommand(&words("-pCrepo log")).unwrap(),
+            (vec![words("-pCrepo")], vec![])
+        );
+        assert!(log.split_subcommand(&words("-pq log")).is_err());
+    }
 }
@@ -0,0 +1,657 @@
+use dash::util::Result;
+use failure::bail;
+use std::fs::read_to_string;
+use std::process::Command as ProcessCommand;
+use yaml_rust::YamlLoader;
+
+/// An option listed in a command's help text.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct HelpOption {
+    pub short: String,
+    pub long: String,
+    /// Name of the option's argument, e.g. `FILE` in `--file=FILE`; empty if it takes none.
+    pub metavar: String,
+    /// The argument may be left out, e.g. `--color[=WHEN]`.
+    pub optional_arg: bool,
+    pub desc: String,
+}
+
+/// A positional argument from the command's usage line.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct HelpParam {
+    pub metavar: String,
+    /// The usage line allows more than one, e.g. `[FILE]...`.
+    pub list: bool,
+}
+
+/// What could be learned about a command from its help text, before deciding on the
+/// semantics a person has to fill in.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Draft {
+    pub name: String,
+    pub long_arg_single_dash: bool,
+    pub options: Vec<HelpOption>,
+    pub params: Vec<HelpParam>,
+}
+
+/// Runs `<command> --help` and returns what it prints.
+/// Some commands print their help on stderr, or exit with an error after printing it, so
+/// either stream is accepted as long as it is not empty.
+pub fn help_text(command: &str) -> Result<String> {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    if words.is_empty() {
+        bail!("no command given");
+    }
+    let output = match ProcessCommand::new(words[0])
+        .args(&words[1..])
+        .arg("--help")
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) => bail!("could not run `{} --help`: {}", command, e),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    if !stdout.trim().is_empty() {
+        return Ok(stdout);
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    if !stderr.trim().is_empty() {
+        return Ok(stderr);
+    }
+    bail!("`{} --help` printed nothing", command)
+}
+
+/// Reads a man page from a local file, either formatted text or roff source, which may be
+/// gzipped as man pages usually are.
+pub fn man_text(file: &str) -> Result<String> {
+    let contents = if file.ends_with(".gz") {
+        let output = match ProcessCommand::new("gzip").arg("-dc").arg(file).output() {
+            Ok(output) => output,
+            Err(e) => bail!("could not run gzip on {}: {}", file, e),
+        };
+        if !output.status.success() {
+            bail!(
+                "could not decompress {}: {}",
+                file,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        String::from_utf8_lossy(&output.stdout).to_string()
+    } else {
+        read_to_string(file)?
+    };
+    if contents
+        .lines()
+        .any(|line| line.starts_with(".TH") || line.starts_with(".SH"))
+    {
+        Ok(strip_roff(&contents))
+    } else {
+        Ok(contents)
+    }
+}
+
+/// Turns roff source into roughly the text `man` would show: requests that set fonts keep their
+/// text, other requests are dropped and escapes are replaced by the characters they stand for.
+pub fn strip_roff(source: &str) -> String {
+    let mut text = String::new();
+    for line in source.lines() {
+        if line.starts_with(".\\\"") || line.starts_with("'\\\"") {
+            continue;
+        }
+        let line = if let Some(request) = line.strip_prefix('.') {
+            let mut parts = request.splitn(2, char::is_whitespace);
+            let name = parts.next().unwrap_or("");
+            let rest = parts.next().unwrap_or("").trim();
+            match name {
+                // alternating fonts put their arguments next to each other
+                "BR" | "BI" | "IB" | "IR" | "RB" | "RI" => roff_args(rest).join(""),
+                "B" | "I" | "SH" | "SS" => roff_args(rest).join(" "),
+                // the first argument of an indented paragraph is its tag
+                "IP" => roff_args(rest).into_iter().next().unwrap_or_default(),
+                _ => continue,
+            }
+        } else {
+            line.to_string()
+        };
+        text.push_str(&unescape_roff(&line));
+        text.push('\n');
+    }
+    text
+}
+
+/// Splits the arguments of a roff request, keeping quoted arguments together.
+fn roff_args(rest: &str) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in rest.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ' ' | '\t' if !quoted => {
+                if !current.is_empty() {
+                    args.push(current.clone());
+                    current.clear();
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+    args
+}
+
+fn unescape_roff(line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut text = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '\\' || i + 1 == chars.len() {
+            text.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        match chars[i + 1] {
+            // font changes: \fB, \fI, \fR, \fP and \f(CW
+            'f' => {
+                i += if chars.get(i + 2) == Some(&'(') { 5 } else { 3 };
+            }
+            '-' => {
+                text.push('-');
+                i += 2;
+            }
+            'e' | '\\' => {
+                text.push('\\');
+                i += 2;
+            }
+            ' ' => {
+                text.push(' ');
+                i += 2;
+            }
+            // named characters such as \(em
+            '(' => {
+                text.push('-');
+                i += 4;
+            }
+            // zero width characters such as \& and \c
+            _ => i += 2,
+        }
+    }
+    text
+}
+
+/// Help text indents descriptions that wrap onto the next line by about 30 columns, and man pages
+/// indent options by 7.
+const MAX_OPTION_INDENT: usize = 12;
+
+/// Finds the options and positional arguments in a command's `--help` output or man page.
+/// Options are lines starting with a dash, described either on the same line after a gap of
+/// two spaces, as in `--help` output, or on the lines after, as in man pages. Lines indented
+/// further than options are in either are taken to continue a description.
+/// Positional arguments come from the `Usage:` line, or the first line of the SYNOPSIS section.
+pub fn parse_help(name: &str, text: &str) -> Draft {
+    let mut draft = Draft {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    let lines: Vec<&str> = text.lines().collect();
+    for (idx, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        if !trimmed.starts_with('-') || line.len() - trimmed.len() > MAX_OPTION_INDENT {
+            continue;
+        }
+        let (spec, desc) = match trimmed.find("  ").or_else(|| trimmed.find('\t')) {
+            Some(gap) => (&trimmed[..gap], trimmed[gap..].trim()),
+            None => (trimmed.trim_end(), ""),
+        };
+        let mut option = match parse_option_spec(spec, &mut draft.long_arg_single_dash) {
+            Some(option) => option,
+            None => continue,
+        };
+        option.desc = if desc.is_empty() {
+            lines
+                .get(idx + 1)
+                .map(|next| next.trim())
+                .filter(|next| !next.starts_with('-'))
+                .unwrap_or("")
+                .to_string()
+        } else {
+            desc.to_string()
+        };
+        let seen = draft.options.iter().any(|other| {
+            (!option.short.is_empty() && other.short == option.short)
+                || (!option.long.is_empty() && other.long == option.long)
+        });
+        if !seen {
+            draft.options.push(option);
+        }
+    }
+    if let Some(usage) = usage_line(&lines) {
+        draft.params = parse_usage(name, &usage, &draft.options);
+    }
+    draft
+}
+
+/// Parses the option part of a help line, e.g. `-n, --lines=[-]NUM` or `-o FILE`.
+fn parse_option_spec(spec: &str, long_arg_single_dash: &mut bool) -> Option<HelpOption> {
+    let mut option = HelpOption::default();
+    for part in spec.split(',').map(|part| part.trim()) {
+        let (name, rest) = if let Some(long) = part.strip_prefix("--") {
+            let end = long.find(['=', '[', ' ']).unwrap_or(long.len());
+            (&long[..end], &long[end..])
+        } else if let Some(short) = part.strip_prefix('-') {
+            let end = short.find(['=', '[', ' ', '<']).unwrap_or(short.len());
+            (&short[..end], &short[end..])
+        } else {
+            continue;
+        };
+        // `-NUM` stands for any number, not an option
+        if name.is_empty()
+            || name.chars().all(|c| c.is_ascii_uppercase())
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            continue;
+        }
+        if part.starts_with("--") || name.len() > 1 {
+            if !part.starts_with("--") {
+                *long_arg_single_dash = true;
+            }
+            if option.long.is_empty() {
+                option.long = name.to_string();
+            }
+        } else if option.short.is_empty() {
+            option.short = name.to_string();
+        }
+        let (metavar, optional) = parse_metavar(rest);
+        if !metavar.is_empty() {
+            option.metavar = metavar;
+            option.optional_arg = optional;
+        }
+    }
+    if option.short.is_empty() && option.long.is_empty() {
+        None
+    } else {
+        Some(option)
+    }
+}
+
+/// Parses what follows an option name: `=FILE`, ` FILE`, `[=WHEN]` or `<file>`.
+fn parse_metavar(rest: &str) -> (String, bool) {
+    let rest = rest.trim_start_matches(['=', ' ']);
+    let optional = rest.starts_with("[=") || (rest.starts_with('[') && rest.ends_with(']'));
+    let rest = if optional {
+        rest.trim_start_matches(['[', '=']).trim_end_matches(']')
+    } else {
+        rest
+    };
+    let metavar = rest
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+        .trim_matches(['<', '>']);
+    (metavar.to_string(), optional)
+}
+
+/// Finds the text after `Usage:`, or the SYNOPSIS section up to the next blank line or heading.
+fn usage_line(lines: &[&str]) -> Option<String> {
+    for (idx, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.to_lowercase().starts_with("usage:") {
+            return Some(trimmed["usage:".len()..].to_string());
+        }
+        if trimmed == "SYNOPSIS" {
+            let synopsis: Vec<&str> = lines[idx + 1..]
+                .iter()
+                .map(|line| line.trim())
+                .skip_while(|line| line.is_empty())
+                .take_while(|line| !line.is_empty() && !is_heading(line))
+                .collect();
+            return Some(synopsis.join(" "));
+        }
+    }
+    None
+}
+
+fn is_heading(line: &str) -> bool {
+    line.chars().all(|c| c.is_ascii_uppercase() || c == ' ')
+}
+
+/// Finds the positional arguments in a usage line such as `head [OPTION]... [FILE]...`.
+/// Bracketed options such as `[-n count]` are dropped along with their arguments.
+fn parse_usage(name: &str, usage: &str, options: &[HelpOption]) -> Vec<HelpParam> {
+    let mut without_options = String::new();
+    let mut skip_depth = 0;
+    let chars: Vec<char> = usage.chars().collect();
+    for (i, c) in chars.iter().enumerate() {
+        match c {
+            '[' if skip_depth > 0 || chars.get(i + 1) == Some(&'-') => skip_depth += 1,
+            ']' if skip_depth > 0 => skip_depth -= 1,
+            _ if skip_depth > 0 => {}
+            _ => without_options.push(*c),
+        }
+    }
+    let name_words = name.split_whitespace().count();
+    let mut params: Vec<HelpParam> = Vec::new();
+    let mut skip_next = false;
+    for word in without_options.split_whitespace().skip(name_words) {
+        if skip_next {
+            skip_next = false;
+            continue;
+        }
+        if word.starts_with('-') {
+            let option = word.trim_start_matches('-');
+            skip_next = !word.contains('=')
+                && options.iter().any(|known| {
+                    (known.short == option || known.long == option)
+                        && !known.metavar.is_empty()
+                        && !known.optional_arg
+                });
+            continue;
+        }
+        if word == "..." || word == "[...]" {
+            if let Some(last) = params.last_mut() {
+                last.list = true;
+            }
+            continue;
+        }
+        let metavar = word.trim_matches(['[', ']', '.']);
+        if metavar.is_empty()
+            || !metavar
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            || metavar.eq_ignore_ascii_case("option")
+            || metavar.eq_ignore_ascii_case("options")
+        {
+            continue;
+        }
+        params.push(HelpParam {
+            metavar: metavar.to_string(),
+            list: word.ends_with("..."),
+        });
+    }
+    params
+}
+
+/// Guesses the annotation type of an argument from its metavariable and option name, e.g.
+/// `FILE` is an input file and `--output=FILE` is an output file.
+pub fn guess_type(metavar: &str, option_name: &str) -> &'static str {
+    let metavar = metavar.to_uppercase();
+    let option_name = option_name.to_lowercase();
+    let output = metavar.contains("OUT")
+        || metavar.contains("DEST")
+        || ["output", "target", "dest"]
+            .iter()
+            .any(|word| option_name.contains(word));
+    let dir = metavar.contains("DIR");
+    let file = metavar.contains("FILE")
+        || metavar.contains("PATH")
+        || metavar.contains("SOURCE")
+        || metavar.contains("SRC")
+        || metavar.contains("INPUT")
+        || metavar.contains("ARCHIVE");
+    if metavar.contains("COMMAND") {
+        "command"
+    } else if dir && output {
+        "output_prefix"
+    } else if dir {
+        "input_file"
+    } else if metavar.contains("OUT") || metavar.contains("DEST") || (file && output) {
+        "output_file"
+    } else if file {
+        "input_file"
+    } else {
+        "str"
+    }
+}
+
+/// Writes the draft as a structured annotation, with TODO comments for what the help text
+/// cannot say: whether the command can be split or reduces its input, and which guesses to
+/// check.
+pub fn render_draft(draft: &Draft, source: &str) -> String {
+    let mut out = String::new();
+    out.push_str(&format!(
+        "# Drafted from {}; check every entry before using it.\ncommands:\n",
+        source
+    ));
+    out.push_str(&format!("  - name: {}\n", draft.name));
+    out.push_str("    # TODO: add splittable_across_input if running the command on parts of its input and\n");
+    out.push_str("    # concatenating the outputs gives the same result, reduces_input if its output is much\n");
+    out.push_str(
+        "    # smaller than its input, and needs_current_dir if it reads files it is not given.\n",
+    );
+    if draft.long_arg_single_dash {
+        out.push_str("    options: [long_arg_single_dash]\n");
+    } else {
+        out.push_str("    options: []\n");
+    }
+    out.push_str("    # TODO: if split outputs must be combined other than by concatenating them, set reducer.\n");
+    let flags: Vec<&HelpOption> = draft
+        .options
+        .iter()
+        .filter(|option| option.metavar.is_empty() || option.optional_arg)
+        .collect();
+    let optparams: Vec<&HelpOption> = draft
+        .options
+        .iter()
+        .filter(|option| !option.metavar.is_empty() && !option.optional_arg)
+        .collect();
+    if !flags.is_empty() {
+        out.push_str("    flags:\n");
+        for option in flags.iter() {
+            if option.optional_arg {
+                out.push_str(&format!(
+                    "      # TODO: takes an optional {}; only the form without it is annotated.\n",
+                    option.metavar
+                ));
+            }
+            out.push_str(&format!(
+                "      - {{{}}}\n",
+                option_fields(option).join(", ")
+            ));
+        }
+    }
+    if !optparams.is_empty() {
+        out.push_str("    optparams:\n");
+        for option in optparams.iter() {
+            let option_name = if option.long.is_empty() {
+                &option.short
+            } else {
+                &option.long
+            };
+            let arg_type = guess_type(&option.metavar, option_name);
+            let mut fields = option_fields(option);
+            fields.push(format!("type: {}", arg_type));
+            fields.push("size: 1".to_string());
+            out.push_str(&format!(
+                "      - {{{}}}{}\n",
+                fields.join(", "),
+                type_note(arg_type, &option.metavar)
+            ));
+        }
+    }
+    if !draft.params.is_empty() {
+        out.push_str("    # TODO: if the command can be split across a list of files, mark it splittable: true.\n");
+        out.push_str("    params:\n");
+        for param in draft.params.iter() {
+            let arg_type = guess_type(&param.metavar, "");
+            let size = if param.list {
+                "size: list, list_separator: space"
+            } else {
+                "size: 1"
+            };
+            out.push_str(&format!(
+                "      - {{type: {}, {}}}{}\n",
+                arg_type,
+                size,
+                type_note(arg_type, &param.metavar)
+            ));
+        }
+    }
+    out
+}
+
+fn option_fields(option: &HelpOption) -> Vec<String> {
+    let mut fields: Vec<String> = Vec::new();
+    if !option.short.is_empty() {
+        fields.push(format!("short: {}", yaml_string(&option.short)));
+    }
+    if !option.long.is_empty() {
+        fields.push(format!("long: {}", yaml_string(&option.long)));
+    }
+    if !option.desc.is_empty() {
+        fields.push(format!(
+            "desc: \"{}\"",
+            option.desc.replace('\\', "\\\\").replace('"', "\\\"")
+        ));
+    }
+    fields
+}
+
+/// Quotes option names YAML would read as something other than a string, such as `null` or `1`.
+fn yaml_string(value: &str) -> String {
+    match YamlLoader::load_from_str(value) {
+        Ok(ref docs) if docs.len() == 1 && docs[0].as_str() == Some(value) => value.to_string(),
+        _ => format!("\"{}\"", value),
+    }
+}
+
+fn type_note(arg_type: &str, metavar: &str) -> String {
+    match arg_type {
+        "str" => String::new(),
+        _ => format!("  # TODO: {} guessed from {}", arg_type, metavar),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cmd_parser::CmdParser;
+    use super::super::structured::parse_structured_annotations;
+    use super::*;
+
+    const HEAD_HELP: &str = "Usage: head [OPTION]... [FILE]...
+Print the first 10 lines of each FILE to standard output.
+
+Mandatory arguments to long options are mandatory for short options too.
+  -c, --bytes=[-]NUM       print the first NUM bytes of each file;
+  -n, --lines=[-]NUM       print the first NUM lines instead of the first 10;
+  -q, --quiet, --silent    never print headers giving file names
+      --color[=WHEN]       colorize the output
+  -o, --output=FILE        write to FILE instead of standard output
+  -z, --zero-terminated    line delimiter is NUL, not newline
+      --null               print a 0 byte after each file name;
+                             -z also ends lines with a 0 byte
+  -NUM                     same as --lines=NUM
+  -1                       print one line
+";
+
+    #[test]
+    fn test_parse_help() {
+        let draft = parse_help("head", HEAD_HELP);
+        assert_eq!(draft.options.len(), 8);
+        assert_eq!(
+            draft.options[0],
+            HelpOption {
+                short: "c".to_string(),
+                long: "bytes".to_string(),
+                metavar: "[-]NUM".to_string(),
+                optional_arg: false,
+                desc: "print the first NUM bytes of each file;".to_string(),
+            }
+        );
+        assert_eq!(draft.options[2].long, "quiet");
+        assert_eq!(draft.options[3].long, "color");
+        assert!(draft.options[3].optional_arg);
+        assert_eq!(draft.options[4].metavar, "FILE");
+        assert_eq!(
+            draft.params,
+            vec![HelpParam {
+                metavar: "FILE".to_string(),
+                list: true,
+            }]
+        );
+        assert!(!draft.long_arg_single_dash);
+    }
+
+    #[test]
+    fn test_guess_type() {
+        assert_eq!(guess_type("FILE", "file"), "input_file");
+        assert_eq!(guess_type("FILE", "output"), "output_file");
+        assert_eq!(guess_type("OUTFILE", "o"), "output_file");
+        assert_eq!(guess_type("DIRECTORY", "target-directory"), "output_prefix");
+        assert_eq!(guess_type("COMMAND", "exec"), "command");
+        assert_eq!(guess_type("DIR", "C"), "input_file");
+        assert_eq!(guess_type("STRING", "output-delimiter"), "str");
+        assert_eq!(guess_type("NUM", "lines"), "str");
+    }
+
+    #[test]
+    fn test_draft_is_a_valid_annotation() {
+        let draft = parse_help("head", HEAD_HELP);
+        let yaml = render_draft(&draft, "`head --help`");
+        assert!(yaml.contains("# TODO: add splittable_across_input"));
+        assert!(yaml.contains("type: output_file, size: 1}  # TODO: output_file guessed from FILE"));
+        assert!(yaml.contains("{long: \"null\", "));
+        assert!(yaml.contains("{short: \"1\", "));
+        let commands = parse_structured_annotations(&yaml).unwrap();
+        assert_eq!(commands.len(), 1);
+
+        let mut parser = CmdParser::new("head");
+        parser.add_annotation(commands[0].clone()).unwrap();
+        let invocation = vec![
+            "-n".to_string(),
+            "5".to_string(),
+            "-o".to_string(),
+            "out.txt".to_string(),
+            "a.txt".to_string(),
+            "b.txt".to_string(),
+        ];
+        assert!(parser.parse_command(&invocation).is_ok());
+    }
+
+    #[test]
+    fn test_parse_man_page() {
+        let roff = r#".\" a comment
+.TH HEAD 1
+.SH NAME
+head \- output the first part of files
+.SH SYNOPSIS
+.B head
+[\fI\,OPTION\/\fR]... [\fI\,FILE\/\fR]...
+.SH OPTIONS
+.TP
+\fB\-n\fR, \fB\-\-lines\fR=\fI\,[\-]NUM\/\fR
+print the first NUM lines
+.TP
+.BR \-v ", " \-\-verbose
+always print headers giving file names
+.IP "\fB\-o\fR \fIFILE\fR"
+write to FILE
+"#;
+        let text = strip_roff(roff);
+        let draft = parse_help("head", &text);
+        assert_eq!(draft.options.len(), 3);
+        assert_eq!(draft.options[0].long, "lines");
+        assert_eq!(draft.options[0].desc, "print the first NUM lines");
+        assert_eq!(draft.options[1].short, "v");
+        assert_eq!(draft.options[1].long, "verbose");
+        assert_eq!(draft.options[2].short, "o");
+        assert_eq!(draft.options[2].metavar, "FILE");
+        assert_eq!(draft.params.len(), 1);
+        assert!(draft.params[0].list);
+    }
+
+    #[test]
+    fn test_parse_usage_skips_option_arguments() {
+        let draft = parse_help(
+            "find",
+            "usage: find [-H] [-L] path ... -name pattern [expression]\n  -name pattern  match names\n",
+        );
+        assert!(draft.long_arg_single_dash);
+        let metavars: Vec<&str> = draft.params.iter().map(|p| p.metavar.as_str()).collect();
+        assert_eq!(metavars, vec!["path", "expression"]);
+        assert!(draft.params[0].list);
+    }
+}
@@ -1,10 +1,15 @@
 ///! Grammar Abstraction that defines the syntax of command line arguments.
 use super::annotation_parser::parse_annotation;
+use super::lint::lint_line;
+use super::structured::{parse_structured_annotations, parse_structured_includes};
 use dash::util::Result;
 use failure::bail;
+use glob::Pattern;
 use nom::types::CompleteByteSlice;
+use regex::Regex;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use std::*;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -19,6 +24,27 @@ impl Default for AccessType {
     }
 }
 
+/// How the outputs of a command split into parallel copies are combined.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Reducer {
+    /// Outputs are concatenated in order, which is correct for filters like grep.
+    Concat,
+    /// The numbers in each column are summed, like the counts printed by wc.
+    Sum,
+    /// Sorted outputs are merged by running the command again with -m, like sort.
+    Merge,
+    /// Counts of repeated lines are merged again, like the output of uniq or uniq -c.
+    Uniq,
+    /// Outputs are concatenated into the stdin of this command line.
+    Custom(String),
+}
+
+impl Default for Reducer {
+    fn default() -> Self {
+        Reducer::Concat
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ArgType {
     InputFile,
@@ -26,6 +52,22 @@ pub enum ArgType {
     Str,
     InputFileList,
     OutputFileList,
+    /// Directory or file name prefix the command creates its output files under, whose names
+    /// are not known ahead of time (e.g. the prefix given to `split`).
+    OutputPrefix,
+    /// Another command line the command runs, matched against that command's annotations (e.g.
+    /// `grep foo` in `xargs grep foo`, or the script of `sh -c`).
+    Command,
+}
+
+impl ArgType {
+    /// Does the command write to files named by arguments of this type?
+    pub fn is_output(&self) -> bool {
+        matches!(
+            self,
+            ArgType::OutputFile | ArgType::OutputFileList | ArgType::OutputPrefix
+        )
+    }
 }
 
 impl Default for ArgType {
@@ -108,12 +150,18 @@ impl Param {
             | (self.param_type == ArgType::InputFileList)
             | (self.param_type == ArgType::OutputFile)
             | (self.param_type == ArgType::OutputFileList)
+            | (self.param_type == ArgType::OutputPrefix)
         {
             return true;
         } else {
             return false;
         }
     }
+
+    /// Is this parameter a file the command writes?
+    pub fn is_output_type(&self) -> bool {
+        self.param_type.is_output()
+    }
 }
 
 /// All the possible things provided in the annotation.
@@ -136,6 +184,135 @@ pub enum SizeInfo {
     Delimiter(ListSeparator),
 }
 
+/// A file a command reads even though it is not named on the command line, like the `.git`
+/// directory git reads.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct ImplicitFile {
+    /// Path of the file or directory.
+    pub path: String,
+    /// Short or long name of the option whose value the path is relative to (like `C` for
+    /// `git -C dir`). If empty, or the option is not given, it is relative to the current
+    /// directory.
+    pub relative_to: String,
+}
+
+/// Estimates of what running a command costs, which the scheduler uses instead of its defaults.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct CostModel {
+    /// Size of the output over the size of the input; the first estimate whose options were all
+    /// given applies.
+    pub selectivity: Vec<Selectivity>,
+    /// Seconds of CPU time per MB of input.
+    pub cpu_cost: Option<f64>,
+    /// Seconds it takes the command to start.
+    pub startup_cost: Option<f64>,
+}
+
+// costs are only parsed from finite numbers, so they are always equal to themselves
+impl Eq for CostModel {}
+
+impl CostModel {
+    /// Adds the estimates given in `other`, which override the costs set so far.
+    pub fn merge(&mut self, other: &CostModel) {
+        self.selectivity.extend(other.selectivity.iter().cloned());
+        if other.cpu_cost.is_some() {
+            self.cpu_cost = other.cpu_cost;
+        }
+        if other.startup_cost.is_some() {
+            self.startup_cost = other.startup_cost;
+        }
+    }
+}
+
+/// Estimated size of a command's output over the size of its input.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Selectivity {
+    pub ratio: f64,
+    /// Short or long names of the options that must be given for the estimate to apply (like
+    /// `c` for `grep -c`). If empty, it always applies.
+    pub when: Vec<String>,
+}
+
+impl Eq for Selectivity {}
+
+/// Changes to the command's options for invocations where an argument value matches a
+/// pattern, like `*.gz`.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ValueCondition {
+    /// Glob (like `*.gz`) or regex the values are matched against.
+    pub pattern: String,
+    pub is_regex: bool,
+    pub selectivity: Option<f64>,
+    pub access_type: Option<AccessType>,
+    pub splittable: Option<bool>,
+}
+
+impl Eq for ValueCondition {}
+
+impl ValueCondition {
+    /// Checks that the pattern is a valid glob or regex.
+    pub fn validate(&self) -> Result<()> {
+        match self.is_regex {
+            true => {
+                if let Err(e) = Regex::new(&self.pattern) {
+                    bail!("invalid regex {:?}: {}", self.pattern, e);
+                }
+            }
+            false => {
+                if let Err(e) = Pattern::new(&self.pattern) {
+                    bail!("invalid glob {:?}: {}", self.pattern, e.msg);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the value matches the pattern; a glob has to match all of it, a regex any part.
+    pub fn matches(&self, value: &str) -> bool {
+        match self.is_regex {
+            true => Regex::new(&self.pattern)
+                .map(|re| re.is_match(value))
+                .unwrap_or(false),
+            false => Pattern::new(&self.pattern)
+                .map(|pattern| pattern.matches(value))
+                .unwrap_or(false),
+        }
+    }
+
+    /// Adds the changes set in `other`.
+    pub fn merge(&mut self, other: &ValueCondition) {
+        if other.selectivity.is_some() {
+            self.selectivity = other.selectivity;
+        }
+        if other.access_type.is_some() {
+            self.access_type = other.access_type;
+        }
+        if other.splittable.is_some() {
+            self.splittable = other.splittable;
+        }
+    }
+
+    /// Applies the changes to the options of an invocation that matched.
+    pub fn apply(&self, options: &mut ParsingOptions) {
+        if let Some(ratio) = self.selectivity {
+            // ahead of the command's own estimates
+            options.cost.selectivity.insert(
+                0,
+                Selectivity {
+                    ratio,
+                    when: Vec::new(),
+                },
+            );
+        }
+        if let Some(access_type) = self.access_type {
+            options.access_type = access_type;
+        }
+        if let Some(splittable) = self.splittable {
+            options.splittable_across_input = splittable;
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ParsingOptions {
     /// Option to configure that long options can be parsed with a single dash.
@@ -148,6 +325,21 @@ pub struct ParsingOptions {
     pub needs_current_dir: bool,
     /// Does command read input sequentially
     pub access_type: AccessType,
+    /// Estimated selectivity and CPU costs, for the scheduler.
+    pub cost: CostModel,
+    /// If the parameters go before the options (e.g., the paths of find)
+    pub params_first: bool,
+    /// How outputs are combined when the command is split into parallel copies.
+    pub reducer: Reducer,
+    /// Files the command reads that are not named by its arguments.
+    pub implicit_inputs: Vec<ImplicitFile>,
+    /// Environment variables the command reads, which are forwarded to wherever it runs.
+    pub env_vars: Vec<String>,
+    /// Changes to these options when an argument value matches a pattern.
+    pub conditions: Vec<ValueCondition>,
+    /// Adds to the annotations for this command in earlier annotation files, instead of
+    /// replacing them.
+    pub extends: bool,
 }
 
 impl Default for ParsingOptions {
@@ -158,6 +350,13 @@ impl Default for ParsingOptions {
             reduces_input: false,
             needs_current_dir: false,
             access_type: AccessType::default(),
+            cost: CostModel::default(),
+            params_first: false,
+            reducer: Reducer::default(),
+            implicit_inputs: Vec::new(),
+            env_vars: Vec::new(),
+            conditions: Vec::new(),
+            extends: false,
         }
     }
 }
@@ -171,6 +370,22 @@ pub enum IndividualParseOption {
     ReducesInput,
     /// Relies on the current dir (implicitly)
     NeedsCurrentDir,
+    /// Seeks in its input files instead of reading them front to back.
+    RandomAccess,
+    /// Parameters go before the options.
+    ParamsFirst,
+    /// Estimates of the command's selectivity or CPU costs.
+    Cost(CostModel),
+    /// Changes to the options when an argument value matches a pattern.
+    Condition(ValueCondition),
+    /// How outputs of parallel copies are combined.
+    Reducer(Reducer),
+    /// Reads a file that is not named on the command line.
+    ImplicitInput(ImplicitFile),
+    /// Reads an environment variable.
+    Env(String),
+    /// Adds to the annotations of earlier files.
+    Extends,
 }
 
 /// An annotation is a command name and a vector of args
@@ -287,15 +502,165 @@ impl Command {
     }
 }
 
+/// Reads the annotations in a file, or in each file in a directory in name order.
+/// Files ending in .yaml or .yml use the structured format (see structured.rs); anything else is
+/// read as one annotation per line.
+/// A file can include others, by path relative to it: `include <path>` lines in the one-line
+/// format, or an `include` list in the structured format, which is read before the file's own
+/// commands. The included annotations take the place of the include.
 pub fn parse_annotation_file(file: &str) -> Result<Vec<Command>> {
+    read_annotations(Path::new(file), &mut Vec::new())
+}
+
+/// Reads annotations as layers: a file is one layer, and a directory is a layer for each file in
+/// it, in name order. Files a layer includes are part of it.
+pub fn parse_annotation_layers(path: &str) -> Result<Vec<Vec<Command>>> {
+    let path = Path::new(path);
+    if !path.is_dir() {
+        return Ok(vec![read_annotations(path, &mut Vec::new())?]);
+    }
+    let mut layers: Vec<Vec<Command>> = Vec::new();
+    for file in annotation_dir_files(path)?.iter() {
+        layers.push(read_annotations(file, &mut Vec::new())?);
+    }
+    Ok(layers)
+}
+
+/// If the line is an include directive in the one-line format, returns the included path.
+pub fn include_directive(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix("include")?;
+    match rest.starts_with(char::is_whitespace) {
+        true => Some(rest.trim()),
+        false => None,
+    }
+}
+
+/// Where a path included from `file` is: relative paths are relative to its directory.
+pub fn include_path(file: &Path, include: &str) -> PathBuf {
+    match file.parent() {
+        Some(dir) => dir.join(include),
+        None => PathBuf::from(include),
+    }
+}
+
+/// Files in an annotation directory, in name order. Hidden files, like editor swap files, are
+/// skipped.
+fn annotation_dir_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let hidden = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.starts_with('.'),
+            None => true,
+        };
+        if !hidden && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Adds every file the annotations at `path` are read from to `sources`: the files in a
+/// directory and those they include. Files that cannot be read are still added, but not followed.
+pub fn annotation_sources(path: &Path, sources: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        sources.push(path.to_path_buf());
+        for file in annotation_dir_files(path).unwrap_or_default().iter() {
+            annotation_sources(file, sources);
+        }
+        return;
+    }
+    if sources.iter().any(|source| source == path) {
+        return;
+    }
+    sources.push(path.to_path_buf());
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return,
+    };
+    let includes: Vec<String> = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml") | Some("yml") => parse_structured_includes(&contents).unwrap_or_default(),
+        _ => contents
+            .lines()
+            .filter_map(include_directive)
+            .map(String::from)
+            .collect(),
+    };
+    for include in includes.iter() {
+        annotation_sources(&include_path(path, include), sources);
+    }
+}
+
+/// Reads a file or directory of annotations, following includes.
+/// `including` holds the files being read, to catch a file that ends up including itself.
+fn read_annotations(path: &Path, including: &mut Vec<PathBuf>) -> Result<Vec<Command>> {
+    if path.is_dir() {
+        let mut ret: Vec<Command> = Vec::new();
+        for file in annotation_dir_files(path)?.iter() {
+            ret.append(&mut read_annotations(file, including)?);
+        }
+        return Ok(ret);
+    }
+    let canonical = match path.canonicalize() {
+        Ok(canonical) => canonical,
+        Err(e) => bail!("{}: {}", path.display(), e),
+    };
+    if including.contains(&canonical) {
+        bail!("{}: included by itself", path.display());
+    }
+    including.push(canonical);
+    let ret = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml") | Some("yml") => parse_structured_annotation_file(path, including),
+        _ => parse_legacy_annotation_file(path, including),
+    };
+    including.pop();
+    ret
+}
+
+fn parse_structured_annotation_file(
+    file: &Path,
+    including: &mut Vec<PathBuf>,
+) -> Result<Vec<Command>> {
+    let contents = fs::read_to_string(file)?;
+    let includes = match parse_structured_includes(&contents) {
+        Ok(includes) => includes,
+        Err(e) => bail!("{}: {}", file.display(), e),
+    };
+    let mut ret: Vec<Command> = Vec::new();
+    for include in includes.iter() {
+        ret.append(&mut read_annotations(
+            &include_path(file, include),
+            including,
+        )?);
+    }
+    match parse_structured_annotations(&contents) {
+        Ok(mut cmds) => ret.append(&mut cmds),
+        Err(e) => bail!("{}: {}", file.display(), e),
+    }
+    Ok(ret)
+}
+
+fn parse_legacy_annotation_file(file: &Path, including: &mut Vec<PathBuf>) -> Result<Vec<Command>> {
     let mut ret: Vec<Command> = Vec::new();
-    let file = File::open(file)?;
-    let reader = BufReader::new(file);
+    let reader = BufReader::new(File::open(file)?);
 
-    for line in reader.lines() {
+    for (idx, line) in reader.lines().enumerate() {
         let line_src = line?;
-        let cmd = Command::new(line_src.as_ref())?;
-        ret.push(cmd);
+        if let Some(include) = include_directive(&line_src) {
+            ret.append(&mut read_annotations(
+                &include_path(file, include),
+                including,
+            )?);
+            continue;
+        }
+        match Command::new(line_src.as_ref()) {
+            Ok(cmd) => ret.push(cmd),
+            Err(e) => match lint_line(&line_src, idx + 1).first() {
+                Some(diagnostic) => bail!("{}:{}", file.display(), diagnostic),
+                None => bail!("{}:{}: {}", file.display(), idx + 1, e),
+            },
+        }
     }
     Ok(ret)
 }
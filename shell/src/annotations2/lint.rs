@@ -0,0 +1,1187 @@
+use super::annotation_parser::parse_annotation;
+use super::cmd_parser::CmdParser;
+use super::grammar::{include_directive, include_path, Command, ValueCondition};
+use super::structured::{parse_structured_entries, parse_structured_includes};
+use dash::util::Result;
+use nom::types::CompleteByteSlice;
+use std::fmt;
+use std::fs::read_to_string;
+use std::path::Path;
+
+/// Command wide keywords, in between the [] after the command name.
+const PARSING_OPTIONS: &[&str] = &[
+    "long_arg_single_dash",
+    "splittable_across_input",
+    "reduces_input",
+    "needs_current_dir",
+    "random_access",
+    "params_first",
+    "extends",
+];
+/// What a `matching:` condition can change, besides `selectivity:<ratio>`.
+const CONDITION_CHANGES: &[&str] = &[
+    "random_access",
+    "sequential_access",
+    "splittable",
+    "not_splittable",
+];
+/// Built in ways of combining the outputs of parallel copies, after `reducer:`.
+const REDUCERS: &[&str] = &["concat", "sum", "merge", "uniq"];
+const SECTIONS: &[&str] = &["FLAGS", "OPTPARAMS", "PARAMS"];
+const TYPES: &[&str] = &[
+    "input_file",
+    "output_file",
+    "output_prefix",
+    "str",
+    "command",
+];
+
+/// Problem found in an annotation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Line in the annotation file, starting at 1.
+    pub line: usize,
+    /// Column in the line, starting at 1.
+    pub column: usize,
+    /// The text at fault.
+    pub fragment: String,
+    pub message: String,
+    /// How to fix the problem, when there is an obvious fix.
+    pub suggestion: Option<String>,
+    /// The full line, to show where the problem is.
+    source: String,
+}
+
+impl Diagnostic {
+    fn new(line: usize, column: usize, fragment: &str, message: &str, source: &str) -> Self {
+        Diagnostic {
+            line,
+            column,
+            fragment: fragment.to_string(),
+            message: message.to_string(),
+            suggestion: None,
+            source: source.to_string(),
+        }
+    }
+
+    fn suggest(mut self, suggestion: &str) -> Self {
+        self.suggestion = Some(suggestion.to_string());
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    /// Shows the problem, the line it is on with the fragment underlined, and the fix.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}:{}: {}", self.line, self.column, self.message)?;
+        writeln!(f, "    {}", self.source)?;
+        let underline = self.fragment.chars().count().max(1);
+        write!(
+            f,
+            "    {}{}",
+            " ".repeat(self.column.saturating_sub(1)),
+            "^".repeat(underline)
+        )?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n    help: {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks every annotation in the file and returns all the problems found, instead of stopping
+/// at the first one like loading the file does.
+/// Files ending in .yaml or .yml are checked as structured annotations; other files as one
+/// annotation per line.
+/// Included files are checked to exist, but are not checked themselves.
+pub fn lint_annotation_file(file: &str) -> Result<Vec<Diagnostic>> {
+    let contents = read_to_string(file)?;
+    let lines: Vec<&str> = contents.lines().collect();
+    let (mut diags, includes) = match Path::new(file).extension().and_then(|ext| ext.to_str()) {
+        Some("yaml") | Some("yml") => (
+            lint_structured(&contents),
+            parse_structured_includes(&contents).unwrap_or_default(),
+        ),
+        _ => (
+            lint_annotations(&contents),
+            lines
+                .iter()
+                .filter_map(|line| include_directive(line))
+                .map(|include| include.to_string())
+                .collect(),
+        ),
+    };
+    for include in includes.iter() {
+        if include_path(Path::new(file), include).exists() {
+            continue;
+        }
+        let lineno = lines
+            .iter()
+            .position(|line| line.contains(include.as_str()))
+            .unwrap_or(0);
+        let source = lines.get(lineno).cloned().unwrap_or("");
+        let column = source.find(include.as_str()).unwrap_or(0) + 1;
+        diags.push(
+            Diagnostic::new(
+                lineno + 1,
+                column,
+                include,
+                &format!("included file `{}` not found", include),
+                source,
+            )
+            .suggest("paths are relative to the file that includes them"),
+        );
+    }
+    diags.sort_by_key(|diag| (diag.line, diag.column));
+    Ok(diags)
+}
+
+/// Checks annotations in the one-line format.
+pub fn lint_annotations(contents: &str) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        diagnostics.append(&mut lint_line(line, idx + 1));
+    }
+    diagnostics
+}
+
+/// Checks a single annotation in the one-line format.
+/// Checks the brackets, quotes and keywords first, as the parser cannot say where those are
+/// wrong; then parses and validates the annotation.
+pub fn lint_line(line: &str, lineno: usize) -> Vec<Diagnostic> {
+    let mut diags: Vec<Diagnostic> = Vec::new();
+    if include_directive(line).is_some() {
+        return diags;
+    }
+    if line.trim().is_empty() {
+        diags.push(
+            Diagnostic::new(lineno, 1, "", "empty line", line)
+                .suggest("remove the line; annotation files have exactly one annotation per line"),
+        );
+        return diags;
+    }
+    let chars: Vec<char> = line.chars().collect();
+    let mut linter = LineLinter {
+        chars: &chars,
+        line,
+        lineno,
+        diags: &mut diags,
+    };
+    if let Some(body) = linter.lint_header() {
+        linter.lint_body(body);
+    }
+    if !diags.is_empty() {
+        return diags;
+    }
+
+    match parse_annotation(CompleteByteSlice(line.as_bytes())) {
+        Ok((rest, Ok(cmd))) => {
+            let rest = std::str::from_utf8(rest.0).unwrap_or("");
+            if !rest.trim().is_empty() {
+                let column = line.len() - rest.len() + 1;
+                diags.push(
+                    Diagnostic::new(
+                        lineno,
+                        column,
+                        rest.trim_end(),
+                        "unexpected text after the annotation, it is ignored",
+                        line,
+                    )
+                    .suggest("sections are written as SECTION:[(...),(...)] and separated by a single space"),
+                );
+            }
+            if let Some(diag) = validate(&cmd, lineno, 1, line) {
+                diags.push(diag);
+            }
+        }
+        Ok((_, Err(e))) => {
+            diags.push(Diagnostic::new(lineno, 1, line, &e.to_string(), line));
+        }
+        Err(e) => {
+            let column = match &e {
+                nom::Err::Error(nom::Context::Code(rest, _))
+                | nom::Err::Failure(nom::Context::Code(rest, _)) => line.len() - rest.0.len() + 1,
+                _ => 1,
+            };
+            let fragment: String = line[column - 1..].chars().take(20).collect();
+            diags.push(Diagnostic::new(
+                lineno,
+                column,
+                &fragment,
+                "could not parse the annotation here",
+                line,
+            ));
+        }
+    }
+    diags
+}
+
+/// Checks annotations in the structured format.
+/// Each entry is checked on its own; problems are reported on the line with the entry's name.
+pub fn lint_structured(contents: &str) -> Vec<Diagnostic> {
+    let lines: Vec<&str> = contents.lines().collect();
+    let entries = match parse_structured_entries(contents) {
+        Ok(entries) => entries,
+        Err(e) => {
+            // yaml errors end in "at line x column y"
+            let message = e.to_string();
+            let (line, column) = yaml_error_position(&message).unwrap_or((1, 1));
+            let source = lines.get(line - 1).cloned().unwrap_or("");
+            return vec![Diagnostic::new(line, column, "", &message, source)];
+        }
+    };
+    let mut diags: Vec<Diagnostic> = Vec::new();
+    for (idx, (name, entry)) in entries.iter().enumerate() {
+        let line = find_entry_line(&lines, name.as_deref(), idx);
+        let source = lines.get(line - 1).cloned().unwrap_or("");
+        let column = source.len() - source.trim_start().len() + 1;
+        let fragment = source.trim();
+        match entry {
+            Ok(cmd) => {
+                if let Some(diag) = validate(cmd, line, column, source) {
+                    diags.push(Diagnostic {
+                        fragment: fragment.to_string(),
+                        ..diag
+                    });
+                }
+            }
+            Err(e) => diags.push(Diagnostic::new(
+                line,
+                column,
+                fragment,
+                &e.to_string(),
+                source,
+            )),
+        }
+    }
+    diags
+}
+
+/// Runs the checks the parser runs when the annotation is added.
+fn validate(cmd: &Command, line: usize, column: usize, source: &str) -> Option<Diagnostic> {
+    let parser = CmdParser::new(&cmd.command_name);
+    match parser.validate(cmd) {
+        Ok(_) => None,
+        Err(e) => {
+            let message = e.to_string();
+            let diag = Diagnostic::new(line, column, &cmd.command_name, &message, source);
+            Some(match message.as_str() {
+                "Cannot have more than 1 arg with splittable turned on" => {
+                    diag.suggest("keep `splittable` on a single argument")
+                }
+                "Cannot have splittable command with size 1" => diag.suggest(
+                    "only arguments with more than one value can be split; remove `splittable`",
+                ),
+                "Cannot have multiple args with size > 1" => {
+                    diag.suggest("only one parameter can take a space separated list of values")
+                }
+                "Cannot have param with size 0" => {
+                    diag.suggest("arguments without values are FLAGS")
+                }
+                _ => diag,
+            })
+        }
+    }
+}
+
+/// Walks a line in the one-line format, keeping track of brackets and the section it is in.
+struct LineLinter<'a> {
+    chars: &'a [char],
+    line: &'a str,
+    lineno: usize,
+    diags: &'a mut Vec<Diagnostic>,
+}
+
+impl<'a> LineLinter<'a> {
+    fn push(&mut self, idx: usize, fragment: &str, message: &str, suggestion: Option<String>) {
+        let mut diag = Diagnostic::new(self.lineno, idx + 1, fragment, message, self.line);
+        diag.suggestion = suggestion;
+        self.diags.push(diag);
+    }
+
+    fn find(&self, from: usize, c: char) -> Option<usize> {
+        (from..self.chars.len()).find(|i| self.chars[*i] == c)
+    }
+
+    /// Like `find`, but skips anything in parentheses, such as a reducer command line.
+    fn find_outside_parens(&self, from: usize, c: char) -> Option<usize> {
+        let mut depth = 0;
+        for i in from..self.chars.len() {
+            match self.chars[i] {
+                '(' => depth += 1,
+                ')' if depth > 0 => depth -= 1,
+                ch if ch == c && depth == 0 => return Some(i),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn word_end(&self, start: usize) -> usize {
+        let mut end = start;
+        while end < self.chars.len()
+            && (self.chars[end].is_alphanumeric() || self.chars[end] == '_')
+        {
+            end += 1;
+        }
+        end
+    }
+
+    /// Checks `name[options]:` and returns where the arguments start.
+    fn lint_header(&mut self) -> Option<usize> {
+        let mut i = 0;
+        while i < self.chars.len() && self.chars[i] != '[' && self.chars[i] != ':' {
+            let c = self.chars[i];
+            if !(c.is_alphabetic() || c == ' ' || c == '/' || c == '-') {
+                self.push(
+                    i,
+                    &c.to_string(),
+                    "command names can only contain letters, spaces, `/` and `-`",
+                    None,
+                );
+                return None;
+            }
+            i += 1;
+        }
+        if i == 0 || self.chars[..i].iter().all(|c| c.is_whitespace()) {
+            self.push(0, "", "missing command name", None);
+            return None;
+        }
+        if i == self.chars.len() {
+            let name: String = self.chars.iter().collect();
+            self.push(
+                0,
+                &name,
+                "missing `:` after the command name",
+                Some(format!(
+                    "write `{}: FLAGS:[...] OPTPARAMS:[...] PARAMS:[...]`",
+                    name.trim()
+                )),
+            );
+            return None;
+        }
+        if self.chars[i] == '[' {
+            let close = match self.find_outside_parens(i, ']') {
+                Some(close) => close,
+                None => {
+                    self.push(
+                        i,
+                        "[",
+                        "unclosed `[`",
+                        Some("add a matching `]`".to_string()),
+                    );
+                    return None;
+                }
+            };
+            let options: String = self.chars[i + 1..close].iter().collect();
+            let mut start = i + 1;
+            for option in split_outside_parens(&options) {
+                if let Some(reducer) = option.strip_prefix("reducer:") {
+                    let custom = reducer.starts_with('(') && reducer.ends_with(')');
+                    if !(REDUCERS.contains(&reducer) || (custom && reducer.len() > 2)) {
+                        let suggestion = match custom {
+                            true => "write the command line between the parentheses".to_string(),
+                            false => format!(
+                                "{}; a command line can also be given, like `reducer:(awk -f sum.awk)`",
+                                unknown_suggestion(reducer, REDUCERS)
+                            ),
+                        };
+                        self.push(
+                            start + "reducer:".len(),
+                            reducer,
+                            &format!("unknown reducer `{}`", reducer),
+                            Some(suggestion),
+                        );
+                    }
+                } else if let Some(input) = option.strip_prefix("implicit_input:") {
+                    if !(input.starts_with('(') && input.ends_with(')') && input.len() > 2) {
+                        self.push(
+                            start + "implicit_input:".len(),
+                            input,
+                            &format!("implicit input `{}` is not in parentheses", input),
+                            Some(
+                                "write it like `implicit_input:(.git)` or `implicit_input:(.git,relative_to:C)`"
+                                    .to_string(),
+                            ),
+                        );
+                    }
+                } else if let Some(ratio) = option.strip_prefix("selectivity:") {
+                    let valid = match ratio
+                        .strip_prefix('(')
+                        .and_then(|spec| spec.strip_suffix(')'))
+                    {
+                        Some(spec) => {
+                            let mut parts = spec.split(",when:");
+                            is_cost(parts.next().unwrap_or(""))
+                                && spec.contains(",when:")
+                                && parts.all(|name| !name.is_empty())
+                        }
+                        None => is_cost(ratio),
+                    };
+                    if !valid {
+                        self.push(
+                            start + "selectivity:".len(),
+                            ratio,
+                            &format!("invalid selectivity `{}`", ratio),
+                            Some(
+                                "write a ratio like `selectivity:0.1`, or `selectivity:(0.001,when:c)` for when an option is given"
+                                    .to_string(),
+                            ),
+                        );
+                    }
+                } else if let Some((key, cost)) = ["cpu_cost:", "startup_cost:"]
+                    .iter()
+                    .find_map(|key| option.strip_prefix(key).map(|cost| (key, cost)))
+                {
+                    if !is_cost(cost) {
+                        self.push(
+                            start + key.len(),
+                            cost,
+                            &format!("invalid cost `{}`", cost),
+                            Some(format!("write a number of seconds, like `{}0.5`", key)),
+                        );
+                    }
+                } else if let Some(condition) = option.strip_prefix("matching:") {
+                    if let Some(problem) = condition_problem(condition) {
+                        self.push(
+                            start + "matching:".len(),
+                            condition,
+                            &problem,
+                            Some(
+                                "write it like `matching:(*.gz,random_access)` or `matching:(/\\.parquet$/,not_splittable,selectivity:0.1)`"
+                                    .to_string(),
+                            ),
+                        );
+                    }
+                } else if let Some(name) = option.strip_prefix("env:") {
+                    if name.is_empty()
+                        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    {
+                        self.push(
+                            start + "env:".len(),
+                            name,
+                            &format!("invalid environment variable name `{}`", name),
+                            Some("use letters, digits and `_`, like `env:LC_ALL`".to_string()),
+                        );
+                    }
+                } else if !option.is_empty() && !PARSING_OPTIONS.contains(&option) {
+                    let suggestion = unknown_suggestion(option, PARSING_OPTIONS);
+                    self.push(
+                        start,
+                        option,
+                        &format!("unknown command option `{}`", option),
+                        Some(suggestion),
+                    );
+                }
+                start += option.chars().count() + 1;
+            }
+            i = close + 1;
+            if self.chars.get(i) != Some(&':') {
+                self.push(
+                    i,
+                    "",
+                    "expected `:` after the command options",
+                    Some("add `:` after the `]`".to_string()),
+                );
+                return None;
+            }
+        }
+        Some(i + 1)
+    }
+
+    /// Checks the brackets, quotes, section names and argument keywords.
+    fn lint_body(&mut self, start: usize) {
+        let mut stack: Vec<(char, usize)> = Vec::new();
+        let mut section: Option<String> = None;
+        let mut i = start;
+        while i < self.chars.len() {
+            let c = self.chars[i];
+            match c {
+                '"' => match self.find(i + 1, '"') {
+                    Some(end) => {
+                        i = end + 1;
+                        continue;
+                    }
+                    None => {
+                        let suggestion = match i > 0 && self.chars[i - 1] == '"' {
+                            true => "remove the extra `\"`",
+                            false => "close the quote with a matching `\"`",
+                        };
+                        self.push(i, "\"", "unbalanced `\"`", Some(suggestion.to_string()));
+                        // cannot tell where the rest of the line is quoted
+                        return;
+                    }
+                },
+                '(' | '[' => stack.push((c, i)),
+                ')' | ']' => {
+                    let open = match c {
+                        ')' => '(',
+                        _ => '[',
+                    };
+                    match stack.iter().rposition(|(o, _)| *o == open) {
+                        Some(pos) => {
+                            for (unclosed, idx) in stack.split_off(pos + 1) {
+                                self.unclosed(unclosed, idx, Some(i));
+                            }
+                            stack.pop();
+                            if stack.is_empty() {
+                                section = None;
+                            }
+                        }
+                        None => self.push(
+                            i,
+                            &c.to_string(),
+                            &format!("unmatched `{}`", c),
+                            Some(format!("remove the `{}`", c)),
+                        ),
+                    }
+                }
+                c if c.is_alphanumeric() || c == '_' => {
+                    let end = self.word_end(i);
+                    let word: String = self.chars[i..end].iter().collect();
+                    let prev = match i {
+                        0 => None,
+                        _ => Some(self.chars[i - 1]),
+                    };
+                    let next = self.chars.get(end).cloned();
+                    if next == Some(':') && stack.is_empty() {
+                        self.lint_section(i, &word);
+                        section = Some(word);
+                        i = end + 1;
+                        continue;
+                    } else if next == Some(':') {
+                        match self.lint_key(i, &word, end + 1, section.as_deref()) {
+                            Some(next) => i = next,
+                            None => return,
+                        }
+                        continue;
+                    } else if !stack.is_empty()
+                        && (prev == Some('(') || prev == Some(','))
+                        && (next == Some(',') || next == Some(')'))
+                    {
+                        self.lint_keyword(i, &word, section.as_deref());
+                    }
+                    i = end;
+                    continue;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        for (unclosed, idx) in stack {
+            self.unclosed(unclosed, idx, None);
+        }
+    }
+
+    fn unclosed(&mut self, open: char, idx: usize, before: Option<usize>) {
+        let close = match open {
+            '(' => ')',
+            _ => ']',
+        };
+        let suggestion = match before {
+            Some(before) => format!("add a matching `{}` before column {}", close, before + 1),
+            None => format!("add a matching `{}`", close),
+        };
+        self.push(
+            idx,
+            &open.to_string(),
+            &format!("unclosed `{}`", open),
+            Some(suggestion),
+        );
+    }
+
+    fn lint_section(&mut self, idx: usize, word: &str) {
+        if !SECTIONS.contains(&word) {
+            let suggestion = unknown_suggestion(word, SECTIONS);
+            self.push(
+                idx,
+                word,
+                &format!("unknown section `{}`", word),
+                Some(suggestion),
+            );
+            return;
+        }
+        let bracket = idx + word.chars().count() + 1;
+        if self.chars.get(bracket) != Some(&'[') {
+            self.push(
+                bracket,
+                "",
+                &format!("expected `[` after `{}:`", word),
+                Some(format!("write `{}:[(...),(...)]`", word)),
+            );
+        }
+    }
+
+    /// Checks a `key:value` inside an argument and returns where to continue from, or None if
+    /// the rest of the line cannot be checked.
+    fn lint_key(
+        &mut self,
+        idx: usize,
+        key: &str,
+        value_idx: usize,
+        section: Option<&str>,
+    ) -> Option<usize> {
+        if !allowed_keys(section).contains(&key) {
+            self.not_allowed(idx, key, &format!("{}:", key), section);
+        }
+        let value: String = self.chars[value_idx..].iter().collect();
+        match key {
+            // free text in between parentheses
+            "desc" | "list_separator" => {
+                if !value.starts_with('(') {
+                    self.push(
+                        value_idx,
+                        "",
+                        &format!("expected `(` after `{}:`", key),
+                        Some(format!("write `{}:(...)`", key)),
+                    );
+                    return Some(value_idx);
+                }
+                match self.find(value_idx + 1, ')') {
+                    Some(close) => {
+                        if key == "list_separator" {
+                            let sep: String = self.chars[value_idx + 1..close].iter().collect();
+                            if sep != " " && sep != "," {
+                                self.push(
+                                    value_idx + 1,
+                                    &sep,
+                                    "list separators can only be a space or a comma",
+                                    Some(
+                                        "write `list_separator:( )` or `list_separator:(,)`"
+                                            .to_string(),
+                                    ),
+                                );
+                            }
+                        }
+                        return Some(close + 1);
+                    }
+                    None => {
+                        self.unclosed('(', value_idx, None);
+                        return None;
+                    }
+                }
+            }
+            "type" => {
+                let end = self.word_end(value_idx);
+                let ty: String = self.chars[value_idx..end].iter().collect();
+                if !TYPES.contains(&ty.as_str()) {
+                    let suggestion = unknown_suggestion(&ty, TYPES);
+                    self.push(
+                        value_idx,
+                        &ty,
+                        &format!("unknown type `{}`", ty),
+                        Some(suggestion),
+                    );
+                }
+                return Some(end);
+            }
+            "size" => {
+                let end = self.word_end(value_idx);
+                let size: String = self.chars[value_idx..end].iter().collect();
+                let next = self.chars.get(end);
+                let ok = match size.as_str() {
+                    "0" | "1" => true,
+                    "list" | "specific_size" => next == Some(&'('),
+                    _ => false,
+                };
+                if !ok {
+                    let suggestion = match size.parse::<u64>() {
+                        Ok(n) => format!(
+                            "write `size:specific_size(size:{},list_separator:(,))`",
+                            n
+                        ),
+                        Err(_) => "sizes are `0`, `1`, `list(list_separator:( ))` or `specific_size(size:n,list_separator:(,))`".to_string(),
+                    };
+                    self.push(
+                        value_idx,
+                        &size,
+                        &format!("unknown size `{}`", size),
+                        Some(suggestion),
+                    );
+                }
+                return Some(end);
+            }
+            "default_value" if !value.starts_with('"') => {
+                let end = value.find([',', ')']).unwrap_or(value.len());
+                self.push(
+                    value_idx,
+                    &value[..end],
+                    "default values must be quoted",
+                    Some(format!("write `default_value:\"{}\"`", &value[..end])),
+                );
+            }
+            _ => {}
+        }
+        Some(value_idx)
+    }
+
+    /// Checks keywords that stand on their own in an argument, like `splittable`.
+    fn lint_keyword(&mut self, idx: usize, word: &str, section: Option<&str>) {
+        if !allowed_keywords(section).contains(&word) {
+            self.not_allowed(idx, word, word, section);
+        }
+    }
+
+    fn not_allowed(&mut self, idx: usize, word: &str, fragment: &str, section: Option<&str>) {
+        let known = SECTIONS
+            .iter()
+            .map(|section| Some(*section))
+            .any(|s| allowed_keys(s).contains(&word) || allowed_keywords(s).contains(&word));
+        match (known, section) {
+            (true, Some(section)) => self.push(
+                idx,
+                fragment,
+                &format!("`{}` is not allowed in {}", word, section),
+                Some(format!(
+                    "{} arguments take {}",
+                    section,
+                    join_names(
+                        &[allowed_keys(Some(section)), allowed_keywords(Some(section))].concat()
+                    )
+                )),
+            ),
+            _ => {
+                let candidates = [allowed_keys(section), allowed_keywords(section)].concat();
+                let suggestion = unknown_suggestion(word, &candidates);
+                self.push(
+                    idx,
+                    fragment,
+                    &format!("unknown argument keyword `{}`", word),
+                    Some(suggestion),
+                );
+            }
+        }
+    }
+}
+
+/// `key:value` pairs each section allows in its arguments.
+fn allowed_keys(section: Option<&str>) -> Vec<&'static str> {
+    match section {
+        Some("FLAGS") => vec!["short", "long", "desc"],
+        Some("OPTPARAMS") => vec![
+            "short",
+            "long",
+            "desc",
+            "type",
+            "size",
+            "default_value",
+            "list_separator",
+        ],
+        Some("PARAMS") => vec!["type", "size", "default_value", "list_separator"],
+        _ => vec![],
+    }
+}
+
+/// Keywords each section allows on their own in its arguments.
+fn allowed_keywords(section: Option<&str>) -> Vec<&'static str> {
+    match section {
+        Some("FLAGS") => vec!["multiple"],
+        Some("OPTPARAMS") => vec!["multiple", "splittable", "attached"],
+        Some("PARAMS") => vec!["multiple", "splittable"],
+        _ => vec![],
+    }
+}
+
+fn join_names(names: &[&str]) -> String {
+    names
+        .iter()
+        .map(|name| format!("`{}`", name))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Suggests the closest known name for a misspelled one, or lists the known names.
+/// Splits the command options on commas that are not inside parentheses.
+fn split_outside_parens(options: &str) -> Vec<&str> {
+    let mut ret: Vec<&str> = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in options.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            ',' if depth == 0 => {
+                ret.push(&options[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    ret.push(&options[start..]);
+    ret
+}
+
+/// What is wrong with the `(<pattern>,<change>...)` of a `matching:` option, if anything.
+fn condition_problem(condition: &str) -> Option<String> {
+    let spec = match condition
+        .strip_prefix('(')
+        .and_then(|spec| spec.strip_suffix(')'))
+    {
+        Some(spec) => spec,
+        None => return Some(format!("condition `{}` is not in parentheses", condition)),
+    };
+    // a regex is between slashes, a glob runs up to the first comma
+    let (pattern, is_regex, changes) = match spec.strip_prefix('/') {
+        Some(rest) => match rest.find('/') {
+            Some(end) => (&rest[..end], true, &rest[end + 1..]),
+            None => return Some("unclosed `/` around the regex".to_string()),
+        },
+        None => match spec.find(',') {
+            Some(end) => (&spec[..end], false, &spec[end..]),
+            None => (spec, false, ""),
+        },
+    };
+    let parsed = ValueCondition {
+        pattern: pattern.to_string(),
+        is_regex,
+        ..Default::default()
+    };
+    if let Err(e) = parsed.validate() {
+        return Some(e.to_string());
+    }
+    let changes: Vec<&str> = match changes.strip_prefix(',') {
+        Some(changes) => changes.split(',').collect(),
+        None if changes.is_empty() => Vec::new(),
+        None => {
+            return Some(format!(
+                "expected `,` after the pattern, found `{}`",
+                changes
+            ))
+        }
+    };
+    if changes.is_empty() {
+        return Some("condition changes nothing".to_string());
+    }
+    for change in changes.iter() {
+        let valid = match change.strip_prefix("selectivity:") {
+            Some(ratio) => is_cost(ratio),
+            None => CONDITION_CHANGES.contains(change),
+        };
+        if !valid {
+            return Some(format!("unknown change `{}`", change));
+        }
+    }
+    None
+}
+
+/// Costs and ratios are numbers that are not negative, like `0.5`.
+fn is_cost(value: &str) -> bool {
+    !value.is_empty()
+        && value.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && value.parse::<f64>().is_ok()
+}
+
+fn unknown_suggestion(word: &str, candidates: &[&str]) -> String {
+    match closest(word, candidates) {
+        Some(name) => format!("did you mean `{}`?", name),
+        None => format!("expected one of {}", join_names(candidates)),
+    }
+}
+
+fn closest<'a>(word: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let word = word.to_lowercase();
+    let mut best: Option<(usize, &'a str)> = None;
+    for candidate in candidates.iter() {
+        let lower = candidate.to_lowercase();
+        let distance = match word.len() >= 3 && lower.starts_with(&word) {
+            true => 0,
+            false => edit_distance(&word, &lower),
+        };
+        if distance <= (candidate.len() / 3).max(1) {
+            match best {
+                Some((best_distance, _)) if best_distance <= distance => {}
+                _ => best = Some((distance, candidate)),
+            }
+        }
+    }
+    best.map(|(_, name)| name)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == *cb { 0 } else { 1 };
+            cur.push(substitution.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Finds the line of the idx-th entry of a structured file, by its name if it has one.
+fn find_entry_line(lines: &[&str], name: Option<&str>, idx: usize) -> usize {
+    if let Some(name) = name {
+        let found = lines.iter().position(|line| {
+            let line = line.trim_start_matches([' ', '-', '{']);
+            line.starts_with("name:")
+                && line["name:".len()..]
+                    .trim_start()
+                    .trim_matches(['"', '\''])
+                    .starts_with(name)
+        });
+        if let Some(found) = found {
+            return found + 1;
+        }
+    }
+    // otherwise the idx-th list item in the file
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.trim_start().starts_with("- "))
+        .nth(idx)
+        .map(|(found, _)| found + 1)
+        .unwrap_or(1)
+}
+
+fn yaml_error_position(message: &str) -> Option<(usize, usize)> {
+    let idx = message.rfind("at line ")?;
+    let mut words = message[idx..].split_whitespace();
+    let line = words.nth(2)?.parse::<usize>().ok()?;
+    let column = words.nth(1)?.parse::<usize>().ok()?;
+    Some((line, column + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::grammar::parse_annotation_file;
+    use super::*;
+
+    #[test]
+    fn test_lint_stray_paren_and_quote() {
+        let contents = read_to_string("../config/ann2.txt").unwrap();
+        let diags = lint_annotations(&contents);
+        let found: Vec<(usize, usize, &str)> = diags
+            .iter()
+            .map(|d| (d.line, d.column, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (4, 32, "unmatched `)`"),
+                (4, 105, "unknown argument keyword `default`"),
+                (4, 118, "unbalanced `\"`"),
+                (5, 32, "unmatched `)`"),
+                (6, 1, "empty line"),
+            ]
+        );
+        assert_eq!(diags[0].fragment, ")");
+        assert_eq!(diags[0].suggestion, Some("remove the `)`".to_string()));
+        assert_eq!(
+            diags[1].suggestion,
+            Some("did you mean `default_value`?".to_string())
+        );
+        assert_eq!(
+            diags[2].suggestion,
+            Some("remove the extra `\"`".to_string())
+        );
+    }
+
+    #[test]
+    fn test_lint_keywords() {
+        let check = |line: &str| -> Vec<(usize, String, Option<String>)> {
+            lint_line(line, 1)
+                .into_iter()
+                .map(|d| (d.column, d.message, d.suggestion))
+                .collect()
+        };
+        assert!(check(
+            "git status[needs_current_dir]: OPTPARAMS:[(short:C,size:1,type:input_file)]"
+        )
+        .is_empty());
+        assert_eq!(
+            check("cat[reduce_input]: PARAMS:[(type:input_file,size:1)]"),
+            vec![(
+                5,
+                "unknown command option `reduce_input`".to_string(),
+                Some("did you mean `reduces_input`?".to_string())
+            )]
+        );
+        assert!(check("sort[reducer:merge]: FLAGS:[(short:n)]").is_empty());
+        assert!(check(
+            "tally[reducer:(awk -F, '{ a[$1] += $2 }'),reduces_input]: FLAGS:[(short:l)]"
+        )
+        .is_empty());
+        assert_eq!(
+            check("wc[reducer:summ]: FLAGS:[(short:l)]"),
+            vec![(
+                12,
+                "unknown reducer `summ`".to_string(),
+                Some(
+                    "did you mean `sum`?; a command line can also be given, like `reducer:(awk -f sum.awk)`"
+                        .to_string()
+                )
+            )]
+        );
+        assert!(check(
+            "git status[implicit_input:(.git,relative_to:C),env:GIT_DIR]: OPTPARAMS:[(short:C,size:1,type:input_file)]"
+        )
+        .is_empty());
+        assert_eq!(
+            check("git[implicit_input:.git,env:GIT-DIR]: FLAGS:[(short:p)]"),
+            vec![
+                (
+                    20,
+                    "implicit input `.git` is not in parentheses".to_string(),
+                    Some(
+                        "write it like `implicit_input:(.git)` or `implicit_input:(.git,relative_to:C)`"
+                            .to_string()
+                    )
+                ),
+                (
+                    29,
+                    "invalid environment variable name `GIT-DIR`".to_string(),
+                    Some("use letters, digits and `_`, like `env:LC_ALL`".to_string())
+                )
+            ]
+        );
+        assert!(check(
+            "grep[selectivity:(0.001,when:c),selectivity:0.1,cpu_cost:0.004,startup_cost:1]: FLAGS:[(short:c)]"
+        )
+        .is_empty());
+        assert!(check(
+            r"zcat[matching:(*.gz,random_access,selectivity:4),matching:(/\.(parquet|orc)$/,not_splittable)]: FLAGS:[(short:c)]"
+        )
+        .is_empty());
+        let problems: Vec<String> = check(
+            "zcat[matching:(*.gz),matching:(/[/,random_access),matching:(*.gz,random)]: FLAGS:[(short:c)]",
+        )
+        .into_iter()
+        .map(|(_, problem, _)| problem)
+        .collect();
+        assert_eq!(problems.len(), 3);
+        assert_eq!(problems[0], "condition changes nothing");
+        assert!(problems[1].starts_with("invalid regex"));
+        assert_eq!(problems[2], "unknown change `random`");
+        assert_eq!(
+            check("gzip[selectivity:(0.3),cpu_cost:fast]: FLAGS:[(short:c)]"),
+            vec![
+                (
+                    18,
+                    "invalid selectivity `(0.3)`".to_string(),
+                    Some(
+                        "write a ratio like `selectivity:0.1`, or `selectivity:(0.001,when:c)` for when an option is given"
+                            .to_string()
+                    )
+                ),
+                (
+                    33,
+                    "invalid cost `fast`".to_string(),
+                    Some("write a number of seconds, like `cpu_cost:0.5`".to_string())
+                )
+            ]
+        );
+        assert_eq!(
+            check("cat: PARAM:[(typ:input_file,size:3)]")[0],
+            (
+                6,
+                "unknown section `PARAM`".to_string(),
+                Some("did you mean `PARAMS`?".to_string())
+            )
+        );
+        assert_eq!(
+            check("cat: PARAMS:[(type:input_fle,size:3)]"),
+            vec![
+                (
+                    20,
+                    "unknown type `input_fle`".to_string(),
+                    Some("did you mean `input_file`?".to_string())
+                ),
+                (
+                    35,
+                    "unknown size `3`".to_string(),
+                    Some("write `size:specific_size(size:3,list_separator:(,))`".to_string())
+                ),
+            ]
+        );
+        assert_eq!(
+            check("wc: FLAGS:[(short:l,type:str)]")[0].1,
+            "`type` is not allowed in FLAGS"
+        );
+        assert_eq!(
+            check("cat: PARAMS:[(type:input_file,size:1)]  FLAGS:[(short:l)]")[0].1,
+            "unexpected text after the annotation, it is ignored"
+        );
+        assert_eq!(
+            check("cat: PARAMS:[(type:input_file,size:1,splittable)]")[0],
+            (
+                1,
+                "Cannot have splittable command with size 1".to_string(),
+                Some(
+                    "only arguments with more than one value can be split; remove `splittable`"
+                        .to_string()
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn test_lint_non_ascii() {
+        let check = |line: &str| -> Vec<(usize, String)> {
+            lint_line(line, 1)
+                .into_iter()
+                .map(|d| (d.column, d.message))
+                .collect()
+        };
+        // the name itself is checked by the annotation parser
+        assert_eq!(
+            check("aé: FLAGS:[(short:l)]"),
+            vec![(2, "could not parse the annotation here".to_string())]
+        );
+        assert_eq!(
+            check("é ü: FLÄGS:[(short:l)]")[0],
+            (6, "unknown section `FLÄGS`".to_string())
+        );
+        assert_eq!(
+            check("ü€: FLAGS:[(short:l)]"),
+            vec![(
+                2,
+                "command names can only contain letters, spaces, `/` and `-`".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_load_error() {
+        let err = parse_annotation_file("../config/ann2.txt")
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("../config/ann2.txt:4:32: unmatched `)`"));
+        assert!(err.ends_with("help: remove the `)`"));
+    }
+
+    #[test]
+    fn test_lint_structured() {
+        let contents = "commands:\n  - name: cat\n    params:\n      - {type: input_file, size: list, splittable: true}\n      - {type: input_file, size: list, list_separator: space, splittable: true}\n  - name: wc\n    flags:\n      - {shrt: l}\n";
+        let diags = lint_structured(contents);
+        assert_eq!(diags.len(), 2);
+        assert_eq!((diags[0].line, diags[0].column), (2, 3));
+        assert_eq!(
+            diags[0].message,
+            "Cannot have more than 1 arg with splittable turned on"
+        );
+        assert_eq!((diags[1].line, diags[1].column), (6, 3));
+        assert!(diags[1].message.contains("flags[0]: unknown key \"shrt\""));
+        let diags = lint_structured("commands:\n  - name: [cat\n");
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].line, 3);
+    }
+
+    #[test]
+    fn test_lint_includes() {
+        let file =
+            std::env::temp_dir().join(format!("posh_lint_include_{}.txt", std::process::id()));
+        std::fs::write(
+            &file,
+            "include missing.txt\ncat: PARAMS:[(type:input_file,size:1)]\n",
+        )
+        .unwrap();
+        let diags = lint_annotation_file(&file.display().to_string()).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(diags.len(), 1);
+        assert_eq!((diags[0].line, diags[0].column), (1, 9));
+        assert_eq!(diags[0].message, "included file `missing.txt` not found");
+    }
+}
@@ -0,0 +1,384 @@
+//! Converts annotation files written for the older `annotations` module into this module's
+//! one-line format.
+//! The two formats look alike, but the old parser stops at the first thing it does not know and
+//! ignores the rest of the line, and accepts annotations this module rejects. Each line is read
+//! the way the old parser reads it, written out again, and checked the way this module checks
+//! annotations when it loads them, so what is left out or changed can be reported.
+use super::cmd_parser::CmdParser;
+use super::grammar::{ArgType, Argument, Command, ListSeparator, Opt, Param, ParamSize};
+use crate::annotations::annotation_parser::parse_annotation as parse_legacy_annotation;
+use crate::annotations::grammar as legacy;
+use dash::util::Result;
+use failure::bail;
+use nom::types::CompleteByteSlice;
+use std::fs::read_to_string;
+
+/// Something about a legacy annotation that could not be carried over.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationProblem {
+    /// Line in the legacy file, starting at 1.
+    pub line: usize,
+    pub message: String,
+    /// Was the annotation still converted, without the part the message is about?
+    pub converted: bool,
+}
+
+/// The converted annotations, one per line, and what could not be converted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Migration {
+    pub annotations: Vec<String>,
+    pub problems: Vec<MigrationProblem>,
+}
+
+/// Converts every annotation in a legacy annotation file.
+pub fn migrate_annotation_file(file: &str) -> Result<Migration> {
+    Ok(migrate_annotations(&read_to_string(file)?))
+}
+
+/// Converts the annotations in the contents of a legacy annotation file.
+/// Blank lines are skipped; the old parser rejected them, so they cannot have held annotations.
+pub fn migrate_annotations(contents: &str) -> Migration {
+    let mut migration = Migration::default();
+    for (idx, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match migrate_line(line) {
+            Ok((annotation, warnings)) => {
+                migration.annotations.push(annotation);
+                migration
+                    .problems
+                    .extend(warnings.into_iter().map(|message| MigrationProblem {
+                        line: idx + 1,
+                        message,
+                        converted: true,
+                    }));
+            }
+            Err(e) => migration.problems.push(MigrationProblem {
+                line: idx + 1,
+                message: e.to_string(),
+                converted: false,
+            }),
+        }
+    }
+    migration
+}
+
+/// Converts a single legacy annotation.
+/// Returns the converted annotation, with warnings about what the old parser ignored and so was
+/// left out; or an error if there is nothing equivalent this module would load.
+pub fn migrate_line(line: &str) -> Result<(String, Vec<String>)> {
+    if let Some(separator) = unsupported_separator(line) {
+        bail!(
+            "list separator `{}` is not supported, only a space or a comma",
+            separator
+        );
+    }
+    let (legacy_cmd, rest) = match parse_legacy_annotation(CompleteByteSlice(line.as_bytes())) {
+        Ok((rest, Ok(cmd))) => (cmd, std::str::from_utf8(rest.0).unwrap_or("").trim()),
+        Ok((_, Err(e))) => bail!("the old parser rejects this annotation: {}", e),
+        Err(nom::Err::Error(nom::Context::Code(rest, _)))
+        | Err(nom::Err::Failure(nom::Context::Code(rest, _))) => bail!(
+            "the old parser rejects this annotation, at `{}`",
+            std::str::from_utf8(rest.0).unwrap_or("").trim()
+        ),
+        Err(_) => bail!("the old parser rejects this annotation"),
+    };
+    let mut warnings: Vec<String> = Vec::new();
+    if !rest.is_empty() {
+        warnings.push(format!(
+            "`{}` was ignored by the old parser and is left out",
+            rest
+        ));
+    }
+    let mut cmd = convert_command(&legacy_cmd);
+    // Written out a section at a time; only the order of the params matters.
+    cmd.args.sort_by_key(|arg| match arg {
+        Argument::LoneOption(_) => 0,
+        Argument::OptWithParam(_, _) => 1,
+        Argument::LoneParam(_) => 2,
+    });
+    let annotation = render_annotation(&cmd);
+    match Command::new(&annotation) {
+        Ok(ref read_back) if read_back == &cmd => {}
+        _ => bail!("cannot be written in the new format: `{}`", annotation),
+    }
+    if let Err(e) = CmdParser::new(&cmd.command_name).validate(&cmd) {
+        bail!("the converted annotation would not load: {}", e);
+    }
+    Ok((annotation, warnings))
+}
+
+/// The old parser panics on list separators other than a space or a comma.
+fn unsupported_separator(line: &str) -> Option<&str> {
+    let tag = "list_separator:(";
+    let mut rest = line;
+    while let Some(start) = rest.find(tag) {
+        rest = &rest[start + tag.len()..];
+        let end = rest.find(')').unwrap_or(rest.len());
+        let separator = &rest[..end];
+        if separator != " " && separator != "," {
+            return Some(separator);
+        }
+    }
+    None
+}
+
+fn convert_command(cmd: &legacy::Command) -> Command {
+    let mut converted = Command {
+        command_name: cmd.command_name.clone(),
+        args: cmd.args.iter().map(convert_argument).collect(),
+        ..Default::default()
+    };
+    let options = &mut converted.parsing_options;
+    options.long_arg_single_dash = cmd.parsing_options.long_arg_single_dash;
+    options.splittable_across_input = cmd.parsing_options.splittable_across_input;
+    options.reduces_input = cmd.parsing_options.reduces_input;
+    options.needs_current_dir = cmd.parsing_options.needs_current_dir;
+    converted
+}
+
+fn convert_argument(arg: &legacy::Argument) -> Argument {
+    match arg {
+        legacy::Argument::LoneOption(opt) => Argument::LoneOption(convert_opt(opt)),
+        legacy::Argument::OptWithParam(opt, param) => {
+            Argument::OptWithParam(convert_opt(opt), convert_param(param))
+        }
+        legacy::Argument::LoneParam(param) => Argument::LoneParam(convert_param(param)),
+    }
+}
+
+fn convert_opt(opt: &legacy::Opt) -> Opt {
+    Opt {
+        short: opt.short.clone(),
+        long: opt.long.clone(),
+        desc: opt.desc.clone(),
+        multiple: opt.multiple,
+    }
+}
+
+fn convert_param(param: &legacy::Param) -> Param {
+    Param {
+        param_type: match param.param_type {
+            legacy::ArgType::InputFile => ArgType::InputFile,
+            legacy::ArgType::OutputFile => ArgType::OutputFile,
+            legacy::ArgType::Str => ArgType::Str,
+            legacy::ArgType::InputFileList => ArgType::InputFileList,
+            legacy::ArgType::OutputFileList => ArgType::OutputFileList,
+        },
+        size: match param.size {
+            legacy::ParamSize::Zero => ParamSize::Zero,
+            legacy::ParamSize::One => ParamSize::One,
+            legacy::ParamSize::SpecificSize(size, separator) => {
+                ParamSize::SpecificSize(size, convert_separator(separator))
+            }
+            legacy::ParamSize::List(separator) => ParamSize::List(convert_separator(separator)),
+        },
+        default_value: param.default_value.clone(),
+        multiple: param.multiple,
+        splittable: param.splittable,
+        attached_to_short: param.attached_to_short,
+    }
+}
+
+fn convert_separator(separator: legacy::ListSeparator) -> ListSeparator {
+    match separator {
+        legacy::ListSeparator::Space => ListSeparator::Space,
+        legacy::ListSeparator::Comma => ListSeparator::Comma,
+    }
+}
+
+/// Writes an annotation in the one-line format.
+/// Only what the legacy format could express is written: the command wide keywords it had, and
+/// the flags, options and params.
+pub fn render_annotation(cmd: &Command) -> String {
+    let options = &cmd.parsing_options;
+    let keywords: Vec<&str> = vec![
+        (options.long_arg_single_dash, "long_arg_single_dash"),
+        (options.splittable_across_input, "splittable_across_input"),
+        (options.reduces_input, "reduces_input"),
+        (options.needs_current_dir, "needs_current_dir"),
+    ]
+    .into_iter()
+    .filter(|(set, _)| *set)
+    .map(|(_, keyword)| keyword)
+    .collect();
+    let mut flags: Vec<String> = Vec::new();
+    let mut optparams: Vec<String> = Vec::new();
+    let mut params: Vec<String> = Vec::new();
+    for arg in cmd.args.iter() {
+        match arg {
+            Argument::LoneOption(opt) => flags.push(render_fields(opt_fields(opt))),
+            Argument::OptWithParam(opt, param) => {
+                let mut fields = opt_fields(opt);
+                fields.append(&mut param_fields(param));
+                optparams.push(render_fields(fields));
+            }
+            Argument::LoneParam(param) => params.push(render_fields(param_fields(param))),
+        }
+    }
+    let mut out = cmd.command_name.clone();
+    if !keywords.is_empty() {
+        out.push_str(&format!("[{}]", keywords.join(",")));
+    }
+    out.push(':');
+    for (section, entries) in [
+        ("FLAGS", flags),
+        ("OPTPARAMS", optparams),
+        ("PARAMS", params),
+    ]
+    .iter()
+    {
+        if !entries.is_empty() {
+            out.push_str(&format!(" {}:[{}]", section, entries.join(",")));
+        }
+    }
+    out
+}
+
+fn render_fields(fields: Vec<String>) -> String {
+    format!("({})", fields.join(","))
+}
+
+fn opt_fields(opt: &Opt) -> Vec<String> {
+    let mut fields: Vec<String> = Vec::new();
+    if !opt.short.is_empty() {
+        fields.push(format!("short:{}", opt.short));
+    }
+    if !opt.long.is_empty() {
+        fields.push(format!("long:{}", opt.long));
+    }
+    if !opt.desc.is_empty() {
+        fields.push(format!("desc:({})", opt.desc));
+    }
+    if opt.multiple {
+        fields.push("multiple".to_string());
+    }
+    fields
+}
+
+fn param_fields(param: &Param) -> Vec<String> {
+    let param_type = match param.param_type {
+        ArgType::InputFile | ArgType::InputFileList => "input_file",
+        ArgType::OutputFile | ArgType::OutputFileList => "output_file",
+        ArgType::OutputPrefix => "output_prefix",
+        ArgType::Str => "str",
+        ArgType::Command => "command",
+    };
+    let mut fields = vec![format!("type:{}", param_type)];
+    if param.splittable {
+        fields.push("splittable".to_string());
+    }
+    fields.push(match param.size {
+        ParamSize::Zero => "size:0".to_string(),
+        ParamSize::One => "size:1".to_string(),
+        ParamSize::SpecificSize(size, separator) => format!(
+            "size:specific_size(size:{},list_separator:({}))",
+            size,
+            render_separator(separator)
+        ),
+        ParamSize::List(separator) => format!(
+            "size:list(list_separator:({}))",
+            render_separator(separator)
+        ),
+    });
+    if !param.default_value.is_empty() {
+        fields.push(format!("default_value:\"{}\"", param.default_value));
+    }
+    if param.multiple {
+        fields.push("multiple".to_string());
+    }
+    if param.attached_to_short {
+        fields.push("attached".to_string());
+    }
+    fields
+}
+
+fn render_separator(separator: ListSeparator) -> &'static str {
+    match separator {
+        ListSeparator::Space => " ",
+        ListSeparator::Comma => ",",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_line() {
+        let line = "tar: FLAGS:[(short:c),(short:z)] OPTPARAMS:[(short:f,type:output_file,size:1)] PARAMS:[(type:input_file,size:list(list_separator:( )))]";
+        let (annotation, warnings) = migrate_line(line).unwrap();
+        assert_eq!(annotation, line);
+        assert!(warnings.is_empty());
+
+        let (annotation, _) = migrate_line(
+            "grep[splittable_across_input,reduces_input]: PARAMS:[(type:str,size:1)] FLAGS:[(short:v,long:invert-match,desc:(invert))] PARAMS:[(size:list(list_separator:( )),type:input_file)]",
+        )
+        .unwrap();
+        assert_eq!(
+            annotation,
+            "grep[splittable_across_input,reduces_input]: FLAGS:[(short:v,long:invert-match,desc:(invert))] PARAMS:[(type:str,size:1),(type:input_file,size:list(list_separator:( )))]"
+        );
+        assert_eq!(
+            Command::new(&annotation).unwrap().args,
+            Command::new("grep: FLAGS:[(short:v,long:invert-match,desc:(invert))] PARAMS:[(type:str,size:1),(type:input_file,size:list(list_separator:( )))]").unwrap().args
+        );
+
+        let (annotation, _) = migrate_line(
+            "tar: OPTPARAMS:[(short:C,type:output_file,size:1,default_value:\".\"),(short:n,type:str,attached,size:specific_size(size:2,list_separator:(,)))]",
+        )
+        .unwrap();
+        assert_eq!(
+            annotation,
+            "tar: OPTPARAMS:[(short:C,type:output_file,size:1,default_value:\".\"),(short:n,type:str,size:specific_size(size:2,list_separator:(,)),attached)]"
+        );
+    }
+
+    #[test]
+    fn test_migrate_problems() {
+        let (annotation, warnings) =
+            migrate_line("cat: PARAMS:[(type:input_file,size:1)] PARAMS:[(type:dir)]").unwrap();
+        assert_eq!(annotation, "cat: PARAMS:[(type:input_file,size:1)]");
+        assert_eq!(
+            warnings,
+            vec!["`PARAMS:[(type:dir)]` was ignored by the old parser and is left out".to_string()]
+        );
+
+        let e = migrate_line("cut: PARAMS:[(type:str,size:list(list_separator:(;)))]").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "list separator `;` is not supported, only a space or a comma"
+        );
+        let e = migrate_line("cat: FLAGS:[(short:1)]").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "the old parser rejects this annotation, at `FLAGS:[(short:1)]`"
+        );
+        let e = migrate_line(
+            "paste: PARAMS:[(type:input_file,size:list(list_separator:( ))),(type:output_file,size:list(list_separator:( )))]",
+        )
+        .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "the converted annotation would not load: Cannot have multiple args with size > 1"
+        );
+    }
+
+    #[test]
+    fn test_migrate_annotations() {
+        let migration = migrate_annotations(
+            "cat: PARAMS:[(type:input_file,size:list(list_separator:( )))]\n\nwc FLAGS:[(short:l)]\nsort: FLAGS:[(short:r,long:reverse)]\n",
+        );
+        assert_eq!(
+            migration.annotations,
+            vec![
+                "cat: PARAMS:[(type:input_file,size:list(list_separator:( )))]".to_string(),
+                "sort: FLAGS:[(short:r,long:reverse)]".to_string(),
+            ]
+        );
+        assert_eq!(migration.problems.len(), 1);
+        assert_eq!(migration.problems[0].line, 3);
+        assert!(!migration.problems[0].converted);
+    }
+}
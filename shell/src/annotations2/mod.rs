@@ -18,7 +18,15 @@ pub mod annotation_parser;
 pub mod argument_matcher;
 /// Builds parser for command line syntax for a single command.
 pub mod cmd_parser;
+/// Drafts annotations from a command's --help output or man page.
+pub mod generate;
 /// Defines command line syntax.
 pub mod grammar;
+/// Checks annotation files and explains what is wrong with them.
+pub mod lint;
+/// Converts annotation files from the older annotations module.
+pub mod migrate;
 /// Parser to match command line with any of the annotations.
 pub mod parser;
+/// Structured (YAML) annotation format.
+pub mod structured;
@@ -1,6 +1,6 @@
 use super::argument_matcher::ArgMatch;
 use super::cmd_parser::CmdParser;
-use super::grammar::parse_annotation_file;
+use super::grammar::{parse_annotation_layers, Argument, Command};
 use dash::util::Result;
 use std::collections::HashMap;
 /// Keeps track of all the annotations and matches command line syntax with a particular annotation
@@ -11,75 +11,109 @@ pub struct Parser {
 impl Parser {
     /// Constructs a new parser from a file containing various annotations.
     pub fn new(annotations_file: &str) -> Result<Self> {
-        let mut parser_map: HashMap<String, CmdParser> = Default::default();
-        let cmds = parse_annotation_file(annotations_file)?;
+        Parser::from_files(&[annotations_file.to_string()])
+    }
 
-        for cmd in cmds {
-            if parser_map.contains_key(&cmd.command_name) {
-                let parser: &mut CmdParser = parser_map.get_mut(&cmd.command_name).unwrap(); // Should be safe to unwrap here
-                parser.add_annotation(cmd)?;
-            } else {
-                let name = cmd.command_name.clone();
-                let mut parser = CmdParser::new(&name);
-                parser.add_annotation(cmd)?;
-                parser_map.insert(name, parser);
+    /// Constructs a parser from annotation files or directories layered in order, e.g. the
+    /// system's, then a team's, then the user's.
+    /// Each file is a layer (see `parse_annotation_layers`). A layer with annotations for a
+    /// command replaces those of earlier layers, unless one of them is marked `extends`; then
+    /// they are tried before the earlier ones.
+    pub fn from_files(annotations_files: &[String]) -> Result<Self> {
+        let mut annotations: HashMap<String, Vec<Command>> = HashMap::default();
+        for path in annotations_files.iter() {
+            for layer in parse_annotation_layers(path)?.into_iter() {
+                let mut layer_annotations: HashMap<String, Vec<Command>> = HashMap::default();
+                for cmd in layer.into_iter() {
+                    layer_annotations
+                        .entry(cmd.command_name.clone())
+                        .or_default()
+                        .push(cmd);
+                }
+                for (name, mut cmds) in layer_annotations.into_iter() {
+                    let earlier = annotations.remove(&name).unwrap_or_default();
+                    if cmds.iter().any(|cmd| cmd.parsing_options.extends) {
+                        cmds.extend(earlier);
+                    }
+                    annotations.insert(name, cmds);
+                }
             }
         }
 
+        let mut parser_map: HashMap<String, CmdParser> = Default::default();
+        for (name, cmds) in annotations.into_iter() {
+            let mut parser = CmdParser::new(&name);
+            for cmd in cmds {
+                parser.add_annotation(cmd)?;
+            }
+            parser_map.insert(name, parser);
+        }
+        link_subcommands(&mut parser_map);
         Ok(Parser {
             cmd_parsers: parser_map,
         })
     }
     /// Constructs a new parser from a hashmap containing various CmdParsers.
-    pub fn construct(map: HashMap<String, CmdParser>) -> Self {
+    pub fn construct(mut map: HashMap<String, CmdParser>) -> Self {
+        link_subcommands(&mut map);
         Parser { cmd_parsers: map }
     }
 
+    /// How many commands have annotations.
+    pub fn num_commands(&self) -> usize {
+        self.cmd_parsers.len()
+    }
+
     /// Takes the specified invocation and returns a possible argument matcher.
     pub fn match_invocation(&self, cmd: &str, invocation: Vec<String>) -> Result<ArgMatch> {
         match self.find_parser_key(cmd, &invocation) {
             Some(cmd_parser_name) => {
                 let parser: &CmdParser = self.cmd_parsers.get(&cmd_parser_name).unwrap();
-                parser.parse_command(&invocation)
+                let mut arg_match = parser.parse_command(&invocation)?;
+                self.match_nested(&mut arg_match)?;
+                Ok(arg_match)
             }
             None => Ok(self.default_parse(cmd, &invocation)),
         }
     }
 
+    /// Matches the command lines the invocation runs (e.g. `grep foo` in `xargs grep foo`)
+    /// against their own annotations.
+    pub fn match_nested(&self, arg_match: &mut ArgMatch) -> Result<()> {
+        for (ind, words) in arg_match.nested_invocations().into_iter() {
+            let nested = self.match_invocation(&words[0], words[1..].to_vec())?;
+            arg_match.set_nested(ind, nested);
+        }
+        Ok(())
+    }
+
+    /// Returns the parser holding the annotations this invocation is matched against, if any.
+    pub fn get_cmd_parser(&self, cmd: &str, invocation: &[String]) -> Option<&CmdParser> {
+        match self.find_parser_key(cmd, invocation) {
+            Some(cmd_parser_name) => self.cmd_parsers.get(&cmd_parser_name),
+            None => None,
+        }
+    }
+
     /// Searches through available parsers and returns key for parser, if one exists.
-    fn find_parser_key(&self, cmd: &str, invocation: &Vec<String>) -> Option<String> {
-        for (parser_name, _) in self.cmd_parsers.iter() {
-            let name_list: Vec<String> = parser_name
-                .clone()
-                .split(" ")
-                .map(|x| x.to_string())
-                .collect();
-            if name_list.len() == 1 {
-                if cmd.to_string() == name_list[0] {
-                    return Some(name_list[0].clone());
-                } else {
-                    continue;
-                }
-            } else {
-                let num_args = name_list.len() - 1;
-                // now join a string with the command name and the first n string args
-                let mut command_name = cmd.to_string();
-                if invocation.len() < num_args {
-                    // this parser isn't going to work
-                    continue;
-                }
-                let args = invocation.clone();
-                for i in 0..num_args {
-                    command_name.push_str(" ");
-                    command_name.push_str(args[i].as_str());
-                }
-                if command_name == *parser_name {
-                    return Some(parser_name.clone());
-                }
+    /// When both a command and its subcommands are annotated (e.g. `git` and `git log`), the
+    /// parser for the longest subcommand the invocation names is used.
+    fn find_parser_key(&self, cmd: &str, invocation: &[String]) -> Option<String> {
+        let mut found: Option<(&String, usize)> = None;
+        for (parser_name, parser) in self.cmd_parsers.iter() {
+            let num_words = parser_name.split(' ').count();
+            if parser_name.split(' ').next() != Some(cmd) {
+                continue;
+            }
+            if num_words > 1 && parser.split_subcommand(invocation).is_err() {
+                continue;
+            }
+            match found {
+                Some((_, longest)) if longest >= num_words => {}
+                _ => found = Some((parser_name, num_words)),
             }
         }
-
-        None
+        found.map(|(parser_name, _)| parser_name.clone())
     }
 
     /// Default parse when no other parser is available.
@@ -87,3 +121,257 @@ impl Parser {
         ArgMatch::new_default(cmd, invocation)
     }
 }
+
+/// Gives each subcommand parser the options of the commands it is part of (e.g. those of `git`
+/// for `git log`), so they can be given before the subcommand word.
+fn link_subcommands(parsers: &mut HashMap<String, CmdParser>) {
+    let mut inherited: Vec<(String, Vec<Vec<Argument>>)> = Vec::new();
+    for name in parsers.keys() {
+        let words: Vec<&str> = name.split(' ').collect();
+        let options: Vec<Vec<Argument>> = (1..words.len())
+            .map(|depth| match parsers.get(&words[..depth].join(" ")) {
+                Some(parent) => parent.get_options(),
+                None => vec![],
+            })
+            .collect();
+        inherited.push((name.clone(), options));
+    }
+    for (name, options) in inherited.into_iter() {
+        parsers.get_mut(&name).unwrap().set_global_options(options);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::grammar::Command;
+    use super::*;
+    use dash::graph::command::NodeArg;
+    use dash::graph::filestream::FileStream;
+    use dash::graph::Location;
+    use std::path::Path;
+
+    fn get_git_parser() -> Parser {
+        let annotations = [
+            "git: FLAGS:[(long:no-pager)] OPTPARAMS:[(short:C,type:input_file,size:1),(short:c,type:str,size:1)] PARAMS:[(type:str,size:list(list_separator:( )))]",
+            "git log[needs_current_dir]: FLAGS:[(long:oneline)] OPTPARAMS:[(short:n,type:str,size:1)] PARAMS:[(type:input_file,size:list(list_separator:( )))]",
+            "git stash[needs_current_dir]: PARAMS:[(type:str,size:list(list_separator:( )))]",
+            "git stash list[needs_current_dir]: FLAGS:[(short:p)]",
+        ];
+        let mut map: HashMap<String, CmdParser> = HashMap::default();
+        for annotation in annotations.iter() {
+            let cmd = Command::new(annotation).unwrap();
+            let mut parser = CmdParser::new(&cmd.command_name);
+            parser.add_annotation(cmd).unwrap();
+            map.insert(parser.get_name().to_string(), parser);
+        }
+        Parser::construct(map)
+    }
+
+    fn words(s: &str) -> Vec<String> {
+        s.split(' ').map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_subcommand_dispatch() {
+        let parser = get_git_parser();
+        let name = |args: &str| {
+            parser
+                .get_cmd_parser("git", &words(args))
+                .map(|p| p.get_name().to_string())
+        };
+        assert_eq!(name("log --oneline"), Some("git log".to_string()));
+        assert_eq!(name("-C repo --no-pager log"), Some("git log".to_string()));
+        assert_eq!(name("stash list -p"), Some("git stash list".to_string()));
+        assert_eq!(name("stash pop"), Some("git stash".to_string()));
+        assert_eq!(name("status"), Some("git".to_string()));
+        // only inherited options can come before the subcommand word
+        assert_eq!(name("--bogus log"), Some("git".to_string()));
+        assert_eq!(name("-C log"), Some("git".to_string()));
+        assert!(parser.get_cmd_parser("svn", &words("log")).is_none());
+    }
+
+    #[test]
+    fn test_subcommand_global_options() {
+        let parser = get_git_parser();
+        let arg_match = parser
+            .match_invocation("git", words("-C /d/c/repo log -n 5 a.txt"))
+            .unwrap();
+        assert!(arg_match.get_needs_current_dir());
+        assert_eq!(
+            arg_match.reconstruct().unwrap(),
+            vec![
+                NodeArg::Str("-C".to_string()),
+                NodeArg::Stream(FileStream::new(Path::new("/d/c/repo"), Location::Client)),
+                NodeArg::Str("log".to_string()),
+                NodeArg::Str("-n".to_string()),
+                NodeArg::Str("5".to_string()),
+                NodeArg::Stream(FileStream::new(Path::new("a.txt"), Location::Client)),
+            ]
+        );
+        let files: Vec<FileStream> = arg_match
+            .file_dependencies()
+            .into_iter()
+            .map(|(_, fs)| fs)
+            .collect();
+        assert_eq!(files.len(), 2);
+
+        // options of the parent command are inherited through nested subcommands
+        let arg_match = parser
+            .match_invocation("git", words("stash -c x=y list -p"))
+            .unwrap();
+        assert_eq!(
+            arg_match.reconstruct().unwrap(),
+            vec![
+                NodeArg::Str("stash".to_string()),
+                NodeArg::Str("-c".to_string()),
+                NodeArg::Str("x=y".to_string()),
+                NodeArg::Str("list".to_string()),
+                NodeArg::Str("-p".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_layered_annotation_files() {
+        let dir = std::env::temp_dir().join(format!("posh_layers_{}", std::process::id()));
+        let write = |file: &str, contents: &str| {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        let list = "(type:input_file,size:list(list_separator:( )))";
+        write("common/cat.txt", &format!("cat: PARAMS:[{}]\n", list));
+        write(
+            "system.txt",
+            &format!(
+                "include common/cat.txt\ngrep: FLAGS:[(short:v)] PARAMS:[(type:str,size:1),{}]\nwc: FLAGS:[(short:l)] PARAMS:[{}]\n",
+                list, list
+            ),
+        );
+        write(
+            "user.d/10-grep.yaml",
+            "commands:\n  - name: grep\n    options: [extends]\n    flags: [{short: c}]\n    params: [{type: str, size: 1}]\n",
+        );
+        write("user.d/20-wc.txt", "wc: PARAMS:[(type:str,size:1)]\n");
+        write("user.d/.20-wc.txt.swp", "not an annotation");
+        write("loop/a.txt", "include b.txt\n");
+        write("loop/b.txt", "include a.txt\n");
+
+        let parser = Parser::from_files(&[
+            dir.join("system.txt").display().to_string(),
+            dir.join("user.d").display().to_string(),
+        ])
+        .unwrap();
+        let annotations = |name: &str| parser.get_cmd_parser(name, &[]).unwrap().get_annotations();
+        // included by the system file
+        assert_eq!(annotations("cat").len(), 1);
+        // extended by the user: the user's annotation is tried first
+        assert_eq!(annotations("grep").len(), 2);
+        assert!(annotations("grep")[0].parsing_options.extends);
+        // replaced by the user
+        assert_eq!(
+            annotations("wc"),
+            &[Command::new("wc: PARAMS:[(type:str,size:1)]").unwrap()]
+        );
+
+        let err = Parser::new(&dir.join("loop/a.txt").display().to_string())
+            .err()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(err.to_string().contains("a.txt: included by itself"));
+    }
+
+    #[test]
+    fn test_nested_commands() {
+        let list = |t: &str| format!("(type:{},size:list(list_separator:( )))", t);
+        let annotations = [
+            format!("grep: FLAGS:[(short:v),(short:l)] PARAMS:[(type:str,size:1),{}]", list("input_file")),
+            format!("gzip: PARAMS:[{}]", list("input_file")),
+            format!("xargs: FLAGS:[(short:r)] OPTPARAMS:[(short:n,type:str,size:1),(short:I,type:str,size:1)] PARAMS:[{}]", list("command")),
+            format!("find[long_arg_single_dash,params_first]: OPTPARAMS:[(long:name,type:str,size:1),(long:exec,type:command,size:list(list_separator:( )))] PARAMS:[{}]", list("input_file")),
+            format!("parallel: PARAMS:[{}]", list("command")),
+            "sh: OPTPARAMS:[(short:c,type:command,size:1)]".to_string(),
+        ];
+        let mut map: HashMap<String, CmdParser> = HashMap::default();
+        for annotation in annotations.iter() {
+            let cmd = Command::new(annotation).unwrap();
+            let mut parser = CmdParser::new(&cmd.command_name);
+            parser.add_annotation(cmd).unwrap();
+            map.insert(parser.get_name().to_string(), parser);
+        }
+        let parser = Parser::construct(map);
+        let str_args = |args: Vec<NodeArg>| -> Vec<String> {
+            args.iter()
+                .map(|arg| match arg {
+                    NodeArg::Str(s) => s.clone(),
+                    NodeArg::Stream(fs) => format!("<{}>", fs.get_name().unwrap()),
+                })
+                .collect()
+        };
+        let nested_files = |arg_match: &ArgMatch| -> Vec<String> {
+            arg_match
+                .nested_dependencies()
+                .iter()
+                .map(|fs| fs.get_name().unwrap())
+                .collect()
+        };
+
+        // options of the nested command are left to it
+        let arg_match = parser
+            .match_invocation("xargs", words("-n 1 grep -v foo /d/a.txt"))
+            .unwrap();
+        assert_eq!(arg_match.get_nested().len(), 1);
+        assert_eq!(
+            str_args(arg_match.reconstruct().unwrap()),
+            words("-n 1 grep -v foo </d/a.txt>")
+        );
+        assert_eq!(nested_files(&arg_match), vec!["/d/a.txt"]);
+        assert!(!arg_match.must_run_on_client());
+
+        // the command runs up to `;` or `{} +`; `{}` is a placeholder, not a file
+        for invocation in ["-exec grep -l foo {} ;", "-exec gzip {} +"].iter() {
+            let arg_match = parser
+                .match_invocation("find", words(&format!("/d -name x {}", invocation)))
+                .unwrap();
+            assert_eq!(arg_match.get_nested().len(), 1);
+            // the paths come first and the options stay in order
+            assert_eq!(
+                str_args(arg_match.reconstruct().unwrap()),
+                words(&format!("</d> -name x {}", invocation))
+            );
+            assert!(nested_files(&arg_match).is_empty());
+        }
+        // outside a nested command, or as part of a longer word, braces are part of a path
+        let arg_match = parser
+            .match_invocation("xargs", words("-I {} gzip {}.txt /d/{a}.txt"))
+            .unwrap();
+        assert_eq!(nested_files(&arg_match), vec!["{}.txt", "/d/{a}.txt"]);
+        let arg_match = parser.match_invocation("gzip", words("{}")).unwrap();
+        assert_eq!(str_args(arg_match.reconstruct().unwrap()), words("<{}>"));
+
+        let arg_match = parser
+            .match_invocation("parallel", words("grep foo ::: a b"))
+            .unwrap();
+        assert_eq!(
+            str_args(arg_match.reconstruct().unwrap()),
+            words("grep foo ::: a b")
+        );
+
+        // a script is passed on as one word; if it names files, it has to run on the client
+        let arg_match = parser
+            .match_invocation(
+                "sh",
+                vec!["-c".to_string(), "grep foo /d/a.txt".to_string()],
+            )
+            .unwrap();
+        assert!(arg_match.must_run_on_client());
+        assert_eq!(
+            str_args(arg_match.reconstruct().unwrap()),
+            vec!["-c", "grep foo /d/a.txt"]
+        );
+        let arg_match = parser
+            .match_invocation("sh", vec!["-c".to_string(), "grep foo".to_string()])
+            .unwrap();
+        assert!(!arg_match.must_run_on_client());
+    }
+}
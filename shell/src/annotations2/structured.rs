@@ -0,0 +1,653 @@
+extern crate yaml_rust;
+use super::grammar::*;
+use dash::util::Result;
+use failure::bail;
+use yaml_rust::{Yaml, YamlLoader};
+
+/// Keys allowed for each part of a structured annotation; anything else is reported as a typo.
+const COMMAND_KEYS: &[&str] = &[
+    "name",
+    "options",
+    "reducer",
+    "implicit_inputs",
+    "env",
+    "selectivity",
+    "cpu_cost",
+    "startup_cost",
+    "conditions",
+    "flags",
+    "optparams",
+    "params",
+];
+const IMPLICIT_INPUT_KEYS: &[&str] = &["path", "relative_to"];
+const SELECTIVITY_KEYS: &[&str] = &["ratio", "when"];
+const CONDITION_KEYS: &[&str] = &["glob", "regex", "selectivity", "access", "splittable"];
+const FLAG_KEYS: &[&str] = &["short", "long", "desc", "multiple"];
+const OPTPARAM_KEYS: &[&str] = &[
+    "short",
+    "long",
+    "desc",
+    "type",
+    "size",
+    "list_separator",
+    "default_value",
+    "multiple",
+    "splittable",
+    "attached",
+];
+const PARAM_KEYS: &[&str] = &[
+    "type",
+    "size",
+    "list_separator",
+    "default_value",
+    "multiple",
+    "splittable",
+];
+
+/// Parses annotations written in the structured (YAML) format into the same commands as the
+/// one-line format.
+/// The document is a list of commands under `commands`, for example:
+/// ```yaml
+/// commands:
+///   - name: grep
+///     options: [splittable_across_input, reduces_input]
+///     flags:
+///       - {short: c, long: count}
+///     optparams:
+///       - {short: e, long: regexp, type: str, size: 1}
+///     params:
+///       - {type: input_file, size: list, list_separator: space}
+/// ```
+/// The schema is in config/annotations.schema.json; errors name the entry and key at fault.
+pub fn parse_structured_annotations(contents: &str) -> Result<Vec<Command>> {
+    let mut ret: Vec<Command> = Vec::new();
+    for (_, cmd) in parse_structured_entries(contents)? {
+        ret.push(cmd?);
+    }
+    Ok(ret)
+}
+
+/// Returns the files listed under `include`, which are read before the file's own commands.
+pub fn parse_structured_includes(contents: &str) -> Result<Vec<String>> {
+    let docs = match YamlLoader::load_from_str(contents) {
+        Ok(docs) => docs,
+        Err(e) => bail!("Could not parse yaml annotations: {}", e),
+    };
+    let mut includes: Vec<String> = Vec::new();
+    for doc in docs.iter() {
+        for (idx, include) in get_list(doc, "include", "annotations")?.iter().enumerate() {
+            match include.as_str() {
+                Some(file) => includes.push(file.to_string()),
+                None => bail!(
+                    "annotations.include[{}]: expected a file name, found {:?}",
+                    idx,
+                    include
+                ),
+            }
+        }
+    }
+    Ok(includes)
+}
+
+/// Parses each entry under `commands` on its own, so a bad entry does not hide problems in the
+/// entries after it.
+/// Returns the name of each entry, if it has one, with the command or the problem with it.
+pub fn parse_structured_entries(contents: &str) -> Result<Vec<(Option<String>, Result<Command>)>> {
+    let docs = match YamlLoader::load_from_str(contents) {
+        Ok(docs) => docs,
+        Err(e) => bail!("Could not parse yaml annotations: {}", e),
+    };
+    let mut ret: Vec<(Option<String>, Result<Command>)> = Vec::new();
+    for doc in docs.iter() {
+        check_keys(doc, &["commands", "include"], "annotations")?;
+        let commands = match &doc["commands"] {
+            Yaml::Array(commands) => commands,
+            // a file can consist of only includes
+            Yaml::BadValue if !get_list(doc, "include", "annotations")?.is_empty() => continue,
+            Yaml::BadValue => bail!("annotations: missing `commands` list"),
+            _ => bail!("annotations: `commands` must be a list"),
+        };
+        for (idx, cmd) in commands.iter().enumerate() {
+            let name = cmd["name"].as_str().map(|name| name.to_string());
+            ret.push((name, parse_command(cmd, &format!("commands[{}]", idx))));
+        }
+    }
+    Ok(ret)
+}
+
+fn parse_command(yaml: &Yaml, path: &str) -> Result<Command> {
+    if yaml.as_hash().is_none() {
+        bail!("{}: expected a mapping, found {:?}", path, yaml);
+    }
+    let command_name = match get_str(yaml, "name", path)? {
+        Some(name) => name,
+        None => bail!("{}: missing `name`", path),
+    };
+    let path = format!("{} ({})", path, command_name);
+    check_keys(yaml, COMMAND_KEYS, &path)?;
+    let mut parsing_options = ParsingOptions::default();
+    for (idx, option) in get_list(yaml, "options", &path)?.iter().enumerate() {
+        match option.as_str() {
+            Some("long_arg_single_dash") => parsing_options.long_arg_single_dash = true,
+            Some("splittable_across_input") => parsing_options.splittable_across_input = true,
+            Some("reduces_input") => parsing_options.reduces_input = true,
+            Some("needs_current_dir") => parsing_options.needs_current_dir = true,
+            Some("random_access") => parsing_options.access_type = AccessType::Random,
+            Some("params_first") => parsing_options.params_first = true,
+            Some("extends") => parsing_options.extends = true,
+            _ => bail!(
+                "{}.options[{}]: unknown option {:?}, expected one of long_arg_single_dash, splittable_across_input, reduces_input, needs_current_dir, random_access, params_first, extends",
+                path,
+                idx,
+                option
+            ),
+        }
+    }
+
+    parsing_options.reducer = parse_reducer(&yaml["reducer"], &format!("{}.reducer", path))?;
+    for (idx, input) in get_list(yaml, "implicit_inputs", &path)?.iter().enumerate() {
+        let input_path = format!("{}.implicit_inputs[{}]", path, idx);
+        parsing_options
+            .implicit_inputs
+            .push(parse_implicit_input(input, &input_path)?);
+    }
+    for (idx, var) in get_list(yaml, "env", &path)?.iter().enumerate() {
+        match var.as_str() {
+            Some(name) => parsing_options.env_vars.push(name.to_string()),
+            None => bail!(
+                "{}.env[{}]: expected a variable name, found {:?}",
+                path,
+                idx,
+                var
+            ),
+        }
+    }
+
+    parsing_options.cost = CostModel {
+        selectivity: parse_selectivity(&yaml["selectivity"], &format!("{}.selectivity", path))?,
+        cpu_cost: get_number(yaml, "cpu_cost", &path)?,
+        startup_cost: get_number(yaml, "startup_cost", &path)?,
+    };
+
+    for (idx, condition) in get_list(yaml, "conditions", &path)?.iter().enumerate() {
+        let condition_path = format!("{}.conditions[{}]", path, idx);
+        parsing_options
+            .conditions
+            .push(parse_condition(condition, &condition_path)?);
+    }
+
+    let mut args: Vec<Argument> = Vec::new();
+    for (idx, flag) in get_list(yaml, "flags", &path)?.iter().enumerate() {
+        let flag_path = format!("{}.flags[{}]", path, idx);
+        check_keys(flag, FLAG_KEYS, &flag_path)?;
+        let opt = parse_opt(flag, &flag_path)?;
+        args.push(Argument::LoneOption(opt));
+    }
+    for (idx, optparam) in get_list(yaml, "optparams", &path)?.iter().enumerate() {
+        let optparam_path = format!("{}.optparams[{}]", path, idx);
+        check_keys(optparam, OPTPARAM_KEYS, &optparam_path)?;
+        let mut opt = parse_opt(optparam, &optparam_path)?;
+        // in the one-line format, multiple belongs to the param of an option with a param
+        opt.multiple = false;
+        let param = parse_param(optparam, &optparam_path)?;
+        args.push(Argument::OptWithParam(opt, param));
+    }
+    for (idx, param) in get_list(yaml, "params", &path)?.iter().enumerate() {
+        let param_path = format!("{}.params[{}]", path, idx);
+        check_keys(param, PARAM_KEYS, &param_path)?;
+        args.push(Argument::LoneParam(parse_param(param, &param_path)?));
+    }
+    Ok(Command {
+        command_name,
+        args,
+        parsing_options,
+    })
+}
+
+fn parse_opt(yaml: &Yaml, path: &str) -> Result<Opt> {
+    let opt = Opt {
+        short: get_str(yaml, "short", path)?.unwrap_or_default(),
+        long: get_str(yaml, "long", path)?.unwrap_or_default(),
+        desc: get_str(yaml, "desc", path)?.unwrap_or_default(),
+        multiple: get_bool(yaml, "multiple", path)?,
+    };
+    if opt.short.is_empty() && opt.long.is_empty() {
+        bail!("{}: needs a `short` or `long` name", path);
+    }
+    if opt.short.starts_with('-') || opt.long.starts_with('-') {
+        bail!("{}: option names are written without leading dashes", path);
+    }
+    Ok(opt)
+}
+
+fn parse_param(yaml: &Yaml, path: &str) -> Result<Param> {
+    let param_type = match get_str(yaml, "type", path)?.as_deref() {
+        Some("input_file") => ArgType::InputFile,
+        Some("output_file") => ArgType::OutputFile,
+        Some("output_prefix") => ArgType::OutputPrefix,
+        Some("str") | None => ArgType::Str,
+        Some("command") => ArgType::Command,
+        Some(other) => bail!(
+            "{}.type: unknown type {:?}, expected one of input_file, output_file, output_prefix, str, command",
+            path,
+            other
+        ),
+    };
+    let separator = match get_str(yaml, "list_separator", path)?.as_deref() {
+        Some("space") | Some(" ") => ListSeparator::Space,
+        Some("comma") | Some(",") => ListSeparator::Comma,
+        None => ListSeparator::default(),
+        Some(other) => bail!(
+            "{}.list_separator: unknown separator {:?}, expected space or comma",
+            path,
+            other
+        ),
+    };
+    let size = match &yaml["size"] {
+        Yaml::BadValue => ParamSize::default(),
+        Yaml::Integer(0) => ParamSize::Zero,
+        Yaml::Integer(1) => ParamSize::One,
+        Yaml::Integer(n) if *n > 1 => ParamSize::SpecificSize(*n as u64, separator),
+        Yaml::String(s) if s == "list" => ParamSize::List(separator),
+        other => bail!(
+            "{}.size: expected a number of values or `list`, found {:?}",
+            path,
+            other
+        ),
+    };
+    Ok(Param {
+        param_type,
+        size,
+        default_value: get_str(yaml, "default_value", path)?.unwrap_or_default(),
+        multiple: get_bool(yaml, "multiple", path)?,
+        splittable: get_bool(yaml, "splittable", path)?,
+        attached_to_short: get_bool(yaml, "attached", path)?,
+    })
+}
+
+/// A reducer is either the name of a built in one, or `{command: ...}` for any command line.
+fn parse_reducer(yaml: &Yaml, path: &str) -> Result<Reducer> {
+    match yaml {
+        Yaml::BadValue => Ok(Reducer::Concat),
+        Yaml::String(name) => match name.as_str() {
+            "concat" => Ok(Reducer::Concat),
+            "sum" => Ok(Reducer::Sum),
+            "merge" => Ok(Reducer::Merge),
+            "uniq" => Ok(Reducer::Uniq),
+            _ => bail!(
+                "{}: unknown reducer {:?}, expected one of concat, sum, merge, uniq or {{command: ...}}",
+                path,
+                name
+            ),
+        },
+        Yaml::Hash(_) => {
+            check_keys(yaml, &["command"], path)?;
+            match get_str(yaml, "command", path)? {
+                Some(command) => Ok(Reducer::Custom(command)),
+                None => bail!("{}: missing `command`", path),
+            }
+        }
+        other => bail!(
+            "{}: expected a reducer name or {{command: ...}}, found {:?}",
+            path,
+            other
+        ),
+    }
+}
+
+/// An implicit input is either a path relative to the current directory, or
+/// `{path: ..., relative_to: <option>}`.
+fn parse_implicit_input(yaml: &Yaml, path: &str) -> Result<ImplicitFile> {
+    if let Some(file) = yaml.as_str() {
+        return Ok(ImplicitFile {
+            path: file.to_string(),
+            relative_to: "".to_string(),
+        });
+    }
+    check_keys(yaml, IMPLICIT_INPUT_KEYS, path)?;
+    let file = match get_str(yaml, "path", path)? {
+        Some(file) => file,
+        None => bail!("{}: missing `path`", path),
+    };
+    Ok(ImplicitFile {
+        path: file,
+        relative_to: get_str(yaml, "relative_to", path)?.unwrap_or_default(),
+    })
+}
+
+/// Selectivity is a ratio, or a list of ratios or `{ratio: ..., when: [<option>, ...]}`, the
+/// first of which whose options were all given applies.
+fn parse_selectivity(yaml: &Yaml, path: &str) -> Result<Vec<Selectivity>> {
+    let items = match yaml {
+        Yaml::BadValue => return Ok(Vec::new()),
+        Yaml::Array(items) => items.iter().collect(),
+        other => vec![other],
+    };
+    let mut ret: Vec<Selectivity> = Vec::new();
+    for (idx, item) in items.into_iter().enumerate() {
+        let item_path = format!("{}[{}]", path, idx);
+        if let Yaml::Hash(_) = item {
+            check_keys(item, SELECTIVITY_KEYS, &item_path)?;
+            let ratio = match get_number(item, "ratio", &item_path)? {
+                Some(ratio) => ratio,
+                None => bail!("{}: missing `ratio`", item_path),
+            };
+            let mut when: Vec<String> = Vec::new();
+            for (when_idx, name) in get_list(item, "when", &item_path)?.iter().enumerate() {
+                match name {
+                    Yaml::String(name) => when.push(name.clone()),
+                    // short options like -1 are read as numbers
+                    Yaml::Integer(n) => when.push(n.to_string()),
+                    _ => bail!(
+                        "{}.when[{}]: expected an option name, found {:?}",
+                        item_path,
+                        when_idx,
+                        name
+                    ),
+                }
+            }
+            ret.push(Selectivity { ratio, when });
+        } else {
+            ret.push(Selectivity {
+                ratio: number(item, &item_path)?,
+                when: Vec::new(),
+            });
+        }
+    }
+    Ok(ret)
+}
+
+/// A condition has a `glob` or a `regex` for the argument values, and the changes to make when
+/// one matches: `selectivity`, `access` (`random` or `sequential`) and `splittable`.
+fn parse_condition(yaml: &Yaml, path: &str) -> Result<ValueCondition> {
+    check_keys(yaml, CONDITION_KEYS, path)?;
+    let (pattern, is_regex) = match (get_str(yaml, "glob", path)?, get_str(yaml, "regex", path)?) {
+        (Some(glob), None) => (glob, false),
+        (None, Some(regex)) => (regex, true),
+        _ => bail!("{}: needs one of `glob` or `regex`", path),
+    };
+    let access_type = match get_str(yaml, "access", path)?.as_deref() {
+        None => None,
+        Some("random") => Some(AccessType::Random),
+        Some("sequential") => Some(AccessType::Sequential),
+        Some(other) => bail!(
+            "{}.access: unknown access {:?}, expected random or sequential",
+            path,
+            other
+        ),
+    };
+    let splittable = match &yaml["splittable"] {
+        Yaml::BadValue => None,
+        _ => Some(get_bool(yaml, "splittable", path)?),
+    };
+    let condition = ValueCondition {
+        pattern,
+        is_regex,
+        selectivity: get_number(yaml, "selectivity", path)?,
+        access_type,
+        splittable,
+    };
+    if condition.selectivity.is_none()
+        && condition.access_type.is_none()
+        && condition.splittable.is_none()
+    {
+        bail!(
+            "{}: needs at least one of `selectivity`, `access` or `splittable`",
+            path
+        );
+    }
+    if let Err(e) = condition.validate() {
+        bail!("{}: {}", path, e);
+    }
+    Ok(condition)
+}
+
+/// Makes sure the yaml is a mapping that only uses the allowed keys.
+fn check_keys(yaml: &Yaml, allowed: &[&str], path: &str) -> Result<()> {
+    let map = match yaml.as_hash() {
+        Some(map) => map,
+        None => bail!("{}: expected a mapping, found {:?}", path, yaml),
+    };
+    for key in map.keys() {
+        match key.as_str() {
+            Some(key) if allowed.contains(&key) => {}
+            Some(key) => bail!(
+                "{}: unknown key {:?}, expected one of {}",
+                path,
+                key,
+                allowed.join(", ")
+            ),
+            None => bail!("{}: keys must be strings, found {:?}", path, key),
+        }
+    }
+    Ok(())
+}
+
+fn get_str(yaml: &Yaml, key: &str, path: &str) -> Result<Option<String>> {
+    match &yaml[key] {
+        Yaml::BadValue => Ok(None),
+        Yaml::String(s) => Ok(Some(s.clone())),
+        // short options like -1 are read as numbers
+        Yaml::Integer(n) => Ok(Some(n.to_string())),
+        other => bail!("{}.{}: expected a string, found {:?}", path, key, other),
+    }
+}
+
+fn get_number(yaml: &Yaml, key: &str, path: &str) -> Result<Option<f64>> {
+    match &yaml[key] {
+        Yaml::BadValue => Ok(None),
+        other => Ok(Some(number(other, &format!("{}.{}", path, key))?)),
+    }
+}
+
+/// Costs and ratios are numbers that are not negative.
+fn number(yaml: &Yaml, path: &str) -> Result<f64> {
+    let value = match yaml {
+        Yaml::Integer(n) => *n as f64,
+        Yaml::Real(_) => yaml.as_f64().unwrap_or(-1.0),
+        _ => -1.0,
+    };
+    if !(value >= 0.0 && value.is_finite()) {
+        bail!(
+            "{}: expected a number that is not negative, found {:?}",
+            path,
+            yaml
+        );
+    }
+    Ok(value)
+}
+
+fn get_bool(yaml: &Yaml, key: &str, path: &str) -> Result<bool> {
+    match &yaml[key] {
+        Yaml::BadValue => Ok(false),
+        Yaml::Boolean(b) => Ok(*b),
+        other => bail!(
+            "{}.{}: expected true or false, found {:?}",
+            path,
+            key,
+            other
+        ),
+    }
+}
+
+fn get_list<'a>(yaml: &'a Yaml, key: &str, path: &str) -> Result<&'a [Yaml]> {
+    match &yaml[key] {
+        Yaml::BadValue => Ok(&[]),
+        Yaml::Array(list) => Ok(list),
+        other => bail!("{}.{}: expected a list, found {:?}", path, key, other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_structured_matches_legacy() {
+        let legacy = [
+            "grep[splittable_across_input,reduces_input]: FLAGS:[(short:c,long:count)] OPTPARAMS:[(short:e,long:regexp,type:str,size:1,multiple)] PARAMS:[(type:input_file,size:list(list_separator:( )))]",
+            "pr: OPTPARAMS:[(short:s,type:str,size:1,attached)] PARAMS:[(type:input_file,size:specific_size(size:2,list_separator:(,)),splittable)]",
+            "git status[needs_current_dir]: OPTPARAMS:[(short:C,size:1,type:input_file)]",
+        ];
+        let structured = r#"
+commands:
+  - name: grep
+    options: [splittable_across_input, reduces_input]
+    flags:
+      - {short: c, long: count}
+    optparams:
+      - {short: e, long: regexp, type: str, size: 1, multiple: true}
+    params:
+      - {type: input_file, size: list, list_separator: space}
+  - name: pr
+    optparams:
+      - {short: s, type: str, size: 1, attached: true}
+    params:
+      - type: input_file
+        size: 2
+        list_separator: comma
+        splittable: true
+  - name: git status
+    options: [needs_current_dir]
+    optparams:
+      - {short: C, size: 1, type: input_file}
+"#;
+        let cmds = parse_structured_annotations(structured).unwrap();
+        assert_eq!(cmds.len(), legacy.len());
+        for (cmd, line) in cmds.iter().zip(legacy.iter()) {
+            assert_eq!(*cmd, Command::new(line).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_structured_file() {
+        let cmds = parse_annotation_file("../config/ann2.yaml").unwrap();
+        let names: Vec<&str> = cmds.iter().map(|cmd| cmd.command_name.as_str()).collect();
+        assert_eq!(names, vec!["grep", "sort", "wc"]);
+        assert_eq!(
+            cmds[1],
+            Command::new("sort: FLAGS:[(short:r,long:reverse)] PARAMS:[(type:input_file,size:list(list_separator:( )))]").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_structured_errors() {
+        let err = |s: &str| parse_structured_annotations(s).unwrap_err().to_string();
+        assert!(err("commands:\n  - flags: []").contains("commands[0]: missing `name`"));
+        assert!(err("commands:\n  - name: cat\n    param: []")
+            .contains("commands[0] (cat): unknown key \"param\""));
+        assert!(
+            err("commands:\n  - name: cat\n    params:\n      - {type: file}")
+                .contains("commands[0] (cat).params[0].type: unknown type")
+        );
+        assert!(
+            err("commands:\n  - name: cat\n    flags:\n      - {desc: nothing}")
+                .contains("needs a `short` or `long` name")
+        );
+        assert!(err("commands: [").contains("Could not parse yaml"));
+        assert!(err("commands:\n  - name: wc\n    reducer: add")
+            .contains("commands[0] (wc).reducer: unknown reducer \"add\""));
+    }
+
+    #[test]
+    fn test_structured_reducer() {
+        let structured = r#"
+commands:
+  - name: wc
+    options: [splittable_across_input]
+    reducer: sum
+    flags:
+      - {short: l}
+  - name: tally
+    reducer: {command: "awk '{ s += $1 } END { print s }'"}
+"#;
+        let cmds = parse_structured_annotations(structured).unwrap();
+        assert_eq!(
+            cmds[0],
+            Command::new("wc[splittable_across_input,reducer:sum]: FLAGS:[(short:l)]").unwrap()
+        );
+        assert_eq!(
+            cmds[1].parsing_options.reducer,
+            Reducer::Custom("awk '{ s += $1 } END { print s }'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_structured_implicit_dependencies() {
+        let structured = r#"
+commands:
+  - name: git status
+    implicit_inputs:
+      - .git
+      - {path: .git, relative_to: C}
+    env: [GIT_DIR]
+    optparams:
+      - {short: C, size: 1, type: input_file}
+"#;
+        let cmds = parse_structured_annotations(structured).unwrap();
+        assert_eq!(
+            cmds[0],
+            Command::new("git status[implicit_input:(.git),implicit_input:(.git,relative_to:C),env:GIT_DIR]: OPTPARAMS:[(short:C,size:1,type:input_file)]").unwrap()
+        );
+
+        let err = parse_structured_annotations(
+            "commands:\n  - name: sort\n    implicit_inputs:\n      - {relative: C}",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("commands[0] (sort).implicit_inputs[0]"));
+    }
+
+    #[test]
+    fn test_structured_cost() {
+        let structured = r#"
+commands:
+  - name: grep
+    selectivity:
+      - {ratio: 0.001, when: [c]}
+      - 0.1
+    cpu_cost: 0.004
+    startup_cost: 1
+    flags:
+      - {short: c}
+  - name: gzip
+    selectivity: 0.3
+"#;
+        let cmds = parse_structured_annotations(structured).unwrap();
+        assert_eq!(
+            cmds[0],
+            Command::new("grep[selectivity:(0.001,when:c),selectivity:0.1,cpu_cost:0.004,startup_cost:1]: FLAGS:[(short:c)]").unwrap()
+        );
+        assert_eq!(cmds[1].parsing_options.cost.selectivity[0].ratio, 0.3);
+
+        let err = parse_structured_annotations("commands:\n  - name: gzip\n    cpu_cost: -2")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("commands[0] (gzip).cpu_cost: expected a number"));
+    }
+
+    #[test]
+    fn test_structured_conditions() {
+        let structured = r#"
+commands:
+  - name: zcat
+    conditions:
+      - {glob: "*.gz", access: random, selectivity: 4}
+      - {regex: '\.(parquet|orc)$', splittable: false}
+    params:
+      - {type: input_file, size: list, list_separator: space}
+"#;
+        let cmds = parse_structured_annotations(structured).unwrap();
+        assert_eq!(
+            cmds[0],
+            Command::new(r"zcat[matching:(*.gz,random_access,selectivity:4),matching:(/\.(parquet|orc)$/,not_splittable)]: PARAMS:[(type:input_file,size:list(list_separator:( )))]").unwrap()
+        );
+
+        let err = parse_structured_annotations(
+            "commands:\n  - name: zcat\n    conditions:\n      - {glob: '*.gz'}",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("commands[0] (zcat).conditions[0]: needs at least one of"));
+    }
+}
@@ -0,0 +1,150 @@
+extern crate exitcode;
+extern crate shell;
+use shell::annotations2::generate::{help_text, man_text, parse_help, render_draft};
+use shell::annotations2::lint::lint_annotation_file;
+use shell::annotations2::migrate::migrate_annotation_file;
+use shell::interpreter::learn::{missing_annotations_report, UsageLog};
+use std::path::Path;
+use std::process::exit;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "annotations", help = "Tools for working with annotation files")]
+enum Opt {
+    #[structopt(
+        name = "lint",
+        help = "Reports every problem in annotation files, with where it is and how to fix it"
+    )]
+    Lint {
+        #[structopt(help = "Annotation files to check")]
+        files: Vec<String>,
+    },
+    #[structopt(
+        name = "generate",
+        help = "Drafts a structured annotation from a command's --help output or man page"
+    )]
+    Generate {
+        #[structopt(help = "Command to annotate, e.g. `head` or `git log`")]
+        command: String,
+        #[structopt(
+            long = "man",
+            help = "Man page to read instead of running the command with --help"
+        )]
+        man: Option<String>,
+    },
+    #[structopt(
+        name = "migrate",
+        help = "Converts annotation files for the old annotations module, printing the converted annotations and reporting what could not be converted"
+    )]
+    Migrate {
+        #[structopt(help = "Old annotation files to convert")]
+        files: Vec<String>,
+    },
+    #[structopt(
+        name = "missing",
+        help = "Ranks the commands logged by `shell-client --learn` that most need annotations"
+    )]
+    Missing {
+        #[structopt(help = "Usage logs to read")]
+        logs: Vec<String>,
+    },
+}
+
+fn main() {
+    match Opt::from_args() {
+        Opt::Lint { files } => exit(lint(&files)),
+        Opt::Generate { command, man } => exit(generate(&command, man)),
+        Opt::Migrate { files } => exit(migrate(&files)),
+        Opt::Missing { logs } => exit(missing(&logs)),
+    }
+}
+
+fn lint(files: &[String]) -> i32 {
+    let mut problems = 0;
+    for file in files.iter() {
+        let diagnostics = match lint_annotation_file(file) {
+            Ok(diagnostics) => diagnostics,
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                return exitcode::NOINPUT;
+            }
+        };
+        for diagnostic in diagnostics.iter() {
+            println!("{}:{}\n", file, diagnostic);
+        }
+        problems += diagnostics.len();
+    }
+    match problems {
+        0 => exitcode::OK,
+        1 => {
+            println!("1 problem found");
+            exitcode::DATAERR
+        }
+        n => {
+            println!("{} problems found", n);
+            exitcode::DATAERR
+        }
+    }
+}
+
+fn generate(command: &str, man: Option<String>) -> i32 {
+    let (text, source) = match man {
+        Some(file) => (man_text(&file), file),
+        None => (help_text(command), format!("`{} --help`", command)),
+    };
+    match text {
+        Ok(text) => {
+            print!("{}", render_draft(&parse_help(command, &text), &source));
+            exitcode::OK
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            exitcode::NOINPUT
+        }
+    }
+}
+
+fn migrate(files: &[String]) -> i32 {
+    let mut problems = 0;
+    for file in files.iter() {
+        let migration = match migrate_annotation_file(file) {
+            Ok(migration) => migration,
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                return exitcode::NOINPUT;
+            }
+        };
+        for annotation in migration.annotations.iter() {
+            println!("{}", annotation);
+        }
+        for problem in migration.problems.iter() {
+            match problem.converted {
+                true => eprintln!("{}:{}: {}", file, problem.line, problem.message),
+                false => eprintln!(
+                    "{}:{}: not converted: {}",
+                    file, problem.line, problem.message
+                ),
+            }
+        }
+        problems += migration.problems.len();
+    }
+    match problems {
+        0 => exitcode::OK,
+        _ => exitcode::DATAERR,
+    }
+}
+
+fn missing(logs: &[String]) -> i32 {
+    let mut observations = Vec::new();
+    for log in logs.iter() {
+        match UsageLog::new(Path::new(log)).read() {
+            Ok(mut read) => observations.append(&mut read),
+            Err(e) => {
+                eprintln!("{}: {}", log, e);
+                return exitcode::NOINPUT;
+            }
+        }
+    }
+    print!("{}", missing_annotations_report(&observations));
+    exitcode::OK
+}
@@ -1,14 +1,17 @@
 extern crate dash;
 extern crate shell;
-use dash::graph::program;
+use dash::graph::Location;
 use dash::runtime::new_client as client;
 use dash::util::Result;
 use failure::bail;
+use shell::interpreter::fallback::ShellFallback;
 use shell::interpreter::interpreter;
-use shell::scheduler::heuristic::HeuristicScheduler;
-use std::env::current_dir;
+use shell::interpreter::jobs::JobControl;
+use shell::interpreter::script::ScriptRunner;
+use shell::shellparser::script::parse_script;
+use std::env::{current_dir, var};
 use std::io::{stdin, stdout, Write};
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::process;
 use std::process::exit;
 use structopt::StructOpt;
@@ -58,10 +61,15 @@ struct Opt {
     mount_file: String,
     #[structopt(
         short = "a",
-        long = "annotations_file",
-        help = "File with annotation list."
+        long = "annotations",
+        help = "File or directory with annotations; repeat to layer files, with later ones overriding earlier ones.",
+        raw(
+            alias = "\"annotations_file\"",
+            required = "true",
+            number_of_values = "1"
+        )
     )]
-    annotation_file: String,
+    annotation_files: Vec<String>,
     #[structopt(
         short = "pwd",
         long = "pwd",
@@ -88,16 +96,37 @@ struct Opt {
         default_value = "off"
     )]
     trace_level: TraceLevel,
+    #[structopt(
+        long = "explain",
+        help = "Print how this command line is matched against the annotations, then exit."
+    )]
+    explain: Option<String>,
+    #[structopt(
+        long = "scheduler",
+        help = "Scheduler to use: heuristic (the default), dp, exact, all-local or max-offload; overrides the config file's `scheduler`."
+    )]
+    scheduler: Option<String>,
+    #[structopt(
+        long = "learn",
+        help = "Log the commands no annotation matches to this file; `annotations missing` ranks them."
+    )]
+    learn: Option<String>,
+    #[structopt(
+        long = "watch",
+        help = "Reload the annotations and mount file before each prompt if they changed."
+    )]
+    watch: bool,
 }
 fn main() {
     let opt = Opt::from_args();
     let mount_info = opt.mount_file;
-    let annotation_file = opt.annotation_file;
+    let annotation_files = opt.annotation_files;
     let runtime_port = opt.runtime_port;
     let given_pwd = opt.pwd;
     let tmp_file = opt.tmp_file;
     let splitting_factor: u32 = opt.splitting_factor;
     let trace_level = opt.trace_level;
+    let watch = opt.watch;
     let subscriber = match trace_level {
         TraceLevel::Debug => FmtSubscriber::builder()
             .with_max_level(Level::DEBUG)
@@ -127,21 +156,10 @@ fn main() {
     if given_pwd != "." {
         pwd = Path::new(&given_pwd).to_path_buf();
     }
-    let mut client = match client::ShellClient::new(&runtime_port, pwd.clone(), &tmp_file) {
-        Ok(s) => s,
-        Err(e) => {
-            error!(
-                "Failed to construct shell client with given mount file: {:?}",
-                e
-            );
-            process::exit(exitcode::USAGE);
-        }
-    };
-
     let mut interpreter = match interpreter::Interpreter::new(
         &mount_info,
-        &annotation_file,
-        Box::new(HeuristicScheduler {}),
+        &annotation_files,
+        opt.scheduler.as_deref(),
     ) {
         Ok(i) => i,
         Err(e) => {
@@ -154,9 +172,45 @@ fn main() {
     };
     interpreter.set_pwd(pwd.clone());
     interpreter.set_splitting_factor(splitting_factor);
+    if let Some(log) = opt.learn {
+        interpreter.set_usage_log(Path::new(&log));
+    }
+    if let Some(line) = opt.explain {
+        match interpreter.explain_command_line(&line) {
+            Ok(explanation) => {
+                print!("{}", explanation);
+                exit(exitcode::OK);
+            }
+            Err(e) => {
+                error!("Failed to explain {:?}: {:?}", line, e);
+                exit(exitcode::DATAERR);
+            }
+        }
+    }
+    let client = match client::ShellClient::new(&runtime_port, pwd.clone(), &tmp_file) {
+        Ok(s) => s,
+        Err(e) => {
+            error!(
+                "Failed to construct shell client with given mount file: {:?}",
+                e
+            );
+            process::exit(exitcode::USAGE);
+        }
+    };
+
+    let mut runner = ScriptRunner::default();
+    let mut jobs = JobControl::new(client);
     print!("\x1B[2J\x1B[1;1H");
     loop {
-        print!("\x1b[92mposh>>>\x1b[0m ");
+        jobs.notify_finished();
+        if watch {
+            match interpreter.reload_if_changed() {
+                Some(Ok(summary)) => println!("reload: {}", summary),
+                Some(Err(e)) => eprintln!("reload: {}", e),
+                None => {}
+            }
+        }
+        print!("{}", prompt(&interpreter));
         let _ = stdout().flush();
         let cmd = match readline() {
             Ok(s) => s,
@@ -165,28 +219,54 @@ fn main() {
                 continue;
             }
         };
-        let dag = match interpreter.parse_command_line(&cmd) {
-            Ok(d) => match d {
-                Some(graph) => graph,
-                None => {
-                    continue;
-                }
-            },
+        let nodes = match parse_script(&cmd) {
+            Ok(n) => n,
             Err(e) => {
-                error!("Failed to parse: {:?}", e);
+                // syntax Posh can't represent: run the whole line locally
+                let status = match ShellFallback::new(&cmd, &e, &mut interpreter).run() {
+                    Ok(status) => status,
+                    Err(e) => {
+                        error!("Failed to run {:?} with the system shell: {:?}", cmd, e);
+                        2
+                    }
+                };
+                interpreter.set_last_status(status);
                 continue;
             }
         };
-        match run_program(dag, &mut client, pwd.clone()) {
+        match runner.run(&mut interpreter, &nodes, &mut jobs) {
             Ok(_) => {}
             Err(e) => {
                 error!("Failed to execute: {:?}", e);
-                continue;
+                interpreter.set_last_status(1);
             }
         }
+        if runner.has_exited() {
+            exit(interpreter.get_shell_env_mut().get_last_status());
+        }
     }
 }
 
+/// Shows the current directory, with the home directory as ~, and where it is stored.
+fn prompt(interpreter: &interpreter::Interpreter) -> String {
+    let pwd = interpreter.get_pwd();
+    let dir = match var("HOME") {
+        Ok(home) if !home.is_empty() && pwd.starts_with(&home) => {
+            let relative = pwd.strip_prefix(&home).unwrap_or(&pwd);
+            Path::new("~").join(relative).display().to_string()
+        }
+        _ => pwd.display().to_string(),
+    };
+    let location = match interpreter.get_pwd_location() {
+        Location::Client => "client".to_string(),
+        Location::Server(ip) => ip,
+    };
+    format!(
+        "\x1b[92mposh\x1b[0m [\x1b[93m{}\x1b[0m] \x1b[94m{}\x1b[0m>>> ",
+        location, dir
+    )
+}
+
 fn readline() -> Result<String> {
     let mut input = String::new();
     match stdin().read_line(&mut input) {
@@ -195,16 +275,3 @@ fn readline() -> Result<String> {
     }
     Ok(input)
 }
-
-fn run_program(
-    prog: program::Program,
-    client: &mut client::ShellClient,
-    pwd: PathBuf,
-) -> Result<()> {
-    // first, set the client's view of the current directory
-    client.set_pwd(pwd.clone());
-    match client.run_command(prog) {
-        Ok(_) => Ok(()),
-        Err(e) => bail!("Error running program: {:?}", e),
-    }
-}
@@ -5,12 +5,14 @@ use dash::graph::program;
 use dash::runtime::new_client as client;
 use dash::util::Result;
 use failure::bail;
+use shell::interpreter::fallback::ShellFallback;
 use shell::interpreter::interpreter;
-use shell::scheduler::heuristic::HeuristicScheduler;
+use shell::interpreter::jobs::JobControl;
+use shell::interpreter::script::ScriptRunner;
+use shell::shellparser::script::parse_script;
 use std::env::current_dir;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::{Path, PathBuf};
+use std::fs::read_to_string;
+use std::path::Path;
 use std::process::exit;
 use structopt::StructOpt;
 use tracing::{error, Level};
@@ -53,6 +55,8 @@ struct Opt {
     runtime_port: String,
     #[structopt(help = "Dash binary to run")]
     binary: String,
+    #[structopt(help = "Arguments passed to the script as $1, $2, ...")]
+    args: Vec<String>,
     #[structopt(
         short = "f",
         long = "mount_file",
@@ -61,10 +65,15 @@ struct Opt {
     mount_file: String,
     #[structopt(
         short = "a",
-        long = "annotations_file",
-        help = "File with annotation list."
+        long = "annotations",
+        help = "File or directory with annotations; repeat to layer files, with later ones overriding earlier ones.",
+        raw(
+            alias = "\"annotations_file\"",
+            required = "true",
+            number_of_values = "1"
+        )
     )]
-    annotation_file: String,
+    annotation_files: Vec<String>,
     #[structopt(
         short = "pwd",
         long = "pwd",
@@ -91,13 +100,24 @@ struct Opt {
         default_value = "off"
     )]
     trace_level: TraceLevel,
+    #[structopt(
+        long = "scheduler",
+        help = "Scheduler to use: heuristic (the default), dp, exact, all-local or max-offload; overrides the config file's `scheduler`."
+    )]
+    scheduler: Option<String>,
+    #[structopt(
+        long = "learn",
+        help = "Log the commands no annotation matches to this file; `annotations missing` ranks them."
+    )]
+    learn: Option<String>,
 }
 
 fn main() {
     let opt = Opt::from_args();
     let binary = opt.binary;
+    let script_args = opt.args;
     let mount_info = opt.mount_file;
-    let annotation_file = opt.annotation_file;
+    let annotation_files = opt.annotation_files;
     let runtime_port = opt.runtime_port;
     let given_pwd = opt.pwd;
     let tmp_file = opt.tmp_file;
@@ -134,7 +154,7 @@ fn main() {
         pwd = Path::new(&given_pwd).to_path_buf();
     }
 
-    let mut client = match client::ShellClient::new(&runtime_port, pwd.clone(), &tmp_file) {
+    let client = match client::ShellClient::new(&runtime_port, pwd.clone(), &tmp_file) {
         Ok(s) => s,
         Err(e) => {
             error!("Failed to construct a shell with the given params: {:?}", e);
@@ -144,15 +164,15 @@ fn main() {
 
     let mut interpreter = match interpreter::Interpreter::new(
         &mount_info,
-        &annotation_file,
-        Box::new(HeuristicScheduler {}),
+        &annotation_files,
+        opt.scheduler.as_deref(),
     ) {
         Ok(i) => i,
         Err(e) => {
             error!(
-                "Failed to construct interpreter with given mount file: {:?} and annotation file: {:?} -> {:?}",
+                "Failed to construct interpreter with given mount file: {:?} and annotation files: {:?} -> {:?}",
                 mount_info,
-                annotation_file,
+                annotation_files,
                 e
             );
             exit(exitcode::USAGE);
@@ -160,80 +180,60 @@ fn main() {
     };
     interpreter.set_pwd(pwd.clone());
     interpreter.set_splitting_factor(splitting_factor);
+    interpreter.set_positional_args(&binary, script_args);
+    if let Some(log) = opt.learn {
+        interpreter.set_usage_log(Path::new(&log));
+    }
     // use more advanced file size query-er
     interpreter.set_offload_filecache(client.clone());
 
-    // loop over the binary, and execute all of the commands
-    let file = match File::open(binary) {
-        Ok(f) => f,
+    // parse the whole script, so multi-line constructs like loops can be evaluated
+    let script = match read_to_string(&binary) {
+        Ok(s) => s,
         Err(e) => {
             error!("Failed to open binary file: {:?}", e);
             exit(exitcode::OSFILE);
         }
     };
-
-    let reader = BufReader::new(file);
-    for (_, line) in reader.lines().enumerate() {
-        let line = match line {
-            Ok(l) => l,
-            Err(e) => {
-                error!("Failed to read line from binary: {:?}", e);
-                exit(exitcode::USAGE);
+    let nodes = match parse_script(&script) {
+        Ok(n) => n,
+        Err(e) => {
+            // syntax Posh can't represent: run the whole script locally
+            if prep {
+                error!("Failed to parse script {:?}: {:?}", binary, e);
+                exit(exitcode::DATAERR);
             }
-        };
-
-        match run_cmd(&line, &mut interpreter, &mut client, prep) {
-            Ok(_) => {}
-            Err(e) => {
-                error!("Failed to run line: {:?} with err {:?}", &line, e);
-                exit(exitcode::USAGE);
+            match ShellFallback::new(&script, &e, &mut interpreter).run() {
+                Ok(status) => exit(status),
+                Err(e) => {
+                    error!(
+                        "Failed to run script {:?} with the system shell: {:?}",
+                        binary, e
+                    );
+                    exit(exitcode::OSERR);
+                }
             }
         }
-    }
-}
+    };
 
-fn run_cmd(
-    cmd: &str,
-    interpreter: &mut interpreter::Interpreter,
-    client: &mut client::ShellClient,
-    prep: bool,
-) -> Result<()> {
-    let pwd = current_dir()?;
-    // if the line begins with a comment, just return
-    match cmd.to_string().starts_with("#") {
-        true => return Ok(()),
-        false => {}
-    }
-    tracing::info!("Scheduling {:?}", cmd);
-    let dag = match interpreter.parse_command_line(&cmd) {
-        Ok(d) => match d {
-            Some(graph) => graph,
-            None => {
-                return Ok(());
-            }
-        },
+    let mut runner = ScriptRunner::default();
+    let result = match prep {
+        // just run the scheduling phases of each pipeline
+        true => runner.run(&mut interpreter, &nodes, &mut |_: program::Program| Ok(())),
+        false => {
+            let mut jobs = JobControl::new(client);
+            let result = runner.run(&mut interpreter, &nodes, &mut jobs);
+            // background jobs would be cut off when the script exits
+            jobs.wait_all();
+            result
+        }
+    };
+    let status = match result {
+        Ok(s) => s,
         Err(e) => {
-            bail!("{:?}", e);
+            error!("Failed to run script {:?}: {:?}", binary, e);
+            exit(exitcode::USAGE);
         }
     };
-    interpreter.set_pwd(pwd.clone());
-    // just run the scheduling phases of this pipeline
-    if prep {
-        return Ok(());
-    }
-    run_program(dag, client, pwd.clone())?;
-    Ok(())
-}
-
-fn run_program(
-    prog: program::Program,
-    client: &mut client::ShellClient,
-    pwd: PathBuf,
-) -> Result<()> {
-    // first, set the client's view of the current directory
-    client.set_pwd(pwd.clone());
-    match client.run_command(prog) {
-        Ok(_) => Ok(()),
-        Err(e) => bail!("Error running program: {:?}", e),
-    }
+    exit(status);
 }
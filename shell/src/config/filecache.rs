@@ -1,4 +1,5 @@
 use super::filesize::{FileSize, QueryFileSize};
+use super::network::FileNetwork;
 use dash::graph::filestream::FileStream;
 use dash::util::Result;
 use std::collections::HashMap;
@@ -68,6 +69,30 @@ impl FileCache {
         self.size_map.insert(path.to_path_buf(), size as u64);
     }
 
+    /// Forgets the sizes of everything whose path starts with `prefix`, and of the directories
+    /// containing it; call after a command creates or changes files there.
+    pub fn invalidate_prefix(&mut self, prefix: &Path) {
+        let prefix_str = prefix.to_string_lossy().to_string();
+        self.size_map.retain(|path, _| {
+            !path.to_string_lossy().starts_with(prefix_str.as_str()) && !prefix.starts_with(path)
+        });
+    }
+
+    /// Updates the mount configuration used to query sizes. Sizes cached under mounts that
+    /// changed should be invalidated separately.
+    pub fn set_config(&mut self, config: &FileNetwork) {
+        self.file_size_module.set_config(config);
+    }
+
+    /// Forgets how relative paths were resolved; call when the working directory changes.
+    pub fn clear_paths(&mut self) {
+        self.path_map.clear();
+    }
+
+    pub fn is_dir(&self, path: &Path) -> bool {
+        self.file_size_module.is_dir(path)
+    }
+
     pub fn resolve_path(&mut self, filestream: &mut FileStream, pwd: &Path) -> Result<()> {
         if filestream.is_absolute() {
             return Ok(());
@@ -111,3 +136,44 @@ impl FileCache {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every file has size 1 when queried.
+    struct UnitFileSize;
+    impl FileSize for UnitFileSize {
+        fn file_size(&self, _path: &Path) -> Result<u64> {
+            Ok(1)
+        }
+        fn is_dir(&self, _path: &Path) -> bool {
+            false
+        }
+        fn dir_size(&self, _path: &Path) -> Result<u64> {
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_invalidate_prefix() {
+        let mut filecache = FileCache::new(Box::new(UnitFileSize {}));
+        let paths = [
+            "/d",
+            "/d/out",
+            "/d/out/x_aa",
+            "/d/out/x_ab",
+            "/d/out2",
+            "/d/in.txt",
+        ];
+        for path in paths.iter() {
+            filecache.set_size(Path::new(path), 10);
+        }
+        filecache.invalidate_prefix(Path::new("/d/out/x_"));
+        let sizes: Vec<f64> = paths
+            .iter()
+            .map(|path| filecache.get_size(PathBuf::from(path)).unwrap())
+            .collect();
+        assert_eq!(sizes, vec![1.0, 1.0, 1.0, 1.0, 10.0, 10.0]);
+    }
+}
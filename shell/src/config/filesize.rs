@@ -41,6 +41,8 @@ pub trait FileSize {
         }
         Ok(ret)
     }
+    /// Called when the mount configuration is reloaded.
+    fn set_config(&mut self, _config: &FileNetwork) {}
 }
 
 impl FileSize for QueryFileSize {
@@ -86,6 +88,10 @@ impl FileSize for OffloadQueryFileSize {
         Ok(total_size)
     }
 
+    fn set_config(&mut self, config: &FileNetwork) {
+        self.config = config.clone();
+    }
+
     fn query_file_list(&self, paths: &Vec<PathBuf>) -> Result<HashMap<PathBuf, u64>> {
         let mut ret: HashMap<PathBuf, u64> = HashMap::default();
         let mut requests: HashMap<Location, Vec<PathBuf>> = HashMap::default();
@@ -10,7 +10,7 @@ use std::collections::HashMap;
 use std::fs::read_to_string;
 use std::path::{Path, PathBuf};
 use std::str::from_utf8;
-use yaml_rust::YamlLoader;
+use yaml_rust::{Yaml, YamlLoader};
 named_complete!(
     parse_client<Location>,
     map!(tag!("client"), |_| { Location::Client })
@@ -52,6 +52,10 @@ pub struct FileNetwork {
     server_info: HashMap<ServerKey, ServerInfo>,
     /// Link speed information (topology information)
     links: HashMap<(Location, Location), u32>,
+    /// CPU speed of each machine, as a percentage of the client's
+    cpu_speeds: HashMap<Location, u32>,
+    /// Name of the scheduler to use, unless one is given on the command line
+    scheduler: Option<String>,
     /// list of servers
     locations: Vec<Location>,
 }
@@ -88,11 +92,14 @@ impl FileNetwork {
         match yaml["mounts"].as_hash() {
             Some(map) => {
                 for (key, value) in map.iter() {
-                    let mount = Path::new(&value.as_str().unwrap()).to_path_buf();
-                    let ip = ServerKey {
-                        ip: key.as_str().unwrap().to_string(),
+                    let (ip, mount) = match (key.as_str(), value.as_str()) {
+                        (Some(ip), Some(mount)) => (ip, mount),
+                        _ => bail!("mounts: expected `ip: path`, found {:?}: {:?}", key, value),
                     };
-                    path_to_addr.insert(mount, ip);
+                    path_to_addr.insert(
+                        Path::new(mount).to_path_buf(),
+                        ServerKey { ip: ip.to_string() },
+                    );
                 }
             }
             None => {
@@ -103,11 +110,17 @@ impl FileNetwork {
         match yaml["links"].as_hash() {
             Some(map) => {
                 for (key, value) in map.iter() {
-                    let link_key =
-                        parse_link_key(CompleteByteSlice(key.as_str().unwrap().as_bytes()))
-                            .unwrap()
-                            .1;
-                    let speed: u32 = value.as_i64().unwrap() as u32;
+                    let link_key = match key
+                        .as_str()
+                        .map(|key| parse_link_key(CompleteByteSlice(key.as_bytes())))
+                    {
+                        Some(Ok((_, link_key))) => link_key,
+                        _ => bail!("links: expected `(ip,ip)`, found {:?}", key),
+                    };
+                    let speed: u32 = match value.as_i64() {
+                        Some(speed) => speed as u32,
+                        None => bail!("links: expected a speed for {:?}, found {:?}", key, value),
+                    };
                     links.insert(link_key, speed);
                 }
             }
@@ -116,14 +129,50 @@ impl FileNetwork {
             }
         }
 
+        let mut cpu_speeds: HashMap<Location, u32> = HashMap::default();
+        if let Some(map) = yaml["cpu_speed"].as_hash() {
+            for (key, value) in map.iter() {
+                let location = match key.as_str() {
+                    Some("client") => Location::Client,
+                    Some(ip) => Location::Server(ip.to_string()),
+                    None => bail!("cpu_speed: expected an ip, found {:?}", key),
+                };
+                match value.as_i64() {
+                    Some(percent) if percent > 0 => {
+                        cpu_speeds.insert(location, percent as u32);
+                    }
+                    _ => bail!(
+                        "cpu_speed: expected a percentage of the client's speed for {:?}, found {:?}",
+                        key,
+                        value
+                    ),
+                }
+            }
+        }
+
+        let scheduler = match &yaml["scheduler"] {
+            Yaml::BadValue => None,
+            value => match value.as_str() {
+                Some(name) => Some(name.to_string()),
+                None => bail!("scheduler: expected a scheduler name, found {:?}", value),
+            },
+        };
+
         // TODO: add in parsing options for servers accessing other machines via NFS
         match yaml["tmp_directory"].as_hash() {
             Some(map) => {
                 for (key, value) in map.iter() {
-                    let ip = ServerKey {
-                        ip: key.as_str().unwrap().to_string(),
+                    let (ip, directory) = match (key.as_str(), value.as_str()) {
+                        (Some(ip), Some(directory)) => (
+                            ServerKey { ip: ip.to_string() },
+                            Path::new(directory).to_path_buf(),
+                        ),
+                        _ => bail!(
+                            "tmp_directory: expected `ip: path`, found {:?}: {:?}",
+                            key,
+                            value
+                        ),
                     };
-                    let directory = Path::new(&value.as_str().unwrap()).to_path_buf();
                     let info = ServerInfo {
                         tmp_directory: directory,
                         other_mounted_directories: Vec::new(),
@@ -145,6 +194,8 @@ impl FileNetwork {
             path_to_addr: path_to_addr,
             server_info: server_info,
             links: links,
+            cpu_speeds,
+            scheduler,
             locations: servers,
         })
     }
@@ -163,10 +214,47 @@ impl FileNetwork {
             path_to_addr: path_to_addr,
             server_info: server_info,
             links: links,
+            cpu_speeds: HashMap::default(),
+            scheduler: None,
             locations: servers,
         }
     }
 
+    pub fn set_cpu_speed(&mut self, location: Location, percent: u32) {
+        self.cpu_speeds.insert(location, percent);
+    }
+
+    /// How fast the machine runs commands compared to the client, which is 1.
+    pub fn cpu_speed(&self, location: &Location) -> f64 {
+        match self.cpu_speeds.get(location) {
+            Some(percent) => *percent as f64 / 100.0,
+            None => 1.0,
+        }
+    }
+
+    /// The scheduler the config file names, if any.
+    pub fn scheduler_name(&self) -> Option<&str> {
+        self.scheduler.as_deref()
+    }
+
+    pub fn num_mounts(&self) -> usize {
+        self.path_to_addr.len()
+    }
+
+    /// Mounts that were added, removed or moved to another server in `other`.
+    pub fn changed_mounts(&self, other: &FileNetwork) -> Vec<PathBuf> {
+        let mut changed: Vec<PathBuf> = self
+            .path_to_addr
+            .iter()
+            .chain(other.path_to_addr.iter())
+            .filter(|(mount, _)| self.path_to_addr.get(*mount) != other.path_to_addr.get(*mount))
+            .map(|(mount, _)| mount.clone())
+            .collect();
+        changed.sort();
+        changed.dedup();
+        changed
+    }
+
     pub fn get_location_list(&self) -> Vec<Location> {
         self.locations.clone()
     }
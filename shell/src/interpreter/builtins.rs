@@ -0,0 +1,207 @@
+use super::Result;
+use failure::bail;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+
+/// Evaluates the arguments to `test` (or `[ ... ]`, with the brackets stripped).
+/// Relative paths are resolved against the given working directory.
+pub fn eval_test(args: &[String], pwd: &Path) -> Result<bool> {
+    if args.is_empty() {
+        return Ok(false);
+    }
+    let mut evaluator = TestEvaluator { args, pos: 0, pwd };
+    let result = evaluator.parse_or()?;
+    if evaluator.pos != args.len() {
+        bail!("test: unexpected argument {:?}", args[evaluator.pos]);
+    }
+    Ok(result)
+}
+
+/// Removes `.` and `..` components from an absolute path without touching the filesystem, like
+/// `cd` does: `cd ..` from a symlinked directory goes back to where it was entered from.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut ret = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                ret.pop();
+            }
+            c => ret.push(c.as_os_str()),
+        }
+    }
+    ret
+}
+
+struct TestEvaluator<'a> {
+    args: &'a [String],
+    pos: usize,
+    pwd: &'a Path,
+}
+
+impl<'a> TestEvaluator<'a> {
+    fn peek(&self) -> Option<&str> {
+        self.args.get(self.pos).map(|x| x.as_str())
+    }
+
+    fn next_arg(&mut self) -> Result<String> {
+        match self.args.get(self.pos) {
+            Some(arg) => {
+                self.pos += 1;
+                Ok(arg.clone())
+            }
+            None => bail!("test: argument expected"),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<bool> {
+        let mut result = self.parse_and()?;
+        while self.peek() == Some("-o") {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            result = result || right;
+        }
+        Ok(result)
+    }
+
+    fn parse_and(&mut self) -> Result<bool> {
+        let mut result = self.parse_not()?;
+        while self.peek() == Some("-a") {
+            self.pos += 1;
+            let right = self.parse_not()?;
+            result = result && right;
+        }
+        Ok(result)
+    }
+
+    fn parse_not(&mut self) -> Result<bool> {
+        if self.peek() == Some("!") && self.args.len() > self.pos + 1 {
+            self.pos += 1;
+            return Ok(!self.parse_not()?);
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<bool> {
+        if self.peek() == Some("(") {
+            self.pos += 1;
+            let result = self.parse_or()?;
+            if self.next_arg()? != ")" {
+                bail!("test: expected \")\"");
+            }
+            return Ok(result);
+        }
+        let first = self.next_arg()?;
+        // binary operators
+        if let Some(op) = self.peek() {
+            if is_binary_op(op) {
+                let op = self.next_arg()?;
+                let second = self.next_arg()?;
+                return eval_binary(&first, &op, &second);
+            }
+        }
+        // unary operators
+        if first.len() == 2 && first.starts_with('-') && self.pos < self.args.len() {
+            let operand = self.next_arg()?;
+            return self.eval_unary(&first, &operand);
+        }
+        Ok(!first.is_empty())
+    }
+
+    fn resolve(&self, path: &str) -> PathBuf {
+        self.pwd.join(path)
+    }
+
+    fn eval_unary(&self, op: &str, operand: &str) -> Result<bool> {
+        let metadata = fs::metadata(self.resolve(operand));
+        Ok(match op {
+            "-z" => operand.is_empty(),
+            "-n" => !operand.is_empty(),
+            "-e" => metadata.is_ok(),
+            "-f" => metadata.map(|m| m.is_file()).unwrap_or(false),
+            "-d" => metadata.map(|m| m.is_dir()).unwrap_or(false),
+            "-s" => metadata.map(|m| m.len() > 0).unwrap_or(false),
+            "-L" | "-h" => fs::symlink_metadata(self.resolve(operand))
+                .map(|m| m.file_type().is_symlink())
+                .unwrap_or(false),
+            "-r" => metadata
+                .map(|m| m.permissions().mode() & 0o444 != 0)
+                .unwrap_or(false),
+            "-w" => metadata
+                .map(|m| m.permissions().mode() & 0o222 != 0)
+                .unwrap_or(false),
+            "-x" => metadata
+                .map(|m| m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false),
+            _ => bail!("test: unknown unary operator {:?}", op),
+        })
+    }
+}
+
+fn is_binary_op(op: &str) -> bool {
+    matches!(
+        op,
+        "=" | "==" | "!=" | "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge"
+    )
+}
+
+fn eval_binary(first: &str, op: &str, second: &str) -> Result<bool> {
+    match op {
+        "=" | "==" => return Ok(first == second),
+        "!=" => return Ok(first != second),
+        _ => {}
+    }
+    let (a, b) = match (first.trim().parse::<i64>(), second.trim().parse::<i64>()) {
+        (Ok(a), Ok(b)) => (a, b),
+        _ => bail!(
+            "test: integer expression expected: {:?} {} {:?}",
+            first,
+            op,
+            second
+        ),
+    };
+    Ok(match op {
+        "-eq" => a == b,
+        "-ne" => a != b,
+        "-lt" => a < b,
+        "-le" => a <= b,
+        "-gt" => a > b,
+        "-ge" => a >= b,
+        _ => unreachable!(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test(args: &str) -> bool {
+        let args: Vec<String> = args.split_whitespace().map(|x| x.to_string()).collect();
+        eval_test(&args, Path::new("/")).unwrap()
+    }
+
+    #[test]
+    fn test_eval_test() {
+        assert!(test("-d tmp"));
+        assert!(!test("-f tmp"));
+        assert!(!test("-e does/not/exist"));
+        assert!(test("abc = abc"));
+        assert!(test("3 -lt 10"));
+        assert!(test("! 3 -gt 10"));
+        assert!(test("-n x -a ( 1 -eq 2 -o 2 -eq 2 )"));
+        assert!(!test("-z x"));
+        assert!(!test(""));
+        let bad: Vec<String> = vec!["a".to_string(), "-lt".to_string(), "b".to_string()];
+        assert!(eval_test(&bad, Path::new("/")).is_err());
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(
+            normalize_path(Path::new("/d/c/folder/../other/./x")),
+            Path::new("/d/c/other/x")
+        );
+        assert_eq!(normalize_path(Path::new("/d/../../")), Path::new("/"));
+    }
+}
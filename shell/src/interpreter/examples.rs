@@ -6,8 +6,10 @@ use config::filesize::FileSize;
 use config::network::{FileNetwork, ServerInfo, ServerKey};
 use dash::graph::Location;
 use dash::util::Result;
+use failure::bail;
 use interpreter::Interpreter;
 use scheduler::dp::DPScheduler;
+use scheduler::Scheduler;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 struct TestFileSize;
@@ -27,6 +29,9 @@ impl FileSize for TestFileSize {
     }
 
     fn file_size(&self, path: &Path) -> Result<u64> {
+        if path.ends_with("missing") {
+            bail!("{}: No such file or directory", path.display());
+        }
         let mounts: Vec<PathBuf> = vec![
             Path::new("/b/a").to_path_buf(),
             Path::new("/c/b").to_path_buf(),
@@ -78,7 +83,26 @@ impl FileSize for TestFileSize {
 fn get_git_clone_parser() -> CmdParser {
     let mut parser = CmdParser::new("git clone");
     let annotation =
-        "git clone[needs_current_dir]: PARAMS:[(type:str,size:1),(type:output_file,size:1)]";
+        "git clone[needs_current_dir]: PARAMS:[(type:str,size:1),(type:output_prefix,size:1)]";
+    parser
+        .add_annotation(Command::new(annotation).unwrap())
+        .unwrap();
+    parser
+}
+
+fn get_git_parser() -> CmdParser {
+    let mut parser = CmdParser::new("git");
+    let annotation = "git: OPTPARAMS:[(short:C,type:input_file,size:1)]";
+    parser
+        .add_annotation(Command::new(annotation).unwrap())
+        .unwrap();
+    parser
+}
+
+fn get_git_status_parser() -> CmdParser {
+    let mut parser = CmdParser::new("git status");
+    let annotation =
+        "git status[implicit_input:(.git,relative_to:C),env:GIT_DIR]: FLAGS:[(short:s,long:short)]";
     parser
         .add_annotation(Command::new(annotation).unwrap())
         .unwrap();
@@ -97,7 +121,7 @@ fn get_git_commit_parser() -> CmdParser {
 // "tar: FLAGS:[(short:o,long:option,desc:(foo foo)),(short:d,long:debug,desc:(debug mode))] OPTPARAMS:[(short:d,long:directory,type:input_file,size:1,default_value:\".\"),(short:p,long:parent,desc:(parent dir),type:str,size:1,default_value:\"..\"),]"
 fn get_cat_parser() -> CmdParser {
     let mut parser = CmdParser::new("cat");
-    let annotation = "cat: PARAMS:[(type:input_file,splittable,size:list(list_separator:( ))),]";
+    let annotation = "cat[matching:(*.parquet,not_splittable)]: PARAMS:[(type:input_file,splittable,size:list(list_separator:( ))),]";
     parser
         .add_annotation(Command::new(annotation).unwrap())
         .unwrap();
@@ -133,7 +157,7 @@ fn get_grep_parser() -> CmdParser {
 fn get_wc_parser() -> CmdParser {
     let mut parser = CmdParser::new("wc");
     let annotation =
-        "wc: FLAGS:[(short:l,long:lines)] PARAMS:[(type:input_file,size:list(list_separator:( )))]";
+        "wc[splittable_across_input,reducer:sum]: FLAGS:[(short:l,long:lines)] PARAMS:[(type:input_file,splittable,size:list(list_separator:( )))]";
     parser
         .add_annotation(Command::new(annotation).unwrap())
         .unwrap();
@@ -217,9 +241,27 @@ fn get_comm_parser() -> CmdParser {
     parser
 }
 
+fn get_cmp_parser() -> CmdParser {
+    let mut parser = CmdParser::new("cmp");
+    let annotation = "cmp[random_access,reduces_input]: FLAGS:[(short:s)] PARAMS:[(type:input_file,size:1),(type:input_file,size:1)]";
+    parser
+        .add_annotation(Command::new(annotation).unwrap())
+        .unwrap();
+    parser
+}
+
+fn get_split_parser() -> CmdParser {
+    let mut parser = CmdParser::new("split");
+    let annotation = "split: OPTPARAMS:[(short:l,type:str,size:1)] PARAMS:[(type:input_file,size:1),(type:output_prefix,size:1)]";
+    parser
+        .add_annotation(Command::new(annotation).unwrap())
+        .unwrap();
+    parser
+}
+
 fn get_sort_parser() -> CmdParser {
     let mut parser = CmdParser::new("sort");
-    let annotation = "sort: OPTPARAMS:[(short:k,size:1,long:key,type:str),(short:t,size:1,type:str)] FLAGS:[(short:n),(short:r)]";
+    let annotation = "sort[splittable_across_input,reducer:merge]: OPTPARAMS:[(short:k,size:1,long:key,type:str),(short:t,size:1,type:str),(short:o,size:1,long:output,type:output_file)] FLAGS:[(short:n),(short:r)] PARAMS:[(type:input_file,size:list(list_separator:( )))]";
     parser
         .add_annotation(Command::new(annotation).unwrap())
         .unwrap();
@@ -235,6 +277,33 @@ fn get_zannotate_parser() -> CmdParser {
     parser
 }
 
+fn get_xargs_parser() -> CmdParser {
+    let mut parser = CmdParser::new("xargs");
+    let annotation = "xargs: FLAGS:[(short:r)] OPTPARAMS:[(short:n,type:str,size:1),(short:I,type:str,size:1)] PARAMS:[(type:command,size:list(list_separator:( )))]";
+    parser
+        .add_annotation(Command::new(annotation).unwrap())
+        .unwrap();
+    parser
+}
+
+fn get_sh_parser() -> CmdParser {
+    let mut parser = CmdParser::new("sh");
+    let annotation = "sh: OPTPARAMS:[(short:c,type:command,size:1)]";
+    parser
+        .add_annotation(Command::new(annotation).unwrap())
+        .unwrap();
+    parser
+}
+
+fn get_gzip_parser() -> CmdParser {
+    let mut parser = CmdParser::new("gzip");
+    let annotation = "gzip[selectivity:(1.5,when:d),selectivity:0.3,cpu_cost:0.02,startup_cost:0.01]: FLAGS:[(short:c),(short:d)] PARAMS:[(type:input_file,size:list(list_separator:( )))]";
+    parser
+        .add_annotation(Command::new(annotation).unwrap())
+        .unwrap();
+    parser
+}
+
 fn get_test_parser() -> Parser {
     let mut parsers: HashMap<String, CmdParser> = HashMap::default();
     parsers.insert("cat".to_string(), get_cat_parser());
@@ -250,9 +319,16 @@ fn get_test_parser() -> Parser {
     parsers.insert("sort".to_string(), get_sort_parser());
     parsers.insert("zannotate".to_string(), get_zannotate_parser());
     parsers.insert("wc".to_string(), get_wc_parser());
+    parsers.insert("git".to_string(), get_git_parser());
+    parsers.insert("git status".to_string(), get_git_status_parser());
     parsers.insert("git clone".to_string(), get_git_clone_parser());
     parsers.insert("git commit".to_string(), get_git_commit_parser());
     parsers.insert("comm".to_string(), get_comm_parser());
+    parsers.insert("cmp".to_string(), get_cmp_parser());
+    parsers.insert("split".to_string(), get_split_parser());
+    parsers.insert("xargs".to_string(), get_xargs_parser());
+    parsers.insert("sh".to_string(), get_sh_parser());
+    parsers.insert("gzip".to_string(), get_gzip_parser());
     Parser::construct(parsers)
 }
 fn get_test_filemap() -> HashMap<PathBuf, ServerKey> {
@@ -344,16 +420,25 @@ fn get_test_server_info() -> HashMap<ServerKey, ServerInfo> {
     }
     ret
 }
-fn get_test_network_config() -> FileNetwork {
+pub fn get_test_network_config() -> FileNetwork {
     FileNetwork::construct(get_test_filemap(), get_test_links(), get_test_server_info())
 }
 
 pub fn get_test_interpreter() -> Interpreter {
-    // TODO: actually choose with scheduler to use
-    let scheduler = Box::new(DPScheduler {});
+    get_test_interpreter_with_config(get_test_network_config())
+}
+
+pub fn get_test_interpreter_with_config(config: FileNetwork) -> Interpreter {
+    get_test_interpreter_with_scheduler(config, Box::new(DPScheduler {}))
+}
+
+pub fn get_test_interpreter_with_scheduler(
+    config: FileNetwork,
+    scheduler: Box<dyn Scheduler>,
+) -> Interpreter {
     let filesizemod = Box::new(TestFileSize {});
     Interpreter::construct(
-        get_test_network_config(),
+        config,
         get_test_parser(),
         scheduler,
         Path::new("/d/c/folder").to_path_buf(),
@@ -0,0 +1,430 @@
+use super::{annotations2, config, Result};
+use annotations2::argument_matcher::ArgMatch;
+use annotations2::grammar::{AccessType, Argument, Opt, ParsingOptions, Reducer, ValueCondition};
+use annotations2::parser::Parser;
+use config::filecache::FileCache;
+use config::network::FileNetwork;
+use dash::graph::command::NodeArg;
+use dash::graph::filestream::FileStream;
+use dash::graph::program::{Elem, Program};
+use dash::graph::stream::DashStream;
+use dash::graph::Location;
+use std::path::Path;
+
+/// Words are printed in a column this wide, followed by what they were matched as.
+const WORD_WIDTH: usize = 16;
+
+/// Explains, for each node of a parsed (but not yet scheduled) program, which annotation its
+/// invocation matched, what each word was matched as, and where the files it uses live.
+/// Nothing in the program is modified; file paths are resolved on copies.
+pub fn explain_program(
+    program: &Program,
+    parser: &Parser,
+    config: &FileNetwork,
+    filecache: &mut FileCache,
+    pwd: &Path,
+) -> Result<String> {
+    let mut out = String::new();
+    for id in program.execution_order() {
+        let node = match program.get_node(id) {
+            Some(node) => node,
+            None => continue,
+        };
+        match node.get_elem() {
+            Elem::Cmd(cmdnode) => {
+                let name = cmdnode.get_name();
+                let invocation = cmdnode.get_string_args();
+                out.push_str(&format!("{}\n", shell_words(&name, &invocation)));
+                if invocation.is_empty() {
+                    out.push_str("  no arguments, so annotations are not consulted\n");
+                    continue;
+                }
+                let arg_match = match parser.get_cmd_parser(&name, &invocation) {
+                    Some(cmd_parser) => match cmd_parser.match_annotations(&invocation) {
+                        Ok((ind, mut arg_match)) => {
+                            out.push_str(&format!(
+                                "  matched annotation {} of {} for `{}`{}\n",
+                                ind + 1,
+                                cmd_parser.get_annotations().len(),
+                                cmd_parser.get_name(),
+                                describe_options(arg_match.get_parsing_options())
+                            ));
+                            explain_nested(&mut out, parser, &mut arg_match);
+                            arg_match
+                        }
+                        Err(reasons) => {
+                            out.push_str(&format!(
+                                "  no annotation for `{}` matched, so every word is a Str param:\n",
+                                cmd_parser.get_name()
+                            ));
+                            for (ind, reason) in reasons.iter().enumerate() {
+                                out.push_str(&format!("    annotation {}: {}\n", ind + 1, reason));
+                            }
+                            ArgMatch::new_default(&name, &invocation)
+                        }
+                    },
+                    None => {
+                        out.push_str(&format!(
+                            "  no annotations for `{}`, so every word is a Str param\n",
+                            name
+                        ));
+                        ArgMatch::new_default(&name, &invocation)
+                    }
+                };
+                explain_arguments(&mut out, &arg_match, config, filecache, pwd)?;
+            }
+            Elem::Read(readnode) => {
+                let input = readnode.get_input_ref();
+                out.push_str(&format!("< {}\n", input.get_path().display()));
+                out.push_str(&format!(
+                    "  {}\n",
+                    describe_file(input, config, filecache, pwd)?
+                ));
+            }
+            Elem::Write(writenode) => {
+                if let DashStream::File(output) = writenode.get_output_ref() {
+                    out.push_str(&format!("> {}\n", output.get_path().display()));
+                    out.push_str(&format!(
+                        "  {}\n",
+                        describe_file(output, config, filecache, pwd)?
+                    ));
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Adds a line for each word of the matched invocation.
+fn explain_arguments(
+    out: &mut String,
+    arg_match: &ArgMatch,
+    config: &FileNetwork,
+    filecache: &mut FileCache,
+    pwd: &Path,
+) -> Result<()> {
+    let options = arg_match.get_parsing_options();
+    let mut param_count = 0;
+    for (argument, values) in arg_match.get_arguments().iter() {
+        let (param, owner) = match argument {
+            Argument::LoneOption(opt) => {
+                push_word(
+                    out,
+                    &opt_word(opt, options),
+                    &format!("flag {}", opt_names(opt)),
+                );
+                continue;
+            }
+            Argument::OptWithParam(opt, param) => {
+                push_word(
+                    out,
+                    &opt_word(opt, options),
+                    &format!("option {}", opt_names(opt)),
+                );
+                (param, format!("option {}", opt_names(opt)))
+            }
+            Argument::LoneParam(param) => {
+                param_count += 1;
+                (param, format!("param {}", param_count))
+            }
+        };
+        for value in values.iter() {
+            match value {
+                NodeArg::Str(s) if param.is_file_type() => {
+                    // `-` in place of a file
+                    push_word(
+                        out,
+                        s,
+                        &format!(
+                            "value of {}, {:?}, {}",
+                            owner,
+                            param.param_type,
+                            match param.is_output_type() {
+                                true => "the command's stdout",
+                                false => "the command's stdin",
+                            }
+                        ),
+                    );
+                }
+                NodeArg::Str(s) => {
+                    push_word(
+                        out,
+                        s,
+                        &format!("value of {}, {:?}", owner, param.param_type),
+                    );
+                }
+                NodeArg::Stream(fs) => {
+                    push_word(
+                        out,
+                        &fs.get_path().display().to_string(),
+                        &format!(
+                            "value of {}, {:?}, {}",
+                            owner,
+                            param.param_type,
+                            describe_file(fs, config, filecache, pwd)?
+                        ),
+                    );
+                }
+            }
+        }
+    }
+    // what the command reads without it being named on the command line
+    let mut resolved = arg_match.clone();
+    resolved.resolve_file_paths(filecache, pwd)?;
+    for fs in resolved.implicit_dependencies().iter() {
+        out.push_str(&format!(
+            "  also reads {}\n",
+            describe_file(fs, config, filecache, pwd)?
+        ));
+    }
+    for fs in resolved.nested_dependencies().iter() {
+        out.push_str(&format!(
+            "  runs a command that uses {}, so it runs there\n",
+            describe_file(fs, config, filecache, pwd)?
+        ));
+    }
+    for var in arg_match.get_env_vars().iter() {
+        out.push_str(&format!(
+            "  also reads ${}, which is forwarded to where it runs\n",
+            var
+        ));
+    }
+    Ok(())
+}
+
+/// Matches the command lines the command runs against their own annotations, and adds a line
+/// for each.
+fn explain_nested(out: &mut String, parser: &Parser, arg_match: &mut ArgMatch) {
+    let nested = arg_match.nested_invocations();
+    if nested.is_empty() {
+        return;
+    }
+    if let Err(e) = parser.match_nested(arg_match) {
+        out.push_str(&format!(
+            "  runs a command line no annotation matched, so it stays on the client: {}\n",
+            e
+        ));
+        return;
+    }
+    for (_, words) in nested.iter() {
+        out.push_str(&format!(
+            "  runs `{}`, matched against the annotations for `{}`\n",
+            words.join(" "),
+            words[0]
+        ));
+    }
+    if arg_match.must_run_on_client() {
+        out.push_str("  runs a script that names files, so it stays on the client\n");
+    }
+}
+
+fn push_word(out: &mut String, word: &str, description: &str) {
+    out.push_str(&format!(
+        "  {:width$} {}\n",
+        word,
+        description,
+        width = WORD_WIDTH
+    ));
+}
+
+/// Resolves the path of a file argument the way the interpreter does, and says where it lives.
+fn describe_file(
+    fs: &FileStream,
+    config: &FileNetwork,
+    filecache: &mut FileCache,
+    pwd: &Path,
+) -> Result<String> {
+    let mut resolved = fs.clone();
+    filecache.resolve_path(&mut resolved, pwd)?;
+    let location = match config.get_location(&resolved) {
+        Location::Client => "client".to_string(),
+        Location::Server(ip) => format!("server {}", ip),
+    };
+    Ok(format!("{} on {}", resolved.get_path().display(), location))
+}
+
+fn describe_options(options: &ParsingOptions) -> String {
+    let mut names: Vec<&str> = Vec::new();
+    if options.long_arg_single_dash {
+        names.push("long_arg_single_dash");
+    }
+    if options.splittable_across_input {
+        names.push("splittable_across_input");
+    }
+    if options.reduces_input {
+        names.push("reduces_input");
+    }
+    if options.needs_current_dir {
+        names.push("needs_current_dir");
+    }
+    if options.access_type == AccessType::Random {
+        names.push("random_access");
+    }
+    if options.params_first {
+        names.push("params_first");
+    }
+    let reducer = match &options.reducer {
+        Reducer::Concat => "".to_string(),
+        Reducer::Sum => "reducer:sum".to_string(),
+        Reducer::Merge => "reducer:merge".to_string(),
+        Reducer::Uniq => "reducer:uniq".to_string(),
+        Reducer::Custom(line) => format!("reducer:({})", line),
+    };
+    if !reducer.is_empty() {
+        names.push(&reducer);
+    }
+    let implicit_inputs: Vec<String> = options
+        .implicit_inputs
+        .iter()
+        .map(|input| match input.relative_to.as_str() {
+            "" => format!("implicit_input:({})", input.path),
+            option => format!("implicit_input:({},relative_to:{})", input.path, option),
+        })
+        .chain(options.env_vars.iter().map(|var| format!("env:{}", var)))
+        .chain(options.cost.selectivity.iter().map(
+            |selectivity| match selectivity.when.is_empty() {
+                true => format!("selectivity:{}", selectivity.ratio),
+                false => format!(
+                    "selectivity:({},when:{})",
+                    selectivity.ratio,
+                    selectivity.when.join(",when:")
+                ),
+            },
+        ))
+        .chain(
+            options
+                .cost
+                .cpu_cost
+                .map(|cost| format!("cpu_cost:{}", cost)),
+        )
+        .chain(
+            options
+                .cost
+                .startup_cost
+                .map(|cost| format!("startup_cost:{}", cost)),
+        )
+        .chain(options.conditions.iter().map(describe_condition))
+        .collect();
+    names.extend(implicit_inputs.iter().map(|name| name.as_str()));
+    match names.is_empty() {
+        true => "".to_string(),
+        false => format!(" [{}]", names.join(",")),
+    }
+}
+
+/// A condition as it is written in an annotation.
+fn describe_condition(condition: &ValueCondition) -> String {
+    let mut parts = vec![match condition.is_regex {
+        true => format!("/{}/", condition.pattern),
+        false => condition.pattern.clone(),
+    }];
+    if let Some(ratio) = condition.selectivity {
+        parts.push(format!("selectivity:{}", ratio));
+    }
+    match condition.access_type {
+        Some(AccessType::Random) => parts.push("random_access".to_string()),
+        Some(AccessType::Sequential) => parts.push("sequential_access".to_string()),
+        None => {}
+    }
+    match condition.splittable {
+        Some(true) => parts.push("splittable".to_string()),
+        Some(false) => parts.push("not_splittable".to_string()),
+        None => {}
+    }
+    format!("matching:({})", parts.join(","))
+}
+
+/// The word the option is written as on the command line.
+fn opt_word(opt: &Opt, options: &ParsingOptions) -> String {
+    if !opt.short.is_empty() {
+        format!("-{}", opt.short)
+    } else if options.long_arg_single_dash {
+        format!("-{}", opt.long)
+    } else {
+        format!("--{}", opt.long)
+    }
+}
+
+fn opt_names(opt: &Opt) -> String {
+    match (opt.short.is_empty(), opt.long.is_empty()) {
+        (false, false) => format!("-{}/--{}", opt.short, opt.long),
+        (false, true) => format!("-{}", opt.short),
+        _ => format!("--{}", opt.long),
+    }
+}
+
+fn shell_words(name: &str, args: &[String]) -> String {
+    let mut words = vec![name.to_string()];
+    words.extend(args.iter().cloned());
+    words.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::examples::get_test_interpreter;
+
+    #[test]
+    fn test_explain() {
+        let mut interpreter = get_test_interpreter();
+        let out = interpreter
+            .explain_command_line("cat foo.txt /b/a/bar.txt | grep -v baz | wc -z > out.txt")
+            .unwrap();
+        assert!(out.contains("matched annotation 1 of 1 for `cat`"));
+        assert!(
+            out.contains("value of param 1, InputFile, /d/c/folder/foo.txt on server 127.0.0.1")
+        );
+        assert!(out.contains("value of param 1, InputFile, /b/a/bar.txt on server 125.0.0.1"));
+        assert!(out.contains("flag -v/--invert-match"));
+        assert!(out.contains("baz              value of param 1, Str"));
+        assert!(out.contains("no annotation for `wc` matched, so every word is a Str param:"));
+        assert!(out.contains("annotation 1: Found argument '-z' which wasn't expected"));
+        assert!(out.contains("/d/c/folder/out.txt on server 127.0.0.1"));
+    }
+
+    #[test]
+    fn test_explain_implicit_dependencies() {
+        let mut interpreter = get_test_interpreter();
+        let out = interpreter
+            .explain_command_line("git -C /b/a/repo status")
+            .unwrap();
+        assert!(out.contains("for `git status` [implicit_input:(.git,relative_to:C),env:GIT_DIR]"));
+        assert!(out.contains("also reads /b/a/repo/.git on server 125.0.0.1"));
+        assert!(out.contains("also reads $GIT_DIR, which is forwarded to where it runs"));
+    }
+
+    #[test]
+    fn test_explain_nested_commands() {
+        let mut interpreter = get_test_interpreter();
+        let out = interpreter
+            .explain_command_line("xargs -n 1 grep foo /e/d/x.txt")
+            .unwrap();
+        assert!(
+            out.contains("runs `grep foo /e/d/x.txt`, matched against the annotations for `grep`")
+        );
+        assert!(out
+            .contains("runs a command that uses /e/d/x.txt on server 128.0.0.1, so it runs there"));
+        let out = interpreter
+            .explain_command_line("sh -c 'grep foo /e/d/x.txt'")
+            .unwrap();
+        assert!(out.contains("runs a script that names files, so it stays on the client"));
+    }
+
+    #[test]
+    fn test_explain_cost() {
+        let mut interpreter = get_test_interpreter();
+        let out = interpreter
+            .explain_command_line("gzip -c /e/d/x.txt")
+            .unwrap();
+        assert!(out.contains(
+            "for `gzip` [selectivity:(1.5,when:d),selectivity:0.3,cpu_cost:0.02,startup_cost:0.01]"
+        ));
+    }
+
+    #[test]
+    fn test_explain_unannotated() {
+        let mut interpreter = get_test_interpreter();
+        let out = interpreter.explain_command_line("ls -l").unwrap();
+        assert!(out.starts_with("ls -l\n  no annotations for `ls`"));
+        assert!(out.contains("-l               value of param 1, Str"));
+    }
+}
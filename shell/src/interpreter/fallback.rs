@@ -0,0 +1,125 @@
+use super::interpreter::Interpreter;
+use super::Result;
+use shellwords::escape;
+use std::collections::HashMap;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Shell used for command lines Posh cannot parse or schedule.
+pub const SYSTEM_SHELL: &str = "/bin/sh";
+
+/// A command line that Posh could not turn into a program, to be run by the system shell on the
+/// client instead of being offloaded.
+/// No command in the line has run when Posh gives up on it, but its expansions may have had
+/// effects (e.g. `${VAR:=default}` assigns VAR), which the system shell repeats when it expands
+/// the line again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShellFallback {
+    /// The command line, as written.
+    line: String,
+    /// Why Posh could not run the line itself.
+    reason: String,
+    /// Directory to run the line in.
+    pwd: PathBuf,
+    /// Shell variables, set at the start of the line so it sees the same values. Exported
+    /// variables are already in the environment the system shell inherits.
+    vars: HashMap<String, String>,
+    /// `$0`.
+    script_name: String,
+    /// `$1`, `$2`, ...
+    args: Vec<String>,
+    /// If the line was started with `&`.
+    background: bool,
+}
+
+impl ShellFallback {
+    pub fn new(line: &str, reason: &failure::Error, interpreter: &mut Interpreter) -> Self {
+        let pwd = interpreter.get_pwd();
+        let shell_env = interpreter.get_shell_env_mut();
+        ShellFallback {
+            line: line.trim().to_string(),
+            reason: reason.to_string(),
+            pwd,
+            vars: shell_env.get_vars().clone(),
+            script_name: shell_env.get_script_name(),
+            args: shell_env.get_positional(),
+            background: false,
+        }
+    }
+
+    pub fn set_background(&mut self, background: bool) {
+        self.background = background;
+    }
+
+    pub fn get_line(&self) -> &str {
+        &self.line
+    }
+
+    pub fn get_reason(&self) -> &str {
+        &self.reason
+    }
+
+    /// Runs the line with the system shell and returns its exit status.
+    /// Prints a one line note saying offload was skipped, and why.
+    pub fn run(&self) -> Result<i32> {
+        eprintln!(
+            "posh: running locally with {}, offload skipped: {}",
+            SYSTEM_SHELL, self.reason
+        );
+        let mut vars: Vec<(&String, &String)> = self.vars.iter().collect();
+        vars.sort();
+        let mut line: String = vars
+            .iter()
+            .map(|(name, value)| format!("{}={}\n", name, escape(value)))
+            .collect();
+        line.push_str(&self.line);
+        if self.background {
+            line.push_str(" &");
+        }
+        let status = Command::new(SYSTEM_SHELL)
+            .arg("-c")
+            .arg(line)
+            .arg(&self.script_name)
+            .args(&self.args)
+            .current_dir(&self.pwd)
+            .status()?;
+        // like sh, report death by a signal as 128 + the signal number
+        Ok(match status.code() {
+            Some(code) => code,
+            None => 128 + status.signal().unwrap_or(0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::examples::get_test_interpreter;
+    use super::*;
+    use failure::format_err;
+    use std::path::Path;
+
+    #[test]
+    fn test_shell_fallback() {
+        let mut interpreter = get_test_interpreter();
+        interpreter.set_pwd(Path::new("/").to_path_buf());
+        interpreter.set_positional_args("script.sh", vec!["a b".to_string()]);
+        interpreter
+            .get_shell_env_mut()
+            .set_var("POSH_FALLBACK_VAR", "x 'y'");
+        let reason = format_err!("Command substitution is not supported");
+        // shell variables are seen by the line, but are not exported to the commands it runs
+        let fallback = ShellFallback::new(
+            "test \"$(pwd)\" = / && test \"$1\" = \"a b\" && test \"$POSH_FALLBACK_VAR\" = \"x 'y'\" && ! printenv POSH_FALLBACK_VAR",
+            &reason,
+            &mut interpreter,
+        );
+        assert_eq!(
+            fallback.get_reason(),
+            "Command substitution is not supported"
+        );
+        assert_eq!(fallback.run().unwrap(), 0);
+        let fallback = ShellFallback::new("exit 3", &reason, &mut interpreter);
+        assert_eq!(fallback.run().unwrap(), 3);
+    }
+}
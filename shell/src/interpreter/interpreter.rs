@@ -1,3 +1,8 @@
+use super::builtins::normalize_path;
+use super::explain::explain_program;
+use super::learn::{observe, UsageLog};
+use super::reducer::reducer_invocation;
+use super::reload::ConfigSources;
 use super::{annotations2, config, scheduler, shellparser, Result};
 use annotations2::{argument_matcher, grammar, parser};
 use argument_matcher::{ArgMatch, RemoteAccessInfo};
@@ -6,19 +11,32 @@ use config::filesize::{FileSize, OffloadQueryFileSize};
 use config::network::FileNetwork;
 use dash::graph::filestream::{FifoMode, FifoStream, FileStream};
 use dash::graph::info::Info;
-use dash::graph::program::{Elem, NodeId, Program};
+use dash::graph::program::{Elem, NodeId, ProgId, Program};
 use dash::graph::stream::{DashStream, IOType, PipeStream};
 use dash::graph::Location;
 use dash::runtime::new_client::ShellClient;
 use failure::bail;
 use grammar::{AccessType, ArgType};
 use parser::Parser;
-use scheduler::Scheduler;
-use shellparser::shellparser::{parse_command, Command};
+use scheduler::{new_scheduler, Scheduler, DEFAULT_SCHEDULER};
+use shellparser::expansion::{expand_line, ShellEnv, ShellToken};
+use shellparser::shellparser::{parse_tokens, Command};
 use std::collections::HashMap;
 use std::env;
-use std::path::PathBuf;
-use tracing::debug;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+/// Program ids start from a per-session seed, so that shells on different clients are unlikely
+/// to use the same ids on a shared server.
+fn prog_id_seed() -> ProgId {
+    let nanos = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.subsec_nanos(),
+        Err(_) => 0,
+    };
+    nanos ^ process::id().rotate_left(16)
+}
 
 pub struct Interpreter {
     /// Where interpreter keeps track of filesystem and link information for scheduling.
@@ -29,32 +47,63 @@ pub struct Interpreter {
     parser: Parser,
     /// Scheduling object that implements the scheduling functionality.
     scheduler: Box<dyn Scheduler>,
+    /// Name of the scheduler the config file chose, which reloading it can change; None if the
+    /// scheduler was chosen on the command line.
+    config_scheduler: Option<String>,
     /// When parallelizing commands on a single machine, what is max way to split?
     splitting_factor: u32,
     /// Current working directory.
     pwd: PathBuf,
-    /// Environment values.
-    env: HashMap<String, String>,
+    /// Directories saved by pushd, most recent last.
+    dir_stack: Vec<PathBuf>,
+    /// Shell variables, positional parameters and last exit status used for expansion.
+    shell_env: ShellEnv,
+    /// Id for the next program; each program gets its own, so concurrently running programs
+    /// (e.g., background jobs) can be told apart.
+    next_prog_id: ProgId,
+    /// Paths each scheduled program writes under, whose cached sizes are stale once it finishes.
+    program_outputs: HashMap<ProgId, Vec<PathBuf>>,
+    /// Where to record the command lines no annotation matches, if learning.
+    usage_log: Option<UsageLog>,
+    /// Files the annotations and mount config were loaded from, to reload them.
+    sources: Option<ConfigSources>,
 }
 
 impl Interpreter {
-    /// Constructs a new interpreter given a  file with config information and a file with
-    /// annotations.
+    /// Constructs a new interpreter given a  file with config information and files with
+    /// annotations, where later files override earlier ones (see `Parser::from_files`).
+    /// The scheduler is the one named (see `scheduler::SCHEDULERS`), or else the one the config
+    /// file names, or else the default.
     pub fn new(
         config_file: &str,
-        annotations_file: &str,
-        scheduler: Box<dyn Scheduler>,
+        annotations_files: &[String],
+        scheduler: Option<&str>,
     ) -> Result<Self> {
-        let parser = Parser::new(annotations_file)?;
+        let parser = Parser::from_files(annotations_files)?;
         let config = FileNetwork::new(config_file)?;
+        let sources = ConfigSources::new(config_file, annotations_files);
+        let (name, config_scheduler) = match scheduler {
+            Some(name) => (name.to_string(), None),
+            None => {
+                let name = config.scheduler_name().unwrap_or(DEFAULT_SCHEDULER);
+                (name.to_string(), Some(name.to_string()))
+            }
+        };
+        let scheduler = new_scheduler(&name)?;
         Ok(Interpreter {
             config: config,
             filecache: FileCache::default(),
             parser: parser,
             scheduler: scheduler,
+            config_scheduler,
             splitting_factor: 1,
             pwd: Default::default(),
-            env: Default::default(),
+            dir_stack: Vec::new(),
+            shell_env: Default::default(),
+            next_prog_id: prog_id_seed(),
+            program_outputs: HashMap::default(),
+            usage_log: None,
+            sources: Some(sources),
         })
     }
 
@@ -76,9 +125,15 @@ impl Interpreter {
             filecache: FileCache::new(filesizemod),
             parser: parser,
             scheduler: scheduler,
+            config_scheduler: None,
             splitting_factor: 1,
             pwd: pwd,
-            env: Default::default(),
+            dir_stack: Vec::new(),
+            shell_env: Default::default(),
+            next_prog_id: prog_id_seed(),
+            program_outputs: HashMap::default(),
+            usage_log: None,
+            sources: None,
         }
     }
     pub fn set_splitting_factor(&mut self, factor: u32) {
@@ -89,16 +144,241 @@ impl Interpreter {
         self.pwd = pwd;
     }
 
+    /// Records every command line no annotation matches in the file, with the options the
+    /// annotations lack and how much data it read; see `annotations missing`.
+    pub fn set_usage_log(&mut self, path: &Path) {
+        self.usage_log = Some(UsageLog::new(path));
+    }
+
+    /// Reloads the annotations and mount config from the files the interpreter was created
+    /// with, and returns a summary of what was loaded. If either fails to parse, the error is
+    /// returned and the old configuration is kept.
+    pub fn reload(&mut self) -> Result<String> {
+        let sources = match &mut self.sources {
+            Some(sources) => sources,
+            None => bail!("not loaded from files"),
+        };
+        // errors are reported once per change to the files, not each time they are checked
+        sources.refresh();
+        let parser = match Parser::from_files(sources.get_annotations_files()) {
+            Ok(parser) => parser,
+            Err(e) => bail!("{}; keeping the old configuration", e),
+        };
+        let config = match FileNetwork::new(sources.get_config_file()) {
+            Ok(config) => config,
+            Err(e) => bail!(
+                "{}: {}; keeping the old configuration",
+                sources.get_config_file(),
+                e
+            ),
+        };
+        // a scheduler given on the command line is kept, whatever the config file says
+        let scheduler = match &self.config_scheduler {
+            Some(current) => {
+                let name = config.scheduler_name().unwrap_or(DEFAULT_SCHEDULER);
+                match new_scheduler(name) {
+                    Ok(scheduler) if name != current => Some((name.to_string(), scheduler)),
+                    Ok(_) => None,
+                    Err(e) => bail!(
+                        "{}: {}; keeping the old configuration",
+                        sources.get_config_file(),
+                        e
+                    ),
+                }
+            }
+            None => None,
+        };
+
+        let changed_mounts = self.config.changed_mounts(&config);
+        for mount in changed_mounts.iter() {
+            self.filecache.invalidate_prefix(mount);
+        }
+        self.filecache.set_config(&config);
+        let mut summary = format!(
+            "loaded annotations for {} commands and {} mounts",
+            parser.num_commands(),
+            config.num_mounts()
+        );
+        if !changed_mounts.is_empty() {
+            let mounts: Vec<String> = changed_mounts
+                .iter()
+                .map(|mount| mount.display().to_string())
+                .collect();
+            summary.push_str(&format!("; mounts changed: {}", mounts.join(", ")));
+        }
+        if let Some((name, scheduler)) = scheduler {
+            summary.push_str(&format!("; scheduler: {}", name));
+            self.scheduler = scheduler;
+            self.config_scheduler = Some(name);
+        }
+        self.parser = parser;
+        self.config = config;
+        Ok(summary)
+    }
+
+    /// Reloads the configuration if any of its files changed since it was last loaded.
+    pub fn reload_if_changed(&mut self) -> Option<Result<String>> {
+        match &self.sources {
+            Some(sources) if sources.changed() => Some(self.reload()),
+            _ => None,
+        }
+    }
+
+    /// Sets `$0` and the positional parameters `$1`, `$2`, ... for script execution.
+    pub fn set_positional_args(&mut self, script_name: &str, args: Vec<String>) {
+        self.shell_env.set_positional(script_name, args);
+    }
+
+    /// Forgets the cached sizes of the files the program wrote; call once it has finished.
+    pub fn finish_program(&mut self, prog_id: ProgId) {
+        if let Some(outputs) = self.program_outputs.remove(&prog_id) {
+            for output in outputs.iter() {
+                self.filecache.invalidate_prefix(output);
+            }
+        }
+    }
+
+    /// Records the exit status of the last command, for `$?`.
+    pub fn set_last_status(&mut self, status: i32) {
+        self.shell_env.set_last_status(status);
+    }
+
+    pub fn get_pwd(&self) -> PathBuf {
+        self.pwd.clone()
+    }
+
+    /// Where the current directory is stored: the client, or the server it is mounted from.
+    pub fn get_pwd_location(&self) -> Location {
+        self.config.get_path_location(self.pwd.clone())
+    }
+
+    /// Changes the working directory, like `cd`.
+    /// Relative paths are resolved against the current directory, `-` goes back to the previous
+    /// directory and no argument goes to $HOME.
+    /// Directories on remote mounts that are not browsable from the client are checked on their
+    /// server, through the file size queries.
+    pub fn change_dir(&mut self, dir: Option<&str>) -> Result<PathBuf> {
+        let target = match dir {
+            None => match self.shell_env.get_var("HOME") {
+                Some(home) => PathBuf::from(home),
+                None => bail!("cd: HOME not set"),
+            },
+            Some("-") => match self.shell_env.get_var("OLDPWD") {
+                Some(old) => PathBuf::from(old),
+                None => bail!("cd: OLDPWD not set"),
+            },
+            Some(dir) => self.pwd.join(dir),
+        };
+        let target = normalize_path(&target);
+        if !self.filecache.is_dir(&target) {
+            let missing = match self.config.get_path_location(target.clone()) {
+                Location::Client => true,
+                _ => self.filecache.get_sizes(&vec![target.clone()]).is_err(),
+            };
+            if missing {
+                bail!("cd: {}: No such directory", target.display());
+            }
+        }
+        // keep the process in the same directory, for anything run locally
+        if target.is_dir() {
+            env::set_current_dir(&target)?;
+        }
+        let old = std::mem::replace(&mut self.pwd, target.clone());
+        self.shell_env.set_var("OLDPWD", &old.to_string_lossy());
+        self.shell_env.set_var("PWD", &target.to_string_lossy());
+        // relative paths now resolve somewhere else
+        self.filecache.clear_paths();
+        Ok(target)
+    }
+
+    /// Like `pushd`: saves the current directory and changes to the given one.
+    /// Without a directory, swaps the current directory with the last saved one.
+    pub fn push_dir(&mut self, dir: Option<&str>) -> Result<PathBuf> {
+        let target = match dir {
+            Some(dir) => dir.to_string(),
+            None => match self.dir_stack.pop() {
+                Some(saved) => saved.to_string_lossy().to_string(),
+                None => bail!("pushd: no other directory"),
+            },
+        };
+        let current = self.pwd.clone();
+        match self.change_dir(Some(&target)) {
+            Ok(new_dir) => {
+                self.dir_stack.push(current);
+                Ok(new_dir)
+            }
+            Err(e) => {
+                if dir.is_none() {
+                    self.dir_stack.push(PathBuf::from(target));
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Like `popd`: changes back to the last directory saved by push_dir.
+    pub fn pop_dir(&mut self) -> Result<PathBuf> {
+        let saved = match self.dir_stack.pop() {
+            Some(saved) => saved,
+            None => bail!("popd: directory stack empty"),
+        };
+        match self.change_dir(Some(&saved.to_string_lossy())) {
+            Ok(new_dir) => Ok(new_dir),
+            Err(e) => {
+                self.dir_stack.push(saved);
+                Err(e)
+            }
+        }
+    }
+
+    /// The current directory, followed by the directories saved by pushd, most recent first.
+    pub fn get_dir_stack(&self) -> Vec<PathBuf> {
+        let mut ret = vec![self.pwd.clone()];
+        ret.extend(self.dir_stack.iter().rev().cloned());
+        ret
+    }
+
+    /// Shell variables and parameters used to expand command lines.
+    pub fn get_shell_env_mut(&mut self) -> &mut ShellEnv {
+        &mut self.shell_env
+    }
+
     /// Takes a command line and returns a program, ready for execution.
     /// Handles parsing, scheduling, and implicit parallelization.
     pub fn parse_command_line(&mut self, command: &str) -> Result<Option<Program>> {
-        // Shell parse pass
-        let prog = parse_command(command)?;
+        // Shell parse pass, which also runs parameter, tilde and brace expansion
+        let tokens = expand_line(command, &mut self.shell_env)?;
+        self.parse_expanded_tokens(tokens)
+    }
+
+    /// Describes how each command in a command line is matched against the annotations and
+    /// where the files it names live, without scheduling or running it.
+    pub fn explain_command_line(&mut self, command: &str) -> Result<String> {
+        // expand against a copy, so explaining an assignment or export changes nothing
+        let mut shell_env = self.shell_env.clone();
+        let tokens = expand_line(command, &mut shell_env)?;
+        match parse_tokens(tokens, &mut shell_env)? {
+            Command::EXPORT(var, value) => Ok(format!(
+                "export {}={}\n  sets an environment variable; no annotations are consulted\n",
+                var, value
+            )),
+            Command::PROGRAM(program) => explain_program(
+                &program,
+                &self.parser,
+                &self.config,
+                &mut self.filecache,
+                &self.pwd,
+            ),
+        }
+    }
+
+    /// Same as parse_command_line, for a command line that has already been expanded.
+    pub fn parse_expanded_tokens(&mut self, tokens: Vec<ShellToken>) -> Result<Option<Program>> {
+        let prog = parse_tokens(tokens, &mut self.shell_env)?;
         match prog {
             Command::EXPORT(var, value) => {
                 // set an environment value
-                env::set_var(var.clone(), value.clone());
-                self.env.insert(var, value);
+                env::set_var(var, value);
                 Ok(None)
             }
             Command::PROGRAM(mut program) => {
@@ -110,8 +390,21 @@ impl Interpreter {
 
     /// Runs parsing pipeline, which parses, parallelizes, and schedules programs.
     fn parse_program(&mut self, program: &mut Program) -> Result<()> {
+        // set the id first, so any nodes and network streams added later carry it
+        program.set_id(self.next_prog_id);
+        self.next_prog_id = self.next_prog_id.wrapping_add(1);
+
         // run parser to produce arg matches for command nodes
         let mut match_map = self.run_parser(program)?;
+        let outputs: Vec<PathBuf> = match_map
+            .values()
+            .flat_map(|argmatch| argmatch.file_dependencies())
+            .filter(|(argtype, _)| argtype.is_output())
+            .map(|(_, fs)| fs.get_path())
+            .collect();
+        if !outputs.is_empty() {
+            self.program_outputs.insert(program.get_id(), outputs);
+        }
 
         debug!("Finished parser");
         // run parallelization to split any nodes into multiple nodes
@@ -192,6 +485,12 @@ impl Interpreter {
             }
         }
         for id in nodes_to_split.iter() {
+            if program.get_node(*id).unwrap().get_stdin_len() <= 1 {
+                continue;
+            }
+            if !self.add_reducer(program, match_map, *id)? {
+                continue;
+            }
             let new_node_ids = program.split_across_input(*id)?;
             if new_node_ids.len() <= 1 {
                 continue;
@@ -205,6 +504,75 @@ impl Interpreter {
         }
         Ok(())
     }
+
+    /// Inserts the command that combines the outputs of the parallel copies of node `id`, if
+    /// its annotation declares a reducer other than concatenation.
+    /// Returns false if the outputs cannot be combined, in which case the node is not split.
+    fn add_reducer(
+        &mut self,
+        program: &mut Program,
+        match_map: &mut HashMap<NodeId, ArgMatch>,
+        id: NodeId,
+    ) -> Result<bool> {
+        let node = program.get_node(id).unwrap();
+        let mut reducer_node = match node.get_elem() {
+            Elem::Cmd(cmdnode) => cmdnode,
+            _ => bail!("Trying to add a reducer after a non-cmd node {:?}", id),
+        };
+        let argmatch = match_map.get(&id).unwrap();
+        let invocation = match reducer_invocation(&reducer_node.get_name(), argmatch)? {
+            Some(invocation) => invocation,
+            None => return Ok(true),
+        };
+        // the copies' outputs must all flow through the reducer
+        let writes_files = argmatch
+            .file_dependencies()
+            .iter()
+            .any(|(argtype, _)| argtype.is_output());
+        match node.get_stdout().as_slice() {
+            [DashStream::Pipe(_)] if !writes_files => {}
+            _ => {
+                debug!("Not splitting node {:?}: its outputs cannot be reduced", id);
+                return Ok(false);
+            }
+        }
+
+        reducer_node.clear_stdin();
+        reducer_node.clear_stdout();
+        reducer_node.clear_stderr();
+        reducer_node.set_name(&invocation.name);
+        reducer_node.set_stdin_as_fifos(invocation.inputs_as_fifos);
+        let reducer_id = program.insert_after(id, Elem::Cmd(reducer_node))?;
+        match_map.insert(
+            reducer_id,
+            ArgMatch::new_default(&invocation.name, &invocation.args),
+        );
+        Ok(true)
+    }
+
+    /// Logs the invocation if no annotation matches it, with the size of the files it names.
+    /// Learning must not stop the command from running, so failures are only reported.
+    fn learn(&mut self, name: &str, invocation: &[String]) {
+        let mut observation = match observe(&self.parser, name, invocation) {
+            Some(observation) => observation,
+            None => return,
+        };
+        for word in invocation.iter().filter(|word| !word.starts_with('-')) {
+            let path = self.pwd.join(word);
+            if path.exists() {
+                observation.bytes += self.filecache.get_size(path).unwrap_or(0.0) as u64;
+            }
+        }
+        if let Some(usage_log) = &self.usage_log {
+            if let Err(e) = usage_log.record(&observation) {
+                warn!(
+                    "Failed to record {:?} in the usage log: {:?}",
+                    observation, e
+                );
+            }
+        }
+    }
+
     /// Finds annotation matches (if any) and resolves Strings in each node of program.
     fn run_parser(&mut self, program: &mut Program) -> Result<HashMap<NodeId, ArgMatch>> {
         let mut match_map: HashMap<NodeId, ArgMatch> = HashMap::default();
@@ -216,39 +584,38 @@ impl Interpreter {
                     continue;
                 } else {
                     // get an argmatch for the invocation and attach it to the command node
-                    let arg_match = self.parser.match_invocation(
-                        command_node.get_name().as_str(),
-                        command_node.get_string_args(),
-                    )?;
+                    let name = command_node.get_name();
+                    let invocation = command_node.get_string_args();
+                    if self.usage_log.is_some() {
+                        self.learn(&name, &invocation);
+                    }
+                    let arg_match = self.parser.match_invocation(name.as_str(), invocation)?;
                     match_map.insert(*id, arg_match);
                 }
             }
         }
 
         // Iterate through all nodes and:
-        //      (1) Resolve any environment variables
-        //      (2) Use glob to split any wildcard arguments for command nodes
-        //      (3) Resolve each filestream to a full path. For scheduling at a later step.
+        //      (1) Use glob to split any wildcard arguments for command nodes
+        //      (2) Resolve each filestream to a full path. For scheduling at a later step.
+        // Environment variables were already expanded by the shell parser.
         for (id, node) in program.get_mut_nodes_iter() {
             match node.get_mut_elem() {
                 Elem::Read(ref mut read_node) => {
                     let filestream = read_node.get_stdin_mut();
-                    filestream.resolve_env_var()?;
                     self.filecache.resolve_path(filestream, &self.pwd)?;
                 }
                 Elem::Write(ref mut write_node) => match write_node.get_stdout_mut() {
                     DashStream::File(ref mut filestream) => {
-                        filestream.resolve_env_var()?;
                         self.filecache.resolve_path(filestream, &self.pwd)?;
                     }
                     _ => {}
                 },
                 Elem::Cmd(_) => {
-                    // resolve all environment variables in associated arg match object
+                    // resolve file paths and globs in associated arg match object
                     let arg_match = match_map.get_mut(&id).unwrap();
                     arg_match.resolve_file_paths(&mut self.filecache, &self.pwd.as_path())?;
                     arg_match.resolve_glob()?;
-                    arg_match.resolve_env_vars()?;
                 }
             }
         }
@@ -378,6 +745,17 @@ impl Interpreter {
                     let argmatch = matches.get(id).unwrap();
                     let arguments = argmatch.reconstruct()?;
                     cmdnode.set_args(arguments);
+                    // forward the variables it reads, as it may not run in this environment
+                    let env: Vec<(String, String)> = argmatch
+                        .get_env_vars()
+                        .iter()
+                        .filter_map(|name| {
+                            self.shell_env
+                                .get_var(name)
+                                .map(|value| (name.clone(), value))
+                        })
+                        .collect();
+                    cmdnode.set_env(env);
                 }
                 _ => {}
             }
@@ -472,12 +850,24 @@ impl Interpreter {
         remote_access_info: &mut RemoteAccessInfo,
     ) -> Result<()> {
         // transfer output file to correct location after job is done
-        if remote_access_info.argtype == ArgType::OutputFile {
-            unimplemented!()
+        match remote_access_info.argtype {
+            ArgType::OutputFile | ArgType::OutputFileList => {
+                return self.setup_output_collect(prog, argmatch, remote_access_info);
+            }
+            ArgType::OutputPrefix => {
+                // the files created are not known, so there is nothing to send back
+                bail!(
+                    "Output prefix {:?} lives at {:?}, so the command must run there, not at {:?}",
+                    remote_access_info.filestream.get_path(),
+                    remote_access_info.origin_location,
+                    remote_access_info.access_location
+                );
+            }
+            _ => {}
         }
         // transfer input file to correct location before job starts
-        if argmatch.get_access_type() != AccessType::Sequential {
-            unimplemented!();
+        if argmatch.get_access_type() == AccessType::Random {
+            return self.setup_staged_access(prog, argmatch, remote_access_info);
         }
 
         // add in a remote fifo read
@@ -517,4 +907,708 @@ impl Interpreter {
         )?;
         Ok(())
     }
+
+    /// Modifies the program so the whole file is copied into the tmp directory where the command
+    /// runs before it starts, for commands that seek in their input and so cannot read it from a
+    /// fifo.
+    fn setup_staged_access(
+        &self,
+        prog: &mut Program,
+        argmatch: &mut ArgMatch,
+        remote_access_info: &mut RemoteAccessInfo,
+    ) -> Result<()> {
+        self.config.strip_file_path(
+            &mut remote_access_info.filestream,
+            &Location::Client,
+            &remote_access_info.origin_location,
+        )?;
+
+        let tmp_path = self.remote_copy_path(
+            "staged",
+            prog.get_id(),
+            prog.get_staged_files().len(),
+            remote_access_info,
+        )?;
+        let staged_file = FileStream::new(
+            tmp_path.as_path(),
+            remote_access_info.access_location.clone(),
+        );
+        remote_access_info.set_tmp_name(staged_file.clone());
+
+        // ensure the argument gets changed
+        argmatch.change_arg(remote_access_info)?;
+
+        prog.add_remote_file_stage(
+            &remote_access_info.origin_location,
+            &remote_access_info.access_location,
+            &remote_access_info.filestream,
+            &staged_file,
+        )?;
+        Ok(())
+    }
+
+    /// Modifies the program so the command writes an output file into the tmp directory where it
+    /// runs, and the file is moved to where it lives once every command there has finished.
+    /// The move is atomic, so a failed command leaves the existing output untouched.
+    fn setup_output_collect(
+        &self,
+        prog: &mut Program,
+        argmatch: &mut ArgMatch,
+        remote_access_info: &mut RemoteAccessInfo,
+    ) -> Result<()> {
+        // output files on the client are already named by their client path
+        if remote_access_info.origin_location != Location::Client {
+            self.config.strip_file_path(
+                &mut remote_access_info.filestream,
+                &Location::Client,
+                &remote_access_info.origin_location,
+            )?;
+        }
+        remote_access_info
+            .filestream
+            .set_location(remote_access_info.origin_location.clone());
+        let tmp_path = self.remote_copy_path(
+            "output",
+            prog.get_id(),
+            prog.get_collected_files().len(),
+            remote_access_info,
+        )?;
+        let produced_file = FileStream::new(
+            tmp_path.as_path(),
+            remote_access_info.access_location.clone(),
+        );
+        remote_access_info.set_tmp_name(produced_file.clone());
+
+        // ensure the argument gets changed
+        argmatch.change_arg(remote_access_info)?;
+
+        prog.add_remote_output_collect(
+            &remote_access_info.access_location,
+            &remote_access_info.origin_location,
+            &produced_file,
+            &remote_access_info.filestream,
+        )?;
+        Ok(())
+    }
+
+    /// Path in the tmp directory of the access location for a copy of a remote file.
+    /// Programs run concurrently and may copy files with the same name, so the copy is named
+    /// after the program and how many files of this kind it already copies.
+    fn remote_copy_path(
+        &self,
+        kind: &str,
+        prog_id: ProgId,
+        index: usize,
+        remote_access_info: &RemoteAccessInfo,
+    ) -> Result<PathBuf> {
+        let file_name = match remote_access_info.filestream.get_path().file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => bail!(
+                "Cannot copy {:?}: it does not name a file",
+                remote_access_info.filestream
+            ),
+        };
+        let stem = format!("{}_{}_{}_{}", kind, prog_id, index, file_name);
+        match &remote_access_info.access_location {
+            Location::Client => Ok(env::temp_dir().join(stem)),
+            location => self.config.get_tmp(Path::new(&stem), location),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::examples::{
+        get_test_interpreter, get_test_interpreter_with_config,
+        get_test_interpreter_with_scheduler, get_test_network_config,
+    };
+    use super::*;
+    use dash::graph::command::{CommandNode, NodeArg};
+
+    #[test]
+    fn test_random_access_is_staged() {
+        let mut interpreter = get_test_interpreter();
+        let program = interpreter
+            .parse_command_line("cmp -s /e/d/x.db /f/e/y.db")
+            .unwrap()
+            .unwrap();
+        let staged = program.get_staged_files();
+        assert_eq!(staged.len(), 1);
+        let staged = &staged[0];
+        assert!(staged.get_path().starts_with("/dash/tmp"));
+        assert!(staged
+            .get_path()
+            .to_string_lossy()
+            .ends_with(&format!("staged_{}_0_x.db", program.get_id())));
+
+        let mut cmd_location: Option<Location> = None;
+        for (_id, node) in program.get_nodes_iter() {
+            match node.get_elem() {
+                Elem::Cmd(cmdnode) => {
+                    // the command reads the local copy instead of the remote file
+                    assert!(cmdnode.get_args().iter().any(|arg| match arg {
+                        NodeArg::Stream(fs) => fs.get_path() == staged.get_path(),
+                        NodeArg::Str(_) => false,
+                    }));
+                    cmd_location = Some(node.get_loc());
+                }
+                Elem::Read(readnode) => {
+                    assert_eq!(readnode.get_input_ref().get_path(), PathBuf::from("x.db"));
+                    assert_eq!(node.get_loc(), Location::Server("128.0.0.1".to_string()));
+                }
+                Elem::Write(_) => {}
+            }
+        }
+        assert_eq!(cmd_location, Some(staged.get_location()));
+    }
+
+    #[test]
+    fn test_split_outputs_are_reduced() {
+        let mut interpreter = get_test_interpreter();
+        let program = interpreter
+            .parse_command_line("cat /d/c/a.txt /b/a/b.txt | sort -n")
+            .unwrap()
+            .unwrap();
+        let mut sort_copies: Vec<NodeId> = Vec::new();
+        let mut merge: Option<NodeId> = None;
+        for (id, node) in program.get_nodes_iter() {
+            if let Elem::Cmd(cmdnode) = node.get_elem() {
+                if !cmdnode.get_name().ends_with("sort") {
+                    continue;
+                }
+                let args = cmdnode.get_string_args();
+                if args.contains(&"-m".to_string()) {
+                    assert!(merge.is_none());
+                    assert!(cmdnode.get_stdin_as_fifos());
+                    assert!(args.contains(&"-n".to_string()));
+                    merge = Some(*id);
+                } else {
+                    assert!(!cmdnode.get_stdin_as_fifos());
+                    assert_eq!(args, vec!["-n".to_string()]);
+                    sort_copies.push(*id);
+                }
+            }
+        }
+        assert_eq!(sort_copies.len(), 2);
+        let merge_id = merge.unwrap();
+        let merge = program.get_node(merge_id).unwrap();
+        // every copy feeds the merge, which writes the final output
+        assert_eq!(merge.get_stdin_len(), 2);
+        for id in sort_copies.iter() {
+            let stdout = program.get_node(*id).unwrap().get_stdout();
+            match stdout.as_slice() {
+                [DashStream::Pipe(pipe)] => assert_eq!(pipe.get_right(), merge_id),
+                [DashStream::Tcp(netstream)] => assert_eq!(netstream.get_right(), merge_id),
+                _ => panic!("sort copy has unexpected stdout {:?}", stdout),
+            }
+        }
+        assert_eq!(merge.get_stdout_len(), 1);
+    }
+
+    #[test]
+    fn test_split_by_file_arguments() {
+        let mut interpreter = get_test_interpreter();
+        let cmd_nodes = |program: &Program, name: &str| -> Vec<Vec<NodeArg>> {
+            program
+                .get_nodes_iter()
+                .filter_map(|(_id, node)| match node.get_elem() {
+                    Elem::Cmd(cmdnode) if cmdnode.get_name() == name => Some(cmdnode.get_args()),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // concatenated outputs are split into a copy per file
+        let program = interpreter
+            .parse_command_line("cat /d/c/a.txt /b/a/b.txt | grep -v foo")
+            .unwrap()
+            .unwrap();
+        let copies = cmd_nodes(&program, "cat");
+        assert_eq!(copies.len(), 2);
+        for args in copies.iter() {
+            assert_eq!(args.len(), 1);
+        }
+
+        // wc labels its counts with the files it reads, so the sum reducer cannot combine copies
+        let program = interpreter
+            .parse_command_line("wc -l /d/c/a.txt /b/a/b.txt | grep -v foo")
+            .unwrap()
+            .unwrap();
+        let copies = cmd_nodes(&program, "wc");
+        assert_eq!(copies.len(), 1);
+        assert_eq!(copies[0].len(), 3);
+        assert!(cmd_nodes(&program, "awk").is_empty());
+    }
+
+    #[test]
+    fn test_output_prefix() {
+        let mut interpreter = get_test_interpreter();
+        // /f/e has the largest files, but the parts can only be written where the prefix lives
+        let program = interpreter
+            .parse_command_line("split -l 1000 /f/e/x.txt /b/a/parts/x_")
+            .unwrap()
+            .unwrap();
+        assert!(program.get_collected_files().is_empty());
+        for (_id, node) in program.get_nodes_iter() {
+            if let Elem::Cmd(cmdnode) = node.get_elem() {
+                assert_eq!(node.get_loc(), Location::Server("125.0.0.1".to_string()));
+                assert_eq!(
+                    cmdnode.get_args().last(),
+                    Some(&NodeArg::Stream(FileStream::new(
+                        Path::new("parts/x_"),
+                        Location::Server("125.0.0.1".to_string())
+                    )))
+                );
+            }
+        }
+
+        // sizes of the parts are forgotten once the program finishes
+        let part = PathBuf::from("/b/a/parts/x_aa");
+        interpreter.filecache.set_size(&part, 7);
+        interpreter.filecache.set_size(Path::new("/f/e/x.txt"), 7);
+        interpreter.finish_program(program.get_id());
+        assert_eq!(interpreter.filecache.get_size(part).unwrap(), 100.0);
+        assert_eq!(
+            interpreter
+                .filecache
+                .get_size(PathBuf::from("/f/e/x.txt"))
+                .unwrap(),
+            7.0
+        );
+    }
+
+    #[test]
+    fn test_std_stream_arguments() {
+        let mut interpreter = get_test_interpreter();
+        let program = interpreter
+            .parse_command_line("cat /e/d/x.txt | grep -- -foo - /e/d/y.txt")
+            .unwrap()
+            .unwrap();
+        let mut found_grep = false;
+        for (_id, node) in program.get_nodes_iter() {
+            if let Elem::Cmd(cmdnode) = node.get_elem() {
+                if cmdnode.get_name() != "grep" {
+                    continue;
+                }
+                found_grep = true;
+                // `-` reads the output of cat over the node's stdin, and is not split off
+                assert_eq!(node.get_stdin_len(), 1);
+                assert_eq!(node.get_loc(), Location::Server("128.0.0.1".to_string()));
+                assert_eq!(
+                    cmdnode.get_args(),
+                    vec![
+                        NodeArg::Str("--".to_string()),
+                        NodeArg::Str("-foo".to_string()),
+                        NodeArg::Str("-".to_string()),
+                        NodeArg::Stream(FileStream::new(
+                            Path::new("y.txt"),
+                            Location::Server("128.0.0.1".to_string())
+                        )),
+                    ]
+                );
+            }
+        }
+        assert!(found_grep);
+
+        // `-o -` writes to stdout, so there is no output file to collect
+        let program = interpreter
+            .parse_command_line("sort -o - /e/d/x.txt")
+            .unwrap()
+            .unwrap();
+        assert!(program.get_collected_files().is_empty());
+    }
+
+    #[test]
+    fn test_nested_commands() {
+        let mut interpreter = get_test_interpreter();
+        let nodes = |program: &Program| -> HashMap<String, (Location, Vec<NodeArg>)> {
+            let mut ret: HashMap<String, (Location, Vec<NodeArg>)> = HashMap::default();
+            for (_id, node) in program.get_nodes_iter() {
+                if let Elem::Cmd(cmdnode) = node.get_elem() {
+                    ret.insert(cmdnode.get_name(), (node.get_loc(), cmdnode.get_args()));
+                }
+            }
+            ret
+        };
+
+        // xargs runs where the file grep reads is, and the path is rewritten for that machine
+        let program = interpreter
+            .parse_command_line("cat /b/a/list.txt | xargs -n 1 grep foo /e/d/x.txt")
+            .unwrap()
+            .unwrap();
+        let (loc, args) = &nodes(&program)["xargs"];
+        assert_eq!(*loc, Location::Server("128.0.0.1".to_string()));
+        assert_eq!(
+            *args,
+            vec![
+                NodeArg::Str("-n".to_string()),
+                NodeArg::Str("1".to_string()),
+                NodeArg::Str("grep".to_string()),
+                NodeArg::Str("foo".to_string()),
+                NodeArg::Stream(FileStream::new(
+                    Path::new("x.txt"),
+                    Location::Server("128.0.0.1".to_string())
+                )),
+            ]
+        );
+
+        // a script naming a file can't be rewritten, so it runs on the client
+        let program = interpreter
+            .parse_command_line("cat /e/d/list.txt | sh -c 'grep foo /e/d/x.txt'")
+            .unwrap()
+            .unwrap();
+        assert_eq!(nodes(&program)["sh"].0, Location::Client);
+    }
+
+    #[test]
+    fn test_cost_model() {
+        let location = |interpreter: &mut Interpreter, command_line: &str| -> Location {
+            let program = interpreter
+                .parse_command_line(command_line)
+                .unwrap()
+                .unwrap();
+            for (_id, node) in program.get_nodes_iter() {
+                if let Elem::Cmd(_) = node.get_elem() {
+                    return node.get_loc();
+                }
+            }
+            unreachable!();
+        };
+        let server = Location::Server("128.0.0.1".to_string());
+
+        // compressing shrinks the data, so it is done next to it
+        let mut interpreter = get_test_interpreter();
+        assert_eq!(location(&mut interpreter, "gzip -c /e/d/x.txt"), server);
+        // decompressing grows it, so the smaller input is moved to the client instead
+        assert_eq!(
+            location(&mut interpreter, "gzip -c -d /e/d/x.gz"),
+            Location::Client
+        );
+
+        // the CPU time outweighs the transfer to a faster machine
+        let mut config = get_test_network_config();
+        config.set_cpu_speed(server.clone(), 10);
+        let mut interpreter = get_test_interpreter_with_config(config);
+        assert_ne!(location(&mut interpreter, "gzip -c /e/d/x.txt"), server);
+    }
+
+    #[test]
+    fn test_exact_scheduler() {
+        let location = |interpreter: &mut Interpreter, command_line: &str| -> Location {
+            let program = interpreter
+                .parse_command_line(command_line)
+                .unwrap()
+                .unwrap();
+            for (_id, node) in program.get_nodes_iter() {
+                if let Elem::Cmd(_) = node.get_elem() {
+                    return node.get_loc();
+                }
+            }
+            unreachable!();
+        };
+        let exact = |config: FileNetwork| {
+            get_test_interpreter_with_scheduler(config, new_scheduler("exact").unwrap())
+        };
+        let server = Location::Server("128.0.0.1".to_string());
+
+        // on a single path, the placement is the DP's
+        let mut interpreter = exact(get_test_network_config());
+        assert_eq!(location(&mut interpreter, "gzip -c /e/d/x.txt"), server);
+        assert_eq!(
+            location(&mut interpreter, "gzip -c -d /e/d/x.gz"),
+            Location::Client
+        );
+        let mut config = get_test_network_config();
+        config.set_cpu_speed(server.clone(), 10);
+        assert_ne!(location(&mut exact(config), "gzip -c /e/d/x.txt"), server);
+
+        // reads and writes stay where their files are
+        let program = interpreter
+            .parse_command_line("cat /e/d/x.txt | grep foo > /f/e/out.txt")
+            .unwrap()
+            .unwrap();
+        for (_id, node) in program.get_nodes_iter() {
+            if let Elem::Write(writenode) = node.get_elem() {
+                let expected = match writenode.get_output_ref() {
+                    DashStream::File(_) => Location::Server("129.0.0.1".to_string()),
+                    _ => Location::Client,
+                };
+                assert_eq!(node.get_loc(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_conditional_annotations() {
+        let mut interpreter = get_test_interpreter();
+        let copies = |interpreter: &mut Interpreter, command_line: &str| -> usize {
+            let program = interpreter
+                .parse_command_line(command_line)
+                .unwrap()
+                .unwrap();
+            program
+                .get_nodes_iter()
+                .filter(|(_id, node)| match node.get_elem() {
+                    Elem::Cmd(cmdnode) => cmdnode.get_name() == "cat",
+                    _ => false,
+                })
+                .count()
+        };
+        assert_eq!(copies(&mut interpreter, "cat /d/c/a.txt /b/a/b.txt"), 2);
+        // the annotation makes cat of parquet files not splittable
+        assert_eq!(
+            copies(&mut interpreter, "cat /d/c/a.parquet /b/a/b.parquet"),
+            1
+        );
+    }
+
+    #[test]
+    fn test_implicit_dependencies() {
+        let mut interpreter = get_test_interpreter();
+        interpreter
+            .get_shell_env_mut()
+            .set_var("GIT_DIR", "/b/a/repo/.git");
+        let cmd_node = |program: &Program| -> (Location, CommandNode) {
+            for (_id, node) in program.get_nodes_iter() {
+                if let Elem::Cmd(cmdnode) = node.get_elem() {
+                    return (node.get_loc(), cmdnode);
+                }
+            }
+            unreachable!();
+        };
+
+        // .git is in the current directory
+        let program = interpreter
+            .parse_command_line("git status -s")
+            .unwrap()
+            .unwrap();
+        let (location, cmdnode) = cmd_node(&program);
+        assert_eq!(location, Location::Server("127.0.0.1".to_string()));
+        assert!(cmdnode.get_options().needs_current_dir);
+        assert_eq!(
+            cmdnode.get_env(),
+            &[("GIT_DIR".to_string(), "/b/a/repo/.git".to_string())]
+        );
+
+        // .git is in the directory given with -C
+        let program = interpreter
+            .parse_command_line("git -C /b/a/repo status")
+            .unwrap()
+            .unwrap();
+        let (location, cmdnode) = cmd_node(&program);
+        assert_eq!(location, Location::Server("125.0.0.1".to_string()));
+        assert!(!cmdnode.get_options().needs_current_dir);
+    }
+
+    #[test]
+    fn test_usage_log() {
+        let dir = env::temp_dir().join(format!("posh_learn_{}", process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.csv");
+        std::fs::write(&input, "a,b\n").unwrap();
+        let mut interpreter = get_test_interpreter();
+        interpreter.set_usage_log(&dir.join("usage.log"));
+        interpreter
+            .parse_command_line(&format!(
+                "cat /f/e/x.txt | awk -P -F , {{print}} {} | sed -n 1p {}",
+                input.display(),
+                input.display()
+            ))
+            .unwrap();
+        let mut observations = UsageLog::new(&dir.join("usage.log")).read().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        // cat matched its annotation, so it is not recorded
+        assert_eq!(observations.len(), 2);
+        observations.sort_by(|a, b| a.command.cmp(&b.command));
+        assert_eq!(observations[0].command, "awk");
+        assert!(observations[0].annotated);
+        assert_eq!(observations[0].unknown_options, vec!["-P".to_string()]);
+        assert_eq!(observations[0].bytes, 100);
+        assert_eq!(observations[1].command, "sed");
+        assert!(!observations[1].annotated);
+        assert_eq!(observations[1].unknown_options, vec!["-n".to_string()]);
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = env::temp_dir().join(format!("posh_reload_{}", process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mount_file = dir.join("mount.config");
+        let annotations_file = dir.join("annotations.txt");
+        let write_mounts = |mount: &str| {
+            std::fs::write(
+                &mount_file,
+                format!(
+                    "mounts:\n  \"127.0.0.1\": \"{}\"\nlinks:\n  \"(127.0.0.1,client)\": 500\n\
+                     tmp_directory:\n  \"127.0.0.1\": \"/tmp/posh\"\n",
+                    mount
+                ),
+            )
+            .unwrap();
+        };
+        write_mounts("/a");
+        std::fs::write(
+            &annotations_file,
+            "cat: PARAMS:[(type:input_file,size:list(list_separator:( )))]\n",
+        )
+        .unwrap();
+        let mut interpreter = Interpreter::new(
+            mount_file.to_str().unwrap(),
+            &[annotations_file.to_str().unwrap().to_string()],
+            None,
+        )
+        .unwrap();
+        assert!(interpreter.reload_if_changed().is_none());
+
+        // a broken annotation file keeps the old configuration, and is reported once
+        std::fs::write(&annotations_file, "cat: PARAMS:[(type:input_file\n").unwrap();
+        write_mounts("/b");
+        assert!(interpreter.reload_if_changed().unwrap().is_err());
+        assert!(interpreter.reload_if_changed().is_none());
+        assert_eq!(interpreter.parser.num_commands(), 1);
+        assert_eq!(
+            interpreter.config.get_path_location(PathBuf::from("/a/x")),
+            Location::Server("127.0.0.1".to_string())
+        );
+
+        std::fs::write(
+            &annotations_file,
+            "cat: PARAMS:[(type:input_file,size:list(list_separator:( )))]\n\
+             wc: PARAMS:[(type:input_file,size:list(list_separator:( )))]\n",
+        )
+        .unwrap();
+        let summary = interpreter.reload_if_changed().unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            summary,
+            "loaded annotations for 2 commands and 1 mounts; mounts changed: /a, /b"
+        );
+        assert_eq!(
+            interpreter.config.get_path_location(PathBuf::from("/a/x")),
+            Location::Client
+        );
+        assert_eq!(
+            interpreter.config.get_path_location(PathBuf::from("/b/x")),
+            Location::Server("127.0.0.1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_schedulers() {
+        let dir = env::temp_dir().join(format!("posh_schedulers_{}", process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mount_file = dir.join("mount.config");
+        let annotations_file = dir.join("annotations.txt");
+        let write_mounts = |scheduler: &str| {
+            std::fs::write(
+                &mount_file,
+                format!(
+                    "mounts:\n  \"127.0.0.1\": \"/a\"\nlinks:\n  \"(127.0.0.1,client)\": 500\n\
+                     tmp_directory:\n  \"127.0.0.1\": \"/tmp/posh\"\nscheduler: {}\n",
+                    scheduler
+                ),
+            )
+            .unwrap();
+        };
+        std::fs::write(
+            &annotations_file,
+            "wc: FLAGS:[(short:l)] PARAMS:[(type:input_file,size:list(list_separator:( )))]\n",
+        )
+        .unwrap();
+        let new_interpreter = |scheduler: Option<&str>| {
+            Interpreter::new(
+                mount_file.to_str().unwrap(),
+                &[annotations_file.to_str().unwrap().to_string()],
+                scheduler,
+            )
+        };
+        let location = |interpreter: &mut Interpreter| -> Location {
+            let program = interpreter
+                .parse_command_line("wc -l /a/x.txt")
+                .unwrap()
+                .unwrap();
+            for (_id, node) in program.get_nodes_iter() {
+                if let Elem::Cmd(_) = node.get_elem() {
+                    return node.get_loc();
+                }
+            }
+            unreachable!();
+        };
+        let server = Location::Server("127.0.0.1".to_string());
+
+        write_mounts("all-local");
+        let mut interpreter = new_interpreter(None).unwrap();
+        assert_eq!(location(&mut interpreter), Location::Client);
+        // a scheduler given on the command line wins over the config file, also on reload
+        let mut chosen = new_interpreter(Some("max-offload")).unwrap();
+        assert_eq!(location(&mut chosen), server);
+
+        write_mounts("max-offload");
+        assert_eq!(
+            interpreter.reload().unwrap(),
+            "loaded annotations for 1 commands and 1 mounts; scheduler: max-offload"
+        );
+        assert_eq!(location(&mut interpreter), server);
+        write_mounts("all-local");
+        assert!(!chosen.reload().unwrap().contains("scheduler"));
+        assert_eq!(location(&mut chosen), server);
+
+        write_mounts("fastest");
+        let e = interpreter.reload().unwrap_err();
+        assert!(e.to_string().contains(
+            "unknown scheduler \"fastest\", expected one of heuristic, dp, exact, all-local, max-offload"
+        ));
+        assert!(new_interpreter(None).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(location(&mut interpreter), server);
+    }
+
+    #[test]
+    fn test_remote_output_is_collected() {
+        let mut interpreter = get_test_interpreter();
+        let program = interpreter
+            .parse_command_line("sort -o /f/e/out.txt /e/d/x.txt")
+            .unwrap()
+            .unwrap();
+        let collected = program.get_collected_files();
+        assert_eq!(collected.len(), 1);
+        let collected = &collected[0];
+        assert_eq!(
+            collected.get_path(),
+            PathBuf::from(format!("/dash/tmp/output_{}_0_out.txt", program.get_id()))
+        );
+
+        let mut found_write = false;
+        for (_id, node) in program.get_nodes_iter() {
+            match node.get_elem() {
+                Elem::Cmd(cmdnode) => {
+                    // the command writes the tmp file where it runs
+                    assert!(cmdnode.get_args().iter().any(|arg| match arg {
+                        NodeArg::Stream(fs) => fs.get_path() == collected.get_path(),
+                        NodeArg::Str(_) => false,
+                    }));
+                    assert_eq!(node.get_loc(), collected.get_location());
+                }
+                Elem::Read(readnode) => {
+                    if readnode.collects_output() {
+                        assert_eq!(node.get_loc(), collected.get_location());
+                    }
+                }
+                Elem::Write(writenode) => {
+                    if writenode.commits_output() {
+                        found_write = true;
+                        assert_eq!(
+                            writenode.get_output_ref(),
+                            &DashStream::File(FileStream::new(
+                                Path::new("out.txt"),
+                                Location::Server("129.0.0.1".to_string())
+                            ))
+                        );
+                        assert_eq!(node.get_loc(), Location::Server("129.0.0.1".to_string()));
+                    }
+                }
+            }
+        }
+        assert!(found_write);
+    }
 }
@@ -0,0 +1,377 @@
+use super::fallback::ShellFallback;
+use super::script::Executor;
+use super::Result;
+use dash::graph::program::{ProgId, Program};
+use dash::graph::Location;
+use dash::runtime::new_client::ShellClient;
+use failure::bail;
+use itertools::join;
+use nix::sys::signal::Signal;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use thread::JoinHandle;
+use tracing::error;
+
+/// A pipeline started with `&`.
+struct Job {
+    /// Job number, as shown by `jobs` and used in `%n`.
+    number: usize,
+    /// Id of the program; identifies its processes at every location.
+    prog_id: ProgId,
+    /// The pipeline, as written.
+    cmd: String,
+    /// Locations the program was split across.
+    locations: Vec<Location>,
+    /// Thread running the program; taken when the job is waited for.
+    handle: Option<JoinHandle<Result<()>>>,
+    /// If the job was stopped with a signal.
+    stopped: bool,
+}
+
+impl Job {
+    fn is_finished(&self) -> bool {
+        match &self.handle {
+            Some(handle) => handle.is_finished(),
+            None => true,
+        }
+    }
+
+    fn state(&self) -> &'static str {
+        if self.is_finished() {
+            "Done"
+        } else if self.stopped {
+            "Stopped"
+        } else {
+            "Running"
+        }
+    }
+
+    /// Waits for the job to finish and returns its exit status.
+    fn join(&mut self) -> i32 {
+        let handle = match self.handle.take() {
+            Some(handle) => handle,
+            None => return 0,
+        };
+        match handle.join() {
+            Ok(Ok(_)) => 0,
+            Ok(Err(e)) => {
+                error!("Job {} failed: {:?}", self.number, e);
+                1
+            }
+            Err(e) => {
+                error!("Job {} panicked: {:?}", self.number, e);
+                1
+            }
+        }
+    }
+}
+
+/// Executes programs with the shell client, keeping track of background programs as jobs.
+/// Each job is tracked by its ProgId, so it can be signalled at every location it was
+/// offloaded to.
+pub struct JobControl {
+    client: ShellClient,
+    jobs: Vec<Job>,
+}
+
+impl JobControl {
+    pub fn new(client: ShellClient) -> Self {
+        JobControl {
+            client,
+            jobs: Vec::new(),
+        }
+    }
+
+    /// Reports and forgets background jobs that finished since the last call.
+    pub fn notify_finished(&mut self) {
+        let mut idx = 0;
+        while idx < self.jobs.len() {
+            if !self.jobs[idx].is_finished() {
+                idx += 1;
+                continue;
+            }
+            let mut job = self.jobs.remove(idx);
+            let status = match job.join() {
+                0 => "Done".to_string(),
+                status => format!("Exit {}", status),
+            };
+            println!("[{}]  {:<24}{}", job.number, status, job.cmd);
+        }
+    }
+
+    /// Waits for every background job; returns the exit status of the last one.
+    pub fn wait_all(&mut self) -> i32 {
+        let mut status = 0;
+        for mut job in self.jobs.drain(..) {
+            status = job.join();
+        }
+        status
+    }
+
+    /// Finds the index of the job a `%` job spec refers to:
+    /// `%n` for job n, `%%`, `%+` or `%` for the current job, `%-` for the previous job,
+    /// `%prefix` for the job whose command starts with prefix and `%?text` for the job whose
+    /// command contains text.
+    /// Without a spec, refers to the current job.
+    fn find_job(&self, spec: Option<&str>) -> Result<usize> {
+        let spec = match spec {
+            Some(spec) => match spec.starts_with('%') {
+                true => &spec[1..],
+                false => bail!("{}: not a job spec", spec),
+            },
+            None => "",
+        };
+        let found = match spec {
+            "" | "%" | "+" => self.jobs.len().checked_sub(1),
+            "-" => self.jobs.len().checked_sub(2),
+            _ => match spec.parse::<usize>() {
+                Ok(number) => self.jobs.iter().position(|job| job.number == number),
+                Err(_) => match spec.starts_with('?') {
+                    true => self
+                        .jobs
+                        .iter()
+                        .position(|job| job.cmd.contains(&spec[1..])),
+                    false => self.jobs.iter().position(|job| job.cmd.starts_with(spec)),
+                },
+            },
+        };
+        match found {
+            Some(idx) => Ok(idx),
+            None => bail!("%{}: no such job", spec),
+        }
+    }
+
+    fn signal_job(&mut self, idx: usize, signal: Signal) -> Result<()> {
+        let job = &mut self.jobs[idx];
+        self.client
+            .signal_program(job.prog_id, &job.locations, signal)?;
+        match signal {
+            Signal::SIGSTOP | Signal::SIGTSTP | Signal::SIGTTIN | Signal::SIGTTOU => {
+                job.stopped = true;
+            }
+            Signal::SIGCONT => {
+                job.stopped = false;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn jobs(&mut self, args: &[String]) -> Result<i32> {
+        let long = args.iter().any(|arg| arg == "-l");
+        let len = self.jobs.len();
+        for (idx, job) in self.jobs.iter().enumerate() {
+            let current = match len - idx {
+                1 => "+",
+                2 => "-",
+                _ => " ",
+            };
+            match long {
+                true => {
+                    let locations = join(job.locations.iter().map(location_name), ", ");
+                    println!(
+                        "[{}]{} {:<10} {:<10}{} & ({})",
+                        job.number,
+                        current,
+                        job.prog_id,
+                        job.state(),
+                        job.cmd,
+                        locations
+                    );
+                }
+                false => println!(
+                    "[{}]{}  {:<24}{} &",
+                    job.number,
+                    current,
+                    job.state(),
+                    job.cmd
+                ),
+            }
+        }
+        Ok(0)
+    }
+
+    fn fg(&mut self, args: &[String]) -> Result<i32> {
+        let idx = self.find_job(args.first().map(|x| x.as_str()))?;
+        if self.jobs[idx].stopped {
+            self.signal_job(idx, Signal::SIGCONT)?;
+        }
+        let mut job = self.jobs.remove(idx);
+        println!("{}", job.cmd);
+        Ok(job.join())
+    }
+
+    fn bg(&mut self, args: &[String]) -> Result<i32> {
+        let idx = self.find_job(args.first().map(|x| x.as_str()))?;
+        self.signal_job(idx, Signal::SIGCONT)?;
+        println!("[{}] {} &", self.jobs[idx].number, self.jobs[idx].cmd);
+        Ok(0)
+    }
+
+    fn wait(&mut self, args: &[String]) -> Result<i32> {
+        if args.is_empty() {
+            return Ok(self.wait_all());
+        }
+        let mut status = 0;
+        for arg in args.iter() {
+            let idx = self.find_job(Some(arg))?;
+            let mut job = self.jobs.remove(idx);
+            status = job.join();
+        }
+        Ok(status)
+    }
+
+    fn kill(&mut self, args: &[String]) -> Result<i32> {
+        let (signal, operands) = match args.first().map(|x| x.as_str()) {
+            Some("-s") | Some("-n") => match args.get(1) {
+                Some(name) => (parse_signal(name)?, &args[2..]),
+                None => bail!("kill: {} requires an argument", args[0]),
+            },
+            Some(arg) if arg.starts_with('-') && arg.len() > 1 => {
+                (parse_signal(&arg[1..])?, &args[1..])
+            }
+            _ => (Signal::SIGTERM, args),
+        };
+        if operands.is_empty() {
+            bail!("usage: kill [-s sigspec | -sigspec] %job ...");
+        }
+        for operand in operands.iter() {
+            let idx = self.find_job(Some(operand))?;
+            self.signal_job(idx, signal)?;
+            // like sh, make sure stopped jobs see the signal
+            if self.jobs[idx].stopped && signal != Signal::SIGSTOP && signal != Signal::SIGCONT {
+                self.signal_job(idx, Signal::SIGCONT)?;
+            }
+        }
+        Ok(0)
+    }
+}
+
+impl Executor for JobControl {
+    fn execute(&mut self, program: Program) -> Result<()> {
+        self.client.run_command(program)
+    }
+
+    fn execute_background(&mut self, program: Program, cmd: &str) -> Result<()> {
+        let number = match self.jobs.last() {
+            Some(job) => job.number + 1,
+            None => 1,
+        };
+        let prog_id = program.get_id();
+        let locations = program.get_locations();
+        let client = self.client.clone();
+        let handle = thread::spawn(move || client.run_command(program));
+        println!("[{}] {}", number, prog_id);
+        self.jobs.push(Job {
+            number,
+            prog_id,
+            cmd: cmd.to_string(),
+            locations,
+            handle: Some(handle),
+            stopped: false,
+        });
+        Ok(())
+    }
+
+    fn execute_fallback(&mut self, fallback: ShellFallback) -> Result<i32> {
+        fallback.run()
+    }
+
+    fn set_pwd(&mut self, pwd: &Path) {
+        self.client.set_pwd(pwd.to_path_buf());
+    }
+
+    fn job_builtin(&mut self, words: &[String]) -> Result<Option<i32>> {
+        let args = &words[1..];
+        let ret = match words[0].as_str() {
+            "jobs" => self.jobs(args),
+            "fg" => self.fg(args),
+            "bg" => self.bg(args),
+            "wait" => self.wait(args),
+            // kill with process ids runs the kill binary
+            "kill" if args.iter().any(|arg| arg.starts_with('%')) => self.kill(args),
+            _ => return Ok(None),
+        };
+        match ret {
+            Ok(status) => Ok(Some(status)),
+            Err(e) => {
+                error!("{}: {:?}", words[0], e);
+                Ok(Some(1))
+            }
+        }
+    }
+}
+
+/// Parses a signal given by number (9), name (KILL) or full name (SIGKILL).
+fn parse_signal(spec: &str) -> Result<Signal> {
+    if let Ok(number) = spec.parse::<i32>() {
+        return match Signal::try_from(number) {
+            Ok(signal) => Ok(signal),
+            Err(_) => bail!("kill: invalid signal number {:?}", spec),
+        };
+    }
+    let name = spec.to_uppercase();
+    let name = match name.starts_with("SIG") {
+        true => name,
+        false => format!("SIG{}", name),
+    };
+    match Signal::from_str(&name) {
+        Ok(signal) => Ok(signal),
+        Err(_) => bail!("kill: invalid signal specification {:?}", spec),
+    }
+}
+
+fn location_name(location: &Location) -> String {
+    match location {
+        Location::Client => "client".to_string(),
+        Location::Server(ip) => ip.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("9").unwrap(), Signal::SIGKILL);
+        assert_eq!(parse_signal("STOP").unwrap(), Signal::SIGSTOP);
+        assert_eq!(parse_signal("sigcont").unwrap(), Signal::SIGCONT);
+        assert!(parse_signal("NOPE").is_err());
+    }
+
+    #[test]
+    fn test_job_table() {
+        let client = ShellClient::new("1235", Path::new("/").to_path_buf(), "/tmp").unwrap();
+        let mut control = JobControl::new(client);
+        // empty programs have no nodes to run, so they finish right away
+        control
+            .execute_background(Program::default(), "grep -r ERROR /logs")
+            .unwrap();
+        control
+            .execute_background(Program::default(), "sort big.txt")
+            .unwrap();
+        assert_eq!(control.find_job(None).unwrap(), 1);
+        assert_eq!(control.find_job(Some("%-")).unwrap(), 0);
+        assert_eq!(control.find_job(Some("%1")).unwrap(), 0);
+        assert_eq!(control.find_job(Some("%sort")).unwrap(), 1);
+        assert_eq!(control.find_job(Some("%?ERROR")).unwrap(), 0);
+        assert!(control.find_job(Some("%3")).is_err());
+        assert!(control.find_job(Some("1")).is_err());
+
+        assert_eq!(control.job_builtin(&words("wait %1")).unwrap(), Some(0));
+        assert_eq!(control.jobs.len(), 1);
+        assert_eq!(control.jobs[0].number, 2);
+        assert_eq!(control.job_builtin(&words("fg")).unwrap(), Some(0));
+        assert!(control.jobs.is_empty());
+        assert_eq!(control.job_builtin(&words("fg")).unwrap(), Some(1));
+        assert_eq!(control.job_builtin(&words("kill -9 %1")).unwrap(), Some(1));
+        assert_eq!(control.job_builtin(&words("kill 1234")).unwrap(), None);
+    }
+}
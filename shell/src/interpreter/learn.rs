@@ -0,0 +1,290 @@
+use super::annotations2::cmd_parser::CmdParser;
+use super::annotations2::parser::Parser;
+use super::Result;
+use failure::bail;
+use std::collections::HashMap;
+use std::fs::{read_to_string, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A command line the annotations did not cover, so it ran with every word typed as a `Str`
+/// and could not be moved off the client.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Observation {
+    pub command: String,
+    /// The command has annotations, but none of them matched.
+    pub annotated: bool,
+    /// Options no annotation declares; for a command without annotations, all of them.
+    pub unknown_options: Vec<String>,
+    /// Size of the files named on the command line.
+    pub bytes: u64,
+}
+
+/// Returns what the annotations are missing to match the invocation, or None if one matches.
+/// The bytes the command reads are left for the caller to fill in.
+pub fn observe(parser: &Parser, name: &str, invocation: &[String]) -> Option<Observation> {
+    match parser.get_cmd_parser(name, invocation) {
+        Some(cmd_parser) => match cmd_parser.match_annotations(invocation) {
+            Ok(_) => None,
+            Err(_) => Some(Observation {
+                command: cmd_parser.get_name().to_string(),
+                annotated: true,
+                unknown_options: cmd_parser.unknown_options(invocation),
+                bytes: 0,
+            }),
+        },
+        None => Some(Observation {
+            command: name.to_string(),
+            annotated: false,
+            unknown_options: CmdParser::new(name).unknown_options(invocation),
+            bytes: 0,
+        }),
+    }
+}
+
+/// Appends observations to a file across sessions, one per line, so they can be ranked later.
+/// Each line has the command, whether it is annotated, the bytes it read and its unknown
+/// options, separated by tabs.
+#[derive(Debug, Clone)]
+pub struct UsageLog {
+    path: PathBuf,
+}
+
+impl UsageLog {
+    pub fn new(path: &Path) -> Self {
+        UsageLog {
+            path: path.to_path_buf(),
+        }
+    }
+
+    pub fn record(&self, observation: &Observation) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}",
+            observation.command,
+            match observation.annotated {
+                true => "annotated",
+                false => "unannotated",
+            },
+            observation.bytes,
+            observation.unknown_options.join(" ")
+        )?;
+        Ok(())
+    }
+
+    pub fn read(&self) -> Result<Vec<Observation>> {
+        let contents = read_to_string(&self.path)?;
+        let mut observations: Vec<Observation> = Vec::new();
+        for (idx, line) in contents.lines().enumerate() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let (annotated, bytes) = match (fields.as_slice(), fields.get(2).map(|b| b.parse())) {
+                ([_, "annotated", _, _], Some(Ok(bytes))) => (true, bytes),
+                ([_, "unannotated", _, _], Some(Ok(bytes))) => (false, bytes),
+                _ => bail!(
+                    "{}:{}: malformed line {:?}",
+                    self.path.display(),
+                    idx + 1,
+                    line
+                ),
+            };
+            observations.push(Observation {
+                command: fields[0].to_string(),
+                annotated,
+                unknown_options: fields[3].split_whitespace().map(String::from).collect(),
+                bytes,
+            });
+        }
+        Ok(observations)
+    }
+}
+
+/// Everything observed about one command.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+struct MissingAnnotation {
+    command: String,
+    annotated: bool,
+    runs: usize,
+    bytes: u64,
+    /// Each unknown option and how many runs used it, most used first.
+    options: Vec<(String, usize)>,
+}
+
+/// Ranks the commands in the observations by how much data they read, and then by how often
+/// they ran: those are the annotations that would let Posh move the most work.
+pub fn missing_annotations_report(observations: &[Observation]) -> String {
+    let mut by_command: HashMap<&str, MissingAnnotation> = HashMap::default();
+    for observation in observations.iter() {
+        let entry = by_command
+            .entry(observation.command.as_str())
+            .or_insert_with(|| MissingAnnotation {
+                command: observation.command.clone(),
+                ..Default::default()
+            });
+        // the latest observation says whether the command is annotated now
+        entry.annotated = observation.annotated;
+        entry.runs += 1;
+        entry.bytes += observation.bytes;
+        for option in observation.unknown_options.iter() {
+            match entry.options.iter_mut().find(|(name, _)| name == option) {
+                Some((_, count)) => *count += 1,
+                None => entry.options.push((option.clone(), 1)),
+            }
+        }
+    }
+    let mut ranked: Vec<MissingAnnotation> = by_command.into_values().collect();
+    ranked.sort_by(|a, b| {
+        b.bytes
+            .cmp(&a.bytes)
+            .then(b.runs.cmp(&a.runs))
+            .then(a.command.cmp(&b.command))
+    });
+    if ranked.is_empty() {
+        return "every command matched an annotation\n".to_string();
+    }
+
+    let mut out = format!("{:>4}  {:>6}  {:>10}  command\n", "rank", "runs", "read");
+    for (idx, missing) in ranked.iter_mut().enumerate() {
+        out.push_str(&format!(
+            "{:>4}  {:>6}  {:>10}  {} ({})\n",
+            idx + 1,
+            missing.runs,
+            format_bytes(missing.bytes),
+            missing.command,
+            match missing.annotated {
+                true => "no annotation matched",
+                false => "no annotations",
+            }
+        ));
+        if !missing.options.is_empty() {
+            missing
+                .options
+                .sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+            let options: Vec<String> = missing
+                .options
+                .iter()
+                .map(|(name, count)| format!("{} ({})", name, count))
+                .collect();
+            out.push_str(&format!(
+                "{:>28}{}: {}\n",
+                "",
+                match missing.annotated {
+                    true => "unknown options",
+                    false => "options",
+                },
+                options.join(", ")
+            ));
+        }
+    }
+    out
+}
+
+fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, units[unit]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::annotations2::grammar::Command;
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::remove_file;
+
+    fn get_parser() -> Parser {
+        let mut map: HashMap<String, CmdParser> = HashMap::default();
+        let cmd = Command::new(
+            "grep: FLAGS:[(short:v)] PARAMS:[(type:str,size:1),(type:input_file,size:list(list_separator:( )))]",
+        )
+        .unwrap();
+        let mut parser = CmdParser::new("grep");
+        parser.add_annotation(cmd).unwrap();
+        map.insert("grep".to_string(), parser);
+        Parser::construct(map)
+    }
+
+    fn words(s: &str) -> Vec<String> {
+        s.split(' ').map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_observe() {
+        let parser = get_parser();
+        assert_eq!(observe(&parser, "grep", &words("-v foo a.txt")), None);
+        assert_eq!(
+            observe(&parser, "grep", &words("-v --color foo a.txt")),
+            Some(Observation {
+                command: "grep".to_string(),
+                annotated: true,
+                unknown_options: vec!["--color".to_string()],
+                bytes: 0,
+            })
+        );
+        assert_eq!(
+            observe(&parser, "awk", &words("-F , {print} a.txt")),
+            Some(Observation {
+                command: "awk".to_string(),
+                annotated: false,
+                unknown_options: vec!["-F".to_string()],
+                bytes: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_missing_annotations_report() {
+        let path = temp_dir().join(format!("posh_usage_log_{}", std::process::id()));
+        let _ = remove_file(&path);
+        let log = UsageLog::new(&path);
+        let observations = [
+            ("grep", true, vec!["--color"], 100),
+            ("awk", false, vec!["-F"], 3 * 1024 * 1024),
+            ("grep", true, vec!["--color", "-P"], 200),
+            ("sed", false, vec![], 0),
+            ("sed", false, vec![], 0),
+        ];
+        for (command, annotated, options, bytes) in observations.iter() {
+            log.record(&Observation {
+                command: command.to_string(),
+                annotated: *annotated,
+                unknown_options: options.iter().map(|x| x.to_string()).collect(),
+                bytes: *bytes,
+            })
+            .unwrap();
+        }
+        let read = log.read().unwrap();
+        remove_file(&path).unwrap();
+        assert_eq!(read.len(), 5);
+        assert_eq!(read[2].unknown_options, vec!["--color", "-P"]);
+
+        let report = missing_annotations_report(&read);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(
+            lines[1].trim(),
+            "1       1      3.0 MB  awk (no annotations)"
+        );
+        assert_eq!(lines[2].trim(), "options: -F (1)");
+        assert_eq!(
+            lines[3].trim(),
+            "2       2       300 B  grep (no annotation matched)"
+        );
+        assert_eq!(lines[4].trim(), "unknown options: --color (2), -P (1)");
+        assert_eq!(
+            lines[5].trim(),
+            "3       2         0 B  sed (no annotations)"
+        );
+    }
+}
@@ -4,7 +4,23 @@ use super::scheduler;
 use super::shellparser;
 use dash::util::Result;
 
+/// Commands evaluated by the shell itself, such as `test`.
+pub mod builtins;
 /// Example interpreter for testing and visualization.
 pub mod examples;
+/// Explains how command lines are matched against the annotations.
+pub mod explain;
+/// Runs command lines Posh cannot handle with the system shell.
+pub mod fallback;
+/// Background jobs and the job control builtins.
+pub mod jobs;
 /// Intepreter object for understanding command lines.
 pub mod interpreter;
+/// Records the command lines the annotations do not cover, to find which annotations to write.
+pub mod learn;
+/// Commands that combine the outputs of the parallel copies of a command.
+pub mod reducer;
+/// Tracks the files the configuration was loaded from, to reload it when they change.
+pub mod reload;
+/// Runs scripts with control flow, scheduling each pipeline with the interpreter.
+pub mod script;
@@ -0,0 +1,122 @@
+use super::{annotations2, Result};
+use annotations2::argument_matcher::ArgMatch;
+use annotations2::grammar::Reducer;
+use failure::bail;
+use shellwords::split;
+
+/// Sums each column of numbers, e.g. the counts printed by wc.
+const SUM_COLUMNS: &str = "{ for (i = 1; i <= NF; i++) sum[i] += $i; if (NF > n) n = NF } END { for (i = 1; i <= n; i++) printf \"%s%s\", sum[i], (i < n ? \" \" : \"\\n\") }";
+
+/// Adds up the counts of repeated lines printed by uniq -c, which may be split across outputs.
+const MERGE_COUNTS: &str = "{ count = $1; line = $0; sub(/^ *[0-9]+ /, \"\", line); if (NR > 1 && line == last) { total += count } else { if (NR > 1) printf \"%7d %s\\n\", total, last; last = line; total = count } } END { if (NR > 0) printf \"%7d %s\\n\", total, last }";
+
+/// Command that combines the outputs of the parallel copies of a command.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ReducerInvocation {
+    pub name: String,
+    pub args: Vec<String>,
+    /// If each output is given to the reducer as a fifo argument instead of being
+    /// concatenated into its stdin.
+    pub inputs_as_fifos: bool,
+}
+
+impl ReducerInvocation {
+    fn new(name: &str, args: Vec<String>, inputs_as_fifos: bool) -> Self {
+        ReducerInvocation {
+            name: name.to_string(),
+            args: args,
+            inputs_as_fifos: inputs_as_fifos,
+        }
+    }
+}
+
+/// Returns the command that combines the outputs of the parallel copies of the command `name`,
+/// as declared by the reducer in its annotation, or None if they are simply concatenated.
+pub fn reducer_invocation(name: &str, argmatch: &ArgMatch) -> Result<Option<ReducerInvocation>> {
+    let invocation = match argmatch.get_reducer() {
+        Reducer::Concat => return Ok(None),
+        Reducer::Sum => ReducerInvocation::new("awk", vec![SUM_COLUMNS.to_string()], false),
+        Reducer::Merge => {
+            // merge with the same ordering options the copies sorted with
+            let mut args = vec!["-m".to_string()];
+            args.append(&mut argmatch.option_words()?);
+            ReducerInvocation::new(name, args, true)
+        }
+        Reducer::Uniq => match argmatch.has_flag("c", "count") {
+            true => ReducerInvocation::new("awk", vec![MERGE_COUNTS.to_string()], false),
+            false => ReducerInvocation::new("uniq", vec![], false),
+        },
+        Reducer::Custom(line) => {
+            let mut words = match split(line) {
+                Ok(words) => words,
+                Err(e) => bail!("Could not split reducer {:?}: {:?}", line, e),
+            };
+            if words.is_empty() {
+                bail!("Reducer for {} is an empty command line", name);
+            }
+            let reducer_name = words.remove(0);
+            ReducerInvocation::new(&reducer_name, words, false)
+        }
+    };
+    Ok(Some(invocation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use annotations2::cmd_parser::CmdParser;
+    use annotations2::grammar::Command;
+
+    fn get_match(annotation: &str, invocation: &[&str]) -> ArgMatch {
+        let command = Command::new(annotation).unwrap();
+        let mut parser = CmdParser::new(&command.command_name);
+        parser.add_annotation(command).unwrap();
+        let invocation: Vec<String> = invocation.iter().map(|s| s.to_string()).collect();
+        parser.parse_command(&invocation).unwrap()
+    }
+
+    #[test]
+    fn test_reducer_invocation() {
+        let grep = get_match(
+            "grep[splittable_across_input]: FLAGS:[(short:v)] PARAMS:[(type:str,size:1)]",
+            &["-v", "foo"],
+        );
+        assert_eq!(reducer_invocation("grep", &grep).unwrap(), None);
+
+        let sort = get_match(
+            "sort[splittable_across_input,reducer:merge]: OPTPARAMS:[(short:k,type:str,size:1),(short:o,type:output_file,size:1)] FLAGS:[(short:n)]",
+            &["-n", "-k", "2", "-o", "out.txt"],
+        );
+        let merge = reducer_invocation("sort", &sort).unwrap().unwrap();
+        assert_eq!(merge.name, "sort");
+        assert!(merge.inputs_as_fifos);
+        assert_eq!(merge.args[0], "-m");
+        assert!(merge.args.contains(&"-n".to_string()));
+        assert!(merge.args.windows(2).any(|w| w == ["-k", "2"]));
+        assert!(!merge.args.contains(&"-o".to_string()));
+
+        let uniq = "uniq[splittable_across_input,reducer:uniq]: FLAGS:[(short:c,long:count)]";
+        let counted = reducer_invocation("uniq", &get_match(uniq, &["-c"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(counted.name, "awk");
+        assert_eq!(counted.args, vec![MERGE_COUNTS.to_string()]);
+        let plain = reducer_invocation("uniq", &get_match(uniq, &[]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(plain, ReducerInvocation::new("uniq", vec![], false));
+
+        let custom = get_match(
+            "tally[splittable_across_input,reducer:(awk -F, '{ s += $1 } END { print s }')]: FLAGS:[(short:l)]",
+            &["-l"],
+        );
+        assert_eq!(
+            reducer_invocation("tally", &custom).unwrap().unwrap(),
+            ReducerInvocation::new(
+                "awk",
+                vec!["-F,".to_string(), "{ s += $1 } END { print s }".to_string()],
+                false
+            )
+        );
+    }
+}
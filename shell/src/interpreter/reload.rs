@@ -0,0 +1,61 @@
+use super::annotations2::grammar::annotation_sources;
+use std::fs::metadata;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The files the interpreter's configuration was loaded from: the mount file and the annotation
+/// files, with when each was last modified, to tell when the configuration needs reloading.
+#[derive(Debug, Clone)]
+pub struct ConfigSources {
+    config_file: String,
+    annotations_files: Vec<String>,
+    /// Every file read, including files in annotation directories and included files, with
+    /// its modification time and size (None if it could not be read).
+    modified: Vec<(PathBuf, Option<(SystemTime, u64)>)>,
+}
+
+impl ConfigSources {
+    pub fn new(config_file: &str, annotations_files: &[String]) -> Self {
+        let mut sources = ConfigSources {
+            config_file: config_file.to_string(),
+            annotations_files: annotations_files.to_vec(),
+            modified: Vec::new(),
+        };
+        sources.refresh();
+        sources
+    }
+
+    pub fn get_config_file(&self) -> &str {
+        &self.config_file
+    }
+
+    pub fn get_annotations_files(&self) -> &[String] {
+        &self.annotations_files
+    }
+
+    /// Whether any file was modified, added or removed since the last refresh.
+    pub fn changed(&self) -> bool {
+        self.modified != self.snapshot()
+    }
+
+    /// Records the current modification times; call after reloading.
+    pub fn refresh(&mut self) {
+        self.modified = self.snapshot();
+    }
+
+    fn snapshot(&self) -> Vec<(PathBuf, Option<(SystemTime, u64)>)> {
+        let mut files: Vec<PathBuf> = vec![Path::new(&self.config_file).to_path_buf()];
+        for annotations_file in self.annotations_files.iter() {
+            annotation_sources(Path::new(annotations_file), &mut files);
+        }
+        files
+            .into_iter()
+            .map(|file| {
+                let modified = metadata(&file)
+                    .and_then(|m| Ok((m.modified()?, m.len())))
+                    .ok();
+                (file, modified)
+            })
+            .collect()
+    }
+}
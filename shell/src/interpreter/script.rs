@@ -0,0 +1,852 @@
+use super::builtins::eval_test;
+use super::fallback::ShellFallback;
+use super::interpreter::Interpreter;
+use super::shellparser::expansion::{
+    expand_glob_string, expand_glob_words, expand_line, expand_string, ShellToken,
+};
+use super::shellparser::script::{CaseArm, Connector, ScriptNode};
+use super::Result;
+use dash::graph::program::Program;
+use failure::{bail, format_err};
+use glob::{glob, Pattern};
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::error;
+
+/// How control leaves a list of commands.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Flow {
+    Normal,
+    /// `break n`: leave n enclosing loops.
+    Break(u32),
+    /// `continue n`: continue the nth enclosing loop.
+    Continue(u32),
+    /// `return` from a function.
+    Return,
+    /// `exit` the script.
+    Exit,
+}
+
+/// Runs the programs produced by a script.
+/// Any `FnMut(Program) -> Result<()>` closure is an executor that runs programs in the
+/// foreground and has no job control.
+pub trait Executor {
+    /// Runs the program to completion.
+    fn execute(&mut self, program: Program) -> Result<()>;
+
+    /// Starts the program as a background job; cmd is the pipeline, as written in the script.
+    fn execute_background(&mut self, _program: Program, _cmd: &str) -> Result<()> {
+        bail!("Background jobs are not supported here")
+    }
+
+    /// Runs a command line Posh could not parse or schedule, and returns its exit status.
+    /// By default, the line fails with the reason Posh could not run it.
+    fn execute_fallback(&mut self, fallback: ShellFallback) -> Result<i32> {
+        bail!("{}", fallback.get_reason())
+    }
+
+    /// Called when the shell changes its working directory.
+    fn set_pwd(&mut self, _pwd: &Path) {}
+
+    /// Runs a job control builtin (jobs, fg, bg, wait, kill) and returns its exit status.
+    /// Returns None if the executor does not handle the command.
+    fn job_builtin(&mut self, _words: &[String]) -> Result<Option<i32>> {
+        Ok(None)
+    }
+}
+
+impl<F: FnMut(Program) -> Result<()>> Executor for F {
+    fn execute(&mut self, program: Program) -> Result<()> {
+        self(program)
+    }
+}
+
+/// Runs scripts: control flow is evaluated at the client, and each leaf pipeline is parsed and
+/// scheduled by the interpreter, then executed by the given executor.
+#[derive(Default)]
+pub struct ScriptRunner {
+    /// Shell functions defined so far.
+    functions: HashMap<String, Vec<ScriptNode>>,
+    /// Number of enclosing loops, for break and continue.
+    loop_depth: u32,
+    /// Number of enclosing function calls, for return.
+    function_depth: u32,
+    /// If the script called exit.
+    exited: bool,
+}
+
+impl ScriptRunner {
+    /// Runs a list of script commands and returns the exit status of the last command.
+    /// executor runs each program the interpreter produces.
+    pub fn run(
+        &mut self,
+        interpreter: &mut Interpreter,
+        nodes: &[ScriptNode],
+        executor: &mut dyn Executor,
+    ) -> Result<i32> {
+        if self.run_list(interpreter, nodes, executor)? == Flow::Exit {
+            self.exited = true;
+        }
+        Ok(interpreter.get_shell_env_mut().get_last_status())
+    }
+
+    /// If the script ran the exit builtin.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    fn run_list(
+        &mut self,
+        interpreter: &mut Interpreter,
+        nodes: &[ScriptNode],
+        executor: &mut dyn Executor,
+    ) -> Result<Flow> {
+        for node in nodes.iter() {
+            let flow = self.run_node(interpreter, node, executor)?;
+            if flow != Flow::Normal {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn run_node(
+        &mut self,
+        interpreter: &mut Interpreter,
+        node: &ScriptNode,
+        executor: &mut dyn Executor,
+    ) -> Result<Flow> {
+        match node {
+            ScriptNode::Pipeline(cmd) => self.run_pipeline(interpreter, cmd, executor),
+            ScriptNode::Not(inner) => {
+                let flow = self.run_node(interpreter, inner, executor)?;
+                let status = match status(interpreter) {
+                    0 => 1,
+                    _ => 0,
+                };
+                interpreter.set_last_status(status);
+                Ok(flow)
+            }
+            ScriptNode::AndOr(first, rest) => {
+                let mut flow = self.run_node(interpreter, first, executor)?;
+                for (connector, next) in rest.iter() {
+                    if flow != Flow::Normal {
+                        break;
+                    }
+                    let succeeded = status(interpreter) == 0;
+                    match (connector, succeeded) {
+                        (Connector::And, true) | (Connector::Or, false) => {
+                            flow = self.run_node(interpreter, next, executor)?;
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(flow)
+            }
+            ScriptNode::If {
+                branches,
+                else_body,
+            } => {
+                for (condition, body) in branches.iter() {
+                    let flow = self.run_list(interpreter, condition, executor)?;
+                    if flow != Flow::Normal {
+                        return Ok(flow);
+                    }
+                    if status(interpreter) == 0 {
+                        return self.run_list(interpreter, body, executor);
+                    }
+                }
+                match else_body {
+                    Some(body) => self.run_list(interpreter, body, executor),
+                    None => {
+                        interpreter.set_last_status(0);
+                        Ok(Flow::Normal)
+                    }
+                }
+            }
+            ScriptNode::For { var, words, body } => {
+                let words = match words {
+                    Some(words) => match self.expand_for_words(interpreter, words) {
+                        Ok(words) => words,
+                        Err(e) => {
+                            return self.run_fallback(
+                                interpreter,
+                                &node.to_string(),
+                                e,
+                                false,
+                                executor,
+                            )
+                        }
+                    },
+                    None => interpreter.get_shell_env_mut().get_positional(),
+                };
+                interpreter.set_last_status(0);
+                self.loop_depth += 1;
+                let ret = self.run_for(interpreter, var, words, body, executor);
+                self.loop_depth -= 1;
+                ret
+            }
+            ScriptNode::While {
+                condition,
+                body,
+                until,
+            } => {
+                self.loop_depth += 1;
+                let ret = self.run_while(interpreter, condition, body, *until, executor);
+                self.loop_depth -= 1;
+                ret
+            }
+            ScriptNode::Case { word, arms } => match case_arm(interpreter, word, arms) {
+                Ok(Some(body)) => self.run_list(interpreter, body, executor),
+                Ok(None) => {
+                    interpreter.set_last_status(0);
+                    Ok(Flow::Normal)
+                }
+                Err(e) => self.run_fallback(interpreter, &node.to_string(), e, false, executor),
+            },
+            ScriptNode::Function { name, body } => {
+                self.functions.insert(name.clone(), body.clone());
+                interpreter.set_last_status(0);
+                Ok(Flow::Normal)
+            }
+            ScriptNode::Group(body) => self.run_list(interpreter, body, executor),
+            ScriptNode::Background(inner) => match inner.as_ref() {
+                ScriptNode::Pipeline(cmd) => self.run_background(interpreter, cmd, executor),
+                _ => bail!("Only single pipelines can be run in the background"),
+            },
+        }
+    }
+
+    fn run_for(
+        &mut self,
+        interpreter: &mut Interpreter,
+        var: &str,
+        words: Vec<String>,
+        body: &[ScriptNode],
+        executor: &mut dyn Executor,
+    ) -> Result<Flow> {
+        for word in words.iter() {
+            interpreter.get_shell_env_mut().set_var(var, word);
+            match self.run_list(interpreter, body, executor)? {
+                Flow::Break(n) if n > 1 => return Ok(Flow::Break(n - 1)),
+                Flow::Break(_) => break,
+                Flow::Continue(n) if n > 1 => return Ok(Flow::Continue(n - 1)),
+                Flow::Continue(_) | Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn run_while(
+        &mut self,
+        interpreter: &mut Interpreter,
+        condition: &[ScriptNode],
+        body: &[ScriptNode],
+        until: bool,
+        executor: &mut dyn Executor,
+    ) -> Result<Flow> {
+        let mut body_status = 0;
+        loop {
+            let flow = self.run_list(interpreter, condition, executor)?;
+            if flow != Flow::Normal {
+                return Ok(flow);
+            }
+            if (status(interpreter) == 0) == until {
+                break;
+            }
+            match self.run_list(interpreter, body, executor)? {
+                Flow::Break(n) if n > 1 => return Ok(Flow::Break(n - 1)),
+                Flow::Break(_) => break,
+                Flow::Continue(n) if n > 1 => return Ok(Flow::Continue(n - 1)),
+                Flow::Continue(_) | Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+            body_status = status(interpreter);
+        }
+        interpreter.set_last_status(body_status);
+        Ok(Flow::Normal)
+    }
+
+    /// Expands the word list of a for loop, including any file name patterns.
+    /// Quoted pattern characters match only themselves.
+    fn expand_for_words(
+        &mut self,
+        interpreter: &mut Interpreter,
+        words: &str,
+    ) -> Result<Vec<String>> {
+        let pwd = interpreter.get_pwd();
+        let mut ret: Vec<String> = Vec::new();
+        for (word, pattern) in expand_glob_words(words, interpreter.get_shell_env_mut())? {
+            if pattern == Pattern::escape(&word) {
+                ret.push(word);
+                continue;
+            }
+            let relative = !Path::new(&word).is_absolute();
+            let pattern = match relative {
+                true => Path::new(&Pattern::escape(&pwd.to_string_lossy())).join(&pattern),
+                false => Path::new(&pattern).to_path_buf(),
+            };
+            let mut matches: Vec<String> = Vec::new();
+            if let Ok(paths) = glob(&pattern.to_string_lossy()) {
+                for path in paths.filter_map(|p| p.ok()) {
+                    let path = match relative {
+                        true => path.strip_prefix(&pwd).unwrap_or(&path).to_path_buf(),
+                        false => path,
+                    };
+                    matches.push(path.to_string_lossy().to_string());
+                }
+            }
+            // like sh, patterns that match nothing are left as is
+            match matches.is_empty() {
+                true => ret.push(word),
+                false => ret.append(&mut matches),
+            }
+        }
+        Ok(ret)
+    }
+
+    fn run_pipeline(
+        &mut self,
+        interpreter: &mut Interpreter,
+        cmd: &str,
+        executor: &mut dyn Executor,
+    ) -> Result<Flow> {
+        // explain expands the command line itself, so it is given the line as written
+        if let Some(line) = explained_line(cmd) {
+            let status = match interpreter.explain_command_line(line) {
+                Ok(explanation) => {
+                    print!("{}", explanation);
+                    0
+                }
+                Err(e) => {
+                    error!("explain: {:?}", e);
+                    1
+                }
+            };
+            interpreter.set_last_status(status);
+            return Ok(Flow::Normal);
+        }
+        let tokens = match expand_line(cmd, interpreter.get_shell_env_mut()) {
+            Ok(tokens) => tokens,
+            Err(e) => return self.run_fallback(interpreter, cmd, e, false, executor),
+        };
+        let words: Option<Vec<String>> = tokens
+            .iter()
+            .map(|token| match token {
+                ShellToken::Word(word) => Some(word.clone()),
+                ShellToken::Op(_) => None,
+            })
+            .collect();
+        if let Some(words) = words {
+            if let Some(flow) = self.run_builtin(interpreter, &words, executor)? {
+                return Ok(flow);
+            }
+        }
+
+        let program = match interpreter.parse_expanded_tokens(tokens) {
+            Ok(program) => program,
+            Err(e) => return self.run_fallback(interpreter, cmd, e, false, executor),
+        };
+        match program {
+            Some(program) => {
+                let prog_id = program.get_id();
+                match executor.execute(program) {
+                    Ok(_) => {
+                        interpreter.set_last_status(0);
+                    }
+                    Err(e) => {
+                        error!("Failed to execute {:?}: {:?}", cmd, e);
+                        interpreter.set_last_status(1);
+                    }
+                }
+                interpreter.finish_program(prog_id);
+            }
+            None => {
+                interpreter.set_last_status(0);
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn run_background(
+        &mut self,
+        interpreter: &mut Interpreter,
+        cmd: &str,
+        executor: &mut dyn Executor,
+    ) -> Result<Flow> {
+        let program = match expand_line(cmd, interpreter.get_shell_env_mut()) {
+            Ok(tokens) => interpreter.parse_expanded_tokens(tokens),
+            Err(e) => Err(e),
+        };
+        let program = match program {
+            Ok(program) => program,
+            Err(e) => return self.run_fallback(interpreter, cmd, e, true, executor),
+        };
+        let status = match program {
+            Some(program) => {
+                let prog_id = program.get_id();
+                let status = match executor.execute_background(program, cmd) {
+                    Ok(_) => 0,
+                    Err(e) => {
+                        error!("Failed to start {:?}: {:?}", cmd, e);
+                        1
+                    }
+                };
+                // the job's outputs are still being written, but sizes cached before it
+                // started are already stale
+                interpreter.finish_program(prog_id);
+                status
+            }
+            None => 0,
+        };
+        interpreter.set_last_status(status);
+        Ok(Flow::Normal)
+    }
+
+    /// Hands a command Posh could not expand, parse or schedule to the executor's fallback.
+    fn run_fallback(
+        &mut self,
+        interpreter: &mut Interpreter,
+        cmd: &str,
+        reason: failure::Error,
+        background: bool,
+        executor: &mut dyn Executor,
+    ) -> Result<Flow> {
+        let mut fallback = ShellFallback::new(cmd, &reason, interpreter);
+        fallback.set_background(background);
+        let status = executor.execute_fallback(fallback)?;
+        interpreter.set_last_status(status);
+        Ok(Flow::Normal)
+    }
+
+    /// How `break n` or `continue n` leaves the enclosing loops.
+    fn loop_flow(&self, builtin: &str, args: &[String]) -> Result<Flow> {
+        if self.loop_depth == 0 {
+            bail!("{}: only meaningful in a loop", builtin);
+        }
+        let n = numeric_arg(builtin, args, 1)? as u32;
+        if n == 0 {
+            bail!("{}: loop count out of range", builtin);
+        }
+        let n = std::cmp::min(n, self.loop_depth);
+        match builtin == "break" {
+            true => Ok(Flow::Break(n)),
+            false => Ok(Flow::Continue(n)),
+        }
+    }
+
+    /// Runs commands that are evaluated by the shell itself.
+    /// Returns None if the command is not a builtin.
+    fn run_builtin(
+        &mut self,
+        interpreter: &mut Interpreter,
+        words: &[String],
+        executor: &mut dyn Executor,
+    ) -> Result<Option<Flow>> {
+        if words.is_empty() {
+            return Ok(Some(Flow::Normal));
+        }
+        // NAME=value assignments without a command set shell variables
+        if words.iter().all(|word| is_assignment(word)) {
+            for word in words.iter() {
+                let idx = word.find('=').unwrap();
+                interpreter
+                    .get_shell_env_mut()
+                    .set_var(&word[..idx], &word[idx + 1..]);
+            }
+            interpreter.set_last_status(0);
+            return Ok(Some(Flow::Normal));
+        }
+
+        let args = &words[1..];
+        let flow = match words[0].as_str() {
+            "true" | ":" => {
+                interpreter.set_last_status(0);
+                Flow::Normal
+            }
+            "false" => {
+                interpreter.set_last_status(1);
+                Flow::Normal
+            }
+            "test" | "[" => {
+                let args = match words[0] == "[" {
+                    true => match args.last().map(|x| x.as_str()) {
+                        Some("]") => Ok(&args[..args.len() - 1]),
+                        _ => Err(format_err!("[: missing ]")),
+                    },
+                    false => Ok(args),
+                };
+                let status = match args.and_then(|args| eval_test(args, &interpreter.get_pwd())) {
+                    Ok(true) => 0,
+                    Ok(false) => 1,
+                    Err(e) => {
+                        error!("{:?}", e);
+                        2
+                    }
+                };
+                interpreter.set_last_status(status);
+                Flow::Normal
+            }
+            "break" | "continue" => match self.loop_flow(&words[0], args) {
+                Ok(flow) => flow,
+                Err(e) => {
+                    error!("{}", e);
+                    interpreter.set_last_status(1);
+                    Flow::Normal
+                }
+            },
+            "return" => {
+                let status = match self.function_depth {
+                    0 => Err(format_err!("return: can only return from a function")),
+                    _ => numeric_arg("return", args, status(interpreter)),
+                };
+                match status {
+                    Ok(status) => {
+                        interpreter.set_last_status(status);
+                        Flow::Return
+                    }
+                    Err(e) => {
+                        error!("{}", e);
+                        interpreter.set_last_status(1);
+                        Flow::Normal
+                    }
+                }
+            }
+            "exit" => {
+                let status = numeric_arg("exit", args, status(interpreter))?;
+                interpreter.set_last_status(status);
+                Flow::Exit
+            }
+            "shift" => {
+                let n = numeric_arg("shift", args, 1)?;
+                interpreter.get_shell_env_mut().shift(n as usize)?;
+                interpreter.set_last_status(0);
+                Flow::Normal
+            }
+            "cd" | "pushd" | "popd" | "dirs" => {
+                let status = match change_directory(interpreter, words) {
+                    Ok(_) => {
+                        executor.set_pwd(&interpreter.get_pwd());
+                        0
+                    }
+                    Err(e) => {
+                        error!("{:?}", e);
+                        1
+                    }
+                };
+                interpreter.set_last_status(status);
+                Flow::Normal
+            }
+            "reload" => {
+                // report parse errors even when tracing is off, as the user asked for the reload
+                let status = match interpreter.reload() {
+                    Ok(summary) => {
+                        println!("reload: {}", summary);
+                        0
+                    }
+                    Err(e) => {
+                        eprintln!("reload: {}", e);
+                        1
+                    }
+                };
+                interpreter.set_last_status(status);
+                Flow::Normal
+            }
+            "jobs" | "fg" | "bg" | "wait" | "kill" => match executor.job_builtin(words)? {
+                Some(status) => {
+                    interpreter.set_last_status(status);
+                    Flow::Normal
+                }
+                None => {
+                    return Ok(None);
+                }
+            },
+            name => match self.functions.get(name).cloned() {
+                Some(body) => {
+                    let saved = interpreter.get_shell_env_mut().get_positional();
+                    interpreter.get_shell_env_mut().set_args(args.to_vec());
+                    self.function_depth += 1;
+                    let ret = self.run_list(interpreter, &body, executor);
+                    self.function_depth -= 1;
+                    interpreter.get_shell_env_mut().set_args(saved);
+                    match ret? {
+                        Flow::Return => Flow::Normal,
+                        flow => flow,
+                    }
+                }
+                None => {
+                    return Ok(None);
+                }
+            },
+        };
+        Ok(Some(flow))
+    }
+}
+
+/// Runs cd, pushd, popd and dirs.
+fn change_directory(interpreter: &mut Interpreter, words: &[String]) -> Result<()> {
+    let args = &words[1..];
+    if args.len() > 1 {
+        bail!("{}: too many arguments", words[0]);
+    }
+    let dir = args.first().map(|x| x.as_str());
+    match words[0].as_str() {
+        "cd" => {
+            let new_dir = interpreter.change_dir(dir)?;
+            // like sh, cd - shows where it went
+            if dir == Some("-") {
+                println!("{}", new_dir.display());
+            }
+            return Ok(());
+        }
+        "pushd" => {
+            interpreter.push_dir(dir)?;
+        }
+        "popd" => {
+            interpreter.pop_dir()?;
+        }
+        _ => {}
+    }
+    let dirs: Vec<String> = interpreter
+        .get_dir_stack()
+        .iter()
+        .map(|dir| dir.display().to_string())
+        .collect();
+    println!("{}", dirs.join(" "));
+    Ok(())
+}
+
+/// Finds the body of the first case arm with a pattern that matches the expanded word.
+fn case_arm<'a>(
+    interpreter: &mut Interpreter,
+    word: &str,
+    arms: &'a [CaseArm],
+) -> Result<Option<&'a [ScriptNode]>> {
+    let value = expand_string(word, interpreter.get_shell_env_mut())?;
+    for arm in arms.iter() {
+        for pattern in arm.patterns.iter() {
+            let (literal, pattern) = expand_glob_string(pattern, interpreter.get_shell_env_mut())?;
+            let matches = match Pattern::new(&pattern) {
+                Ok(p) => p.matches(&value),
+                Err(_) => literal == value,
+            };
+            if matches {
+                return Ok(Some(&arm.body));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The command line after `explain`, unexpanded, if the pipeline runs the explain builtin.
+fn explained_line(cmd: &str) -> Option<&str> {
+    let cmd = cmd.trim_start();
+    let rest = cmd.strip_prefix("explain")?;
+    match rest.is_empty() || rest.starts_with(char::is_whitespace) {
+        true => Some(rest.trim()),
+        false => None,
+    }
+}
+
+fn status(interpreter: &mut Interpreter) -> i32 {
+    interpreter.get_shell_env_mut().get_last_status()
+}
+
+fn is_assignment(word: &str) -> bool {
+    match word.find('=') {
+        Some(idx) if idx > 0 => {
+            let name = &word[..idx];
+            !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// Parses the optional numeric argument of a builtin like `exit` or `shift`.
+fn numeric_arg(builtin: &str, args: &[String], default: i32) -> Result<i32> {
+    match args.first() {
+        Some(arg) => match arg.parse::<i32>() {
+            Ok(n) if n >= 0 => Ok(n),
+            _ => bail!("{}: numeric argument required: {:?}", builtin, arg),
+        },
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::examples::get_test_interpreter;
+    use super::super::shellparser::script::parse_script;
+    use super::*;
+    use dash::graph::program::Elem;
+    use dash::graph::Location;
+    use std::path::PathBuf;
+
+    /// Runs the script and returns the arguments of each command that was executed.
+    fn run_script(script: &str) -> (i32, Vec<String>) {
+        let mut interpreter = get_test_interpreter();
+        let nodes = parse_script(script).unwrap();
+        let mut executed: Vec<String> = Vec::new();
+        let mut runner = ScriptRunner::default();
+        let status = runner
+            .run(&mut interpreter, &nodes, &mut |program: Program| {
+                for (_, node) in program.get_nodes_iter() {
+                    if let Elem::Cmd(cmd) = node.get_elem() {
+                        let mut invocation = vec![cmd.get_name()];
+                        invocation.append(&mut cmd.get_string_args());
+                        executed.push(invocation.join(" "));
+                    }
+                }
+                Ok(())
+            })
+            .unwrap();
+        (status, executed)
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let script = r#"
+log() {
+    echo "$1" done
+}
+for day in mon tue wed; do
+    case $day in
+        t*) continue ;;
+    esac
+    if [ $day = mon ] && true; then
+        log $day
+    elif false; then
+        echo unreachable
+    else
+        echo $day
+    fi
+done
+COUNT=a
+while [ $COUNT != aaa ]; do
+    COUNT=${COUNT}a
+done
+echo $COUNT
+"#;
+        let (status, executed) = run_script(script);
+        assert_eq!(status, 0);
+        assert_eq!(executed, vec!["echo mon done", "echo wed", "echo aaa"]);
+    }
+
+    #[test]
+    fn test_quoted_patterns() {
+        let script = r#"
+for f in "/*" /"[a]"; do echo $f; done
+case abc in "a*") echo quoted;; a*) echo pattern;; esac
+X='a*'
+case abc in $X) echo variable;; esac
+case 'a*' in "a"'*') echo literal;; esac
+"#;
+        let (_, executed) = run_script(script);
+        assert_eq!(
+            executed,
+            vec![
+                "echo /*",
+                "echo /[a]",
+                "echo pattern",
+                "echo variable",
+                "echo literal"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_change_directory() {
+        let mut interpreter = get_test_interpreter();
+        let script =
+            "cd ../../../b/a/logs; pushd /c/b; cd -; cd -; popd; cd /c/b/missing; cd /no/such/dir";
+        let nodes = parse_script(script).unwrap();
+        let mut runner = ScriptRunner::default();
+        let mut recorder = Recorder::default();
+        let status = runner.run(&mut interpreter, &nodes, &mut recorder).unwrap();
+        // the last two cds fail: the remote directory does not exist on its server, and the
+        // local one does not exist either
+        assert_eq!(status, 1);
+        let expected: Vec<PathBuf> = vec!["/b/a/logs", "/c/b", "/b/a/logs", "/c/b", "/b/a/logs"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(recorder.pwds, expected);
+        assert_eq!(interpreter.get_pwd(), Path::new("/b/a/logs"));
+        assert_eq!(
+            interpreter.get_pwd_location(),
+            Location::Server("125.0.0.1".to_string())
+        );
+        assert!(interpreter.pop_dir().is_err());
+    }
+
+    #[test]
+    fn test_fallback() {
+        let mut interpreter = get_test_interpreter();
+        // here-documents span lines, so the whole script is left to the system shell
+        assert!(parse_script("for f in a b; do cat $f <<EOF\n$f\nEOF\ndone\n").is_err());
+
+        let script = "for f in a b; do cat $f; done; echo $(date) &\nfor d in $(ls /logs); do rm -r $d; done\ncase `hostname` in a*) echo a;; esac";
+        let nodes = parse_script(script).unwrap();
+        let mut runner = ScriptRunner::default();
+        let mut recorder = Recorder::default();
+        let status = runner.run(&mut interpreter, &nodes, &mut recorder).unwrap();
+        assert_eq!(status, 7);
+        // compound commands whose words can't be expanded fall back as a whole
+        assert_eq!(
+            recorder.fallbacks,
+            vec![
+                "echo $(date)",
+                "for d in $(ls /logs); do rm -r $d; done",
+                "case `hostname` in a*) echo a; ;; esac",
+            ]
+        );
+    }
+
+    /// Records the directories the shell changes to and the lines it can't run itself.
+    #[derive(Default)]
+    struct Recorder {
+        pwds: Vec<PathBuf>,
+        fallbacks: Vec<String>,
+    }
+
+    impl Executor for Recorder {
+        fn execute(&mut self, _program: Program) -> Result<()> {
+            Ok(())
+        }
+
+        fn execute_fallback(&mut self, fallback: ShellFallback) -> Result<i32> {
+            self.fallbacks.push(fallback.get_line().to_string());
+            Ok(7)
+        }
+
+        fn set_pwd(&mut self, pwd: &Path) {
+            self.pwds.push(pwd.to_path_buf());
+        }
+    }
+
+    #[test]
+    fn test_explained_line() {
+        assert_eq!(explained_line("explain grep 'a b' f"), Some("grep 'a b' f"));
+        assert_eq!(
+            explained_line("explain cat $X | sort"),
+            Some("cat $X | sort")
+        );
+        assert_eq!(explained_line("explain"), Some(""));
+        assert_eq!(explained_line("explainer x"), None);
+        assert_eq!(explained_line("echo explain"), None);
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let (status, executed) = run_script("false || echo a; ! true; exit 3; echo b");
+        assert_eq!(status, 3);
+        assert_eq!(executed, vec!["echo a"]);
+
+        // misused builtins fail, but the script keeps going
+        let (status, executed) = run_script("[ -d / || echo a; break; echo $?; return");
+        assert_eq!(status, 1);
+        assert_eq!(executed, vec!["echo a", "echo 1"]);
+    }
+}
@@ -15,6 +15,10 @@ use std::f64::INFINITY;
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 
+/// Transfer times are file sizes in bytes over link speeds in Mbps; this many of those units
+/// make up a second.
+const UNITS_PER_SECOND: f64 = 125_000.0;
+
 type NodeAssignment = (NodeId, Location);
 #[derive(PartialEq, Debug, Clone, Default)]
 struct DP {
@@ -100,34 +104,9 @@ impl Scheduler for DPScheduler {
         pwd: &Path,
     ) -> Result<HashMap<NodeId, Location>> {
         let mut assignments: HashMap<NodeId, Location> = HashMap::new();
-        // iterate through each node to cache input file sizes
-        let start = Instant::now();
-        let mut query_paths: Vec<PathBuf> = Vec::new();
-        for (id, node) in prog.get_nodes_iter() {
-            match node.get_elem() {
-                Elem::Cmd(_cmdnode) => {
-                    let argmatch = match_map.get(id).unwrap();
-                    // query for all of the file locations at once, at the server
-                    for (argtype, fs) in argmatch.file_dependencies().iter() {
-                        match argtype {
-                            ArgType::InputFile => {
-                                query_paths.push(fs.get_path());
-                            }
-                            _ => {}
-                        }
-                    }
-                }
-                _ => {}
-            }
-        }
-        // query for file sizes
-        filecache.get_sizes(&query_paths)?;
-        tracing::error!(
-            "Took {:?} to get all necessary filepaths",
-            start.elapsed().as_secs()
-        );
-        // estimate weights of each edge
-        let edge_weights = calculate_edge_weights(prog, match_map, filecache)?;
+        query_input_sizes(prog, match_map, filecache)?;
+        // estimate weights of each edge, and how much each node reads
+        let (edge_weights, input_sizes) = calculate_edge_weights(prog, match_map, filecache)?;
 
         // define a new DP to fill in
         let mut dp = DP::default();
@@ -149,6 +128,7 @@ impl Scheduler for DPScheduler {
                     filecache,
                     pwd,
                     &edge_weights,
+                    &input_sizes,
                     &mut dp,
                 )?;
                 tracing::debug!("Calculating dp for {:?} -> {:?}", entry, val);
@@ -170,6 +150,41 @@ impl Scheduler for DPScheduler {
     }
 }
 
+/// Caches the sizes of the commands' input files, querying for all of them at once.
+pub fn query_input_sizes(
+    prog: &Program,
+    match_map: &HashMap<NodeId, ArgMatch>,
+    filecache: &mut FileCache,
+) -> Result<()> {
+    // iterate through each node to cache input file sizes
+    let start = Instant::now();
+    let mut query_paths: Vec<PathBuf> = Vec::new();
+    for (id, node) in prog.get_nodes_iter() {
+        match node.get_elem() {
+            Elem::Cmd(_cmdnode) => {
+                let argmatch = match_map.get(id).unwrap();
+                // query for all of the file locations at once, at the server
+                for (argtype, fs) in argmatch.file_dependencies().iter() {
+                    match argtype {
+                        ArgType::InputFile => {
+                            query_paths.push(fs.get_path());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    // query for file sizes
+    filecache.get_sizes(&query_paths)?;
+    tracing::error!(
+        "Took {:?} to get all necessary filepaths",
+        start.elapsed().as_secs()
+    );
+    Ok(())
+}
+
 fn backtrack(prog: &Program, config: &FileNetwork, dp: &mut DP) -> Result<()> {
     // backtrack the DP to figure out where to execute everything
     for sink_id in prog.get_sinks().iter() {
@@ -228,13 +243,16 @@ fn backtrack(prog: &Program, config: &FileNetwork, dp: &mut DP) -> Result<()> {
 
     Ok(())
 }
-fn calculate_edge_weights(
+/// Estimates the bytes flowing over each edge, and the bytes each node reads from its edges
+/// and input files. A command outputs its input scaled by its selectivity.
+pub fn calculate_edge_weights(
     prog: &Program,
     match_map: &mut HashMap<NodeId, ArgMatch>,
     filecache: &mut FileCache,
-) -> Result<HashMap<Link, f64>> {
+) -> Result<(HashMap<Link, f64>, HashMap<NodeId, f64>)> {
     let execution_order = prog.execution_order();
     let mut edge_weights: HashMap<Link, f64> = HashMap::new();
+    let mut input_sizes: HashMap<NodeId, f64> = HashMap::new();
     for id in execution_order.iter() {
         let mut input_size: f64 = 0.0;
         // find the total size of the preceeding edges
@@ -251,13 +269,11 @@ fn calculate_edge_weights(
                 }
             }
         }
-        let mut is_filter_node = false;
+        let mut selectivity = 1.0;
         match prog.get_node(*id).unwrap().get_elem() {
             Elem::Cmd(_cmdnode) => {
                 let argmatch = match_map.get(id).unwrap();
-                if argmatch.get_reduces_input() {
-                    is_filter_node = true;
-                }
+                selectivity = argmatch.get_selectivity();
                 for (argtype, fs) in argmatch.file_dependencies().iter() {
                     match argtype {
                         ArgType::InputFile => {
@@ -278,10 +294,8 @@ fn calculate_edge_weights(
             }
         }
 
-        let mut output_size = match is_filter_node {
-            true => input_size / 2.0,
-            false => input_size as f64,
-        };
+        input_sizes.insert(*id, input_size);
+        let mut output_size = input_size * selectivity;
 
         // if this node is a cmdnode, and writes to output files, output edge size is 0
         // assume all flow is directed to the output file
@@ -290,7 +304,7 @@ fn calculate_edge_weights(
                 let argmatch = match_map.get(id).unwrap();
                 for (argtype, _) in argmatch.file_dependencies().iter() {
                     match argtype {
-                        ArgType::OutputFile => {
+                        ArgType::OutputFile | ArgType::OutputPrefix => {
                             output_size = 0.0;
                         }
                         _ => {}
@@ -320,7 +334,7 @@ fn calculate_edge_weights(
         }
     }
 
-    Ok(edge_weights)
+    Ok((edge_weights, input_sizes))
 }
 
 /// Represent constraints by returning 0 if the assigned location is not the potential location
@@ -340,6 +354,7 @@ fn calculate_dp(
     filecache: &mut FileCache,
     pwd: &Path,
     edge_weights: &HashMap<Link, f64>,
+    input_sizes: &HashMap<NodeId, f64>,
     dp: &mut DP,
 ) -> Result<f64> {
     let id = entry.0;
@@ -356,15 +371,10 @@ fn calculate_dp(
                         bail!("No edge between {:?} and {:?}");
                     }
                 };
-                // if no link between two machines, dp is infinite
-                let bw = config.network_speed(&loc, location).unwrap_or(0.0);
                 let dp_val = dp.get(&(prev_id, loc.clone()))?;
-                tracing::debug!("prev node: {:?}, curr node: {:?}, prev node loc: {:?}, edge size: {:?}, bw: {:?}, prev dp: {:?}, res: {:?}", prev_id, id, loc.clone(), edge_size, bw, dp_val, dp_val + edge_size/bw);
-                if bw != 0.0 {
-                    Ok(dp_val + edge_size / bw)
-                } else {
-                    Ok(INFINITY)
-                }
+                let transfer = transfer_cost(*edge_size, loc, location, config);
+                tracing::debug!("prev node: {:?}, curr node: {:?}, prev node loc: {:?}, edge size: {:?}, prev dp: {:?}, res: {:?}", prev_id, id, loc.clone(), edge_size, dp_val, dp_val + transfer);
+                Ok(dp_val + transfer)
             })
             .collect();
         let vals = vals_result?;
@@ -392,6 +402,49 @@ fn calculate_dp(
         }
     };
 
+    // calculate sum of transferring previous nodes
+    let mut dp_val = 0.0;
+    for preceeding_id in prog.get_dependent_nodes(id).iter() {
+        // will calculate min execution location for all preceeding terms
+        let transfer_term = min_term(*preceeding_id, dp)?;
+        dp_val += transfer_term;
+    }
+    let cost = node_cost(
+        id,
+        location,
+        prog,
+        match_map,
+        config,
+        filecache,
+        pwd,
+        input_sizes,
+    )?;
+    Ok(dp_val + cost)
+}
+
+/// Time to send an edge's bytes from one location to another; infinite if there is no link.
+pub fn transfer_cost(edge_size: f64, from: &Location, to: &Location, config: &FileNetwork) -> f64 {
+    // if no link between two machines, the transfer never finishes
+    let bw = config.network_speed(from, to).unwrap_or(0.0);
+    if bw != 0.0 {
+        edge_size / bw
+    } else {
+        INFINITY
+    }
+}
+
+/// Cost of running the node at the location, besides receiving its input edges: reading its
+/// input files from there and running it, or infinite if it cannot run there.
+pub fn node_cost(
+    id: NodeId,
+    location: &Location,
+    prog: &Program,
+    match_map: &HashMap<NodeId, ArgMatch>,
+    config: &FileNetwork,
+    filecache: &mut FileCache,
+    pwd: &Path,
+    input_sizes: &HashMap<NodeId, f64>,
+) -> Result<f64> {
     let node = prog.get_node(id).unwrap();
     match node.get_elem() {
         Elem::Cmd(_cmdnode) => {
@@ -414,9 +467,24 @@ fn calculate_dp(
                             input_time += file_size / speed;
                         }
                     }
+                    ArgType::OutputPrefix => {
+                        // the files it creates can only be found where the prefix lives
+                        input_time += constraint(location, &config.get_location(fs))?;
+                    }
                     _ => {}
                 }
             }
+            // implicit inputs are not arguments, so they cannot be read remotely
+            for fs in argmatch.implicit_dependencies().iter() {
+                input_time += constraint(location, &config.get_location(fs))?;
+            }
+            // neither can the files named by the commands it runs
+            for fs in argmatch.nested_dependencies().iter() {
+                input_time += constraint(location, &config.get_location(fs))?;
+            }
+            if argmatch.must_run_on_client() {
+                input_time += constraint(location, &Location::Client)?;
+            }
 
             if argmatch.get_needs_current_dir() {
                 // Querying for filesize can be extremely expensive
@@ -435,26 +503,18 @@ fn calculate_dp(
                 input_time += time;
             }
 
-            // calculate sum of transferring previous nodes
-            let mut dp_val = 0.0;
-            for preceeding_id in prog.get_dependent_nodes(id).iter() {
-                // will calculate min execution location for all preceeding terms
-                let transfer_term = min_term(*preceeding_id, dp)?;
-                dp_val += transfer_term;
-            }
-            return Ok(dp_val + input_time);
+            // time to run the command here, from the costs in its annotation
+            let input_size = input_sizes.get(&id).cloned().unwrap_or(0.0);
+            let compute_time =
+                argmatch.get_cpu_time(input_size) * UNITS_PER_SECOND / config.cpu_speed(location);
+
+            return Ok(input_time + compute_time);
         }
         Elem::Read(readnode) => {
             assert!(prog.get_dependent_nodes(id).len() == 0);
             return constraint(location, &config.get_location(readnode.get_input_ref()));
         }
         Elem::Write(writenode) => {
-            // calculate the preceeding min execution times
-            let mut dp_val = 0.0;
-            for preceeding_id in prog.get_dependent_nodes(id).iter() {
-                let transfer_term = min_term(*preceeding_id, dp)?;
-                dp_val += transfer_term;
-            }
             let writecost = match writenode.get_stdout() {
                 Some(stdout) => match stdout {
                     DashStream::File(fs) => constraint(location, &config.get_location(&fs)),
@@ -469,7 +529,7 @@ fn calculate_dp(
                     unreachable!();
                 }
             }?;
-            return Ok(dp_val + writecost);
+            return Ok(writecost);
         }
     }
 }
@@ -0,0 +1,391 @@
+use super::annotations2::argument_matcher::ArgMatch;
+use super::config::filecache::FileCache;
+use super::config::network::FileNetwork;
+use super::dp::{calculate_edge_weights, node_cost, query_input_sizes, transfer_cost, DPScheduler};
+use super::{required_location, Scheduler};
+use dash::graph::program::{Link, NodeId, Program};
+use dash::graph::Location;
+use dash::util::Result;
+use failure::bail;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Largest number of ways to place the nodes that are not pinned for which the search is run;
+/// larger programs keep the improved DP placement.
+const EXACT_SEARCH_LIMIT: f64 = 200_000.0;
+/// Bound on the rounds of moving single nodes, which each lower the cost.
+const MAX_IMPROVEMENT_ROUNDS: usize = 100;
+
+/// Places the whole program at once, minimizing the DP scheduler's estimate of the time it takes:
+/// the cost of running each node where it is placed plus the time to send each edge's data
+/// between the locations of its ends.
+/// Unlike `HeuristicScheduler`, paths may change location any number of times, and unlike
+/// `DPScheduler`, a node that feeds several others is placed once and its cost counted once.
+/// With more than two locations this is a multiway cut, which is NP-hard, so the search is only
+/// run when the nodes that are not pinned to a location (see `required_location`) can be placed
+/// in few enough ways; it is then exact. It starts from the DP placement, improved by moving one
+/// node at a time while that lowers the cost, which is what larger programs get.
+pub struct ExactScheduler;
+
+impl Scheduler for ExactScheduler {
+    fn schedule(
+        &mut self,
+        prog: &Program,
+        match_map: &mut HashMap<NodeId, ArgMatch>,
+        config: &FileNetwork,
+        filecache: &mut FileCache,
+        pwd: &Path,
+    ) -> Result<HashMap<NodeId, Location>> {
+        query_input_sizes(prog, match_map, filecache)?;
+        let (edge_weights, input_sizes) = calculate_edge_weights(prog, match_map, filecache)?;
+        let mut locations = config.get_location_list();
+        locations.sort_by_key(|location| format!("{:?}", location));
+        let nodes = prog.execution_order();
+        let index: HashMap<NodeId, usize> =
+            nodes.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+
+        let mut placement = Placement::default();
+        for id in nodes.iter() {
+            let mut costs: Vec<f64> = Vec::new();
+            for location in locations.iter() {
+                costs.push(node_cost(
+                    *id,
+                    location,
+                    prog,
+                    match_map,
+                    config,
+                    filecache,
+                    pwd,
+                    &input_sizes,
+                )?);
+            }
+            let candidates: Vec<usize> = match required_location(prog, *id, match_map, config, pwd)?
+            {
+                Some(required) => match locations.iter().position(|loc| *loc == required) {
+                    Some(loc) => {
+                        // a command tied to several machines runs on the client anyway
+                        if costs[loc] == f64::INFINITY {
+                            costs[loc] = 0.0;
+                        }
+                        vec![loc]
+                    }
+                    None => bail!("{:?} has to run at unknown location {:?}", id, required),
+                },
+                None => (0..locations.len())
+                    .filter(|loc| costs[*loc] < f64::INFINITY)
+                    .collect(),
+            };
+            if candidates.is_empty() {
+                bail!("No location can run {:?}", id);
+            }
+            placement.costs.push(costs);
+            placement.candidates.push(candidates);
+        }
+        for id in nodes.iter() {
+            let mut preceeding = prog.get_dependent_nodes(*id);
+            preceeding.sort();
+            preceeding.dedup();
+            for prev in preceeding.into_iter() {
+                let edge_size = match edge_weights.get(&Link::new(prev, *id)) {
+                    Some(size) => *size,
+                    None => bail!("No edge weight between {:?} and {:?}", prev, id),
+                };
+                let mut costs: Vec<f64> = Vec::new();
+                for from in locations.iter() {
+                    for to in locations.iter() {
+                        costs.push(transfer_cost(edge_size, from, to, config));
+                    }
+                }
+                placement.edges.push((index[&prev], index[id], costs));
+            }
+        }
+
+        // start from the DP placement, where it has one
+        let mut start = placement.cheapest_nodes();
+        let mut dp_scheduler = DPScheduler {};
+        if let Ok(dp_assignment) = dp_scheduler.schedule(prog, match_map, config, filecache, pwd) {
+            let from_dp: Vec<usize> = nodes
+                .iter()
+                .enumerate()
+                .map(|(i, id)| {
+                    dp_assignment
+                        .get(id)
+                        .and_then(|location| locations.iter().position(|loc| loc == location))
+                        .filter(|loc| placement.candidates[i].contains(loc))
+                        .unwrap_or(start[i])
+                })
+                .collect();
+            if placement.cost(&from_dp) <= placement.cost(&start) {
+                start = from_dp;
+            }
+        }
+        placement.improve(&mut start);
+        let assignment = match placement.search_space() <= EXACT_SEARCH_LIMIT {
+            true => placement.search(start),
+            false => {
+                tracing::info!(
+                    "{} ways to place the program, too many to search; keeping the DP placement",
+                    placement.search_space()
+                );
+                start
+            }
+        };
+        if placement.cost(&assignment) == f64::INFINITY {
+            bail!("No placement of the program can run it");
+        }
+        Ok(nodes
+            .iter()
+            .zip(assignment.iter())
+            .map(|(id, loc)| (*id, locations[*loc].clone()))
+            .collect())
+    }
+}
+
+/// Placing nodes at locations, both given by index, to minimize the cost.
+#[derive(Debug, Default)]
+struct Placement {
+    /// Cost of each node at each location.
+    costs: Vec<Vec<f64>>,
+    /// Locations each node may be placed at.
+    candidates: Vec<Vec<usize>>,
+    /// Edges between two nodes, with the cost of each pair of locations of its ends, indexed
+    /// by `from * locations + to`.
+    edges: Vec<(usize, usize, Vec<f64>)>,
+}
+
+impl Placement {
+    fn num_locations(&self) -> usize {
+        self.costs.first().map(|costs| costs.len()).unwrap_or(0)
+    }
+
+    fn edge_cost(&self, edge: usize, from: usize, to: usize) -> f64 {
+        self.edges[edge].2[from * self.num_locations() + to]
+    }
+
+    fn cost(&self, assignment: &[usize]) -> f64 {
+        let nodes: f64 = assignment
+            .iter()
+            .enumerate()
+            .map(|(node, loc)| self.costs[node][*loc])
+            .sum();
+        let edges: f64 = self
+            .edges
+            .iter()
+            .enumerate()
+            .map(|(edge, (from, to, _))| self.edge_cost(edge, assignment[*from], assignment[*to]))
+            .sum();
+        nodes + edges
+    }
+
+    /// Number of ways to place the nodes.
+    fn search_space(&self) -> f64 {
+        self.candidates
+            .iter()
+            .map(|candidates| candidates.len() as f64)
+            .product()
+    }
+
+    /// Each node at its cheapest location, ignoring the edges.
+    fn cheapest_nodes(&self) -> Vec<usize> {
+        self.candidates
+            .iter()
+            .enumerate()
+            .map(|(node, candidates)| {
+                let mut best = candidates[0];
+                for loc in candidates.iter() {
+                    if self.costs[node][*loc] < self.costs[node][best] {
+                        best = *loc;
+                    }
+                }
+                best
+            })
+            .collect()
+    }
+
+    /// Cost of the node at the location and of its edges to the nodes `placed` has a location
+    /// for.
+    fn local_cost(&self, node: usize, loc: usize, placed: &[Option<usize>]) -> f64 {
+        let mut cost = self.costs[node][loc];
+        for (edge, (from, to, _)) in self.edges.iter().enumerate() {
+            if *from == node {
+                if let Some(other) = placed[*to] {
+                    cost += self.edge_cost(edge, loc, other);
+                }
+            } else if *to == node {
+                if let Some(other) = placed[*from] {
+                    cost += self.edge_cost(edge, other, loc);
+                }
+            }
+        }
+        cost
+    }
+
+    /// Moves one node at a time to the location where it costs least, given where the others
+    /// are, until no move lowers the cost.
+    fn improve(&self, assignment: &mut [usize]) {
+        let mut placed: Vec<Option<usize>> = assignment.iter().map(|loc| Some(*loc)).collect();
+        for _ in 0..MAX_IMPROVEMENT_ROUNDS {
+            let mut moved = false;
+            for node in 0..assignment.len() {
+                placed[node] = None;
+                let mut best = assignment[node];
+                let mut best_cost = self.local_cost(node, best, &placed);
+                for loc in self.candidates[node].iter() {
+                    let cost = self.local_cost(node, *loc, &placed);
+                    if cost < best_cost {
+                        best = *loc;
+                        best_cost = cost;
+                    }
+                }
+                moved |= best != assignment[node];
+                assignment[node] = best;
+                placed[node] = Some(best);
+            }
+            if !moved {
+                break;
+            }
+        }
+    }
+
+    /// Branch and bound over every placement, in node order; returns the cheapest, or `best`
+    /// if none is cheaper.
+    fn search(&self, best: Vec<usize>) -> Vec<usize> {
+        let mut state = SearchState {
+            best_cost: self.cost(&best),
+            best,
+            placed: vec![None; self.costs.len()],
+        };
+        self.branch(0, 0.0, &mut state);
+        state.best
+    }
+
+    fn branch(&self, node: usize, cost: f64, state: &mut SearchState) {
+        if node == self.costs.len() {
+            if cost < state.best_cost {
+                state.best_cost = cost;
+                state.best = state.placed.iter().map(|loc| loc.unwrap()).collect();
+            }
+            return;
+        }
+        // each node left costs at least its cheapest location with the edges to placed nodes;
+        // edges between nodes left are not counted, so this never overestimates
+        let bound: f64 = (node..self.costs.len())
+            .map(|left| {
+                self.candidates[left]
+                    .iter()
+                    .map(|loc| self.local_cost(left, *loc, &state.placed))
+                    .fold(f64::INFINITY, f64::min)
+            })
+            .sum();
+        if cost + bound >= state.best_cost {
+            return;
+        }
+        let mut options: Vec<(f64, usize)> = self.candidates[node]
+            .iter()
+            .map(|loc| (self.local_cost(node, *loc, &state.placed), *loc))
+            .collect();
+        // cheap locations first, to find good placements early
+        options.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        for (local_cost, loc) in options.into_iter() {
+            if cost + local_cost >= state.best_cost {
+                break;
+            }
+            state.placed[node] = Some(loc);
+            self.branch(node + 1, cost + local_cost, state);
+            state.placed[node] = None;
+        }
+    }
+}
+
+struct SearchState {
+    best: Vec<usize>,
+    best_cost: f64,
+    /// Location of each node placed so far.
+    placed: Vec<Option<usize>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheapest placement by trying every one.
+    fn brute_force(placement: &Placement) -> f64 {
+        let mut best = f64::INFINITY;
+        let mut assignment: Vec<usize> = placement.candidates.iter().map(|c| c[0]).collect();
+        let mut positions = vec![0; assignment.len()];
+        loop {
+            best = best.min(placement.cost(&assignment));
+            let mut node = 0;
+            while node < positions.len() {
+                positions[node] += 1;
+                if positions[node] < placement.candidates[node].len() {
+                    assignment[node] = placement.candidates[node][positions[node]];
+                    break;
+                }
+                positions[node] = 0;
+                assignment[node] = placement.candidates[node][0];
+                node += 1;
+            }
+            if node == positions.len() {
+                return best;
+            }
+        }
+    }
+
+    #[test]
+    fn test_search_is_exact() {
+        // small pseudo random DAGs over three locations, with the first node pinned
+        let mut seed: u64 = 7;
+        let mut next = |n: u64| {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 33) % n
+        };
+        for _ in 0..50 {
+            let nodes = 2 + next(6) as usize;
+            let mut placement = Placement::default();
+            for node in 0..nodes {
+                placement
+                    .costs
+                    .push((0..3).map(|_| next(100) as f64).collect());
+                placement.candidates.push(match node {
+                    0 => vec![next(3) as usize],
+                    _ => vec![0, 1, 2],
+                });
+            }
+            for to in 1..nodes {
+                for from in 0..to {
+                    if next(3) == 0 || from + 1 == to {
+                        let size = next(50) as f64;
+                        let costs = (0..9)
+                            .map(|pair| if pair % 4 == 0 { 0.0 } else { size })
+                            .collect();
+                        placement.edges.push((from, to, costs));
+                    }
+                }
+            }
+            let mut start = placement.cheapest_nodes();
+            placement.improve(&mut start);
+            assert!(placement.cost(&start) <= placement.cost(&placement.cheapest_nodes()));
+            let best = placement.search(start);
+            assert_eq!(placement.cost(&best), brute_force(&placement));
+            assert_eq!(best[0], placement.candidates[0][0]);
+        }
+    }
+
+    #[test]
+    fn test_shared_node_is_counted_once() {
+        // a reads 100 at location 1 and feeds b and c, which have to be at location 0: moving
+        // the data once beats placing a at 1, which the paths through b and c each prefer alone
+        let transfer = |size: f64| vec![0.0, size, size, 0.0];
+        let placement = Placement {
+            costs: vec![vec![120.0, 0.0], vec![0.0, 0.0], vec![0.0, 0.0]],
+            candidates: vec![vec![0, 1], vec![0], vec![0]],
+            edges: vec![(0, 1, transfer(100.0)), (0, 2, transfer(100.0))],
+        };
+        let best = placement.search(vec![1, 0, 0]);
+        assert_eq!(best, vec![0, 0, 0]);
+        assert_eq!(placement.cost(&best), 120.0);
+    }
+}
@@ -239,6 +239,16 @@ impl Scheduler for HeuristicScheduler {
                         .collect();
                     let mut dependent_locations: HashSet<Location> =
                         HashSet::from_iter(file_locations);
+                    for fs in argmatch.implicit_dependencies().iter() {
+                        dependent_locations.insert(config.get_location(fs));
+                    }
+                    // the files the commands it runs name must be where it runs
+                    for fs in argmatch.nested_dependencies().iter() {
+                        dependent_locations.insert(config.get_location(fs));
+                    }
+                    if argmatch.must_run_on_client() {
+                        dependent_locations.insert(Location::Client);
+                    }
                     if argmatch.get_needs_current_dir() {
                         let pwd_location =
                             config.get_location(&FileStream::new(pwd, Location::Client));
@@ -0,0 +1,33 @@
+use super::annotations2::argument_matcher::ArgMatch;
+use super::config::filecache::FileCache;
+use super::config::network::FileNetwork;
+use super::{required_location, Scheduler};
+use dash::graph::program::{NodeId, Program};
+use dash::graph::Location;
+use dash::util::Result;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Runs every command on the client, reading remote files over the network, unless it has to
+/// run where its files are. What a program costs without offloading, to compare the other
+/// schedulers against.
+pub struct AllLocalScheduler;
+
+impl Scheduler for AllLocalScheduler {
+    fn schedule(
+        &mut self,
+        prog: &Program,
+        match_map: &mut HashMap<NodeId, ArgMatch>,
+        config: &FileNetwork,
+        _filecache: &mut FileCache,
+        pwd: &Path,
+    ) -> Result<HashMap<NodeId, Location>> {
+        let mut assignments: HashMap<NodeId, Location> = HashMap::new();
+        for id in prog.execution_order() {
+            let location =
+                required_location(prog, id, match_map, config, pwd)?.unwrap_or(Location::Client);
+            assignments.insert(id, location);
+        }
+        Ok(assignments)
+    }
+}
@@ -2,10 +2,13 @@ use super::config::filecache::FileCache;
 use super::config::network::FileNetwork;
 use super::{annotations2, config};
 use annotations2::argument_matcher::ArgMatch;
-use dash::graph::program::{NodeId, Program};
+use annotations2::grammar::ArgType;
+use dash::graph::program::{Elem, NodeId, Program};
+use dash::graph::stream::DashStream;
 use dash::graph::Location;
 use dash::util::Result;
-use std::collections::HashMap;
+use failure::bail;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 pub trait Scheduler {
     fn schedule(
@@ -18,5 +21,116 @@ pub trait Scheduler {
     ) -> Result<HashMap<NodeId, Location>>;
 }
 
+/// Scheduler used when neither `--scheduler` nor the config file names one.
+pub const DEFAULT_SCHEDULER: &str = "heuristic";
+
+/// Names `--scheduler` and the `scheduler` key of the config file accept, with what each does.
+pub const SCHEDULERS: &[(&str, &str)] = &[
+    (
+        "heuristic",
+        "cuts each path where the data is smallest, by the annotations' reduces_input",
+    ),
+    (
+        "dp",
+        "minimizes transfer and CPU time over the link speeds, file sizes and cost estimates",
+    ),
+    (
+        "exact",
+        "places the whole program at once at the least estimated time, searching exhaustively when it is small",
+    ),
+    (
+        "all-local",
+        "runs every command on the client that is not tied to another machine",
+    ),
+    (
+        "max-offload",
+        "runs every command on a server that is not tied to the client",
+    ),
+];
+
+/// Builds the scheduler with the given name.
+pub fn new_scheduler(name: &str) -> Result<Box<dyn Scheduler>> {
+    Ok(match name {
+        "heuristic" => Box::new(heuristic::HeuristicScheduler {}),
+        "dp" => Box::new(dp::DPScheduler {}),
+        "exact" => Box::new(exact::ExactScheduler {}),
+        "all-local" => Box::new(local::AllLocalScheduler {}),
+        "max-offload" => Box::new(offload::MaxOffloadScheduler {}),
+        _ => {
+            let names: Vec<&str> = SCHEDULERS.iter().map(|(name, _)| *name).collect();
+            bail!(
+                "unknown scheduler {:?}, expected one of {}",
+                name,
+                names.join(", ")
+            )
+        }
+    })
+}
+
+/// Where a node has to run, whatever the cost: reads and writes where their file is (writes to
+/// the terminal on the client), and commands where the files they cannot reach from another
+/// machine are, as the DP scheduler constrains them. A command tied to several machines runs on
+/// the client. Returns None for commands that can run anywhere.
+pub fn required_location(
+    prog: &Program,
+    id: NodeId,
+    match_map: &HashMap<NodeId, ArgMatch>,
+    config: &FileNetwork,
+    pwd: &Path,
+) -> Result<Option<Location>> {
+    let node = match prog.get_node(id) {
+        Some(node) => node,
+        None => bail!("No node with id {:?}", id),
+    };
+    let argmatch = match node.get_elem() {
+        Elem::Read(readnode) => return Ok(Some(config.get_location(readnode.get_input_ref()))),
+        Elem::Write(writenode) => {
+            return match writenode.get_output_ref() {
+                DashStream::File(fs) => Ok(Some(config.get_location(fs))),
+                DashStream::Stdout | DashStream::Stderr => Ok(Some(Location::Client)),
+                _ => bail!(
+                    "During scheduling stage, writenode cannot have TCP, Pipe or Fifo as output"
+                ),
+            }
+        }
+        Elem::Cmd(_) => match match_map.get(&id) {
+            Some(argmatch) => argmatch,
+            None => bail!("No annotation match for node {:?}", id),
+        },
+    };
+    let mut locations: HashSet<Location> = argmatch
+        .file_dependencies()
+        .iter()
+        .filter(|(argtype, _)| *argtype == ArgType::OutputPrefix)
+        .map(|(_, fs)| config.get_location(fs))
+        .collect();
+    for fs in argmatch
+        .implicit_dependencies()
+        .iter()
+        .chain(argmatch.nested_dependencies().iter())
+    {
+        locations.insert(config.get_location(fs));
+    }
+    if argmatch.must_run_on_client() {
+        locations.insert(Location::Client);
+    }
+    if argmatch.get_needs_current_dir() {
+        locations.insert(config.get_path_location(pwd.to_path_buf()));
+    }
+    Ok(match locations.len() {
+        0 => None,
+        1 => locations.into_iter().next(),
+        _ => Some(Location::Client),
+    })
+}
+
+/// Minimizes the estimated time to run the program.
 pub mod dp;
+/// Places the whole program at the least estimated time.
+pub mod exact;
+/// Cuts each path at its smallest edge.
 pub mod heuristic;
+/// Runs everything it can on the client, as a baseline.
+pub mod local;
+/// Runs everything it can on a server, as a baseline.
+pub mod offload;
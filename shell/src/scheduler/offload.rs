@@ -0,0 +1,61 @@
+use super::annotations2::argument_matcher::ArgMatch;
+use super::annotations2::grammar::ArgType;
+use super::config::filecache::FileCache;
+use super::config::network::FileNetwork;
+use super::{required_location, Scheduler};
+use dash::graph::program::{NodeId, Program};
+use dash::graph::Location;
+use dash::util::Result;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Runs every command on a server unless it has to run on the client, whatever the data it
+/// moves. A command runs where its first remote input file is, or else where the first command
+/// it reads from runs, or else on the first server by address.
+pub struct MaxOffloadScheduler;
+
+impl Scheduler for MaxOffloadScheduler {
+    fn schedule(
+        &mut self,
+        prog: &Program,
+        match_map: &mut HashMap<NodeId, ArgMatch>,
+        config: &FileNetwork,
+        _filecache: &mut FileCache,
+        pwd: &Path,
+    ) -> Result<HashMap<NodeId, Location>> {
+        let mut servers: Vec<Location> = config
+            .get_location_list()
+            .into_iter()
+            .filter(|location| *location != Location::Client)
+            .collect();
+        servers.sort_by_key(|location| format!("{:?}", location));
+        let mut assignments: HashMap<NodeId, Location> = HashMap::new();
+        // execution order puts the nodes a command reads from before it
+        for id in prog.execution_order() {
+            if let Some(location) = required_location(prog, id, match_map, config, pwd)? {
+                assignments.insert(id, location);
+                continue;
+            }
+            let input_location = match_map.get(&id).and_then(|argmatch| {
+                argmatch
+                    .file_dependencies()
+                    .iter()
+                    .filter(|(argtype, _)| *argtype == ArgType::InputFile)
+                    .map(|(_, fs)| config.get_location(fs))
+                    .find(|location| *location != Location::Client)
+            });
+            let upstream_location = prog
+                .get_dependent_nodes(id)
+                .iter()
+                .filter_map(|prev| assignments.get(prev))
+                .find(|location| **location != Location::Client)
+                .cloned();
+            let location = input_location
+                .or(upstream_location)
+                .or_else(|| servers.first().cloned())
+                .unwrap_or(Location::Client);
+            assignments.insert(id, location);
+        }
+        Ok(assignments)
+    }
+}
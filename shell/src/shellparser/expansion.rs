@@ -0,0 +1,1062 @@
+extern crate glob;
+use dash::util::Result;
+use failure::bail;
+use glob::Pattern;
+use std::collections::HashMap;
+use std::env;
+use std::fs::read_to_string;
+use std::process;
+
+/// Characters used to split the result of unquoted expansions into separate fields.
+const IFS: &[char] = &[' ', '\t', '\n'];
+
+/// Shell state that words are expanded against.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShellEnv {
+    /// Shell variables, set by exports, assignments and `${VAR:=default}`.
+    vars: HashMap<String, String>,
+    /// Name of the running script (`$0`).
+    script_name: String,
+    /// Positional parameters (`$1`, `$2`, ...).
+    positional: Vec<String>,
+    /// Exit status of the last pipeline (`$?`).
+    last_status: i32,
+}
+
+impl Default for ShellEnv {
+    fn default() -> Self {
+        ShellEnv {
+            vars: HashMap::default(),
+            script_name: "posh".to_string(),
+            positional: vec![],
+            last_status: 0,
+        }
+    }
+}
+
+impl ShellEnv {
+    pub fn set_var(&mut self, name: &str, value: &str) {
+        self.vars.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn unset_var(&mut self, name: &str) {
+        self.vars.remove(name);
+    }
+
+    /// Shell variables take precedence over the process environment.
+    pub fn get_var(&self, name: &str) -> Option<String> {
+        match self.vars.get(name) {
+            Some(val) => Some(val.clone()),
+            None => env::var(name).ok(),
+        }
+    }
+
+    pub fn get_vars(&self) -> &HashMap<String, String> {
+        &self.vars
+    }
+
+    pub fn set_positional(&mut self, script_name: &str, args: Vec<String>) {
+        self.script_name = script_name.to_string();
+        self.positional = args;
+    }
+
+    /// Replaces the positional parameters but keeps `$0`, e.g. for function calls.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.positional = args;
+    }
+
+    /// Drops the first `n` positional parameters.
+    pub fn shift(&mut self, n: usize) -> Result<()> {
+        if n > self.positional.len() {
+            bail!("shift: shift count out of range: {}", n);
+        }
+        self.positional.drain(..n);
+        Ok(())
+    }
+
+    pub fn get_script_name(&self) -> String {
+        self.script_name.clone()
+    }
+
+    pub fn get_positional(&self) -> Vec<String> {
+        self.positional.clone()
+    }
+
+    pub fn set_last_status(&mut self, status: i32) {
+        self.last_status = status;
+    }
+
+    pub fn get_last_status(&self) -> i32 {
+        self.last_status
+    }
+
+    /// Looks up a parameter, including the special parameters `$?`, `$$`, `$#` and `$0..$9`.
+    /// Returns None if the parameter is unset.
+    fn lookup(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.last_status.to_string()),
+            "$" => Some(process::id().to_string()),
+            "#" => Some(self.positional.len().to_string()),
+            "@" | "*" => Some(self.positional.join(" ")),
+            "0" => Some(self.script_name.clone()),
+            _ => {
+                if name.chars().all(|c| c.is_ascii_digit()) {
+                    match name.parse::<usize>() {
+                        Ok(0) => Some(self.script_name.clone()),
+                        Ok(idx) => self.positional.get(idx - 1).cloned(),
+                        Err(_) => None,
+                    }
+                } else {
+                    self.get_var(name)
+                }
+            }
+        }
+    }
+}
+
+/// Token produced by splitting and expanding a command line.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ShellToken {
+    /// A word; after expansion, all quoting has been removed.
+    Word(String),
+    /// An unquoted shell operator, such as `|`, `>` or `<(`.
+    Op(String),
+}
+
+/// Splits the command line into words and operators, and runs brace, tilde and parameter
+/// expansion on each word, in that order.
+/// Single quoted text is kept literal; double quoted text is expanded but not split into
+/// fields.
+pub fn expand_line(line: &str, env: &mut ShellEnv) -> Result<Vec<ShellToken>> {
+    let mut ret: Vec<ShellToken> = Vec::new();
+    for token in lex(line)? {
+        match token {
+            ShellToken::Op(op) => ret.push(ShellToken::Op(op)),
+            ShellToken::Word(raw) => {
+                for braced in expand_braces(&raw) {
+                    for field in expand_word(&braced, env, true)? {
+                        ret.push(ShellToken::Word(field));
+                    }
+                }
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// Expands a line that is only allowed to contain words (e.g., the list in a for loop).
+pub fn expand_words(line: &str, env: &mut ShellEnv) -> Result<Vec<String>> {
+    let mut ret: Vec<String> = Vec::new();
+    for token in expand_line(line, env)? {
+        match token {
+            ShellToken::Word(word) => ret.push(word),
+            ShellToken::Op(op) => bail!("Unexpected operator {:?} in word list {:?}", op, line),
+        }
+    }
+    Ok(ret)
+}
+
+/// Expands a single word without splitting it into fields, e.g. for the value of an
+/// assignment.
+pub fn expand_string(word: &str, env: &mut ShellEnv) -> Result<String> {
+    let fields = expand_word(word, env, false)?;
+    Ok(fields.join(" "))
+}
+
+/// Expands a word list like expand_words, and returns each word with a glob pattern that
+/// matches its quoted parts literally, e.g. `"*".txt` becomes `[*].txt`.
+pub fn expand_glob_words(line: &str, env: &mut ShellEnv) -> Result<Vec<(String, String)>> {
+    let mut ret: Vec<(String, String)> = Vec::new();
+    for token in lex(line)? {
+        match token {
+            ShellToken::Word(raw) => {
+                for braced in expand_braces(&raw) {
+                    ret.append(&mut expand_word_patterns(&braced, env, true)?);
+                }
+            }
+            ShellToken::Op(op) => bail!("Unexpected operator {:?} in word list {:?}", op, line),
+        }
+    }
+    Ok(ret)
+}
+
+/// Expands a single word like expand_string, and returns it with its glob pattern, e.g. for the
+/// patterns of a case statement.
+pub fn expand_glob_string(word: &str, env: &mut ShellEnv) -> Result<(String, String)> {
+    let fields = expand_word_patterns(word, env, false)?;
+    let (values, patterns): (Vec<String>, Vec<String>) = fields.into_iter().unzip();
+    Ok((values.join(" "), patterns.join(" ")))
+}
+
+/// Index of the closing quote matching the quote at `start`.
+fn find_quote_end(chars: &[char], start: usize) -> Result<usize> {
+    let quote = chars[start];
+    let mut i = start + 1;
+    while i < chars.len() {
+        if chars[i] == '\\' && quote == '"' {
+            i += 2;
+            continue;
+        }
+        if chars[i] == quote {
+            return Ok(i);
+        }
+        i += 1;
+    }
+    bail!("Mismatched quotes error: unclosed {}", quote);
+}
+
+/// Index of the `}` that closes the `{` (of a `${`) at `start`.
+fn find_param_end(chars: &[char], start: usize) -> Result<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => {
+                i += 1;
+            }
+            '\'' | '"' => {
+                i = find_quote_end(chars, i)?;
+            }
+            '{' => {
+                depth += 1;
+            }
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    bail!("Bad substitution: unclosed ${{");
+}
+
+/// Index of the `)` that closes the `(` (of a `$(`) at `start`.
+fn find_subst_end(chars: &[char], start: usize) -> Result<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => {
+                i += 1;
+            }
+            '\'' | '"' | '`' => {
+                i = find_quote_end(chars, i)?;
+            }
+            '(' => {
+                depth += 1;
+            }
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    bail!("Bad substitution: unclosed $(");
+}
+
+/// Splits a command line into raw words (with quotes left in place) and unquoted operators.
+/// Comments are dropped.
+pub fn lex(line: &str) -> Result<Vec<ShellToken>> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens: Vec<ShellToken> = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\'' | '"' => {
+                let end = find_quote_end(&chars, i)?;
+                let quoted: String = chars[i..=end].iter().collect();
+                word.push_str(&quoted);
+                in_word = true;
+                i = end + 1;
+                continue;
+            }
+            '\\' => {
+                word.push(c);
+                if i + 1 < chars.len() {
+                    word.push(chars[i + 1]);
+                }
+                in_word = true;
+                i += 2;
+                continue;
+            }
+            '$' => match chars.get(i + 1) {
+                Some('(') => {
+                    // kept whole, so the pipeline can be handed to the system shell
+                    let end = find_subst_end(&chars, i + 1)?;
+                    word.extend(chars[i..=end].iter());
+                    in_word = true;
+                    i = end + 1;
+                    continue;
+                }
+                Some('{') => {
+                    let end = find_param_end(&chars, i + 1)?;
+                    word.extend(chars[i..=end].iter());
+                    in_word = true;
+                    i = end + 1;
+                    continue;
+                }
+                _ => {
+                    word.push(c);
+                    in_word = true;
+                }
+            },
+            '`' => {
+                let end = find_quote_end(&chars, i)?;
+                word.extend(chars[i..=end].iter());
+                in_word = true;
+                i = end + 1;
+                continue;
+            }
+            ' ' | '\t' | '\n' => {
+                if in_word {
+                    tokens.push(ShellToken::Word(word.clone()));
+                    word.clear();
+                    in_word = false;
+                }
+            }
+            '#' if !in_word => {
+                // rest of the line is a comment
+                break;
+            }
+            '|' | '&' | ';' | '<' | '>' | '(' | ')' => {
+                // `2>` is only a redirection when the 2 is a word by itself
+                if c == '>' && in_word && word == "2" {
+                    word.clear();
+                    in_word = false;
+                    if chars.get(i + 1) == Some(&'>') {
+                        tokens.push(ShellToken::Op("2>>".to_string()));
+                        i += 2;
+                    } else {
+                        tokens.push(ShellToken::Op("2>".to_string()));
+                        i += 1;
+                    }
+                    continue;
+                }
+                if in_word {
+                    tokens.push(ShellToken::Word(word.clone()));
+                    word.clear();
+                    in_word = false;
+                }
+                let next = chars.get(i + 1).cloned();
+                let op = match (c, next) {
+                    ('|', Some('|')) => "||",
+                    ('&', Some('&')) => "&&",
+                    (';', Some(';')) => ";;",
+                    ('>', Some('>')) => ">>",
+                    ('<', Some('(')) => "<(",
+                    ('<', Some('<')) => "<<",
+                    _ => "",
+                };
+                if !op.is_empty() {
+                    tokens.push(ShellToken::Op(op.to_string()));
+                    i += 2;
+                } else {
+                    tokens.push(ShellToken::Op(c.to_string()));
+                    i += 1;
+                }
+                continue;
+            }
+            _ => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+        i += 1;
+    }
+    if in_word {
+        tokens.push(ShellToken::Word(word));
+    }
+    Ok(tokens)
+}
+
+/// Runs brace expansion (`a{b,c}d`, `{1..3}`) on a raw word.
+/// Braces inside quotes or belonging to `${...}` are left alone.
+fn expand_braces(raw: &str) -> Vec<String> {
+    let chars: Vec<char> = raw.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => {
+                i += 2;
+                continue;
+            }
+            '\'' | '"' => match find_quote_end(&chars, i) {
+                Ok(end) => {
+                    i = end + 1;
+                    continue;
+                }
+                Err(_) => {
+                    return vec![raw.to_string()];
+                }
+            },
+            '$' if chars.get(i + 1) == Some(&'{') => match find_param_end(&chars, i + 1) {
+                Ok(end) => {
+                    i = end + 1;
+                    continue;
+                }
+                Err(_) => {
+                    return vec![raw.to_string()];
+                }
+            },
+            '{' => {
+                if let Some((end, alternatives)) = brace_alternatives(&chars, i) {
+                    let prefix: String = chars[..i].iter().collect();
+                    let suffix: String = chars[end + 1..].iter().collect();
+                    let mut ret: Vec<String> = Vec::new();
+                    for alternative in alternatives.iter() {
+                        let word = format!("{}{}{}", prefix, alternative, suffix);
+                        ret.append(&mut expand_braces(&word));
+                    }
+                    return ret;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    vec![raw.to_string()]
+}
+
+/// If the `{` at `start` begins a valid brace expression, returns the index of the closing
+/// brace and the list of alternatives.
+fn brace_alternatives(chars: &[char], start: usize) -> Option<(usize, Vec<String>)> {
+    let mut depth = 0;
+    let mut commas: Vec<usize> = Vec::new();
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => {
+                i += 1;
+            }
+            '\'' | '"' => {
+                i = find_quote_end(chars, i).ok()?;
+            }
+            '{' => {
+                depth += 1;
+            }
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            ',' if depth == 1 => {
+                commas.push(i);
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    if i >= chars.len() {
+        return None;
+    }
+    let end = i;
+    if !commas.is_empty() {
+        let mut alternatives: Vec<String> = Vec::new();
+        let mut last = start + 1;
+        for comma in commas.iter() {
+            alternatives.push(chars[last..*comma].iter().collect());
+            last = comma + 1;
+        }
+        alternatives.push(chars[last..end].iter().collect());
+        return Some((end, alternatives));
+    }
+
+    // sequence expressions: {1..5} or {a..e}
+    let inner: String = chars[start + 1..end].iter().collect();
+    let bounds: Vec<&str> = inner.split("..").collect();
+    if bounds.len() != 2 {
+        return None;
+    }
+    if let (Ok(first), Ok(last)) = (bounds[0].parse::<i64>(), bounds[1].parse::<i64>()) {
+        let seq: Vec<String> = match first <= last {
+            true => (first..=last).map(|x| x.to_string()).collect(),
+            false => (last..=first).rev().map(|x| x.to_string()).collect(),
+        };
+        return Some((end, seq));
+    }
+    let first: Vec<char> = bounds[0].chars().collect();
+    let last: Vec<char> = bounds[1].chars().collect();
+    if first.len() == 1 && last.len() == 1 && first[0].is_ascii() && last[0].is_ascii() {
+        let (a, b) = (first[0] as u8, last[0] as u8);
+        let seq: Vec<String> = match a <= b {
+            true => (a..=b).map(|x| (x as char).to_string()).collect(),
+            false => (b..=a).rev().map(|x| (x as char).to_string()).collect(),
+        };
+        return Some((end, seq));
+    }
+    None
+}
+
+/// Accumulates the fields produced while expanding a single word.
+/// Each field is also kept as a glob pattern, in which quoted text matches only itself.
+#[derive(Default)]
+struct Fields {
+    fields: Vec<(String, String)>,
+    current: String,
+    pattern: String,
+    /// If the current field exists, even if it is empty (e.g., after `""`).
+    started: bool,
+    /// If unquoted expansions are split into fields on whitespace.
+    split: bool,
+}
+
+impl Fields {
+    fn new(split: bool) -> Self {
+        Fields {
+            split,
+            ..Default::default()
+        }
+    }
+
+    /// Adds unquoted text.
+    fn push_literal(&mut self, s: &str) {
+        self.current.push_str(s);
+        self.pattern.push_str(s);
+        self.started = true;
+    }
+
+    /// Adds quoted text, or the result of a quoted expansion.
+    fn push_quoted(&mut self, s: &str) {
+        self.current.push_str(s);
+        self.pattern.push_str(&Pattern::escape(s));
+        self.started = true;
+    }
+
+    /// Adds the result of an unquoted expansion, splitting it into fields on whitespace.
+    fn push_split(&mut self, value: &str) {
+        if !self.split {
+            self.push_literal(value);
+            return;
+        }
+        for c in value.chars() {
+            if IFS.contains(&c) {
+                self.end_field();
+            } else {
+                self.push_literal(&c.to_string());
+            }
+        }
+    }
+
+    fn end_field(&mut self) {
+        if self.started {
+            self.fields
+                .push((self.current.clone(), self.pattern.clone()));
+            self.current.clear();
+            self.pattern.clear();
+            self.started = false;
+        }
+    }
+
+    /// Returns each field with its glob pattern.
+    fn finish(mut self) -> Vec<(String, String)> {
+        self.end_field();
+        self.fields
+    }
+}
+
+/// Returns the home directory for `~` or `~user`.
+fn home_directory(user: &str) -> Option<String> {
+    if user.is_empty() {
+        return env::var("HOME").ok();
+    }
+    let passwd = read_to_string("/etc/passwd").ok()?;
+    for line in passwd.lines() {
+        let entries: Vec<&str> = line.split(':').collect();
+        if entries.len() > 5 && entries[0] == user {
+            return Some(entries[5].to_string());
+        }
+    }
+    None
+}
+
+/// Length of a leading `~`, `~user`, `~+` or `~-` prefix that can be expanded, and its value.
+fn tilde_prefix(chars: &[char], start: usize, env: &ShellEnv) -> Option<(usize, String)> {
+    if chars.get(start) != Some(&'~') {
+        return None;
+    }
+    let mut end = start + 1;
+    while end < chars.len() && chars[end] != '/' && chars[end] != ':' {
+        match chars[end] {
+            '\'' | '"' | '\\' | '$' => {
+                return None;
+            }
+            _ => {}
+        }
+        end += 1;
+    }
+    let user: String = chars[start + 1..end].iter().collect();
+    let value = match user.as_str() {
+        "+" => env.get_var("PWD"),
+        "-" => env.get_var("OLDPWD"),
+        _ => home_directory(&user),
+    }?;
+    Some((end - start, value))
+}
+
+/// Expands a single raw word into zero or more fields.
+/// If `split` is false, unquoted expansions are not split on whitespace.
+fn expand_word(raw: &str, env: &mut ShellEnv, split: bool) -> Result<Vec<String>> {
+    let fields = expand_word_patterns(raw, env, split)?;
+    Ok(fields.into_iter().map(|(field, _)| field).collect())
+}
+
+/// Same as expand_word, but also returns the glob pattern of each field.
+fn expand_word_patterns(
+    raw: &str,
+    env: &mut ShellEnv,
+    split: bool,
+) -> Result<Vec<(String, String)>> {
+    let chars: Vec<char> = raw.chars().collect();
+    let mut fields = Fields::new(split);
+    let mut i = 0;
+
+    // tilde expansion at the start of the word, or after the `=` of an assignment
+    let assignment_value = match raw.find('=') {
+        Some(idx) if is_name(&raw[..idx]) => Some(raw[..idx].chars().count() + 1),
+        _ => None,
+    };
+    for start in [Some(0), assignment_value].iter().filter_map(|x| *x) {
+        if let Some((len, value)) = tilde_prefix(&chars, start, env) {
+            let before: String = chars[i..start].iter().collect();
+            fields.push_literal(&before);
+            fields.push_quoted(&value);
+            i = start + len;
+            break;
+        }
+    }
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\'' => {
+                let end = find_quote_end(&chars, i)?;
+                let literal: String = chars[i + 1..end].iter().collect();
+                fields.push_quoted(&literal);
+                i = end + 1;
+            }
+            '"' => {
+                let end = find_quote_end(&chars, i)?;
+                let quoted: String = chars[i + 1..end].iter().collect();
+                // "$@" without positional parameters is no field at all, not an empty one
+                let no_args =
+                    (quoted == "$@" || quoted == "${@}") && env.get_positional().is_empty();
+                if !no_args {
+                    fields.push_quoted("");
+                    expand_double_quoted(&chars[i + 1..end], env, &mut fields)?;
+                }
+                i = end + 1;
+            }
+            '\\' => {
+                if let Some(next) = chars.get(i + 1) {
+                    fields.push_quoted(&next.to_string());
+                }
+                i += 2;
+            }
+            '$' => {
+                i = expand_dollar(&chars, i, env, &mut fields, false)?;
+            }
+            '`' => {
+                bail!("Command substitution is not supported: {:?}", raw);
+            }
+            _ => {
+                fields.push_literal(&c.to_string());
+                i += 1;
+            }
+        }
+    }
+    Ok(fields.finish())
+}
+
+/// Expands the inside of a double quoted string; only `$` and backslash are special.
+fn expand_double_quoted(chars: &[char], env: &mut ShellEnv, fields: &mut Fields) -> Result<()> {
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => {
+                match chars.get(i + 1) {
+                    Some(next) if ['$', '"', '\\', '`'].contains(next) => {
+                        fields.push_quoted(&next.to_string());
+                    }
+                    Some(next) => {
+                        fields.push_quoted(&format!("\\{}", next));
+                    }
+                    None => {
+                        fields.push_quoted("\\");
+                    }
+                }
+                i += 2;
+            }
+            '$' => {
+                i = expand_dollar(chars, i, env, fields, true)?;
+            }
+            '`' => {
+                bail!("Command substitution is not supported");
+            }
+            c => {
+                fields.push_quoted(&c.to_string());
+                i += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_name(s: &str) -> bool {
+    let mut it = s.chars();
+    match it.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => {
+            return false;
+        }
+    }
+    it.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Expands the parameter starting with the `$` at index `start`.
+/// Returns the index after the expansion.
+fn expand_dollar(
+    chars: &[char],
+    start: usize,
+    env: &mut ShellEnv,
+    fields: &mut Fields,
+    quoted: bool,
+) -> Result<usize> {
+    let push_value = |fields: &mut Fields, value: &str| {
+        if quoted {
+            fields.push_quoted(value);
+        } else {
+            fields.push_split(value);
+        }
+    };
+    // "$@" expands to one field per positional parameter
+    let push_positional = |fields: &mut Fields, positional: Vec<String>| {
+        for (idx, arg) in positional.iter().enumerate() {
+            if idx > 0 {
+                fields.end_field();
+            }
+            push_value(fields, arg);
+        }
+    };
+    let next = match chars.get(start + 1) {
+        Some(c) => *c,
+        None => {
+            fields.push_literal("$");
+            return Ok(start + 1);
+        }
+    };
+    match next {
+        '(' => {
+            let subst: String = chars[start..].iter().collect();
+            bail!("Command substitution is not supported: {:?}", subst);
+        }
+        '{' => {
+            let end = find_param_end(chars, start + 1)?;
+            let inner: String = chars[start + 2..end].iter().collect();
+            if inner == "@" {
+                push_positional(fields, env.get_positional());
+                return Ok(end + 1);
+            }
+            let value = expand_braced_param(&inner, env)?;
+            push_value(fields, &value);
+            Ok(end + 1)
+        }
+        '@' => {
+            push_positional(fields, env.get_positional());
+            Ok(start + 2)
+        }
+        '*' | '#' | '?' | '$' => {
+            let value = env.lookup(&next.to_string()).unwrap_or_default();
+            push_value(fields, &value);
+            Ok(start + 2)
+        }
+        c if c.is_ascii_digit() => {
+            let value = env.lookup(&c.to_string()).unwrap_or_default();
+            push_value(fields, &value);
+            Ok(start + 2)
+        }
+        c if c.is_ascii_alphabetic() || c == '_' => {
+            let mut end = start + 1;
+            while end < chars.len() && (chars[end].is_ascii_alphanumeric() || chars[end] == '_') {
+                end += 1;
+            }
+            let name: String = chars[start + 1..end].iter().collect();
+            let value = env.lookup(&name).unwrap_or_default();
+            push_value(fields, &value);
+            Ok(end)
+        }
+        _ => {
+            fields.push_literal("$");
+            Ok(start + 1)
+        }
+    }
+}
+
+/// Expands the contents of `${...}`.
+fn expand_braced_param(inner: &str, env: &mut ShellEnv) -> Result<String> {
+    // ${#VAR}: length of the value
+    if inner.len() > 1 && inner.starts_with('#') {
+        let name = &inner[1..];
+        if is_name(name) || name.chars().all(|c| c.is_ascii_digit()) || name.len() == 1 {
+            let value = env.lookup(name).unwrap_or_default();
+            return Ok(value.chars().count().to_string());
+        }
+    }
+
+    let name_len = match inner.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => inner
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(inner.len()),
+        Some(c) if c.is_ascii_digit() => inner
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(inner.len()),
+        Some('@') | Some('*') | Some('#') | Some('?') | Some('$') => 1,
+        _ => bail!("Bad substitution: ${{{}}}", inner),
+    };
+    let name = &inner[..name_len];
+    let rest = &inner[name_len..];
+    let value = env.lookup(name);
+    if rest.is_empty() {
+        return Ok(value.unwrap_or_default());
+    }
+
+    let operators = [
+        ":-", ":=", ":+", ":?", "-", "=", "+", "?", "%%", "%", "##", "#",
+    ];
+    let op = match operators.iter().find(|op| rest.starts_with(*op)) {
+        Some(op) => *op,
+        None => bail!("Bad substitution: ${{{}}}", inner),
+    };
+    let word = &rest[op.len()..];
+    let is_null = match &value {
+        Some(v) => op.starts_with(':') && v.is_empty(),
+        None => true,
+    };
+    match op {
+        ":-" | "-" => match is_null {
+            true => expand_string(word, env),
+            false => Ok(value.unwrap_or_default()),
+        },
+        ":=" | "=" => match is_null {
+            true => {
+                if !is_name(name) {
+                    bail!("${{{}}}: cannot assign in this way", inner);
+                }
+                let default = expand_string(word, env)?;
+                env.set_var(name, &default);
+                Ok(default)
+            }
+            false => Ok(value.unwrap_or_default()),
+        },
+        ":+" | "+" => match is_null {
+            true => Ok("".to_string()),
+            false => expand_string(word, env),
+        },
+        ":?" | "?" => match is_null {
+            true => {
+                let message = match word {
+                    "" => "parameter null or not set".to_string(),
+                    _ => expand_string(word, env)?,
+                };
+                bail!("{}: {}", name, message);
+            }
+            false => Ok(value.unwrap_or_default()),
+        },
+        _ => {
+            let value = value.unwrap_or_default();
+            let pattern = expand_string(word, env)?;
+            Ok(remove_pattern(&value, &pattern, op))
+        }
+    }
+}
+
+/// Implements `%`, `%%`, `#` and `##`: removes the shortest or longest suffix or prefix of
+/// the value that matches the glob pattern.
+fn remove_pattern(value: &str, pattern: &str, op: &str) -> String {
+    let pattern = match Pattern::new(pattern) {
+        Ok(p) => p,
+        Err(_) => Pattern::new(&Pattern::escape(pattern)).unwrap(),
+    };
+    let mut boundaries: Vec<usize> = value.char_indices().map(|(idx, _)| idx).collect();
+    boundaries.push(value.len());
+    match op {
+        "%" => {
+            for idx in boundaries.iter().rev() {
+                if pattern.matches(&value[*idx..]) {
+                    return value[..*idx].to_string();
+                }
+            }
+        }
+        "%%" => {
+            for idx in boundaries.iter() {
+                if pattern.matches(&value[*idx..]) {
+                    return value[..*idx].to_string();
+                }
+            }
+        }
+        "#" => {
+            for idx in boundaries.iter() {
+                if pattern.matches(&value[..*idx]) {
+                    return value[*idx..].to_string();
+                }
+            }
+        }
+        "##" => {
+            for idx in boundaries.iter().rev() {
+                if pattern.matches(&value[..*idx]) {
+                    return value[*idx..].to_string();
+                }
+            }
+        }
+        _ => unreachable!(),
+    }
+    value.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str, env: &mut ShellEnv) -> Vec<String> {
+        expand_words(line, env).unwrap()
+    }
+
+    #[test]
+    fn test_lex_operators() {
+        let tokens = lex("cat a.txt | grep 'a|b' > out.txt 2> err.txt").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                ShellToken::Word("cat".to_string()),
+                ShellToken::Word("a.txt".to_string()),
+                ShellToken::Op("|".to_string()),
+                ShellToken::Word("grep".to_string()),
+                ShellToken::Word("'a|b'".to_string()),
+                ShellToken::Op(">".to_string()),
+                ShellToken::Word("out.txt".to_string()),
+                ShellToken::Op("2>".to_string()),
+                ShellToken::Word("err.txt".to_string()),
+            ]
+        );
+        // command substitutions stay in one word, and fail when expanded
+        assert_eq!(
+            lex("echo $(ls \"a b\" | wc -l)x `date`").unwrap(),
+            vec![
+                ShellToken::Word("echo".to_string()),
+                ShellToken::Word("$(ls \"a b\" | wc -l)x".to_string()),
+                ShellToken::Word("`date`".to_string()),
+            ]
+        );
+        assert!(expand_line("echo $(ls)", &mut ShellEnv::default()).is_err());
+        assert!(expand_line("echo \"`date`\"", &mut ShellEnv::default()).is_err());
+        assert!(lex("echo 'unclosed").is_err());
+    }
+
+    #[test]
+    fn test_parameter_expansion() {
+        let mut env = ShellEnv::default();
+        env.set_var("FILE", "/data/logs/access.log.gz");
+        env.set_var("EMPTY", "");
+        assert_eq!(words("${FILE}", &mut env), vec!["/data/logs/access.log.gz"]);
+        assert_eq!(
+            words("${FILE%.gz}", &mut env),
+            vec!["/data/logs/access.log"]
+        );
+        assert_eq!(words("${FILE%%.*}", &mut env), vec!["/data/logs/access"]);
+        assert_eq!(
+            words("${FILE#*/}", &mut env),
+            vec!["data/logs/access.log.gz"]
+        );
+        assert_eq!(words("${FILE##*/}", &mut env), vec!["access.log.gz"]);
+        assert_eq!(words("${#FILE}", &mut env), vec!["24"]);
+        assert_eq!(words("${EMPTY:-default}", &mut env), vec!["default"]);
+        assert_eq!(words("${EMPTY-default}", &mut env), Vec::<String>::new());
+        assert_eq!(
+            words("${UNSET_POSH_VAR:-$FILE}", &mut env),
+            vec!["/data/logs/access.log.gz"]
+        );
+        assert_eq!(words("${NEW_POSH_VAR:=value}", &mut env), vec!["value"]);
+        assert_eq!(env.get_var("NEW_POSH_VAR"), Some("value".to_string()));
+        assert!(expand_words("${UNSET_POSH_VAR:?missing}", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_special_parameters() {
+        let mut env = ShellEnv::default();
+        env.set_positional("script.sh", vec!["a b".to_string(), "c".to_string()]);
+        env.set_last_status(3);
+        assert_eq!(words("$1 $2 $3", &mut env), vec!["a", "b", "c"]);
+        assert_eq!(words("\"$1\"", &mut env), vec!["a b"]);
+        assert_eq!(words("\"$@\"", &mut env), vec!["a b", "c"]);
+        assert_eq!(words("\"${@}\"", &mut env), vec!["a b", "c"]);
+        assert_eq!(words("x\"${@}\"y", &mut env), vec!["xa b", "cy"]);
+        assert_eq!(words("${@}", &mut env), vec!["a", "b", "c"]);
+        assert_eq!(words("${00} ${1}", &mut env), vec!["script.sh", "a", "b"]);
+        env.set_positional("script.sh", vec![]);
+        assert_eq!(words("\"$@\"", &mut env), Vec::<String>::new());
+        assert_eq!(words("a \"${@}\" b", &mut env), vec!["a", "b"]);
+        assert_eq!(words("x\"$@\" \"$@\"\"\"", &mut env), vec!["x", ""]);
+        env.set_positional("script.sh", vec!["a b".to_string(), "c".to_string()]);
+        assert_eq!(words("$# $? $0", &mut env), vec!["2", "3", "script.sh"]);
+        assert_eq!(words("$$", &mut env), vec![process::id().to_string()]);
+    }
+
+    #[test]
+    fn test_glob_patterns() {
+        let mut env = ShellEnv::default();
+        env.set_var("VAR", "*.gz x");
+        assert_eq!(
+            expand_glob_words("\"*\".txt '?' $VAR \"$VAR\"", &mut env).unwrap(),
+            vec![
+                ("*.txt".to_string(), "[*].txt".to_string()),
+                ("?".to_string(), "[?]".to_string()),
+                ("*.gz".to_string(), "*.gz".to_string()),
+                ("x".to_string(), "x".to_string()),
+                ("*.gz x".to_string(), "[*].gz x".to_string()),
+            ]
+        );
+        assert_eq!(
+            expand_glob_string("\\[a]*", &mut env).unwrap(),
+            ("[a]*".to_string(), "[[]a]*".to_string())
+        );
+    }
+
+    #[test]
+    fn test_quoting() {
+        let mut env = ShellEnv::default();
+        env.set_var("VAR", "x y");
+        assert_eq!(words("'$VAR'", &mut env), vec!["$VAR"]);
+        assert_eq!(words("\"$VAR\"", &mut env), vec!["x y"]);
+        assert_eq!(words("$VAR", &mut env), vec!["x", "y"]);
+        assert_eq!(words("\\$VAR", &mut env), vec!["$VAR"]);
+        assert_eq!(words("\"\"", &mut env), vec![""]);
+        assert_eq!(words("'{a,b}'", &mut env), vec!["{a,b}"]);
+    }
+
+    #[test]
+    fn test_brace_and_tilde_expansion() {
+        let mut env = ShellEnv::default();
+        assert_eq!(
+            words("log.{a,b}.txt", &mut env),
+            vec!["log.a.txt", "log.b.txt"]
+        );
+        assert_eq!(words("{1..3}", &mut env), vec!["1", "2", "3"]);
+        assert_eq!(words("{x,y{1,2}}", &mut env), vec!["x", "y1", "y2"]);
+        assert_eq!(words("{single}", &mut env), vec!["{single}"]);
+        let home = env::var("HOME").unwrap_or_default();
+        if !home.is_empty() {
+            assert_eq!(words("~/data", &mut env), vec![format!("{}/data", home)]);
+            assert_eq!(words("'~'/data", &mut env), vec!["~/data"]);
+        }
+    }
+}
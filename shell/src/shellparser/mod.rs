@@ -11,5 +11,7 @@ macro_rules! named_complete {
     )
 }
 
+pub mod expansion;
+pub mod script;
 pub mod shellparser;
 pub mod special_commands;
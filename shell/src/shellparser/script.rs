@@ -0,0 +1,660 @@
+use super::expansion::{lex, ShellToken};
+use dash::util::Result;
+use failure::bail;
+use itertools::join;
+use std::fmt;
+
+/// Words that start or end compound commands; only special at the start of a command.
+const RESERVED: &[&str] = &[
+    "if", "then", "elif", "else", "fi", "for", "while", "until", "do", "done", "case", "esac",
+    "function", "{", "}",
+];
+
+/// Tokens that end a pipeline.
+const SEPARATORS: &[&str] = &[";", "\n", "&", "&&", "||", ";;"];
+
+/// How the commands in an and-or list are chained together.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Connector {
+    /// `&&`: run the next command if the last one succeeded.
+    And,
+    /// `||`: run the next command if the last one failed.
+    Or,
+}
+
+/// One arm of a case statement.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CaseArm {
+    /// Unexpanded glob patterns, e.g. `*.gz`.
+    pub patterns: Vec<String>,
+    pub body: Vec<ScriptNode>,
+}
+
+/// Script level syntax tree.
+/// Leaf pipelines are kept as unexpanded text, so that expansions see the values variables
+/// have when the pipeline runs (e.g., loop variables); the interpreter parses and schedules
+/// them.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ScriptNode {
+    /// A single pipeline, to be run by the Posh interpreter.
+    Pipeline(String),
+    /// `! command`: inverts the exit status.
+    Not(Box<ScriptNode>),
+    /// `a && b || c`: the first command, followed by each connector and command.
+    AndOr(Box<ScriptNode>, Vec<(Connector, ScriptNode)>),
+    /// `if cond; then ...; elif cond; then ...; else ...; fi`
+    If {
+        branches: Vec<(Vec<ScriptNode>, Vec<ScriptNode>)>,
+        else_body: Option<Vec<ScriptNode>>,
+    },
+    /// `for var in words; do ...; done`; without `in`, loops over the positional arguments.
+    For {
+        var: String,
+        words: Option<String>,
+        body: Vec<ScriptNode>,
+    },
+    /// `while cond; do ...; done`, or `until` if `until` is set.
+    While {
+        condition: Vec<ScriptNode>,
+        body: Vec<ScriptNode>,
+        until: bool,
+    },
+    /// `case word in pattern) ...;; esac`
+    Case { word: String, arms: Vec<CaseArm> },
+    /// `name() { ...; }`
+    Function { name: String, body: Vec<ScriptNode> },
+    /// `{ ...; }`
+    Group(Vec<ScriptNode>),
+    /// `pipeline &`: runs the pipeline as a background job.
+    Background(Box<ScriptNode>),
+}
+
+impl fmt::Display for ScriptNode {
+    /// Writes the command back as shell syntax, on one line.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptNode::Pipeline(cmd) => write!(f, "{}", cmd),
+            ScriptNode::Not(inner) => write!(f, "! {}", inner),
+            ScriptNode::AndOr(first, rest) => {
+                write!(f, "{}", first)?;
+                for (connector, next) in rest.iter() {
+                    match connector {
+                        Connector::And => write!(f, " && {}", next)?,
+                        Connector::Or => write!(f, " || {}", next)?,
+                    }
+                }
+                Ok(())
+            }
+            ScriptNode::If {
+                branches,
+                else_body,
+            } => {
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    let keyword = match i {
+                        0 => "if",
+                        _ => "elif",
+                    };
+                    write!(
+                        f,
+                        "{} {} then {} ",
+                        keyword,
+                        list_str(condition),
+                        list_str(body)
+                    )?;
+                }
+                if let Some(body) = else_body {
+                    write!(f, "else {} ", list_str(body))?;
+                }
+                write!(f, "fi")
+            }
+            ScriptNode::For { var, words, body } => {
+                match words {
+                    Some(words) => write!(f, "for {} in {}; ", var, words)?,
+                    None => write!(f, "for {}; ", var)?,
+                }
+                write!(f, "do {} done", list_str(body))
+            }
+            ScriptNode::While {
+                condition,
+                body,
+                until,
+            } => {
+                let keyword = match until {
+                    true => "until",
+                    false => "while",
+                };
+                write!(
+                    f,
+                    "{} {} do {} done",
+                    keyword,
+                    list_str(condition),
+                    list_str(body)
+                )
+            }
+            ScriptNode::Case { word, arms } => {
+                write!(f, "case {} in ", word)?;
+                for arm in arms.iter() {
+                    write!(f, "{}) ", arm.patterns.join("|"))?;
+                    if !arm.body.is_empty() {
+                        write!(f, "{} ", list_str(&arm.body))?;
+                    }
+                    write!(f, ";; ")?;
+                }
+                write!(f, "esac")
+            }
+            ScriptNode::Function { name, body } => write!(f, "{}() {{ {} }}", name, list_str(body)),
+            ScriptNode::Group(body) => write!(f, "{{ {} }}", list_str(body)),
+            ScriptNode::Background(inner) => write!(f, "{} &", inner),
+        }
+    }
+}
+
+/// Writes a list of commands, each ended by `;` unless it already ends in `&`.
+fn list_str(nodes: &[ScriptNode]) -> String {
+    let commands: Vec<String> = nodes
+        .iter()
+        .map(|node| match node {
+            ScriptNode::Background(_) => node.to_string(),
+            _ => format!("{};", node),
+        })
+        .collect();
+    commands.join(" ")
+}
+
+/// Parses a script into a list of commands.
+/// Scripts with here-documents can't be split into lines, so they are rejected as a whole.
+pub fn parse_script(script: &str) -> Result<Vec<ScriptNode>> {
+    let mut tokens: Vec<ShellToken> = Vec::new();
+    let mut continued = String::new();
+    for line in script.lines() {
+        continued.push_str(line);
+        // quoted strings can span lines
+        if has_open_quote(&continued) {
+            continued.push('\n');
+            continue;
+        }
+        // lines ending in a backslash continue onto the next line
+        if line.ends_with('\\') && !line.ends_with("\\\\") {
+            continued.pop();
+            continue;
+        }
+        tokens.append(&mut lex_line(&continued)?);
+        tokens.push(ShellToken::Op("\n".to_string()));
+        continued.clear();
+    }
+    if !continued.is_empty() {
+        tokens.append(&mut lex_line(&continued)?);
+    }
+
+    let mut parser = ScriptParser { tokens, pos: 0 };
+    let nodes = parser.parse_list(&[])?;
+    if let Some(token) = parser.peek() {
+        bail!("Syntax error near unexpected token {:?}", token_str(token));
+    }
+    Ok(nodes)
+}
+
+/// Whether the text ends inside a quoted string.
+fn has_open_quote(text: &str) -> bool {
+    let mut quote: Option<char> = None;
+    let mut chars = text.chars().peekable();
+    let mut word_start = true;
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                chars.next();
+            }
+            (None, '\'') | (None, '"') => quote = Some(c),
+            // the rest of the line is a comment
+            (None, '#') if word_start => {
+                while chars.peek().map_or(false, |c| *c != '\n') {
+                    chars.next();
+                }
+            }
+            _ => {}
+        }
+        word_start = quote.is_none() && (c.is_whitespace() || ";&|()".contains(c));
+    }
+    quote.is_some()
+}
+
+/// Lexes one line of a script.
+fn lex_line(line: &str) -> Result<Vec<ShellToken>> {
+    let tokens = lex(line)?;
+    // the body of a here-document is on the lines that follow
+    if tokens.contains(&ShellToken::Op("<<".to_string())) {
+        bail!("Here-documents are not supported");
+    }
+    Ok(tokens)
+}
+
+fn token_str(token: &ShellToken) -> &str {
+    match token {
+        ShellToken::Word(word) => word,
+        ShellToken::Op(op) => op,
+    }
+}
+
+struct ScriptParser {
+    tokens: Vec<ShellToken>,
+    pos: usize,
+}
+
+impl ScriptParser {
+    fn peek(&self) -> Option<&ShellToken> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_word(&self) -> Option<&str> {
+        match self.peek() {
+            Some(ShellToken::Word(word)) => Some(word),
+            _ => None,
+        }
+    }
+
+    fn peek_op(&self) -> Option<&str> {
+        match self.peek() {
+            Some(ShellToken::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn advance(&mut self) -> Option<ShellToken> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek_op() == Some("\n") {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_separators(&mut self) {
+        while self.peek_op() == Some("\n") || self.peek_op() == Some(";") {
+            self.pos += 1;
+        }
+    }
+
+    fn expect_word(&mut self, expected: &str) -> Result<()> {
+        match self.advance() {
+            Some(ShellToken::Word(ref word)) if word == expected => Ok(()),
+            Some(token) => bail!(
+                "Syntax error: expected {:?}, found {:?}",
+                expected,
+                token_str(&token)
+            ),
+            None => bail!("Syntax error: expected {:?} before end of script", expected),
+        }
+    }
+
+    fn expect_op(&mut self, expected: &str) -> Result<()> {
+        match self.advance() {
+            Some(ShellToken::Op(ref op)) if op == expected => Ok(()),
+            Some(token) => bail!(
+                "Syntax error: expected {:?}, found {:?}",
+                expected,
+                token_str(&token)
+            ),
+            None => bail!("Syntax error: expected {:?} before end of script", expected),
+        }
+    }
+
+    /// Parses commands until one of the terminator words, `;;` or the end of the script.
+    fn parse_list(&mut self, terminators: &[&str]) -> Result<Vec<ScriptNode>> {
+        let mut nodes: Vec<ScriptNode> = Vec::new();
+        loop {
+            self.skip_separators();
+            match self.peek() {
+                None => break,
+                Some(ShellToken::Word(word)) if terminators.contains(&word.as_str()) => break,
+                Some(ShellToken::Op(op)) if op == ";;" => break,
+                _ => {}
+            }
+            let node = self.parse_and_or()?;
+            if self.peek_op() == Some("&") {
+                self.advance();
+                match node {
+                    ScriptNode::Pipeline(_) => nodes.push(ScriptNode::Background(Box::new(node))),
+                    _ => bail!("Only single pipelines can be run in the background"),
+                }
+                continue;
+            }
+            nodes.push(node);
+        }
+        Ok(nodes)
+    }
+
+    fn parse_and_or(&mut self) -> Result<ScriptNode> {
+        let first = self.parse_pipeline()?;
+        let mut rest: Vec<(Connector, ScriptNode)> = Vec::new();
+        loop {
+            let connector = match self.peek_op() {
+                Some("&&") => Connector::And,
+                Some("||") => Connector::Or,
+                _ => break,
+            };
+            self.advance();
+            self.skip_newlines();
+            rest.push((connector, self.parse_pipeline()?));
+        }
+        match rest.is_empty() {
+            true => Ok(first),
+            false => Ok(ScriptNode::AndOr(Box::new(first), rest)),
+        }
+    }
+
+    fn parse_pipeline(&mut self) -> Result<ScriptNode> {
+        if self.peek_word() == Some("!") {
+            self.advance();
+            return Ok(ScriptNode::Not(Box::new(self.parse_command()?)));
+        }
+        self.parse_command()
+    }
+
+    fn parse_command(&mut self) -> Result<ScriptNode> {
+        let is_function = match (self.tokens.get(self.pos + 1), self.tokens.get(self.pos + 2)) {
+            (Some(ShellToken::Op(open)), Some(ShellToken::Op(close))) => {
+                open == "(" && close == ")" && self.peek_word().is_some()
+            }
+            _ => false,
+        };
+        let node = match self.peek_word() {
+            Some("if") => self.parse_if()?,
+            Some("for") => self.parse_for()?,
+            Some("while") => self.parse_while(false)?,
+            Some("until") => self.parse_while(true)?,
+            Some("case") => self.parse_case()?,
+            Some("{") => {
+                self.advance();
+                let body = self.parse_list(&["}"])?;
+                self.expect_word("}")?;
+                ScriptNode::Group(body)
+            }
+            Some("function") => {
+                self.advance();
+                self.parse_function()?
+            }
+            Some(_) if is_function => self.parse_function()?,
+            Some(word) if RESERVED.contains(&word) => {
+                bail!("Syntax error near unexpected token {:?}", word);
+            }
+            _ => return self.parse_simple(),
+        };
+        // compound commands can't be piped or redirected yet
+        match self.peek() {
+            Some(ShellToken::Op(op)) if !SEPARATORS.contains(&op.as_str()) => {
+                bail!(
+                    "Pipes and redirections on compound commands are not supported: {:?}",
+                    op
+                );
+            }
+            _ => Ok(node),
+        }
+    }
+
+    /// Collects the tokens of a pipeline, up to the next separator.
+    fn parse_simple(&mut self) -> Result<ScriptNode> {
+        if self.peek_op() == Some("|") {
+            bail!("Syntax error near unexpected token \"|\"");
+        }
+        let mut parts: Vec<String> = Vec::new();
+        while let Some(token) = self.peek() {
+            if let ShellToken::Op(op) = token {
+                if SEPARATORS.contains(&op.as_str()) {
+                    break;
+                }
+            }
+            parts.push(token_str(token).to_string());
+            self.advance();
+        }
+        if parts.is_empty() {
+            match self.peek() {
+                Some(token) => bail!("Syntax error near unexpected token {:?}", token_str(token)),
+                None => bail!("Syntax error: unexpected end of script"),
+            }
+        }
+        Ok(ScriptNode::Pipeline(join(parts, " ")))
+    }
+
+    fn parse_if(&mut self) -> Result<ScriptNode> {
+        self.expect_word("if")?;
+        let mut branches: Vec<(Vec<ScriptNode>, Vec<ScriptNode>)> = Vec::new();
+        let mut else_body: Option<Vec<ScriptNode>> = None;
+        loop {
+            let condition = self.parse_list(&["then"])?;
+            self.expect_word("then")?;
+            let body = self.parse_list(&["elif", "else", "fi"])?;
+            branches.push((condition, body));
+            match self.advance() {
+                Some(ShellToken::Word(ref word)) if word == "elif" => {}
+                Some(ShellToken::Word(ref word)) if word == "else" => {
+                    else_body = Some(self.parse_list(&["fi"])?);
+                    self.expect_word("fi")?;
+                    break;
+                }
+                Some(ShellToken::Word(ref word)) if word == "fi" => break,
+                _ => bail!("Syntax error: expected \"fi\" to close if statement"),
+            }
+        }
+        Ok(ScriptNode::If {
+            branches,
+            else_body,
+        })
+    }
+
+    fn parse_for(&mut self) -> Result<ScriptNode> {
+        self.expect_word("for")?;
+        let var = match self.advance() {
+            Some(ShellToken::Word(word)) => word,
+            _ => bail!("Syntax error: expected variable name after \"for\""),
+        };
+        self.skip_newlines();
+        let mut words: Option<String> = None;
+        if self.peek_word() == Some("in") {
+            self.advance();
+            let mut parts: Vec<String> = Vec::new();
+            while let Some(ShellToken::Word(word)) = self.peek() {
+                parts.push(word.clone());
+                self.advance();
+            }
+            words = Some(join(parts, " "));
+        }
+        self.skip_separators();
+        self.expect_word("do")?;
+        let body = self.parse_list(&["done"])?;
+        self.expect_word("done")?;
+        Ok(ScriptNode::For { var, words, body })
+    }
+
+    fn parse_while(&mut self, until: bool) -> Result<ScriptNode> {
+        self.advance();
+        let condition = self.parse_list(&["do"])?;
+        self.expect_word("do")?;
+        let body = self.parse_list(&["done"])?;
+        self.expect_word("done")?;
+        Ok(ScriptNode::While {
+            condition,
+            body,
+            until,
+        })
+    }
+
+    fn parse_case(&mut self) -> Result<ScriptNode> {
+        self.expect_word("case")?;
+        let word = match self.advance() {
+            Some(ShellToken::Word(word)) => word,
+            _ => bail!("Syntax error: expected word after \"case\""),
+        };
+        self.skip_newlines();
+        self.expect_word("in")?;
+        let mut arms: Vec<CaseArm> = Vec::new();
+        loop {
+            self.skip_separators();
+            if self.peek_word() == Some("esac") {
+                break;
+            }
+            if self.peek_op() == Some("(") {
+                self.advance();
+            }
+            let mut patterns: Vec<String> = Vec::new();
+            loop {
+                match self.advance() {
+                    Some(ShellToken::Word(pattern)) => patterns.push(pattern),
+                    _ => bail!("Syntax error: expected pattern in case statement"),
+                }
+                if self.peek_op() == Some("|") {
+                    self.advance();
+                } else {
+                    break;
+                }
+            }
+            self.expect_op(")")?;
+            let body = self.parse_list(&["esac"])?;
+            arms.push(CaseArm { patterns, body });
+            if self.peek_op() == Some(";;") {
+                self.advance();
+            }
+        }
+        self.expect_word("esac")?;
+        Ok(ScriptNode::Case { word, arms })
+    }
+
+    /// Parses `name() { ...; }`; the `function` keyword has already been consumed.
+    fn parse_function(&mut self) -> Result<ScriptNode> {
+        let name = match self.advance() {
+            Some(ShellToken::Word(word)) => word,
+            _ => bail!("Syntax error: expected function name"),
+        };
+        if self.peek_op() == Some("(") {
+            self.advance();
+            self.expect_op(")")?;
+        }
+        self.skip_newlines();
+        self.expect_word("{")?;
+        let body = self.parse_list(&["}"])?;
+        self.expect_word("}")?;
+        Ok(ScriptNode::Function { name, body })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(cmd: &str) -> ScriptNode {
+        ScriptNode::Pipeline(cmd.to_string())
+    }
+
+    #[test]
+    fn test_parse_for_and_if() {
+        let script = "for day in /logs/2020-*; do\n  if [ -d $day ]; then\n    cat $day/*.log | grep ERROR > $day.err\n  else\n    echo missing $day\n  fi\ndone\n";
+        let nodes = parse_script(script).unwrap();
+        assert_eq!(
+            nodes,
+            vec![ScriptNode::For {
+                var: "day".to_string(),
+                words: Some("/logs/2020-*".to_string()),
+                body: vec![ScriptNode::If {
+                    branches: vec![(
+                        vec![pipeline("[ -d $day ]")],
+                        vec![pipeline("cat $day/*.log | grep ERROR > $day.err")],
+                    )],
+                    else_body: Some(vec![pipeline("echo missing $day")]),
+                }],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_while_case_function() {
+        let script = r#"
+count() {
+    wc -l $1
+}
+while [ $i -lt 3 ] && true; do echo $i; done
+case "$1" in
+    *.gz|*.zip) echo compressed ;;
+    *) count "$1"
+esac
+"#;
+        let nodes = parse_script(script).unwrap();
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(
+            nodes[0],
+            ScriptNode::Function {
+                name: "count".to_string(),
+                body: vec![pipeline("wc -l $1")],
+            }
+        );
+        match &nodes[2] {
+            ScriptNode::Case { word, arms } => {
+                assert_eq!(word, "\"$1\"");
+                assert_eq!(arms.len(), 2);
+                assert_eq!(arms[0].patterns, vec!["*.gz", "*.zip"]);
+                assert_eq!(arms[1].body, vec![pipeline("count \"$1\"")]);
+            }
+            _ => panic!("expected a case statement"),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_script("if true; then echo a").is_err());
+        assert!(parse_script("for x in a b; do echo $x; done | sort").is_err());
+        assert!(parse_script("echo a; fi").is_err());
+        assert!(parse_script("| grep a").is_err());
+        assert!(parse_script("true && sleep 10 &").is_err());
+        assert!(parse_script("cat $f << EOF\nbody\nEOF\n").is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        let script = "for f in \"$@\"; do\n  if [ -f $f ]; then cat $f | sort & else echo $f; fi\ndone\ncase $x in *.gz|*.zip) gzip -d $x;; *) ;; esac";
+        let nodes = parse_script(script).unwrap();
+        let lines: Vec<String> = nodes.iter().map(|node| node.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "for f in \"$@\"; do if [ -f $f ]; then cat $f | sort & else echo $f; fi; done",
+                "case $x in *.gz|*.zip) gzip -d $x; ;; *) ;; esac",
+            ]
+        );
+        // the written commands parse back to the same commands
+        for (node, line) in nodes.iter().zip(lines.iter()) {
+            assert_eq!(parse_script(line).unwrap(), vec![node.clone()]);
+        }
+    }
+
+    #[test]
+    fn test_parse_background() {
+        let nodes = parse_script("grep -r ERROR /logs > errors.txt & wc -l a.txt; wait").unwrap();
+        assert_eq!(
+            nodes,
+            vec![
+                ScriptNode::Background(Box::new(pipeline("grep -r ERROR /logs > errors.txt"))),
+                pipeline("wc -l a.txt"),
+                pipeline("wait"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_multiline_quotes() {
+        let script = "echo 'a\nb' \"c\n# d\" # it's\ngrep \"x\\\"\n\" f\n";
+        assert_eq!(
+            parse_script(script).unwrap(),
+            vec![
+                pipeline("echo 'a\nb' \"c\n# d\""),
+                pipeline("grep \"x\\\"\n\" f"),
+            ]
+        );
+        assert!(parse_script("echo 'a\nb").is_err());
+        assert_eq!(
+            parse_script("echo 'a\\\nb' c\\\nd").unwrap(),
+            vec![pipeline("echo 'a\\\nb' cd")]
+        );
+    }
+}
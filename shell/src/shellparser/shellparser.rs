@@ -1,6 +1,7 @@
 extern crate dash;
 extern crate itertools;
 extern crate shellwords;
+use super::expansion::{expand_line, ShellEnv, ShellToken};
 use super::special_commands::parse_export_command;
 use cmd::{CommandNode, NodeArg};
 use dash::graph::command as cmd;
@@ -48,6 +49,35 @@ pub fn parse_command(command: &str) -> Result<Command> {
     }
 }
 
+/// Parses a command after running shell expansions (parameters, tilde, braces) on each word.
+/// Export commands update the shell environment used for later expansions.
+pub fn parse_expanded_command(command: &str, env: &mut ShellEnv) -> Result<Command> {
+    let tokens = expand_line(command, env)?;
+    parse_tokens(tokens, env)
+}
+
+/// Parses a command from tokens that have already been expanded.
+pub fn parse_tokens(tokens: Vec<ShellToken>, env: &mut ShellEnv) -> Result<Command> {
+    if tokens.first() == Some(&ShellToken::Word("export".to_string())) {
+        if tokens.len() != 2 {
+            bail!("Export takes exactly one NAME=value argument: {:?}", tokens);
+        }
+        let (var, value) = match &tokens[1] {
+            ShellToken::Word(word) => match word.find('=') {
+                Some(idx) => (word[..idx].to_string(), word[idx + 1..].to_string()),
+                None => (word.clone(), env.get_var(word).unwrap_or_default()),
+            },
+            ShellToken::Op(op) => bail!("Unexpected {:?} in export command", op),
+        };
+        env.set_var(&var, &value);
+        return Ok(Command::EXPORT(var, value));
+    }
+    let shellsplit = ShellSplit::from_tokens(tokens)?;
+    let shellgraph = shellsplit.convert_into_shell_graph()?;
+    let program = shellgraph.convert_into_program()?;
+    Ok(Command::PROGRAM(program))
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Hash, Eq)]
 pub struct SubCommand {
     pub elts: Vec<RawShellElement>,
@@ -543,6 +573,18 @@ impl RawShellElement {
     }
 }
 
+/// Maps a pipe or redirection operator to its shell element.
+fn redirection_element(op: &str) -> Result<RawShellElement> {
+    match op {
+        "<" => Ok(RawShellElement::Stdin),
+        ">" => Ok(RawShellElement::Stdout),
+        ">>" => Ok(RawShellElement::StdoutAppend),
+        "2>" => Ok(RawShellElement::Stderr),
+        "|" => Ok(RawShellElement::Pipe),
+        _ => bail!("Unsupported shell syntax: {:?}", op),
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct ShellSplit {
     elts: Vec<RawShellElement>,
@@ -629,6 +671,49 @@ impl ShellSplit {
         Ok(ShellSplit { elts: elements })
     }
 
+    /// Builds the shell split from already expanded tokens.
+    /// Unlike `new`, quoted words are never mistaken for operators.
+    pub fn from_tokens(tokens: Vec<ShellToken>) -> Result<Self> {
+        let mut elements: Vec<RawShellElement> = Vec::new();
+        let mut it = tokens.into_iter();
+        while let Some(token) = it.next() {
+            match token {
+                ShellToken::Word(word) => {
+                    elements.push(RawShellElement::Str(word));
+                }
+                ShellToken::Op(op) => match op.as_ref() {
+                    "<(" => {
+                        let mut found_close_parens = false;
+                        let mut subcommand: Vec<RawShellElement> = Vec::new();
+                        for inner in it.by_ref() {
+                            match inner {
+                                ShellToken::Word(word) => {
+                                    subcommand.push(RawShellElement::Str(word));
+                                }
+                                ShellToken::Op(inner_op) => {
+                                    if inner_op == ")" {
+                                        found_close_parens = true;
+                                        break;
+                                    }
+                                    subcommand.push(redirection_element(&inner_op)?);
+                                }
+                            }
+                        }
+                        if !found_close_parens {
+                            bail!("Unclosed parens!");
+                        }
+                        elements.push(RawShellElement::Stdin);
+                        elements.push(RawShellElement::Subcmd(SubCommand::new(subcommand)));
+                    }
+                    _ => {
+                        elements.push(redirection_element(&op)?);
+                    }
+                },
+            }
+        }
+        Ok(ShellSplit { elts: elements })
+    }
+
     /// Takes the Shell Split and converts it into a graph.
     pub fn convert_into_shell_graph(&self) -> Result<ShellGraph> {
         let mut graph = ShellGraph::default();
@@ -818,4 +903,35 @@ mod test {
             }
         };
     }
+
+    #[test]
+    fn test_expanded_command() {
+        let mut env = ShellEnv::default();
+        match parse_expanded_command("export PATTERN='a|b'", &mut env).unwrap() {
+            Command::EXPORT(var, value) => {
+                assert_eq!(var, "PATTERN");
+                assert_eq!(value, "a|b");
+            }
+            Command::PROGRAM(_) => panic!("expected an export command"),
+        }
+        let tokens = expand_line(
+            "grep \"$PATTERN\" file.txt | wc > ${PATTERN%|b}.out",
+            &mut env,
+        )
+        .unwrap();
+        let shell_split = ShellSplit::from_tokens(tokens).unwrap();
+        assert_eq!(
+            shell_split.elts,
+            vec![
+                RawShellElement::Str("grep".to_string()),
+                RawShellElement::Str("a|b".to_string()),
+                RawShellElement::Str("file.txt".to_string()),
+                RawShellElement::Pipe,
+                RawShellElement::Str("wc".to_string()),
+                RawShellElement::Stdout,
+                RawShellElement::Str("a.out".to_string()),
+            ]
+        );
+        assert!(parse_expanded_command("cat a.txt && cat b.txt", &mut env).is_err());
+    }
 }